@@ -0,0 +1,325 @@
+// Actions which send an item's content to an LLM: summarize, explain and translate, or any prompt
+// template, where {content} is replaced with the item's content. The response is streamed into a
+// new item, in the same stack as the item it's about, with the streaming event, as content piped
+// through a shell command is. The new item's source is the item it was made from.
+//
+// The endpoint is any OpenAI compatible chat completions API, OpenAI's by default. Its key is kept
+// in the system keychain, falling back to openai_access_token.
+
+use futures::StreamExt;
+use scru128::Scru128Id;
+use serde::Serialize;
+
+use crate::commands::Content;
+use crate::error::StacksError;
+use crate::events;
+use crate::state::SharedState;
+use crate::store::{InProgressStream, MimeType, Settings, Tokenizer};
+use crate::ui::{PreviewLimits, StreamPreview};
+
+pub const DEFAULT_URL: &str = "https://api.openai.com/v1/chat/completions";
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
+
+const KEYCHAIN_SERVICE: &str = "stacks";
+const KEYCHAIN_USER: &str = "llm";
+
+pub const TEMPLATES: [(&str, &str); 3] = [
+    (
+        "summarize",
+        "Summarize the following concisely:\n\n{content}",
+    ),
+    (
+        "explain",
+        "Explain the following clearly, for someone new to it:\n\n{content}",
+    ),
+    (
+        "translate",
+        "Translate the following into English:\n\n{content}",
+    ),
+];
+
+// A built-in action's name is replaced with its template. Templates without {content} have the
+// content appended.
+pub fn prompt(template: &str, content: &str) -> String {
+    let template = TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == template)
+        .map(|(_, template)| *template)
+        .unwrap_or(template);
+    if template.contains("{content}") {
+        template.replace("{content}", content)
+    } else {
+        format!("{}\n\n{}", template, content)
+    }
+}
+
+pub fn key_get() -> Option<String> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER).ok()?;
+    entry.get_password().ok().filter(|key| !key.is_empty())
+}
+
+// Stores the key in the keychain, or removes it
+pub fn key_set(key: Option<&str>) -> Result<(), String> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER).map_err(|e| e.to_string())?;
+    match key {
+        Some(key) => entry.set_password(key).map_err(|e| e.to_string()),
+        None => match entry.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub url: String,
+    pub model: String,
+    pub key: Option<String>,
+}
+
+impl Config {
+    pub fn from_settings(settings: &Settings, key: Option<String>) -> Config {
+        let fallback = Some(settings.openai_access_token.clone()).filter(|key| !key.is_empty());
+        let selected = Some(settings.openai_selected_model.clone()).filter(|m| !m.is_empty());
+        Config {
+            url: settings.llm_url.clone().unwrap_or(DEFAULT_URL.to_string()),
+            model: settings
+                .llm_model
+                .clone()
+                .or(selected)
+                .unwrap_or(DEFAULT_MODEL.to_string()),
+            key: key.or(fallback),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: [Message<'a>; 1],
+    stream: bool,
+}
+
+// Takes the complete server-sent events from the buffer, returning the text they add, and whether
+// the stream is done
+pub fn parse_events(buffer: &mut Vec<u8>) -> (String, bool) {
+    let mut text = String::new();
+    let mut done = false;
+    // chunks can end mid character, so only complete lines are decoded
+    while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line);
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            continue;
+        };
+        let data = data.trim();
+        if data == "[DONE]" {
+            done = true;
+            continue;
+        }
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else {
+            continue;
+        };
+        if let Some(delta) = event["choices"][0]["delta"]["content"].as_str() {
+            text.push_str(delta);
+        }
+    }
+    (text, done)
+}
+
+fn emit_streaming(
+    app: &tauri::AppHandle,
+    streamer: &InProgressStream,
+    previewer: &mut StreamPreview,
+) {
+    let (preview, truncated) = previewer.update(
+        &streamer.content,
+        &streamer.content_meta.mime_type,
+        &streamer.content_meta.content_type,
+    );
+    let content = Content {
+        mime_type: streamer.content_meta.mime_type.clone(),
+        content_type: streamer.content_meta.content_type.clone(),
+        terse: streamer.content_meta.terse.clone(),
+        tiktokens: streamer.content_meta.tiktokens,
+        words: streamer.content_meta.stats.words,
+        chars: streamer.content_meta.stats.chars,
+        lines: streamer.content_meta.stats.lines,
+        bytes: streamer.content_meta.stats.bytes,
+        preview: preview.to_string(),
+        truncated,
+        truncated_from: None,
+        thumbnail: None,
+    };
+    let scope = events::Scope {
+        stack_id: streamer.packet.stack_id.as_ref(),
+        mime_type: Some(&streamer.content_meta.mime_type),
+    };
+    events::emit_scoped(app, "streaming", &scope, (streamer.item_id(), content));
+}
+
+// Runs the action on the item, returning the id of the item the response was streamed into
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    id: Scru128Id,
+    template: &str,
+) -> Result<Scru128Id, StacksError> {
+    let (stack_id, content) = state.with_lock(|state| {
+        let item = state
+            .view
+            .items
+            .get(&id)
+            .filter(|item| !item.is_stack)
+            .ok_or(StacksError::not_found(&id))?;
+        let stack_id = item.stack_id.ok_or(StacksError::not_found(&id))?;
+        let meta = state.store.get_content_meta(&item.hash);
+        if !meta.is_some_and(|meta| meta.mime_type == MimeType::TextPlain) {
+            return Err(StacksError::invalid_input(
+                "only text can be sent to an LLM",
+            ));
+        }
+        let content = state
+            .store
+            .get_content(&item.hash)
+            .ok_or(StacksError::content_not_found(&id))?;
+        Ok((stack_id, String::from_utf8_lossy(&content).to_string()))
+    })?;
+    run_prompt(app, state, &prompt(template, &content), stack_id, id).await
+}
+
+// Sends the prompt, streaming the response into a new item in the stack, whose source is the item
+// the prompt was made from. Returns the new item's id.
+pub async fn run_prompt(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    prompt: &str,
+    stack_id: Scru128Id,
+    source_id: Scru128Id,
+) -> Result<Scru128Id, StacksError> {
+    let key = tauri::async_runtime::spawn_blocking(key_get)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (config, tokenizer, mut previewer) = state.with_lock(|state| {
+        if state.is_read_only(&stack_id) {
+            return Err(StacksError::read_only(&stack_id));
+        }
+        let settings = state.store.settings_get().unwrap_or_default();
+        Ok((
+            Config::from_settings(&settings, key),
+            Tokenizer::from_settings(&settings),
+            StreamPreview::new(
+                PreviewLimits::from_settings(Some(settings)),
+                &state.ui.theme.syntax,
+            ),
+        ))
+    })?;
+
+    let mut req = reqwest::Client::new().post(&config.url).json(&ChatRequest {
+        model: &config.model,
+        messages: [Message {
+            role: "user",
+            content: prompt,
+        }],
+        stream: true,
+    });
+    if let Some(key) = &config.key {
+        req = req.bearer_auth(key);
+    }
+    let res = req.send().await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        let status = res.status();
+        return Err(format!("{}: {}", status, res.text().await.unwrap_or_default()).into());
+    }
+
+    let mut streamer = state.with_lock(|state| {
+        let streamer = InProgressStream::new(stack_id, MimeType::TextPlain, "Text".to_string());
+        state.merge(&streamer.packet);
+        streamer
+    });
+    events::emit(app, "refresh-items", true);
+
+    let mut body = res.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                tracing::error!(name = "actions", %e, "response ended early");
+                break;
+            }
+        };
+        buffer.extend_from_slice(&chunk);
+        let (text, done) = parse_events(&mut buffer);
+        if !text.is_empty() {
+            streamer.append(text.as_bytes());
+            streamer.count_tiktokens(text.as_bytes(), tokenizer);
+            emit_streaming(app, &streamer, &mut previewer);
+        }
+        if done {
+            break;
+        }
+    }
+
+    let new_id = state.with_lock(|state| {
+        let packet = streamer.end_stream(&mut state.store);
+        state.store.insert_packet(&packet);
+        state.merge(&packet);
+        state.store.source_set(packet.id, &source_id.to_string());
+        packet.id
+    });
+    events::emit(app, "refresh-items", true);
+    Ok(new_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt() {
+        assert_eq!(
+            prompt("summarize", "retry with backoff"),
+            "Summarize the following concisely:\n\nretry with backoff"
+        );
+        assert_eq!(prompt("Say {content} twice", "hi"), "Say hi twice");
+        assert_eq!(prompt("Fix the grammar", "hi"), "Fix the grammar\n\nhi");
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut buffer = Vec::from(concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"cont",
+        ));
+        assert_eq!(parse_events(&mut buffer), ("Hello".to_string(), false));
+        // an incomplete event is left for the next chunk
+        assert_eq!(buffer, b"data: {\"choices\":[{\"delta\":{\"cont");
+        buffer.extend_from_slice(b"ent\":\"!\"}}]}\n\ndata: [DONE]\n\n");
+        assert_eq!(parse_events(&mut buffer), ("!".to_string(), true));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_config() {
+        let mut settings = Settings::default();
+        settings.openai_access_token = "sk-settings".to_string();
+        let config = Config::from_settings(&settings, None);
+        assert_eq!(config.url, DEFAULT_URL);
+        assert_eq!(config.model, DEFAULT_MODEL);
+        assert_eq!(config.key.as_deref(), Some("sk-settings"));
+
+        settings.openai_selected_model = "gpt-4o".to_string();
+        let config = Config::from_settings(&settings, Some("sk-keychain".to_string()));
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.key.as_deref(), Some("sk-keychain"));
+    }
+}
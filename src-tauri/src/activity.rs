@@ -0,0 +1,166 @@
+// Activity over time: how many items were copied in each hour or day, and where from, and of which
+// mime types, so the history can be drawn without sending every item to the webview. Buckets
+// start on the local hour or day, and only ones with items are returned.
+
+use std::collections::BTreeMap;
+
+use chrono::{Local, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::state::State;
+use crate::store::MimeType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    Day,
+}
+
+// milliseconds since the epoch, from start up to, but not including, end
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Period {
+    pub start: u64,
+    pub items: usize,
+    // by the host of the URL the item was copied from, or its source, for items which have one
+    pub sources: BTreeMap<String, usize>,
+    pub mime_types: BTreeMap<String, usize>,
+}
+
+// When the bucket the time falls in starts, in local time
+pub fn bucket_start(ms: u64, bucket: Bucket) -> u64 {
+    let Some(time) = Local.timestamp_millis_opt(ms as i64).earliest() else {
+        return ms;
+    };
+    let start = match bucket {
+        Bucket::Hour => time
+            .with_minute(0)
+            .and_then(|t| t.with_second(0))
+            .and_then(|t| t.with_nanosecond(0)),
+        Bucket::Day => time
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|t| Local.from_local_datetime(&t).earliest()),
+    };
+    start.map_or(ms, |start| start.timestamp_millis() as u64)
+}
+
+fn source_key(source: &str) -> String {
+    reqwest::Url::parse(source)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| source.to_string())
+}
+
+// Groups the items, as when they were copied, their source and mime type, into periods
+pub fn aggregate<'a>(
+    items: impl IntoIterator<Item = (u64, Option<String>, &'a MimeType)>,
+    range: Range,
+    bucket: Bucket,
+) -> Vec<Period> {
+    let mut periods: BTreeMap<u64, Period> = BTreeMap::new();
+    for (ms, source, mime_type) in items {
+        if ms < range.start || ms >= range.end {
+            continue;
+        }
+        let start = bucket_start(ms, bucket);
+        let period = periods.entry(start).or_insert_with(|| Period {
+            start,
+            ..Default::default()
+        });
+        period.items += 1;
+        if let Some(source) = source {
+            *period.sources.entry(source_key(&source)).or_default() += 1;
+        }
+        *period
+            .mime_types
+            .entry(mime_type.as_str().to_string())
+            .or_default() += 1;
+    }
+    periods.into_values().collect()
+}
+
+pub fn timeline(state: &State, range: Range, bucket: Bucket) -> Vec<Period> {
+    let items: Vec<_> = state
+        .view
+        .items
+        .values()
+        .filter(|item| !item.is_stack)
+        .filter_map(|item| {
+            let meta = state.store.get_content_meta(&item.hash)?;
+            Some((
+                item.id.timestamp(),
+                state.store.source_get(&item.id),
+                meta.mime_type,
+            ))
+        })
+        .collect();
+    aggregate(
+        items
+            .iter()
+            .map(|(ms, source, mime_type)| (*ms, source.clone(), mime_type)),
+        range,
+        bucket,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let hour = 60 * 60 * 1000;
+        let t = bucket_start(1_700_000_000_000, Bucket::Day) + 9 * hour;
+        let (text, png) = (MimeType::TextPlain, MimeType::ImagePng);
+        let items = vec![
+            (t, Some("https://github.com/rust-lang".to_string()), &text),
+            (
+                t + 10,
+                Some("https://github.com/tauri-apps".to_string()),
+                &text,
+            ),
+            (t + hour + 10, None, &png),
+            (t + 30 * hour, Some("com.apple.Terminal".to_string()), &text),
+            // outside the range
+            (t + 60 * hour, None, &text),
+        ];
+        let range = Range {
+            start: t,
+            end: t + 48 * hour,
+        };
+
+        let hours = aggregate(items.clone(), range, Bucket::Hour);
+        assert_eq!(
+            hours.iter().map(|p| (p.start, p.items)).collect::<Vec<_>>(),
+            vec![(t, 2), (t + hour, 1), (t + 30 * hour, 1)]
+        );
+        assert_eq!(
+            hours[0].sources,
+            BTreeMap::from([("github.com".to_string(), 2)])
+        );
+        assert_eq!(
+            hours[1].mime_types,
+            BTreeMap::from([("image/png".to_string(), 1)])
+        );
+        assert_eq!(
+            hours[2].sources,
+            BTreeMap::from([("com.apple.Terminal".to_string(), 1)])
+        );
+
+        let days = aggregate(items, range, Bucket::Day);
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].start, bucket_start(t, Bucket::Day));
+        assert_eq!(days[0].items, 3);
+        assert_eq!(
+            days[0].mime_types,
+            BTreeMap::from([("image/png".to_string(), 1), ("text/plain".to_string(), 2)])
+        );
+    }
+}
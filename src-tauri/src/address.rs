@@ -0,0 +1,168 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::paste;
+
+// Items which are entirely a postal address have this content type
+pub const CONTENT_TYPE: &str = "Address";
+
+// street suffix, as written -> its USPS abbreviation
+const SUFFIXES: &[(&str, &str)] = &[
+    ("Street", "St"),
+    ("St", "St"),
+    ("Avenue", "Ave"),
+    ("Ave", "Ave"),
+    ("Road", "Rd"),
+    ("Rd", "Rd"),
+    ("Boulevard", "Blvd"),
+    ("Blvd", "Blvd"),
+    ("Lane", "Ln"),
+    ("Ln", "Ln"),
+    ("Drive", "Dr"),
+    ("Dr", "Dr"),
+    ("Way", "Way"),
+    ("Court", "Ct"),
+    ("Ct", "Ct"),
+    ("Place", "Pl"),
+    ("Pl", "Pl"),
+    ("Parkway", "Pkwy"),
+    ("Pkwy", "Pkwy"),
+    ("Square", "Sq"),
+    ("Sq", "Sq"),
+    ("Terrace", "Ter"),
+    ("Ter", "Ter"),
+    ("Highway", "Hwy"),
+    ("Hwy", "Hwy"),
+    ("Circle", "Cir"),
+    ("Cir", "Cir"),
+    ("Loop", "Loop"),
+];
+
+lazy_static! {
+    static ref ADDRESS: Regex = {
+        let suffixes = SUFFIXES
+            .iter()
+            .map(|(suffix, _)| *suffix)
+            .collect::<Vec<_>>()
+            .join("|");
+        // street names are capitalized words, which keeps "3 dogs in my way" from matching
+        Regex::new(&format!(
+            concat!(
+                r"\b(\d{{1,6}})\s+((?:[A-Z0-9][A-Za-z0-9'-]*\.?\s+){{1,4}}?)({})\.?",
+                r"(?:,?\s+((?i:apt|suite|ste|unit)\.?\s*[\w-]+|#\s*[\w-]+))?",
+                r"(?:,?\s+([A-Z][A-Za-z'-]*(?:\s+[A-Z][A-Za-z'-]*){{0,3}}),?\s+([A-Z]{{2}})\s+(\d{{5}}(?:-\d{{4}})?))?\b"
+            ),
+            suffixes
+        ))
+        .unwrap()
+    };
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Address {
+    // the address as it appears in the text
+    pub text: String,
+    // the address on a single line, with a standard street suffix
+    pub normalized: String,
+}
+
+impl Address {
+    // provider is one of: apple or google
+    pub fn map_link(&self, provider: &str) -> Option<String> {
+        let query = paste::transform(&paste::Transform::UrlEncode, &self.normalized)?;
+        match provider {
+            "apple" => Some(format!("https://maps.apple.com/?q={}", query)),
+            "google" => Some(format!(
+                "https://www.google.com/maps/search/?api=1&query={}",
+                query
+            )),
+            _ => None,
+        }
+    }
+}
+
+// Returns the postal addresses found in the text, in the order they appear
+pub fn find(input: &str) -> Vec<Address> {
+    ADDRESS
+        .captures_iter(input)
+        .map(|caps| {
+            let name = caps[2].split_whitespace().collect::<Vec<_>>().join(" ");
+            let suffix = SUFFIXES
+                .iter()
+                .find(|(suffix, _)| *suffix == &caps[3])
+                .map(|(_, abbreviation)| *abbreviation)
+                .unwrap_or(&caps[3]);
+
+            let mut parts = vec![format!("{} {} {}", &caps[1], name, suffix)];
+            if let Some(unit) = caps.get(4) {
+                parts.push(
+                    unit.as_str()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            if let (Some(city), Some(state), Some(zip)) = (caps.get(5), caps.get(6), caps.get(7)) {
+                let city = city
+                    .as_str()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                parts.push(city);
+                parts.push(format!("{} {}", state.as_str(), zip.as_str()));
+            }
+
+            Address {
+                text: caps[0].to_string(),
+                normalized: parts.join(", "),
+            }
+        })
+        .collect()
+}
+
+// Returns true if the text is a single address and nothing else
+pub fn is_address(input: &str) -> bool {
+    let input = input.trim();
+    ADDRESS
+        .find(input)
+        .is_some_and(|m| m.start() == 0 && m.end() == input.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let text = "Meet at 1600 Amphitheatre Parkway,\nMountain View, CA 94043 then 221 Baker Street, Suite 2B.";
+        let found = find(text);
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[0].normalized,
+            "1600 Amphitheatre Pkwy, Mountain View, CA 94043"
+        );
+        assert_eq!(found[1].normalized, "221 Baker St, Suite 2B");
+
+        assert_eq!(find("I have 3 dogs in my way"), vec![]);
+    }
+
+    #[test]
+    fn test_is_address() {
+        assert!(is_address("  1 Infinite Loop, Cupertino, CA 95014\n"));
+        assert!(!is_address("send it to 1 Infinite Loop"));
+    }
+
+    #[test]
+    fn test_map_link() {
+        let address = &find("10 Downing Street")[0];
+        assert_eq!(
+            address.map_link("apple"),
+            Some("https://maps.apple.com/?q=10%20Downing%20St".to_string())
+        );
+        assert_eq!(
+            address.map_link("google"),
+            Some("https://www.google.com/maps/search/?api=1&query=10%20Downing%20St".to_string())
+        );
+        assert_eq!(address.map_link("bing"), None);
+    }
+}
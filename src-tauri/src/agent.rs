@@ -0,0 +1,164 @@
+// Running Stacks as a background agent: launched when the user logs in, by a LaunchAgent, and,
+// with background_agent set, without creating the spotlight window until the activation shortcut,
+// or the menubar's Open Stacks, asks for it. Capture, the HTTP server, and the cross.stream sync
+// run either way.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::State;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Agent {
+    pub launch_at_login: bool,
+    pub background_agent: bool,
+}
+
+pub fn get(state: &State) -> Agent {
+    let settings = state.store.settings_get().unwrap_or_default();
+    Agent {
+        launch_at_login: settings.launch_at_login.unwrap_or(false),
+        background_agent: settings.background_agent.unwrap_or(false),
+    }
+}
+
+// The user's LaunchAgents directory
+pub fn launch_agents_dir() -> Option<PathBuf> {
+    Some(dirs::home_dir()?.join("Library").join("LaunchAgents"))
+}
+
+pub fn login_item_path(dir: &Path, label: &str) -> PathBuf {
+    dir.join(format!("{}.plist", label))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// A LaunchAgent which runs the program when the user logs in
+pub fn plist(label: &str, program: &Path) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        escape(label),
+        escape(&program.to_string_lossy())
+    )
+}
+
+// Adds, or removes, the LaunchAgent. It's rewritten each time, so it follows the app if it's moved.
+pub fn set_login_item(
+    dir: &Path,
+    label: &str,
+    program: &Path,
+    enabled: bool,
+) -> Result<(), String> {
+    let path = login_item_path(dir, label);
+    if enabled {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        std::fs::write(&path, plist(label, program)).map_err(|e| e.to_string())
+    } else {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Whether the program is the executable of an app bundle, e.g. Stacks.app/Contents/MacOS/stacks,
+// rather than a build in the source tree
+pub fn is_app_bundle(program: &Path) -> bool {
+    let mut dirs = program.ancestors().skip(1);
+    match (dirs.next(), dirs.next(), dirs.next()) {
+        (Some(macos), Some(contents), Some(app)) => {
+            macos.ends_with("MacOS")
+                && contents.ends_with("Contents")
+                && app.extension().map_or(false, |ext| ext == "app")
+        }
+        _ => false,
+    }
+}
+
+fn sync_login_item(app: &tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let dir = launch_agents_dir().ok_or("no home directory")?;
+    let program = std::env::current_exe().map_err(|e| e.to_string())?;
+    // only installed copies of the app are launched at login, not development builds, which move
+    // and go stale; the login item can still be removed from any build
+    if enabled && (cfg!(debug_assertions) || !is_app_bundle(&program)) {
+        return Err("launch at login needs a release build of Stacks.app".to_string());
+    }
+    let label = &app.config().tauri.bundle.identifier;
+    set_login_item(&dir, label, &program, enabled)
+}
+
+// Saves the settings, and adds or removes the login item to match
+pub fn set(app: &tauri::AppHandle, state: &mut State, agent: Agent) -> Result<(), String> {
+    sync_login_item(app, agent.launch_at_login)?;
+    let mut settings = state.store.settings_get().unwrap_or_default();
+    settings.launch_at_login = Some(agent.launch_at_login);
+    settings.background_agent = Some(agent.background_agent);
+    state.store.settings_save(settings);
+    Ok(())
+}
+
+// At startup, points the login item at this copy of the app
+pub fn start(app: &tauri::AppHandle, state: &State) {
+    if get(state).launch_at_login {
+        if let Err(e) = sync_login_item(app, true) {
+            tracing::warn!(name = "agent", %e, "couldn't update the login item");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_login_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("LaunchAgents");
+        let label = "stream.cross.stacks";
+        let program = Path::new("/Applications/Stacks & Co.app/Contents/MacOS/stacks");
+        let path = login_item_path(&dir, label);
+
+        set_login_item(&dir, label, program, true).unwrap();
+        let plist = std::fs::read_to_string(&path).unwrap();
+        assert!(plist.contains("<string>stream.cross.stacks</string>"));
+        assert!(plist
+            .contains("<string>/Applications/Stacks &amp; Co.app/Contents/MacOS/stacks</string>"));
+        assert!(plist.contains("<key>RunAtLoad</key>\n    <true/>"));
+
+        set_login_item(&dir, label, program, false).unwrap();
+        assert!(!path.exists());
+        // removing it again is fine
+        set_login_item(&dir, label, program, false).unwrap();
+    }
+
+    #[test]
+    fn test_is_app_bundle() {
+        assert!(is_app_bundle(Path::new(
+            "/Applications/Stacks.app/Contents/MacOS/stacks"
+        )));
+        assert!(!is_app_bundle(Path::new(
+            "/Users/me/src/stacks/src-tauri/target/release/stacks"
+        )));
+        assert!(!is_app_bundle(Path::new("/Applications/Stacks.app/stacks")));
+        assert!(!is_app_bundle(Path::new("stacks")));
+    }
+}
@@ -0,0 +1,384 @@
+// Snapshots of the store, taken on a schedule, so a corrupted store isn't a total loss. This is
+// off unless backup_interval_hours is set. Each snapshot is a directory, stacks-<id>, holding the
+// packet log and content metadata as lines of JSON, and the content the packets refer to, hard
+// linked from the content store where it can be, so snapshots share it rather than each copying
+// it. Snapshots are written under a temporary name and renamed once complete, so a snapshot which
+// was interrupted is never mistaken for a good one. Only the last few are kept.
+//
+// Sensitive items, and items due to expire, aren't snapshotted, and purged items are pruned from
+// the snapshots, so a snapshot doesn't keep what the store has let go of.
+
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::Serialize;
+use ssri::Integrity;
+
+use crate::events;
+use crate::state::{SharedState, State};
+use crate::store::{ContentMeta, Packet, Settings};
+use crate::view::View;
+
+pub const DEFAULT_KEEP: usize = 7;
+// snapshots are written here, within the store's directory, unless backup_dir is set
+pub const DEFAULT_DIR: &str = "snapshots";
+
+const PREFIX: &str = "stacks-";
+const PARTIAL_SUFFIX: &str = ".partial";
+const PACKETS_FILE: &str = "packets.jsonl";
+const CONTENT_META_FILE: &str = "content_meta.jsonl";
+const CAS_DIR: &str = "cas";
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Backup {
+    pub id: Scru128Id,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    // when the snapshot was taken, in milliseconds since the epoch
+    pub fn timestamp(&self) -> u64 {
+        self.id.timestamp()
+    }
+}
+
+pub fn target_dir(store_path: &str, settings: Option<&Settings>) -> PathBuf {
+    settings
+        .and_then(|settings| settings.backup_dir.clone())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(store_path).join(DEFAULT_DIR))
+}
+
+// The complete snapshots in dir, oldest first
+pub fn list(dir: &Path) -> Vec<Backup> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<Backup> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = name.strip_prefix(PREFIX)?.parse::<Scru128Id>().ok()?;
+            Some(Backup {
+                id,
+                path: entry.path(),
+            })
+        })
+        .collect();
+    backups.sort_by_key(|backup| backup.id);
+    backups
+}
+
+// Links each file in from into to, or copies it if it can't be linked, e.g. as to is on another
+// filesystem. Files in the content store are never changed once written, so the link can be shared.
+fn link_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &target)?;
+        } else if !target.exists() {
+            // the content store is content addressed, so a file which is already there is the same
+            std::fs::hard_link(entry.path(), &target)
+                .or_else(|_| std::fs::copy(entry.path(), &target).map(|_| ()))?;
+        }
+    }
+    Ok(())
+}
+
+// Removes content from a snapshot's content store which none of its packets refer to, returning
+// the metadata of the content which is left
+fn remove_unreferenced(cas: &Path, packets: &[Packet], metas: &[ContentMeta]) -> Vec<ContentMeta> {
+    let referenced: HashSet<&Integrity> = packets.iter().filter_map(|p| p.hash.as_ref()).collect();
+    let (kept, removed): (Vec<&ContentMeta>, Vec<&ContentMeta>) = metas
+        .iter()
+        .partition(|meta| referenced.contains(&meta.hash));
+    for meta in removed {
+        let _ = cacache::remove_hash_sync(cas, &meta.hash);
+    }
+    kept.into_iter().cloned().collect()
+}
+
+fn write_lines<T: Serialize>(path: &Path, values: impl Iterator<Item = T>) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    for value in values {
+        serde_json::to_writer(&mut file, &value)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner()?.sync_all()
+}
+
+// Replaces a file, writing it to the side first, so it's never left partially written
+fn rewrite_lines<T: Serialize>(
+    path: &Path,
+    values: impl Iterator<Item = T>,
+) -> std::io::Result<()> {
+    let partial = path.with_extension("partial");
+    write_lines(&partial, values)?;
+    std::fs::rename(&partial, path)
+}
+
+fn read_lines<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Vec<T>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::io::BufReader::new(file)
+        .lines()
+        .map(|line| {
+            let line = line.map_err(|e| e.to_string())?;
+            serde_json::from_str(&line).map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect()
+}
+
+pub fn create(
+    dir: &Path,
+    cache_path: &str,
+    packets: &[Packet],
+    metas: &[ContentMeta],
+) -> std::io::Result<Backup> {
+    let id = scru128::new();
+    let path = dir.join(format!("{}{}", PREFIX, id));
+    let partial = dir.join(format!("{}{}{}", PREFIX, id, PARTIAL_SUFFIX));
+    std::fs::create_dir_all(&partial)?;
+    let cas = partial.join(CAS_DIR);
+    if Path::new(cache_path).exists() {
+        link_dir(Path::new(cache_path), &cas)?;
+    }
+    let metas = remove_unreferenced(&cas, packets, metas);
+    write_lines(&partial.join(PACKETS_FILE), packets.iter())?;
+    write_lines(&partial.join(CONTENT_META_FILE), metas.iter())?;
+    std::fs::rename(&partial, &path)?;
+    Ok(Backup { id, path })
+}
+
+// Removes all but the newest keep snapshots, and any left partial
+pub fn rotate(dir: &Path, keep: usize) -> std::io::Result<()> {
+    let backups = list(dir);
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        std::fs::remove_dir_all(&backup.path)?;
+    }
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SUFFIX)
+        {
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// Takes purged items out of every snapshot in dir: their packets, and the content no other packet
+// refers to. Returns how many snapshots were changed.
+pub fn prune(dir: &Path, ids: &[Scru128Id]) -> Result<usize, String> {
+    let mut pruned = 0;
+    for backup in list(dir) {
+        let packets: Vec<Packet> = read_lines(&backup.path.join(PACKETS_FILE))?;
+        let count = packets.len();
+        let packets: Vec<Packet> = packets
+            .into_iter()
+            .filter(|p| !ids.contains(&p.id) && !p.source_id.is_some_and(|id| ids.contains(&id)))
+            .collect();
+        if packets.len() == count {
+            continue;
+        }
+        let metas: Vec<ContentMeta> = read_lines(&backup.path.join(CONTENT_META_FILE))?;
+        let metas = remove_unreferenced(&backup.path.join(CAS_DIR), &packets, &metas);
+        rewrite_lines(&backup.path.join(PACKETS_FILE), packets.iter())
+            .and_then(|_| rewrite_lines(&backup.path.join(CONTENT_META_FILE), metas.iter()))
+            .map_err(|e| e.to_string())?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+// Replaces the store's packets with the snapshot's, restoring any content it's missing. Returns
+// how many packets were restored.
+pub fn restore(state: &mut State, path: &Path) -> Result<usize, String> {
+    let packets: Vec<Packet> = read_lines(&path.join(PACKETS_FILE))?;
+    let metas: Vec<ContentMeta> = read_lines(&path.join(CONTENT_META_FILE))?;
+    let cas = path.join(CAS_DIR);
+    if cas.exists() {
+        link_dir(&cas, Path::new(&state.store.cache_path)).map_err(|e| e.to_string())?;
+    }
+    for meta in metas {
+        if state.store.get_content_meta(&meta.hash).is_some() {
+            continue;
+        }
+        if let Some(content) = state.store.cas_read(&meta.hash) {
+            state
+                .store
+                .cas_write(&content, meta.mime_type, meta.content_type);
+        }
+    }
+
+    let ids: Vec<Scru128Id> = state.store.scan().map(|packet| packet.id).collect();
+    for id in ids {
+        state.store.remove_packet(&id);
+    }
+    for packet in &packets {
+        state.store.insert_packet(packet);
+    }
+    let mut view = View::new();
+    state.store.scan().for_each(|p| view.merge(&p));
+    state.ui = state.ui.renew(&view);
+    state.view = view;
+    Ok(packets.len())
+}
+
+// Takes a snapshot, unless one was taken within the interval. Forced snapshots are always taken.
+pub async fn run(state: &SharedState, force: bool) -> Result<Option<Backup>, String> {
+    let (dir, keep, interval) = state.with_lock(|state| {
+        let settings = state.store.settings_get();
+        let dir = target_dir(&state.store.path, settings.as_ref());
+        let settings = settings.unwrap_or_default();
+        (
+            dir,
+            settings.backup_keep.unwrap_or(DEFAULT_KEEP),
+            settings.backup_interval_hours.unwrap_or(0),
+        )
+    });
+    if !force {
+        // scheduled snapshots are off unless an interval is set
+        if interval == 0 {
+            return Ok(None);
+        }
+        let interval = interval * 60 * 60 * 1000;
+        let last = list(&dir).last().map(|backup| backup.timestamp());
+        if last.is_some_and(|last| crate::privacy::now() < last + interval) {
+            return Ok(None);
+        }
+    }
+
+    take(state, &dir, Some(keep)).await.map(Some)
+}
+
+// Snapshots the store into dir, then rotates the snapshots there if keep is set
+async fn take(state: &SharedState, dir: &Path, keep: Option<usize>) -> Result<Backup, String> {
+    let (cache_path, packets, metas) = state.with_lock(|state| {
+        let private: HashSet<Scru128Id> = state
+            .view
+            .items
+            .keys()
+            .filter(|id| state.store.is_sensitive(id) || state.store.has_expiry(id))
+            .copied()
+            .collect();
+        let packets: Vec<Packet> = state
+            .store
+            .scan()
+            .filter(|p| {
+                !private.contains(&p.id) && !p.source_id.is_some_and(|id| private.contains(&id))
+            })
+            .collect();
+        let metas: Vec<ContentMeta> = state.store.scan_content_meta().into_values().collect();
+        (state.store.cache_path.clone(), packets, metas)
+    });
+    let dir = dir.to_path_buf();
+    let backup = tauri::async_runtime::spawn_blocking(move || {
+        let backup = create(&dir, &cache_path, &packets, &metas)?;
+        if let Some(keep) = keep {
+            rotate(&dir, keep.max(1))?;
+        }
+        Ok::<_, std::io::Error>(backup)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    tracing::info!(name = "backup", path = ?backup.path);
+    Ok(backup)
+}
+
+// Restores the snapshot at path, after taking a snapshot of the store as it is
+pub async fn restore_from(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    path: &Path,
+) -> Result<usize, String> {
+    if !path.join(PACKETS_FILE).exists() {
+        return Err(format!("not a backup: {}", path.display()));
+    }
+    // not rotated, which could remove the snapshot being restored
+    let dir =
+        state.with_lock(|state| target_dir(&state.store.path, state.store.settings_get().as_ref()));
+    take(state, &dir, None).await?;
+    let restored = state.with_lock(|state| restore(state, path))?;
+    tracing::info!(name = "backup", ?path, restored, "restored");
+    events::emit(app, "refresh-items", true);
+    Ok(restored)
+}
+
+pub fn spawn(state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            if let Err(e) = run(&state, false).await {
+                tracing::error!(name = "backup", %e, "failed to back up the store");
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MimeType, StackLockStatus};
+
+    #[test]
+    fn test_create_rotate_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store");
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path.to_str().unwrap(), sender);
+
+        let stack = state.store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        state.merge(&stack);
+        let item = state.store.add(b"hello", MimeType::TextPlain, stack.id);
+        state.merge(&item);
+
+        let target = dir.path().join("backups");
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let metas: Vec<ContentMeta> = state.store.scan_content_meta().into_values().collect();
+        let backup = create(&target, &state.store.cache_path, &packets, &metas).unwrap();
+        assert_eq!(list(&target), vec![backup.clone()]);
+
+        // a store which has lost its content, and most of its packets
+        let hash = item.hash.clone().unwrap();
+        cacache::remove_hash_sync(&state.store.cache_path, &hash).unwrap();
+        state.store.remove_packet(&item.id);
+        let gone = state.store.delete(stack.id);
+        state.merge(&gone);
+
+        assert_eq!(restore(&mut state, &backup.path), Ok(2));
+        assert_eq!(state.store.scan().collect::<Vec<_>>(), packets);
+        assert!(state.view.items.contains_key(&item.id));
+        assert_eq!(state.store.get_content(&hash).unwrap(), b"hello");
+
+        for _ in 0..3 {
+            create(&target, &state.store.cache_path, &packets, &metas).unwrap();
+        }
+        std::fs::create_dir_all(target.join(format!("{}x{}", PREFIX, PARTIAL_SUFFIX))).unwrap();
+        rotate(&target, 2).unwrap();
+        let kept = list(&target);
+        assert_eq!(kept.len(), 2);
+        assert!(!kept.contains(&backup));
+        assert_eq!(std::fs::read_dir(&target).unwrap().count(), 2);
+
+        // purging the item takes it out of both
+        assert_eq!(prune(&target, &[item.id]), Ok(2));
+        assert_eq!(prune(&target, &[item.id]), Ok(0));
+        for backup in list(&target) {
+            let pruned: Vec<Packet> = read_lines(&backup.path.join(PACKETS_FILE)).unwrap();
+            assert_eq!(pruned, vec![stack.clone()]);
+            let metas: Vec<ContentMeta> = read_lines(&backup.path.join(CONTENT_META_FILE)).unwrap();
+            assert!(metas.iter().all(|meta| meta.hash != hash));
+            assert!(cacache::read_hash_sync(backup.path.join(CAS_DIR), &hash).is_err());
+        }
+        assert_eq!(state.store.get_content(&hash).unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,160 @@
+// Multi-select actions are sent as one batch of ops, applied under a single lock with a single
+// refresh, rather than a round-trip and repaint per item. Ops are checked before any is applied:
+// if one can't be, none are.
+
+use std::collections::HashSet;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::state::State;
+use crate::store::MimeType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    Delete {
+        id: Scru128Id,
+    },
+    // moves the item from its stack into another
+    Move {
+        id: Scru128Id,
+        stack_id: Scru128Id,
+    },
+    // bumps the item to the top of its stack
+    Touch {
+        id: Scru128Id,
+    },
+    // adds the item to another stack as well, or removes it
+    Link {
+        id: Scru128Id,
+        stack_id: Scru128Id,
+        linked: bool,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BatchError {
+    // the op which couldn't be applied
+    pub index: usize,
+    pub error: String,
+}
+
+fn check(state: &State, deleted: &HashSet<Scru128Id>, op: &Op) -> Result<(), String> {
+    let item = |id: &Scru128Id| match state.view.items.get(id) {
+        Some(item) if !deleted.contains(id) => Ok(item),
+        _ => Err(format!("item not found: {}", id)),
+    };
+    let stack = |id: &Scru128Id| match item(id) {
+        Ok(stack) if stack.is_stack => Ok(()),
+        _ => Err(format!("stack not found: {}", id)),
+    };
+    let writable = |id: &Scru128Id| match state.is_read_only(id) {
+        true => Err(format!("read-only: {}", id)),
+        false => Ok(()),
+    };
+    match op {
+        Op::Delete { id } => {
+            item(id)?;
+            writable(id)
+        }
+        Op::Move { id, stack_id } => {
+            if item(id)?.is_stack {
+                return Err(format!("stacks can't be moved: {}", id));
+            }
+            stack(stack_id)?;
+            writable(id)?;
+            writable(stack_id)
+        }
+        Op::Touch { id } => item(id).map(|_| ()),
+        Op::Link { id, stack_id, .. } => {
+            item(id)?;
+            stack(stack_id)?;
+            writable(stack_id)
+        }
+    }
+}
+
+// Applies the ops in order, returning how many were applied
+pub fn apply(state: &mut State, ops: &[Op]) -> Result<usize, BatchError> {
+    let mut deleted = HashSet::new();
+    for (index, op) in ops.iter().enumerate() {
+        check(state, &deleted, op).map_err(|error| BatchError { index, error })?;
+        if let Op::Delete { id } = op {
+            deleted.insert(*id);
+        }
+    }
+
+    for op in ops {
+        let packet = match op {
+            Op::Delete { id } => state.store.delete(*id),
+            Op::Move { id, stack_id } => {
+                state
+                    .store
+                    .update(*id, None, MimeType::TextPlain, Some(*stack_id))
+            }
+            Op::Touch { id } => state.store.update_touch(*id),
+            Op::Link {
+                id,
+                stack_id,
+                linked,
+            } => state.store.link(*id, *stack_id, *linked),
+        };
+        state.merge(&packet);
+    }
+    Ok(ops.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StackLockStatus;
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let inbox = state.store.add_stack(b"Inbox", StackLockStatus::Unlocked);
+        state.merge(&inbox);
+        let done = state.store.add_stack(b"Done", StackLockStatus::Unlocked);
+        state.merge(&done);
+        let mut ids = Vec::new();
+        for content in ["one", "two", "three"] {
+            let packet = state
+                .store
+                .add(content.as_bytes(), MimeType::TextPlain, inbox.id);
+            state.merge(&packet);
+            ids.push(packet.id);
+        }
+
+        let ops: Vec<Op> = serde_json::from_str(&format!(
+            r#"[{{"op": "delete", "id": "{}"}}, {{"op": "move", "id": "{}", "stack_id": "{}"}}]"#,
+            ids[0], ids[1], done.id
+        ))
+        .unwrap();
+        assert_eq!(apply(&mut state, &ops), Ok(2));
+        assert!(!state.view.items.contains_key(&ids[0]));
+        assert_eq!(state.view.items[&ids[1]].stack_id, Some(done.id));
+
+        // nothing is applied if any op can't be
+        let ops = vec![
+            Op::Touch { id: ids[2] },
+            Op::Delete { id: ids[2] },
+            Op::Delete { id: ids[2] },
+        ];
+        let last_touched = state.view.items[&ids[2]].last_touched;
+        assert_eq!(apply(&mut state, &ops).unwrap_err().index, 2);
+        assert_eq!(state.view.items[&ids[2]].last_touched, last_touched);
+
+        let read_only = state
+            .store
+            .update_stack_lock_status(done.id, StackLockStatus::ReadOnly);
+        state.merge(&read_only);
+        let ops = vec![Op::Delete { id: ids[1] }];
+        assert_eq!(apply(&mut state, &ops).unwrap_err().index, 0);
+        assert!(state.view.items.contains_key(&ids[1]));
+    }
+}
@@ -0,0 +1,188 @@
+// A command line client for Stacks' HTTP server, so the store can be used from scripts, and over
+// SSH with the port forwarded.
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: stacks-cli <command>
+
+commands:
+  put                  adds stdin to the current stack, printing the new item's id
+  get <id>             writes the item's content to stdout
+  search <filter>      lists the items matching the filter
+  list --stack <name>  lists the stack's items
+
+options:
+  --limit <n>          the most items to list, defaults to 20
+
+environment:
+  STACKS_ADDR          the server's address, defaults to where the running app listens
+  STACKS_TOKEN         the API token, read from the store's directory by default
+  STACK_DB_PATH        the store's directory, if it isn't the default";
+
+#[derive(serde::Deserialize, Debug)]
+struct ListedItem {
+    id: String,
+    stack: Option<String>,
+    content_type: String,
+    terse: String,
+}
+
+struct Client {
+    base: String,
+    http: reqwest::blocking::Client,
+}
+
+fn store_dir() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("STACK_DB_PATH") {
+        return Some(PathBuf::from(path));
+    }
+    dirs::data_dir().map(|dir| dir.join("stream.cross.stacks/store-v3.0"))
+}
+
+fn api_token() -> Result<String, String> {
+    if let Ok(token) = std::env::var("STACKS_TOKEN") {
+        return Ok(token);
+    }
+    let path = store_dir()
+        .ok_or("couldn't find the store: set STACK_DB_PATH")?
+        .join("api-token");
+    std::fs::read_to_string(&path)
+        .map(|token| token.trim().to_string())
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ServerInfo {
+    addr: Option<String>,
+}
+
+// The running app records where its server listens, as the configured port may have been taken
+fn server_addr() -> String {
+    if let Ok(addr) = std::env::var("STACKS_ADDR") {
+        return addr;
+    }
+    store_dir()
+        .and_then(|dir| std::fs::read_to_string(dir.join("server.json")).ok())
+        .and_then(|info| serde_json::from_str::<ServerInfo>(&info).ok())
+        .and_then(|info| info.addr)
+        .unwrap_or("127.0.0.1:9146".to_string())
+}
+
+impl Client {
+    fn new() -> Self {
+        let addr = server_addr();
+        Client {
+            base: format!("http://{}", addr),
+            http: reqwest::blocking::Client::new(),
+        }
+    }
+
+    fn put(&self, content: Vec<u8>) -> Result<String, String> {
+        let res = self
+            .http
+            .post(format!("{}/", self.base))
+            .bearer_auth(api_token()?)
+            .body(content)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        res.text().map_err(|e| e.to_string())
+    }
+
+    fn get(&self, id: &str) -> Result<Vec<u8>, String> {
+        let res = self
+            .http
+            .get(format!("{}/{}", self.base, id))
+            .bearer_auth(api_token()?)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(res.bytes().map_err(|e| e.to_string())?.to_vec())
+    }
+
+    fn items(&self, query: &[(&str, String)]) -> Result<Vec<ListedItem>, String> {
+        let res = self
+            .http
+            .get(format!("{}/items", self.base))
+            .bearer_auth(api_token()?)
+            .query(query)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        res.json().map_err(|e| e.to_string())
+    }
+}
+
+fn print_items(items: &[ListedItem]) {
+    for item in items {
+        println!(
+            "{}\t{}\t{}\t{}",
+            item.id,
+            item.stack.as_deref().unwrap_or(""),
+            item.content_type,
+            item.terse.replace(['\n', '\t'], " ")
+        );
+    }
+}
+
+// Returns the value following the option, if it's given
+fn option(args: &[String], name: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    args.get(i + 1).cloned()
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let client = Client::new();
+    let limit = option(args, "--limit").unwrap_or("20".to_string());
+    match args.first().map(String::as_str) {
+        Some("put") => {
+            let mut content = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut content)
+                .map_err(|e| e.to_string())?;
+            println!("{}", client.put(content)?);
+        }
+        Some("get") => {
+            let id = args.get(1).ok_or(USAGE)?;
+            let content = client.get(id)?;
+            std::io::stdout()
+                .write_all(&content)
+                .map_err(|e| e.to_string())?;
+        }
+        Some("search") => {
+            let filter = args.get(1).ok_or(USAGE)?;
+            print_items(&client.items(&[("q", filter.clone()), ("limit", limit)])?);
+        }
+        Some("list") => {
+            let stack = option(args, "--stack").ok_or(USAGE)?;
+            print_items(&client.items(&[("stack", stack), ("limit", limit)])?);
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_option() {
+        let args: Vec<String> = ["list", "--stack", "Work", "--limit"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(option(&args, "--stack"), Some("Work".to_string()));
+        assert_eq!(option(&args, "--limit"), None);
+        assert_eq!(option(&args, "--nope"), None);
+    }
+}
@@ -0,0 +1,252 @@
+// Stacks can be shared as bundles: a single JSON file holding the stack's name and its items, with
+// their content inline. Each item carries its content hash, and the bundle the integrity of its
+// items, so a bundle which was corrupted or edited in transit is rejected on import.
+
+use base64::{engine::general_purpose, Engine as _};
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use ssri::Integrity;
+
+use crate::state::State;
+use crate::store::{MimeType, StackLockStatus, Store};
+use crate::view::View;
+
+pub const VERSION: u32 = 1;
+
+pub const FILE_EXTENSION: &str = "stack.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub version: u32,
+    pub name: String,
+    pub items: Vec<BundleItem>,
+    // the integrity of the items, serialized as JSON
+    pub integrity: Integrity,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BundleItem {
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    pub note: Option<String>,
+    // base64
+    pub content: String,
+}
+
+fn integrity(items: &[BundleItem]) -> Integrity {
+    Integrity::from(serde_json::to_vec(items).unwrap())
+}
+
+// Items are bundled in the order the stack shows them. Nested stacks, and references to local
+// files, aren't included.
+pub fn export(store: &Store, view: &View, stack_id: &Scru128Id) -> Option<Bundle> {
+    let stack = view.items.get(stack_id).filter(|item| item.is_stack)?;
+    let name = String::from_utf8_lossy(&store.get_content(&stack.hash)?).to_string();
+    let items: Vec<_> = view
+        .children(stack)
+        .into_iter()
+        .filter_map(|id| view.items.get(&id))
+        .filter(|item| !item.is_stack)
+        .filter_map(|item| {
+            let meta = store.get_content_meta(&item.hash)?;
+            if meta.mime_type == MimeType::FileRef {
+                return None;
+            }
+            let content = store.get_content(&item.hash)?;
+            Some(BundleItem {
+                hash: item.hash.clone(),
+                mime_type: meta.mime_type,
+                content_type: meta.content_type,
+                note: store.note_get(&item.id),
+                content: general_purpose::STANDARD.encode(content),
+            })
+        })
+        .collect();
+    Some(Bundle {
+        version: VERSION,
+        name,
+        integrity: integrity(&items),
+        items,
+    })
+}
+
+// Parses and verifies a bundle, returning each item's decoded content alongside it. References to
+// local files are dropped: export leaves them out, so a bundle which has them was made by hand, to
+// point Stacks at a file of its choosing.
+pub fn parse(data: &[u8]) -> Result<(Bundle, Vec<Vec<u8>>), String> {
+    let mut bundle: Bundle = serde_json::from_slice(data).map_err(|e| e.to_string())?;
+    if bundle.version > VERSION {
+        return Err(format!("unsupported bundle version: {}", bundle.version));
+    }
+    if integrity(&bundle.items) != bundle.integrity {
+        return Err("bundle failed verification".to_string());
+    }
+    let contents = bundle
+        .items
+        .iter()
+        .map(|item| {
+            let content = general_purpose::STANDARD
+                .decode(&item.content)
+                .map_err(|e| e.to_string())?;
+            item.hash
+                .check(&content)
+                .map_err(|_| format!("item failed verification: {}", item.hash))?;
+            Ok(content)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let (items, contents): (Vec<_>, Vec<_>) = bundle
+        .items
+        .into_iter()
+        .zip(contents)
+        .filter(|(item, _)| item.mime_type != MimeType::FileRef)
+        .unzip();
+    bundle.items = items;
+    Ok((bundle, contents))
+}
+
+// A stack named for the bundle is suffixed with a number if the name is already taken, e.g.
+// "Snippets (2)"
+pub fn unique_name(name: &str, taken: &[String]) -> String {
+    if !taken.iter().any(|t| t == name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+// Adds the bundle's items to a new stack, returning the stack's id. Duplicate items in the bundle
+// are added once.
+pub fn import(state: &mut State, bundle: &Bundle, contents: &[Vec<u8>]) -> Scru128Id {
+    let taken: Vec<String> = state
+        .view
+        .items
+        .values()
+        .filter(|item| item.is_stack)
+        .filter_map(|item| state.store.get_content(&item.hash))
+        .map(|name| String::from_utf8_lossy(&name).to_string())
+        .collect();
+    let name = unique_name(&bundle.name, &taken);
+    let stack = state
+        .store
+        .add_stack(name.as_bytes(), StackLockStatus::Unlocked);
+    state.merge(&stack);
+
+    let mut added: Vec<&Integrity> = Vec::new();
+    // oldest first, so the stack shows the items in the bundle's order
+    for (item, content) in bundle.items.iter().zip(contents).rev() {
+        if added.contains(&&item.hash) {
+            continue;
+        }
+        added.push(&item.hash);
+        let packet = state.store.add(content, item.mime_type.clone(), stack.id);
+        state.merge(&packet);
+        let meta = state.store.get_content_meta(&item.hash);
+        if meta.is_some_and(|meta| meta.content_type != item.content_type) {
+            let packet = state
+                .store
+                .update_content_type(item.hash.clone(), item.content_type.clone());
+            state.merge(&packet);
+        }
+        if let Some(note) = &item.note {
+            state.store.note_set(packet.id, note);
+        }
+    }
+    tracing::info!(name = "bundle", stack = %name, items = added.len(), "imported");
+    stack.id
+}
+
+// Reads a bundle from a file, or downloads it from an http(s) URL
+pub async fn fetch(path_or_url: &str) -> Result<Vec<u8>, String> {
+    if path_or_url.starts_with("https://") || path_or_url.starts_with("http://") {
+        let res = reqwest::get(path_or_url)
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| e.to_string())?;
+        let bytes = res.bytes().await.map_err(|e| e.to_string())?;
+        return Ok(bytes.to_vec());
+    }
+    tokio::fs::read(path_or_url)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack = state
+            .store
+            .add_stack(b"Snippets", StackLockStatus::Unlocked);
+        state.merge(&stack);
+        let first = state.store.add(b"SELECT 1", MimeType::TextPlain, stack.id);
+        state.merge(&first);
+        let second = state.store.add(b"# Notes", MimeType::TextPlain, stack.id);
+        state.merge(&second);
+        state.store.note_set(first.id, "sanity check");
+
+        let bundle = export(&state.store, &state.view, &stack.id).unwrap();
+        assert_eq!(bundle.name, "Snippets");
+        assert_eq!(bundle.items.len(), 2);
+        let data = serde_json::to_vec(&bundle).unwrap();
+
+        let (parsed, contents) = parse(&data).unwrap();
+        assert_eq!(parsed, bundle);
+        let id = import(&mut state, &parsed, &contents);
+        let imported = export(&state.store, &state.view, &id).unwrap();
+        assert_eq!(imported.name, "Snippets (2)");
+        assert_eq!(imported.items, bundle.items);
+
+        // edited content is rejected
+        let mut edited = bundle.clone();
+        edited.items[0].content = general_purpose::STANDARD.encode("DROP TABLE users");
+        let data = serde_json::to_vec(&edited).unwrap();
+        assert!(parse(&data).is_err());
+        edited.integrity = integrity(&edited.items);
+        let data = serde_json::to_vec(&edited).unwrap();
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn test_parse_drops_file_refs() {
+        let item = |content: &str, mime_type: MimeType| BundleItem {
+            hash: Integrity::from(content),
+            mime_type,
+            content_type: "Text".to_string(),
+            note: None,
+            content: general_purpose::STANDARD.encode(content),
+        };
+        let items = vec![
+            item("SELECT 1", MimeType::TextPlain),
+            item("{\"path\":\"/Users/me/.ssh/id_rsa\"}", MimeType::FileRef),
+        ];
+        let bundle = Bundle {
+            version: VERSION,
+            name: "Snippets".to_string(),
+            integrity: integrity(&items),
+            items,
+        };
+        let data = serde_json::to_vec(&bundle).unwrap();
+
+        let (parsed, contents) = parse(&data).unwrap();
+        assert_eq!(parsed.items, bundle.items[..1]);
+        assert_eq!(contents, vec![b"SELECT 1".to_vec()]);
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let taken = vec!["Snippets".to_string(), "Snippets (2)".to_string()];
+        assert_eq!(unique_name("SQL", &taken), "SQL");
+        assert_eq!(unique_name("Snippets", &taken), "Snippets (3)");
+    }
+}
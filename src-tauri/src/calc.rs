@@ -0,0 +1,323 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref CONVERSION: Regex =
+        Regex::new(r"(?i)^\s*(-?\d+(?:\.\d+)?)\s*([a-z°]+)\s+(?:to|in)\s+([a-z°]+)\s*$").unwrap();
+    static ref DATE: Regex = Regex::new(r"^\s*\d{4}-\d{2}-\d{2}").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Dimension {
+    Length,
+    Mass,
+    Volume,
+    Time,
+    Data,
+    Temperature,
+}
+
+// unit name -> (dimension, factor to the dimension's base unit)
+fn unit(name: &str) -> Option<(Dimension, f64)> {
+    use Dimension::*;
+    let unit = match name.to_lowercase().as_str() {
+        "mm" => (Length, 0.001),
+        "cm" => (Length, 0.01),
+        "m" => (Length, 1.0),
+        "km" => (Length, 1000.0),
+        "in" | "inch" | "inches" => (Length, 0.0254),
+        "ft" | "feet" => (Length, 0.3048),
+        "yd" => (Length, 0.9144),
+        "mi" | "mile" | "miles" => (Length, 1609.344),
+        "mg" => (Mass, 0.001),
+        "g" => (Mass, 1.0),
+        "kg" => (Mass, 1000.0),
+        "oz" => (Mass, 28.349523125),
+        "lb" | "lbs" => (Mass, 453.59237),
+        "ml" => (Volume, 0.001),
+        "l" => (Volume, 1.0),
+        "gal" => (Volume, 3.785411784),
+        "ms" => (Time, 0.001),
+        "s" | "sec" => (Time, 1.0),
+        "min" => (Time, 60.0),
+        "h" | "hr" => (Time, 3600.0),
+        "d" | "day" | "days" => (Time, 86400.0),
+        "b" => (Data, 1.0),
+        "kb" => (Data, 1e3),
+        "mb" => (Data, 1e6),
+        "gb" => (Data, 1e9),
+        "tb" => (Data, 1e12),
+        "kib" => (Data, 1024.0),
+        "mib" => (Data, 1024.0 * 1024.0),
+        "gib" => (Data, 1024.0 * 1024.0 * 1024.0),
+        "c" | "°c" => (Temperature, 0.0),
+        "f" | "°f" => (Temperature, 0.0),
+        "k" => (Temperature, 0.0),
+        _ => return None,
+    };
+    Some(unit)
+}
+
+fn to_celsius(value: f64, unit: &str) -> f64 {
+    match unit.trim_start_matches('°') {
+        "f" => (value - 32.0) * 5.0 / 9.0,
+        "k" => value - 273.15,
+        _ => value,
+    }
+}
+
+fn from_celsius(value: f64, unit: &str) -> f64 {
+    match unit.trim_start_matches('°') {
+        "f" => value * 9.0 / 5.0 + 32.0,
+        "k" => value + 273.15,
+        _ => value,
+    }
+}
+
+fn convert(input: &str) -> Option<String> {
+    let caps = CONVERSION.captures(input)?;
+    let value = caps[1].parse::<f64>().ok()?;
+    let from = caps[2].to_lowercase();
+    let to = caps[3].to_lowercase();
+
+    let (from_dim, from_factor) = unit(&from)?;
+    let (to_dim, to_factor) = unit(&to)?;
+    if from_dim != to_dim {
+        return None;
+    }
+
+    let result = if from_dim == Dimension::Temperature {
+        from_celsius(to_celsius(value, &from), &to)
+    } else {
+        value * from_factor / to_factor
+    };
+    Some(format!("{} {}", format_number(result), &caps[3]))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Num(f64),
+    Op(char),
+    Open,
+    Close,
+}
+
+// Commas are only taken as thousands separators, e.g. 1,234.5: 1,2 isn't a number
+fn parse_number(text: &str) -> Option<f64> {
+    if text.contains(',') {
+        let (int, frac) = text.split_once('.').unwrap_or((text, ""));
+        let mut groups = int.split(',');
+        let first = groups.next()?;
+        if frac.contains(',')
+            || !(1..=3).contains(&first.len())
+            || groups.any(|group| group.len() != 3)
+        {
+            return None;
+        }
+    }
+    text.replace(',', "").parse().ok()
+}
+
+fn tokenize(input: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' | '_' => i += 1,
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_digit() || matches!(chars[i], '.' | ','))
+                {
+                    i += 1;
+                }
+                let num: String = chars[start..i].iter().collect();
+                // 0x10 is hex, not 0 times 10
+                if num == "0" && matches!(chars.get(i), Some('x' | 'X')) {
+                    return None;
+                }
+                tokens.push(Token::Num(parse_number(&num)?));
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => {
+                tokens.push(Token::Op(c));
+                i += 1;
+            }
+            'x' | '×' => {
+                tokens.push(Token::Op('*'));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Option<f64> {
+        let mut value = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Some(value)
+    }
+
+    fn term(&mut self) -> Option<f64> {
+        let mut value = self.factor()?;
+        while let Some(Token::Op(op @ ('*' | '/' | '%'))) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = match op {
+                '*' => value * rhs,
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Some(value)
+    }
+
+    fn factor(&mut self) -> Option<f64> {
+        let base = self.unary()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            let exponent = self.factor()?;
+            return Some(base.powf(exponent));
+        }
+        Some(base)
+    }
+
+    fn unary(&mut self) -> Option<f64> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                Some(-self.unary()?)
+            }
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Option<f64> {
+        match self.next()? {
+            Token::Num(n) => Some(n),
+            Token::Open => {
+                let value = self.expr()?;
+                match self.next()? {
+                    Token::Close => Some(value),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn arithmetic(input: &str) -> Option<String> {
+    if DATE.is_match(input) {
+        return None;
+    }
+
+    let tokens = tokenize(input)?;
+    // a lone number isn't a calculation: require at least one binary operator
+    let has_operator = tokens
+        .windows(2)
+        .any(|w| matches!(w, [Token::Num(_) | Token::Close, Token::Op(_)]));
+    if !has_operator {
+        return None;
+    }
+
+    let mut parser = Parser { tokens, pos: 0 };
+    let value = parser.expr()?;
+    if parser.pos != parser.tokens.len() || !value.is_finite() {
+        return None;
+    }
+    Some(format_number(value))
+}
+
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        return format!("{}", value as i64);
+    }
+    let formatted = format!("{:.10}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+// Evaluates the filter input as an arithmetic expression, e.g. "(2 + 3) * 4", or a unit
+// conversion, e.g. "10 km to mi". Returns None if the input doesn't look like either.
+pub fn evaluate(input: &str) -> Option<String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
+    }
+    convert(input).or_else(|| arithmetic(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(evaluate("1 + 2 * 3"), Some("7".to_string()));
+        assert_eq!(evaluate("(1 + 2) * 3"), Some("9".to_string()));
+        assert_eq!(evaluate("2 ^ 3 ^ 2"), Some("512".to_string()));
+        assert_eq!(evaluate("-4 / 8"), Some("-0.5".to_string()));
+        assert_eq!(evaluate("10 % 4"), Some("2".to_string()));
+        assert_eq!(evaluate("1 / 3"), Some("0.3333333333".to_string()));
+        assert_eq!(evaluate("1,000 * 2.5"), Some("2500".to_string()));
+    }
+
+    #[test]
+    fn test_not_a_calculation() {
+        assert_eq!(evaluate("42"), None);
+        assert_eq!(evaluate("-42"), None);
+        assert_eq!(evaluate("hello world"), None);
+        assert_eq!(evaluate("2023-10-01"), None);
+        assert_eq!(evaluate("1 / 0"), None);
+        assert_eq!(evaluate("(1 + 2"), None);
+        assert_eq!(evaluate("0x10"), None);
+        assert_eq!(evaluate("1,2"), None);
+        assert_eq!(evaluate("1,2 + 3"), None);
+        assert_eq!(evaluate("1,0000 + 3"), None);
+    }
+
+    #[test]
+    fn test_conversion() {
+        assert_eq!(evaluate("1 km to m"), Some("1000 m".to_string()));
+        assert_eq!(evaluate("100 C to F"), Some("212 F".to_string()));
+        assert_eq!(evaluate("2 kib in b"), Some("2048 b".to_string()));
+        assert_eq!(evaluate("1 kg to km"), None);
+    }
+}
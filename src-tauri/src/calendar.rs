@@ -0,0 +1,367 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Items with this content type are parsed into an Event
+pub const CONTENT_TYPE: &str = "Event";
+
+// natural language events are expected to be a short line or two, e.g. "Lunch with Ada tomorrow
+// at 1pm": anything longer is more likely prose which happens to mention a time
+const MAX_TEXT_LEN: usize = 200;
+const MAX_TEXT_LINES: usize = 3;
+
+lazy_static! {
+    static ref ISO_DATE: Regex = Regex::new(r"\b(\d{4})-(\d{2})-(\d{2})\b").unwrap();
+    static ref MONTH_DATE: Regex = Regex::new(
+        r"(?i)\b(?:on\s+)?(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?\s+(\d{1,2})(?:st|nd|rd|th)?(?:,?\s+(\d{4}))?\b"
+    )
+    .unwrap();
+    static ref RELATIVE_DATE: Regex = Regex::new(
+        r"(?i)\b(?:(?:on|next)\s+)?(today|tonight|tomorrow|monday|tuesday|wednesday|thursday|friday|saturday|sunday)\b"
+    )
+    .unwrap();
+    static ref TIME: Regex =
+        Regex::new(r"(?i)\b(?:at\s+)?(?:(\d{1,2})(?::(\d{2}))?\s*([ap])\.?m\.?|(\d{1,2}):(\d{2})|(noon))\b")
+            .unwrap();
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    pub summary: String,
+    pub start: NaiveDateTime,
+    pub end: Option<NaiveDateTime>,
+    pub all_day: bool,
+    pub location: Option<String>,
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\N", "\n")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Returns the local date time, and whether the value was a date only
+fn parse_ics_datetime(value: &str) -> Option<(NaiveDateTime, bool)> {
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        let local = Utc.from_utc_datetime(&naive).with_timezone(&Local);
+        return Some((local.naive_local(), false));
+    }
+    if let Ok(naive) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Some((naive, false));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+    Some((date.and_hms_opt(0, 0, 0)?, true))
+}
+
+fn parse_ics(input: &str) -> Option<Event> {
+    // continuation lines start with whitespace and belong to the previous line
+    let mut lines: Vec<String> = Vec::new();
+    for line in input.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.trim_end().to_string()),
+        }
+    }
+
+    let mut in_event = false;
+    let mut summary = None;
+    let mut start = None;
+    let mut end = None;
+    let mut location = None;
+    for line in &lines {
+        let (key, value) = match line.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        // strip parameters, e.g. DTSTART;TZID=America/New_York
+        let key = key.split(';').next().unwrap_or("").to_uppercase();
+        match key.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VEVENT") => in_event = true,
+            "END" if value.eq_ignore_ascii_case("VEVENT") => break,
+            _ if !in_event => {}
+            "SUMMARY" => summary = Some(unescape(value)),
+            "LOCATION" => location = Some(unescape(value)),
+            "DTSTART" => start = parse_ics_datetime(value),
+            "DTEND" => end = parse_ics_datetime(value),
+            _ => {}
+        }
+    }
+
+    let (start, all_day) = start?;
+    Some(Event {
+        summary: summary.unwrap_or_default(),
+        start,
+        end: end.map(|(end, _)| end),
+        all_day,
+        location,
+    })
+}
+
+fn month_number(month: &str) -> Option<u32> {
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let index = months.iter().position(|m| month.eq_ignore_ascii_case(m))?;
+    Some(index as u32 + 1)
+}
+
+fn parse_date(input: &str, today: NaiveDate) -> Option<(NaiveDate, std::ops::Range<usize>)> {
+    if let Some(caps) = ISO_DATE.captures(input) {
+        let date = NaiveDate::from_ymd_opt(
+            caps[1].parse().ok()?,
+            caps[2].parse().ok()?,
+            caps[3].parse().ok()?,
+        )?;
+        return Some((date, caps.get(0)?.range()));
+    }
+
+    if let Some(caps) = MONTH_DATE.captures(input) {
+        let month = month_number(&caps[1])?;
+        let day = caps[2].parse().ok()?;
+        let date = match caps.get(3) {
+            Some(year) => NaiveDate::from_ymd_opt(year.as_str().parse().ok()?, month, day)?,
+            None => {
+                // without a year, assume the next occurrence of the date
+                let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+                if date < today {
+                    NaiveDate::from_ymd_opt(today.year() + 1, month, day)?
+                } else {
+                    date
+                }
+            }
+        };
+        return Some((date, caps.get(0)?.range()));
+    }
+
+    if let Some(caps) = RELATIVE_DATE.captures(input) {
+        let word = caps[1].to_lowercase();
+        let date = match word.as_str() {
+            "today" | "tonight" => today,
+            "tomorrow" => today + Duration::days(1),
+            weekday => {
+                let weekday: Weekday = weekday.parse().ok()?;
+                let days = (7 + weekday.num_days_from_monday() as i64
+                    - today.weekday().num_days_from_monday() as i64)
+                    % 7;
+                // a bare weekday means the next one, not today
+                today + Duration::days(if days == 0 { 7 } else { days })
+            }
+        };
+        return Some((date, caps.get(0)?.range()));
+    }
+
+    None
+}
+
+fn parse_time(input: &str) -> Option<(NaiveTime, std::ops::Range<usize>)> {
+    let caps = TIME.captures(input)?;
+    let range = caps.get(0)?.range();
+    if caps.get(6).is_some() {
+        return Some((NaiveTime::from_hms_opt(12, 0, 0)?, range));
+    }
+    if let Some(meridiem) = caps.get(3) {
+        let hour: u32 = caps[1].parse().ok()?;
+        let minute: u32 = caps.get(2).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        if hour == 0 || hour > 12 {
+            return None;
+        }
+        let hour = match meridiem.as_str().to_lowercase().as_str() {
+            "a" => hour % 12,
+            _ => hour % 12 + 12,
+        };
+        return Some((NaiveTime::from_hms_opt(hour, minute, 0)?, range));
+    }
+    let hour = caps[4].parse().ok()?;
+    let minute = caps[5].parse().ok()?;
+    Some((NaiveTime::from_hms_opt(hour, minute, 0)?, range))
+}
+
+// Parses natural language like "Lunch with Ada tomorrow at 1pm". Both a date and a time are
+// required: either on its own is too common in ordinary text.
+fn parse_text(input: &str, now: NaiveDateTime) -> Option<Event> {
+    if input.len() > MAX_TEXT_LEN || input.lines().count() > MAX_TEXT_LINES {
+        return None;
+    }
+
+    let (date, date_range) = parse_date(input, now.date())?;
+    let (time, time_range) = parse_time(input)?;
+    if date_range.start < time_range.end && time_range.start < date_range.end {
+        return None;
+    }
+
+    let mut summary = input.to_string();
+    let (first, second) = if date_range.start > time_range.start {
+        (date_range, time_range)
+    } else {
+        (time_range, date_range)
+    };
+    summary.replace_range(first, " ");
+    summary.replace_range(second, " ");
+    let summary = summary
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c == ',' || c == '.' || c == '-' || c.is_whitespace())
+        .to_string();
+
+    let start = date.and_time(time);
+    Some(Event {
+        summary,
+        start,
+        end: Some(start + Duration::hours(1)),
+        all_day: false,
+        location: None,
+    })
+}
+
+// Parses ICS content, or natural language describing an event. Relative dates, e.g. "tomorrow",
+// are resolved against `now`.
+pub fn parse(input: &str, now: NaiveDateTime) -> Option<Event> {
+    let input = input.trim();
+    if input.contains("BEGIN:VCALENDAR") || input.contains("BEGIN:VEVENT") {
+        parse_ics(input)
+    } else {
+        parse_text(input, now)
+    }
+}
+
+// Whether the input looks like an event, which is checked as clips are captured: it's only parsed,
+// with parse, once the event is asked for
+pub fn is_event(input: &str) -> bool {
+    let input = input.trim();
+    if input.contains("BEGIN:VEVENT") {
+        return input.contains("DTSTART");
+    }
+    input.len() <= MAX_TEXT_LEN
+        && input.lines().count() <= MAX_TEXT_LINES
+        && (ISO_DATE.is_match(input) || MONTH_DATE.is_match(input) || RELATIVE_DATE.is_match(input))
+        && TIME.is_match(input)
+}
+
+impl Event {
+    pub fn to_ics(&self, uid: &str, stamp: DateTime<Utc>) -> String {
+        let format = |dt: &NaiveDateTime| {
+            if self.all_day {
+                format!(";VALUE=DATE:{}", dt.format("%Y%m%d"))
+            } else {
+                // floating time: the calendar interprets it in the local time zone
+                format!(":{}", dt.format("%Y%m%dT%H%M%S"))
+            }
+        };
+
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//Stacks//EN".to_string(),
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", uid),
+            format!("DTSTAMP:{}", stamp.format("%Y%m%dT%H%M%SZ")),
+            format!("SUMMARY:{}", escape(&self.summary)),
+            format!("DTSTART{}", format(&self.start)),
+        ];
+        if let Some(end) = &self.end {
+            lines.push(format!("DTEND{}", format(end)));
+        }
+        if let Some(location) = &self.location {
+            lines.push(format!("LOCATION:{}", escape(location)));
+        }
+        lines.push("END:VEVENT".to_string());
+        lines.push("END:VCALENDAR".to_string());
+        lines.join("\r\n") + "\r\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> NaiveDateTime {
+        // a Sunday
+        NaiveDate::from_ymd_opt(2023, 10, 1)
+            .unwrap()
+            .and_hms_opt(9, 30, 0)
+            .unwrap()
+    }
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_ics() {
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Design review\\, round 2\r\nDTSTART;TZID=Europe/London:20231012T150000\r\nDTEND:20231012T160000\r\nLOCATION:Room\r\n 4\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        let event = parse(ics, now()).unwrap();
+        assert_eq!(event.summary, "Design review, round 2");
+        assert_eq!(event.start, at(2023, 10, 12, 15, 0));
+        assert_eq!(event.end, Some(at(2023, 10, 12, 16, 0)));
+        assert_eq!(event.location, Some("Room4".to_string()));
+        assert!(!event.all_day);
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let event = parse("Lunch with Ada tomorrow at 1pm", now()).unwrap();
+        assert_eq!(event.summary, "Lunch with Ada");
+        assert_eq!(event.start, at(2023, 10, 2, 13, 0));
+        assert_eq!(event.end, Some(at(2023, 10, 2, 14, 0)));
+
+        let event = parse("Standup on Friday 9:15am", now()).unwrap();
+        assert_eq!(event.start, at(2023, 10, 6, 9, 15));
+
+        let event = parse("Dentist, Mar 3 at noon", now()).unwrap();
+        assert_eq!(event.summary, "Dentist");
+        assert_eq!(event.start, at(2024, 3, 3, 12, 0));
+
+        let event = parse("Release 2023-10-20 17:00", now()).unwrap();
+        assert_eq!(event.summary, "Release");
+        assert_eq!(event.start, at(2023, 10, 20, 17, 0));
+    }
+
+    #[test]
+    fn test_not_an_event() {
+        assert_eq!(parse("see you tomorrow", now()), None);
+        assert_eq!(parse("the build takes 10:30 on a good day", now()), None);
+        assert_eq!(parse("fn main() {}", now()), None);
+    }
+
+    #[test]
+    fn test_is_event() {
+        assert!(is_event("Lunch with Ada tomorrow at 1pm"));
+        assert!(is_event(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nDTSTART:20231012T150000\r\n"
+        ));
+        assert!(!is_event(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:No start\r\n"
+        ));
+        assert!(!is_event("see you tomorrow"));
+        assert!(!is_event("the build takes 10:30 on a good day"));
+        assert!(!is_event(&"Lunch tomorrow at 1pm. ".repeat(20)));
+    }
+
+    #[test]
+    fn test_to_ics() {
+        let event = parse("Lunch with Ada tomorrow at 1pm", now()).unwrap();
+        let stamp = Utc.with_ymd_and_hms(2023, 10, 1, 8, 30, 0).unwrap();
+        let ics = event.to_ics("abc@stacks", stamp);
+        assert!(ics.contains("DTSTAMP:20231001T083000Z\r\n"));
+        assert!(ics.contains("DTSTART:20231002T130000\r\n"));
+        assert!(ics.contains("DTEND:20231002T140000\r\n"));
+        assert_eq!(parse(&ics, now()), Some(event));
+    }
+}
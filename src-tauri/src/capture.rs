@@ -0,0 +1,131 @@
+// Selections pushed from the companion browser extension, via POST /capture. Along with the
+// selected text, the extension sends the page's URL and title: they're kept as the item's origin,
+// and matched by the filter like the item's content.
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::state::State;
+use crate::store::MimeType;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Capture {
+    #[serde(default)]
+    pub content: String,
+    pub url: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    // the selection as HTML, used when there's no plain text content
+    #[serde(default)]
+    pub selection_html: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub url: String,
+    pub title: Option<String>,
+}
+
+// Adds the selection to the current stack, returning its item's id. Empty selections are ignored.
+pub fn add(state: &mut State, capture: &Capture) -> Option<Scru128Id> {
+    let html = capture
+        .selection_html
+        .as_deref()
+        .map(str::trim)
+        .filter(|html| !html.is_empty());
+    let (content, content_type) = match capture.content.trim() {
+        "" => (html?, Some("HTML")),
+        _ => (capture.content.as_str(), None),
+    };
+
+    let stack_id = state.get_curr_stack();
+    let packet = state
+        .store
+        .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+    // a selection the stack already held is moved to the top, with the page it came from this time
+    let id = state.merge_add(&packet);
+    state.store.source_set(id, &capture.url);
+    let title = capture
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|title| !title.is_empty());
+    state.store.origin_set(
+        id,
+        &Origin {
+            url: capture.url.clone(),
+            title: title.map(str::to_string),
+        },
+    );
+    if let (Some(content_type), Some(hash)) = (content_type, packet.hash.clone()) {
+        let packet = state
+            .store
+            .update_content_type(hash, content_type.to_string());
+        state.merge(&packet);
+    }
+    Some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let capture: Capture = serde_json::from_str(
+            r#"{
+                "content": "Rust is a multi-paradigm language",
+                "url": "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                "title": "Rust (programming language) - Wikipedia",
+                "selection_html": "<p>Rust is a <b>multi-paradigm</b> language</p>"
+            }"#,
+        )
+        .unwrap();
+        let id = add(&mut state, &capture).unwrap();
+        let origin = state.store.origin_get(&id).unwrap();
+        assert_eq!(
+            origin.title.as_deref(),
+            Some("Rust (programming language) - Wikipedia")
+        );
+        assert_eq!(state.store.source_get(&id), Some(capture.url.clone()));
+        // the filter matches words in the page's URL and title, by prefix
+        assert_eq!(state.store.origins_query("wikipedia"), vec![id]);
+        assert_eq!(state.store.origins_query("Rust wiki"), vec![id]);
+        assert_eq!(state.store.origins_query("python"), vec![]);
+
+        // the same selection, from another page, is the same item
+        let again = Capture {
+            url: "https://www.rust-lang.org/".to_string(),
+            title: None,
+            ..capture.clone()
+        };
+        assert_eq!(add(&mut state, &again), Some(id));
+        assert_eq!(state.store.origin_get(&id).unwrap().url, again.url);
+        assert_eq!(state.store.origins_query("wikipedia"), vec![]);
+        assert_eq!(state.store.origins_query("rust-lang"), vec![id]);
+
+        // without plain text, the HTML is kept
+        let html_only = Capture {
+            content: "".to_string(),
+            ..capture.clone()
+        };
+        let id = add(&mut state, &html_only).unwrap();
+        let hash = state.view.items[&id].hash.clone();
+        assert_eq!(
+            state.store.get_content_meta(&hash).unwrap().content_type,
+            "HTML"
+        );
+
+        let empty = Capture {
+            content: " ".to_string(),
+            selection_html: None,
+            ..capture
+        };
+        assert_eq!(add(&mut state, &empty), None);
+    }
+}
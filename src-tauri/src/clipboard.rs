@@ -1,76 +1,655 @@
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
 use tauri::api::process::{Command, CommandEvent};
-use tauri::Manager;
 
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
 use tracing::info;
 
+#[cfg(target_os = "linux")]
+use crate::clipboard_linux;
+#[cfg(target_os = "macos")]
+use crate::clipboard_macos;
+use crate::clipboard_writer;
+use crate::disk;
+use crate::events;
+use crate::file_ref;
+use crate::file_ref::FileRef;
+use crate::notifications;
+use crate::plugins;
+use crate::privacy;
+use crate::rules;
+use crate::shutdown;
+use crate::stack_settings;
 use crate::state;
 use crate::state::SharedState;
-use crate::store::MimeType;
+use crate::store::{MimeType, IMAGE_TYPES};
+use crate::tray;
 use crate::util;
 
+// The capture sidecar is restarted if it exits, after a delay which doubles with each restart in
+// a row, up to BACKOFF_MAX. It's back to BACKOFF_MIN once the sidecar has run for BACKOFF_MAX.
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// macOS only: how the clipboard is watched, by the x-macos-pasteboard sidecar, or by polling the
+// pasteboard in process: see clipboard_macos
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureBackend {
+    #[default]
+    Sidecar,
+    Native,
+}
+
+// Clipboard changes which follow each other within the debounce window, e.g. an app rewriting the
+// pasteboard many times a second, are collapsed into the last of them. A burst which doesn't
+// settle is handed on after DEBOUNCE_MAX.
+pub const DEFAULT_DEBOUNCE_MS: u64 = 150;
+const DEBOUNCE_MAX: Duration = Duration::from_secs(2);
+
+// Clips larger than max_item_bytes are skipped, or, with oversized_items set to truncate, text is
+// cut to the limit, so a single giant copy doesn't bloat the store and previews
+pub const DEFAULT_MAX_ITEM_BYTES: u64 = 10 << 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Oversized {
+    #[default]
+    Skip,
+    Truncate,
+}
+
+// The clip's content, within the limit, or None if it's skipped. Only text can be truncated: a
+// cut image won't decode. A max of 0 is no limit.
+fn limit_size(
+    mut content: Vec<u8>,
+    mime_type: &MimeType,
+    max: usize,
+    oversized: Oversized,
+) -> Option<Vec<u8>> {
+    if max == 0 || content.len() <= max {
+        return Some(content);
+    }
+    if oversized == Oversized::Skip || *mime_type != MimeType::TextPlain {
+        return None;
+    }
+    // cut before a character, rather than through it
+    let mut end = max;
+    while end > 0 && content[end] & 0b1100_0000 == 0b1000_0000 {
+        end -= 1;
+    }
+    content.truncate(end);
+    Some(content)
+}
+
+fn megabytes(bytes: usize) -> String {
+    format!("{:.1} MB", bytes as f64 / (1 << 20) as f64)
+}
+
+// Markers apps, e.g. password managers, put on the pasteboard alongside a clip: see
+// nspasteboard.org. Concealed clips, e.g. passwords, are never captured; transient ones are purged
+// after transient_ttl_secs.
+pub const CONCEALED_TYPE: &str = "org.nspasteboard.ConcealedType";
+pub const TRANSIENT_TYPE: &str = "org.nspasteboard.TransientType";
+pub const DEFAULT_TRANSIENT_TTL_SECS: u64 = 60;
+
+// pasteboard types which hold where the clip was copied from
+const SOURCE_TYPES: &[&str] = &["org.chromium.source-url", "public.file-url"];
+
+// Where the clip came from: the document's URL or file path if the pasteboard has one, otherwise
+// the source reported by the capture backend
+fn capture_source(clipped: &Value) -> Option<String> {
+    let types = clipped["types"].as_object()?;
+    SOURCE_TYPES
+        .iter()
+        .find_map(|t| types.get(*t)?.as_str())
+        .map(|encoded| {
+            let decoded = util::b64decode(encoded);
+            String::from_utf8_lossy(&decoded)
+                .trim_end_matches('\0')
+                .trim()
+                .to_string()
+        })
+        .or_else(|| clipped["source"].as_str().map(|s| s.to_string()))
+        .filter(|source| !source.is_empty())
+}
+
+fn capture_file_ref(clipped: &Value) -> Option<FileRef> {
+    let types = clipped["types"].as_object()?;
+    let decode = |t: &str| {
+        let decoded = util::b64decode(types.get(t)?.as_str()?);
+        Some(
+            String::from_utf8_lossy(&decoded)
+                .trim_end_matches('\0')
+                .to_string(),
+        )
+    };
+    let text = decode("public.utf8-plain-text");
+    file_ref::from_pasteboard(&decode("public.file-url")?, text.as_deref())
+}
+
+// Returns the new clip's id, if the clip was added, rather than skipped or a duplicate
 #[tracing::instrument(skip_all)]
-fn handle_clipboard_update(state: &mut state::State, line: &str, app: &tauri::AppHandle) {
+fn handle_clipboard_update(
+    state: &mut state::State,
+    line: &str,
+    app: &tauri::AppHandle,
+) -> Option<Scru128Id> {
     let clipped: Value = serde_json::from_str(line).unwrap();
 
     let change_num = clipped["change"].as_i64().unwrap();
+    if state.last_change_num.replace(change_num) == Some(change_num) {
+        info!("CLIPBOARD UPDATE: {} REPEATED", &change_num);
+        return None;
+    }
+
+    // clearing a sensitive item, or restoring the clip from before it, is Stacks' own write
+    if let Some(cleared) = clipboard_writer::take_cleared() {
+        state.skip_change_num = Some(cleared);
+    }
     if let Some(skip_change_num) = state.skip_change_num {
         if change_num == skip_change_num {
             info!("CLIPBOARD UPDATE: {} SKIP", &change_num);
-            return;
+            return None;
         }
     }
+    clipboard_writer::changed(change_num);
 
-    let types = clipped["types"].as_object().unwrap();
-    let source = clipped["source"].as_str();
-    let _source = source.map(|s| s.to_string());
+    let now = privacy::now();
+    let privacy = state.privacy.filter(|p| p.is_active(now));
+    if state.capture_paused || privacy.is_some_and(|p| p.mode == privacy::Mode::Pause) {
+        return None;
+    }
 
-    let curr_stack = state.get_curr_stack();
+    let types = clipped["types"].as_object().unwrap();
+    if types.contains_key(CONCEALED_TYPE) {
+        info!("CLIPBOARD UPDATE: {} CONCEALED", &change_num);
+        return None;
+    }
+    let transient = types.contains_key(TRANSIENT_TYPE);
+    let file_ref = capture_file_ref(&clipped);
+    // a file reference's URL is the file itself, rather than where it came from
+    let source = match file_ref {
+        Some(_) => None,
+        None => capture_source(&clipped),
+    };
 
-    let packet = if types.contains_key("public.utf8-plain-text") {
+    let (content, mime_type) = if let Some(file_ref) = file_ref {
+        (file_ref.to_bytes(), MimeType::FileRef)
+    } else if types.contains_key("public.utf8-plain-text") {
         let content = util::b64decode(types["public.utf8-plain-text"].as_str().unwrap());
         if let Ok(str_ref) = std::str::from_utf8(&content) {
             if str_ref.trim().is_empty() {
-                return;
+                return None;
+            }
+        }
+        (content, MimeType::TextPlain)
+    } else if let Some((pasteboard_type, mime_type)) = IMAGE_TYPES
+        .iter()
+        .find(|(pasteboard_type, _)| types.contains_key(*pasteboard_type))
+    {
+        let content = util::b64decode(types[*pasteboard_type].as_str().unwrap());
+        (content, mime_type.clone())
+    } else {
+        return None;
+    };
+
+    // when disk space is low, large clips are dropped rather than filling the disk
+    let low_disk = state.disk_status.as_ref().is_some_and(|status| status.low);
+    if low_disk && content.len() > disk::LARGE_ITEM_BYTES {
+        tracing::warn!(size = content.len(), "low disk space: skipping large clip");
+        return None;
+    }
+
+    let settings = state.store.settings_get().unwrap_or_default();
+    let size = content.len();
+    let max = settings.max_item_bytes.unwrap_or(DEFAULT_MAX_ITEM_BYTES) as usize;
+    let oversized = settings.oversized_items.unwrap_or_default();
+    let Some(content) = limit_size(content, &mime_type, max, oversized) else {
+        tracing::warn!(size, max, "skipping oversized clip");
+        let body = format!(
+            "The clip is {}, over the {} limit",
+            megabytes(size),
+            megabytes(max)
+        );
+        notifications::notify(
+            app,
+            &settings,
+            notifications::Category::Capture,
+            "Clip not captured",
+            &body,
+        );
+        return None;
+    };
+    // the size the clip was before it was truncated
+    let truncated = (content.len() < size).then_some(size);
+    // a clip is purged after the shorter of the privacy mode's and, if it's transient, the
+    // transient TTL
+    let privacy_ttl = match privacy.map(|p| p.mode) {
+        Some(privacy::Mode::Expire { after_secs }) => Some(after_secs),
+        _ => None,
+    };
+    let transient_ttl = transient.then(|| {
+        settings
+            .transient_ttl_secs
+            .unwrap_or(DEFAULT_TRANSIENT_TTL_SECS)
+    });
+    let expire_after = privacy_ttl.into_iter().chain(transient_ttl).min();
+
+    let outcome = rules::apply(rules::cached(state), content, &mime_type, source.as_deref());
+    if !outcome.notifications.is_empty() {
+        for notification in &outcome.notifications {
+            events::emit(app, "rule-notify", notification);
+            notifications::notify(
+                app,
+                &settings,
+                notifications::Category::Rule,
+                &notification.rule,
+                &notification.message,
+            );
+        }
+    }
+    if outcome.delete {
+        info!("CLIPBOARD UPDATE: {} DELETED BY RULE", &change_num);
+        return None;
+    }
+    let content = outcome.content;
+    // a rule can only move clips to a stack which can be added to
+    let rule_stack = outcome.stack_id.filter(|id| {
+        state.view.items.get(id).is_some_and(|item| item.is_stack) && !state.is_read_only(id)
+    });
+
+    let hash = ssri::Integrity::from(&content);
+    let (id, added) = match state.touch_recent_duplicate(&hash) {
+        Some(packet) => {
+            info!("CLIPBOARD UPDATE: {} DUPLICATE", &change_num);
+            (packet.source_id.unwrap(), false)
+        }
+        None => {
+            let curr_stack = rule_stack.unwrap_or_else(|| state.get_curr_stack());
+            let settings = state.store.stack_settings_get(&curr_stack);
+            let content = stack_settings::transform_incoming(&settings, content, &mime_type);
+            let packet = state.store.add(&content, mime_type, curr_stack);
+            if let (Some(size), Some(hash)) = (truncated, &packet.hash) {
+                state.store.update_truncated_from(hash.clone(), size as u64);
+            }
+            // the clip may already be in the stack, which is touched instead: side data goes on
+            // that item
+            let id = state.merge_add(&packet);
+            if let Some(source) = &source {
+                state.store.source_set(id, source);
+            }
+            if !outcome.tags.is_empty() {
+                let note = rules::tag_note(state.store.note_get(&id), &outcome.tags);
+                state.store.note_set(id, &note);
             }
+            (id, true)
         }
+    };
+    // a duplicate is the clip copied again, so it's marked as a new clip would be. Transient clips,
+    // e.g. one-time codes, are treated as sensitive
+    if transient {
+        state.store.sensitive_set(id, true);
+    }
+    if let Some(after_secs) = expire_after {
+        state.store.expiry_set(id, now + after_secs * 1000);
+    }
+
+    // if Stacks isn't active, focus the new clip
+    if !state.ui.is_visible {
+        let focus = state.view.get_focus_for_id(&id);
+        state.ui.select(focus);
+    }
+
+    events::emit(app, "refresh-items", true);
+    added.then_some(id)
+}
 
-        Some(state.store.add(&content, MimeType::TextPlain, curr_stack))
-    } else if types.contains_key("public.png") {
-        let content = util::b64decode(types["public.png"].as_str().unwrap());
-        Some(state.store.add(&content, MimeType::ImagePng, curr_stack))
+// Sent as the capture-health event when capture stops, and when it starts again
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Health {
+    pub up: bool,
+    // restarts in a row, so far
+    pub restarts: u32,
+    // when capture is down, how long until it's restarted
+    pub retry_in_secs: Option<u64>,
+}
+
+// How long to wait before the nth restart in a row, counting from 0
+fn backoff(restarts: u32) -> Duration {
+    BACKOFF_MIN
+        .saturating_mul(2u32.saturating_pow(restarts))
+        .min(BACKOFF_MAX)
+}
+
+fn set_health(app: &tauri::AppHandle, state: &SharedState, health: Health) {
+    if health.up {
+        tracing::info!(name = "clipboard", ?health, "restarting capture");
     } else {
-        None
+        tracing::warn!(name = "clipboard", ?health, "capture stopped");
+    }
+    state.with_lock(|state| state.capture_down = !health.up);
+    events::emit(app, "capture-health", &health);
+    tray::refresh(app);
+}
+
+#[cfg(target_os = "macos")]
+lazy_static::lazy_static! {
+    // the x-macos-pasteboard sidecar, to stop on shutdown
+    static ref SIDECAR: std::sync::Mutex<Option<tauri::api::process::CommandChild>> =
+        std::sync::Mutex::new(None);
+}
+
+// Each capture backend emits a line of JSON per clipboard change, in the x-macos-pasteboard
+// sidecar's format: {"change": i64, "types": {<pasteboard type>: <base64 data>}, "source": ..}
+//
+// Runs the backend until it stops, sending on its lines, and calling started once it's running.
+// Returns false once there's nothing to send them to.
+#[cfg(target_os = "macos")]
+async fn run_backend(
+    state: &SharedState,
+    tx: &tokio::sync::mpsc::Sender<String>,
+    started: impl FnOnce(),
+) -> bool {
+    let backend = state.with_read(|state| {
+        state
+            .store
+            .settings_get()
+            .and_then(|settings| settings.capture_backend)
+            .unwrap_or_default()
+    });
+    if backend == CaptureBackend::Native {
+        started();
+        clipboard_macos::watch(tx).await;
+        return false;
+    }
+
+    let (mut events, child) = match Command::new_sidecar("x-macos-pasteboard")
+        .and_then(|command| command.spawn())
+    {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            tracing::error!(name = "clipboard", %e, "couldn't start the sidecar: polling instead");
+            started();
+            clipboard_macos::watch(tx).await;
+            return false;
+        }
     };
+    *SIDECAR.lock().unwrap() = Some(child);
+    started();
+    // a sidecar which is blocked before it's sent anything, e.g. by Gatekeeper, is given up on for
+    // polling; other exits are restarted as usual
+    let mut sent = false;
+    let mut blocked = false;
+    while let Some(event) = events.recv().await {
+        match event {
+            CommandEvent::Stdout(line) => {
+                if tx.send(line).await.is_err() {
+                    return false;
+                }
+                sent = true;
+            }
+            CommandEvent::Terminated(exit) => {
+                tracing::warn!(
+                    name = "clipboard",
+                    code = ?exit.code,
+                    signal = ?exit.signal,
+                    "sidecar exited"
+                );
+                blocked = !sent && is_blocked(exit.code, exit.signal);
+                break;
+            }
+            _ => {}
+        }
+    }
+    SIDECAR.lock().unwrap().take();
+    if blocked {
+        tracing::error!(
+            name = "clipboard",
+            "the sidecar was blocked: polling instead"
+        );
+        clipboard_macos::watch(tx).await;
+        return false;
+    }
+    true
+}
+
+// Whether the sidecar's exit looks like it wasn't allowed to run: Gatekeeper kills a binary it
+// blocks with SIGKILL, and the shell's 126 and 127 are for a binary which can't be run, or found
+#[cfg(target_os = "macos")]
+fn is_blocked(code: Option<i32>, signal: Option<i32>) -> bool {
+    const SIGKILL: i32 = 9;
+    signal == Some(SIGKILL) || matches!(code, Some(126) | Some(127))
+}
+
+#[cfg(target_os = "linux")]
+async fn run_backend(
+    state: &SharedState,
+    tx: &tokio::sync::mpsc::Sender<String>,
+    started: impl FnOnce(),
+) -> bool {
+    let capture_primary = state.with_read(|state| {
+        state
+            .store
+            .settings_get()
+            .and_then(|settings| settings.capture_primary_selection)
+            .unwrap_or(false)
+    });
+    // the watchers drop their end of the channel when they stop, e.g. clipnotify isn't installed
+    let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+    let mut rx = clipboard_linux::watch(capture_primary, started_tx);
+    if started_rx.await.is_ok() {
+        started();
+    }
+    while let Some(line) = rx.recv().await {
+        if tx.send(line).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+// The backend is supervised: if it stops, e.g. the sidecar crashed, capture-health is sent, and
+// it's restarted after a backoff, unless the app is shutting down. Capture is only reported up
+// again once the restarted backend is running.
+fn watch(app: &tauri::AppHandle, state: &SharedState) -> tokio::sync::mpsc::Receiver<String> {
+    use futures::FutureExt;
 
-    if let Some(packet) = packet {
-        state.merge(&packet);
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let app = app.clone();
+    let state = state.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut restarts = 0;
+        let mut down = false;
+        loop {
+            let started = std::time::Instant::now();
+            let up = Health {
+                up: true,
+                restarts,
+                retry_in_secs: None,
+            };
+            let running = run_backend(&state, &tx, || {
+                if down {
+                    set_health(&app, &state, up);
+                }
+            })
+            .await;
+            if !running {
+                return;
+            }
+            // the backend is stopped on shutdown
+            if shutdown::requested().now_or_never().is_some() {
+                return;
+            }
+            if started.elapsed() >= BACKOFF_MAX {
+                restarts = 0;
+            }
+            let delay = backoff(restarts);
+            restarts += 1;
+            let health = Health {
+                up: false,
+                restarts,
+                retry_in_secs: Some(delay.as_secs()),
+            };
+            set_health(&app, &state, health);
+            down = true;
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {},
+                _ = shutdown::requested() => return,
+            }
+        }
+    });
+    rx
+}
 
-        // if Stacks isn't active, focus the new clip
-        if !state.ui.is_visible {
-            let focus = state.view.get_focus_for_id(&packet.id);
-            state.ui.select(focus);
+// Stops the capture sidecar: see shutdown
+pub fn stop() {
+    #[cfg(target_os = "macos")]
+    if let Some(child) = SIDECAR.lock().unwrap().take() {
+        if let Err(e) = child.kill() {
+            tracing::warn!(name = "clipboard", %e, "couldn't stop the sidecar");
         }
+    }
+}
 
-        app.emit_all("refresh-items", true).unwrap();
+// Waits for the next change, then for the clipboard to settle, returning the last change, and how
+// many before it were collapsed into it
+async fn debounce(
+    rx: &mut tokio::sync::mpsc::Receiver<String>,
+    window: Duration,
+) -> Option<(String, usize)> {
+    let mut line = rx.recv().await?;
+    let mut collapsed = 0;
+    let deadline = tokio::time::Instant::now() + DEBOUNCE_MAX;
+    while !window.is_zero() {
+        let wait = window.min(deadline.saturating_duration_since(tokio::time::Instant::now()));
+        match tokio::time::timeout(wait, rx.recv()).await {
+            Ok(Some(next)) => {
+                line = next;
+                collapsed += 1;
+            }
+            // settled, or the backend stopped: either way the last change is handled
+            Ok(None) | Err(_) => break,
+        }
     }
+    Some((line, collapsed))
 }
 
 pub fn start(app: tauri::AppHandle, state: &SharedState) {
-    let (mut rx, _child) = Command::new_sidecar("x-macos-pasteboard")
-        .unwrap()
-        .spawn()
-        .unwrap();
+    let mut rx = watch(&app, state);
 
     let state = state.clone();
     tauri::async_runtime::spawn(async move {
-        while let Some(event) = rx.recv().await {
-            if let CommandEvent::Stdout(line) = event {
-                state.with_lock(|state| {
-                    handle_clipboard_update(state, &line, &app);
-                });
+        loop {
+            let window = state.with_read(|state| {
+                state
+                    .store
+                    .settings_get()
+                    .and_then(|settings| settings.capture_debounce_ms)
+                    .unwrap_or(DEFAULT_DEBOUNCE_MS)
+            });
+            let debounced = tokio::select! {
+                debounced = debounce(&mut rx, Duration::from_millis(window)) => debounced,
+                _ = shutdown::requested() => None,
+            };
+            let Some((line, collapsed)) = debounced else {
+                break;
+            };
+            if collapsed > 0 {
+                info!("CLIPBOARD UPDATE: {} CHANGES COLLAPSED", collapsed);
+            }
+            // a clip being captured is added before the app exits
+            let _guard = shutdown::guard();
+            let added = state.with_lock(|state| handle_clipboard_update(state, &line, &app));
+            if let Some(id) = added {
+                plugins::on_clip(&app, &state, id);
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_size() {
+        let text = "héllo".as_bytes().to_vec();
+        let limit = |content: &[u8], mime_type, max, oversized| {
+            limit_size(content.to_vec(), &mime_type, max, oversized)
+        };
+        assert_eq!(
+            limit(&text, MimeType::TextPlain, 6, Oversized::Skip),
+            Some(text.clone())
+        );
+        assert_eq!(limit(&text, MimeType::TextPlain, 5, Oversized::Skip), None);
+        assert_eq!(
+            limit(&text, MimeType::TextPlain, 0, Oversized::Skip),
+            Some(text.clone())
+        );
+
+        // text is cut before the é, rather than through it
+        assert_eq!(
+            limit(&text, MimeType::TextPlain, 2, Oversized::Truncate),
+            Some(b"h".to_vec())
+        );
+        assert_eq!(
+            limit(&text, MimeType::TextPlain, 3, Oversized::Truncate),
+            Some("hé".as_bytes().to_vec())
+        );
+        // images can't be truncated
+        assert_eq!(
+            limit(&text, MimeType::ImagePng, 2, Oversized::Truncate),
+            None
+        );
+    }
+
+    #[test]
+    fn test_debounce() {
+        tauri::async_runtime::block_on(async {
+            let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+            for line in ["one", "two", "three"] {
+                tx.send(line.to_string()).await.unwrap();
+            }
+            let window = Duration::from_millis(50);
+            assert_eq!(
+                debounce(&mut rx, window).await,
+                Some(("three".to_string(), 2))
+            );
+
+            // a change after the window has passed is handled on its own
+            tx.send("four".to_string()).await.unwrap();
+            assert_eq!(
+                debounce(&mut rx, window).await,
+                Some(("four".to_string(), 0))
+            );
+
+            // with no window, each change is handled
+            tx.send("five".to_string()).await.unwrap();
+            tx.send("six".to_string()).await.unwrap();
+            drop(tx);
+            assert_eq!(
+                debounce(&mut rx, Duration::ZERO).await,
+                Some(("five".to_string(), 0))
+            );
+            assert_eq!(
+                debounce(&mut rx, Duration::ZERO).await,
+                Some(("six".to_string(), 0))
+            );
+            assert_eq!(debounce(&mut rx, window).await, None);
+        });
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(32));
+        assert_eq!(backoff(6), BACKOFF_MAX);
+        assert_eq!(backoff(100), BACKOFF_MAX);
+    }
+}
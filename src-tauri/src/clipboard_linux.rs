@@ -0,0 +1,236 @@
+// Linux clipboard capture: a stand-in for the x-macos-pasteboard sidecar. Changes are read using
+// wl-paste on Wayland, or clipnotify (an XFixes selection listener) and xclip on X11, and are
+// emitted in the sidecar's line format so they flow through the same capture path.
+
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use tokio::io::AsyncBufReadExt;
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::clipboard::CONCEALED_TYPE;
+use crate::store::IMAGE_TYPES;
+use crate::util;
+
+// mime types we capture, and the pasteboard types they're reported as
+const TEXT_TYPES: &[&str] = &["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+const URI_LIST_TYPE: &str = "text/uri-list";
+// KDE's equivalent of the concealed pasteboard marker, set by password managers, e.g. KeePassXC
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Wayland,
+    X11,
+}
+
+impl Backend {
+    fn detect() -> Self {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Backend::Wayland
+        } else {
+            Backend::X11
+        }
+    }
+
+    fn list_types(&self, primary: bool) -> Command {
+        match self {
+            Backend::Wayland => {
+                let mut cmd = Command::new("wl-paste");
+                if primary {
+                    cmd.arg("--primary");
+                }
+                cmd.arg("--list-types");
+                cmd
+            }
+            Backend::X11 => self.read(primary, "TARGETS"),
+        }
+    }
+
+    fn read(&self, primary: bool, mime_type: &str) -> Command {
+        match self {
+            Backend::Wayland => {
+                let mut cmd = Command::new("wl-paste");
+                if primary {
+                    cmd.arg("--primary");
+                }
+                cmd.args(["--no-newline", "--type", mime_type]);
+                cmd
+            }
+            Backend::X11 => {
+                let mut cmd = Command::new("xclip");
+                let selection = if primary { "primary" } else { "clipboard" };
+                cmd.args(["-o", "-selection", selection, "-t", mime_type]);
+                cmd
+            }
+        }
+    }
+}
+
+async fn output(mut cmd: Command) -> Option<Vec<u8>> {
+    let output = cmd.output().await.ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+// Reads the current selection, in the sidecar's format
+// Change numbers are shared by the selections, and kept across restarts, so that, as with the
+// macOS pasteboard's changeCount, each change has its own
+static CHANGE: AtomicI64 = AtomicI64::new(0);
+
+fn next_change() -> i64 {
+    CHANGE.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+async fn read_selection(backend: Backend, primary: bool, change: i64) -> Option<String> {
+    let types = output(backend.list_types(primary)).await?;
+    let types: Vec<String> = String::from_utf8_lossy(&types)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .collect();
+
+    // a password is reported as concealed, without reading it
+    if types.iter().any(|t| t == PASSWORD_MANAGER_HINT) {
+        let clipped = serde_json::json!({
+            "change": change,
+            "types": { (CONCEALED_TYPE): "" },
+            "source": null,
+        });
+        return Some(clipped.to_string());
+    }
+
+    // files copied in a file manager are offered as a list of file URLs
+    if types.iter().any(|t| t == URI_LIST_TYPE) {
+        let uris = output(backend.read(primary, URI_LIST_TYPE)).await?;
+        let uris = String::from_utf8_lossy(&uris);
+        if let Some(url) = uris.lines().find(|line| line.starts_with("file://")) {
+            let clipped = serde_json::json!({
+                "change": change,
+                "types": { "public.file-url": util::b64encode(&url.trim().as_bytes().to_vec()) },
+                "source": null,
+            });
+            return Some(clipped.to_string());
+        }
+    }
+
+    let image = IMAGE_TYPES
+        .iter()
+        .find(|(_, mime_type)| types.iter().any(|t| t == mime_type.as_str()));
+    let (pasteboard_type, mime_type) = if let Some((pasteboard_type, mime_type)) = image {
+        (*pasteboard_type, mime_type.as_str())
+    } else {
+        let mime_type = TEXT_TYPES
+            .iter()
+            .find(|mime_type| types.iter().any(|t| t == *mime_type))?;
+        ("public.utf8-plain-text", *mime_type)
+    };
+
+    let content = output(backend.read(primary, mime_type)).await?;
+    let clipped = serde_json::json!({
+        "change": change,
+        "types": { (pasteboard_type): util::b64encode(&content) },
+        "source": null,
+    });
+    Some(clipped.to_string())
+}
+
+async fn watch_wayland(
+    primary: bool,
+    tx: mpsc::Sender<String>,
+    started: Option<oneshot::Sender<()>>,
+) {
+    // wl-paste runs the given command on every change: we only use it as a notification
+    let mut cmd = Command::new("wl-paste");
+    if primary {
+        cmd.arg("--primary");
+    }
+    let child = cmd
+        .args(["--watch", "echo"])
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!(name = "clipboard::linux", ?e, "failed to spawn wl-paste");
+            return;
+        }
+    };
+    if let Some(started) = started {
+        let _ = started.send(());
+    }
+
+    let mut lines = tokio::io::BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Ok(Some(_)) = lines.next_line().await {
+        if let Some(line) = read_selection(Backend::Wayland, primary, next_change()).await {
+            if tx.send(line).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn watch_x11(
+    primary: bool,
+    tx: mpsc::Sender<String>,
+    mut started: Option<oneshot::Sender<()>>,
+) {
+    let selection = if primary { "primary" } else { "clipboard" };
+    loop {
+        // clipnotify exits as soon as the selection changes
+        let child = Command::new("clipnotify")
+            .args(["-s", selection])
+            .kill_on_drop(true)
+            .spawn();
+        if let (Ok(_), Some(started)) = (&child, started.take()) {
+            let _ = started.send(());
+        }
+        let status = match child {
+            Ok(mut child) => child.wait().await,
+            Err(e) => Err(e),
+        };
+        match status {
+            Ok(status) if status.success() => {}
+            res => {
+                tracing::error!(name = "clipboard::linux", ?res, "clipnotify failed");
+                return;
+            }
+        }
+
+        if let Some(line) = read_selection(Backend::X11, primary, next_change()).await {
+            if tx.send(line).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Watches the clipboard, and optionally the primary selection, returning a channel of changes.
+// started is sent once the clipboard's watcher is running, or dropped if it can't be started.
+pub fn watch(capture_primary: bool, started: oneshot::Sender<()>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel(10);
+    let backend = Backend::detect();
+    tracing::info!(
+        name = "clipboard::linux",
+        ?backend,
+        capture_primary,
+        "watching"
+    );
+
+    let selections = if capture_primary {
+        vec![false, true]
+    } else {
+        vec![false]
+    };
+    let mut started = Some(started);
+    for primary in selections {
+        let tx = tx.clone();
+        let started = if primary { None } else { started.take() };
+        tauri::async_runtime::spawn(async move {
+            match backend {
+                Backend::Wayland => watch_wayland(primary, tx, started).await,
+                Backend::X11 => watch_x11(primary, tx, started).await,
+            }
+        });
+    }
+    rx
+}
@@ -0,0 +1,112 @@
+// A native fallback for the x-macos-pasteboard sidecar, for when the sidecar's missing, or blocked
+// by Gatekeeper: NSPasteboard's changeCount is polled, in process, and each change is read into a
+// line in the sidecar's format.
+
+use std::ffi::CStr;
+use std::time::Duration;
+
+use cocoa::base::{id, nil};
+use cocoa::foundation::NSAutoreleasePool;
+use objc::{class, msg_send, sel, sel_impl};
+use tokio::sync::mpsc;
+
+use crate::shutdown;
+use crate::util;
+
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// see clipboard::CONCEALED_TYPE
+const MARKER_PREFIX: &str = "org.nspasteboard.";
+
+fn change_count() -> i64 {
+    unsafe {
+        let pasteboard: id = msg_send![class!(NSPasteboard), generalPasteboard];
+        msg_send![pasteboard, changeCount]
+    }
+}
+
+unsafe fn to_string(ns_string: id) -> Option<String> {
+    if ns_string == nil {
+        return None;
+    }
+    let utf8: *const std::ffi::c_char = msg_send![ns_string, UTF8String];
+    if utf8.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(utf8).to_string_lossy().into_owned())
+}
+
+// Each of the pasteboard's types, base64 encoded, and the frontmost app's name, as the source
+fn read(change: i64) -> String {
+    let mut types = serde_json::Map::new();
+    let source = unsafe {
+        let pool = NSAutoreleasePool::new(nil);
+        let pasteboard: id = msg_send![class!(NSPasteboard), generalPasteboard];
+        let pasteboard_types: id = msg_send![pasteboard, types];
+        let count: usize = if pasteboard_types == nil {
+            0
+        } else {
+            msg_send![pasteboard_types, count]
+        };
+        for i in 0..count {
+            let pasteboard_type: id = msg_send![pasteboard_types, objectAtIndex: i];
+            let Some(name) = to_string(pasteboard_type) else {
+                continue;
+            };
+            let data: id = msg_send![pasteboard, dataForType: pasteboard_type];
+            if data == nil {
+                // markers, e.g. that the clip is concealed, are forwarded even without data
+                if name.starts_with(MARKER_PREFIX) {
+                    types.insert(name, "".into());
+                }
+                continue;
+            }
+            let len: usize = msg_send![data, length];
+            let bytes: *const u8 = msg_send![data, bytes];
+            let content = if len == 0 || bytes.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(bytes, len).to_vec()
+            };
+            types.insert(name, util::b64encode(&content).into());
+        }
+
+        let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
+        let app: id = msg_send![workspace, frontmostApplication];
+        let source = if app == nil {
+            None
+        } else {
+            let name: id = msg_send![app, localizedName];
+            to_string(name)
+        };
+        let () = msg_send![pool, drain];
+        source
+    };
+    serde_json::json!({
+        "change": change,
+        "types": types,
+        "source": source,
+    })
+    .to_string()
+}
+
+// Polls the pasteboard, sending a line per change, until there's nothing to send them to, or
+// shutdown is requested
+pub async fn watch(tx: &mpsc::Sender<String>) {
+    tracing::info!(name = "clipboard::macos", "polling the pasteboard");
+    let mut last = change_count();
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = shutdown::requested() => return,
+        }
+        let change = change_count();
+        if change == last {
+            continue;
+        }
+        last = change;
+        if tx.send(read(change)).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// Writes content to the system clipboard. Each representation is a pasteboard type, e.g.
+// public.utf8-plain-text, and its data.
+//
+// Sensitive items can be cleared from the clipboard a while after they're copied: see clear_after.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(target_os = "macos")]
+use cocoa::base::nil;
+#[cfg(target_os = "macos")]
+use cocoa::foundation::NSString;
+#[cfg(target_os = "macos")]
+use objc::{msg_send, sel, sel_impl};
+
+// Returns the clipboard's new change number
+#[cfg(target_os = "macos")]
+pub fn write(types: &[(&str, &[u8])]) -> Option<i64> {
+    unsafe {
+        let pasteboard: *mut objc::runtime::Object =
+            msg_send![objc::class!(NSPasteboard), generalPasteboard];
+
+        let i: i64 = msg_send![pasteboard, clearContents];
+
+        for (mime_type, data) in types {
+            let nsdata: *mut objc::runtime::Object = msg_send![objc::class!(NSData), alloc];
+            let nsdata: *mut objc::runtime::Object =
+                msg_send![nsdata, initWithBytes:data.as_ptr() length:data.len()];
+
+            let ns_type = NSString::alloc(nil).init_str(mime_type);
+
+            let success: bool = msg_send![pasteboard, setData: nsdata forType: ns_type];
+
+            // After the data is set, release the nsdata object to prevent a memory leak.
+            let () = msg_send![nsdata, release];
+            let () = msg_send![ns_type, release];
+
+            if !success {
+                return None;
+            }
+        }
+        Some(i)
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn clear() -> Option<i64> {
+    unsafe {
+        let pasteboard: *mut objc::runtime::Object =
+            msg_send![objc::class!(NSPasteboard), generalPasteboard];
+        Some(msg_send![pasteboard, clearContents])
+    }
+}
+
+// The clipboard's current change number
+#[cfg(target_os = "macos")]
+pub fn change_count() -> Option<i64> {
+    unsafe {
+        let pasteboard: *mut objc::runtime::Object =
+            msg_send![objc::class!(NSPasteboard), generalPasteboard];
+        Some(msg_send![pasteboard, changeCount])
+    }
+}
+
+// The mime type a pasteboard type is offered as on Linux
+#[cfg(target_os = "linux")]
+fn linux_mime_type(pasteboard_type: &str) -> &str {
+    match pasteboard_type {
+        "public.utf8-plain-text" => "text/plain;charset=utf-8",
+        "public.png" => "image/png",
+        "public.jpeg" => "image/jpeg",
+        "public.tiff" => "image/tiff",
+        "com.compuserve.gif" => "image/gif",
+        "org.webmproject.webp" => "image/webp",
+        "public.html" => "text/html",
+        "public.file-url" => "text/uri-list",
+        "net.daringfireball.markdown" => "text/markdown",
+        other => other,
+    }
+}
+
+// wl-copy and xclip only offer a single type, so the first, primary, representation is written.
+// There's no change number on Linux: the write will be captured, and deduplicated, like any
+// other clipboard change.
+#[cfg(target_os = "linux")]
+pub fn write(types: &[(&str, &[u8])]) -> Option<i64> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let (pasteboard_type, data) = types.first()?;
+    let mime_type = linux_mime_type(pasteboard_type);
+    let mut cmd = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut cmd = Command::new("wl-copy");
+        cmd.args(["--type", mime_type]);
+        cmd
+    } else {
+        let mut cmd = Command::new("xclip");
+        cmd.args(["-selection", "clipboard", "-t", mime_type, "-i"]);
+        cmd
+    };
+    let mut child = cmd.stdin(Stdio::piped()).spawn().ok()?;
+    child.stdin.take()?.write_all(data).ok()?;
+    child.wait().ok()?.success().then_some(-1)
+}
+
+#[cfg(target_os = "linux")]
+pub fn clear() -> Option<i64> {
+    use std::process::{Command, Stdio};
+
+    let mut cmd = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut cmd = Command::new("wl-copy");
+        cmd.arg("--clear");
+        cmd
+    } else {
+        // xclip can't clear the clipboard: it's left holding nothing instead
+        let mut cmd = Command::new("xclip");
+        cmd.args(["-selection", "clipboard", "-i", "/dev/null"]);
+        cmd
+    };
+    let status = cmd.stdin(Stdio::null()).status().ok()?;
+    status.success().then_some(-1)
+}
+
+#[cfg(target_os = "linux")]
+pub fn change_count() -> Option<i64> {
+    None
+}
+
+struct PendingClear {
+    timer: tauri::async_runtime::JoinHandle<()>,
+    // the change number of the write being cleared; without one, e.g. on Linux, the first change
+    // seen after scheduling is taken to be it
+    written: Option<i64>,
+}
+
+lazy_static::lazy_static! {
+    static ref PENDING_CLEAR: Mutex<Option<PendingClear>> = Mutex::new(None);
+    // the change number of the last clear, or restore, not yet seen by the capture: see take_cleared
+    static ref CLEARED: Mutex<Option<i64>> = Mutex::new(None);
+}
+
+// Cancels clearing the clipboard, e.g. because something else has been copied
+pub fn cancel_clear() {
+    if let Some(pending) = PENDING_CLEAR.lock().unwrap().take() {
+        pending.timer.abort();
+    }
+}
+
+// Called with each change the capture sees, other than Stacks' own writes: anything copied after a
+// sensitive item, other than the item itself, cancels clearing it
+pub fn changed(change_num: i64) {
+    let mut pending = PENDING_CLEAR.lock().unwrap();
+    match pending.as_mut().map(|pending| pending.written) {
+        None => {}
+        Some(Some(written)) if written == change_num => {}
+        Some(None) => pending.as_mut().unwrap().written = Some(change_num),
+        Some(Some(_)) => {
+            tracing::info!(
+                name = "clipboard_writer",
+                "clipboard changed: clear cancelled"
+            );
+            if let Some(pending) = pending.take() {
+                pending.timer.abort();
+            }
+        }
+    }
+}
+
+// The change number of the last clear, or restored clip, once: the capture skips it, as it's
+// Stacks' own write
+pub fn take_cleared() -> Option<i64> {
+    CLEARED.lock().unwrap().take()
+}
+
+// Clears the clipboard after a while, e.g. once a password's been pasted, or, given a clip to
+// restore, as its representations, writes that instead. It's left alone if it's changed since
+// `written`, the change number of the write being cleared; without one, e.g. on Linux, it's left
+// alone once a change other than the write has been seen: see changed. Only the last timer runs:
+// scheduling another cancels it.
+pub fn clear_after(after: Duration, written: Option<i64>, restore: Vec<(&'static str, Vec<u8>)>) {
+    let timer = tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(after).await;
+        if written.is_some() && change_count() != written {
+            tracing::info!(name = "clipboard_writer", "clipboard changed: not cleared");
+            return;
+        }
+        let types: Vec<_> = restore
+            .iter()
+            .map(|(mime_type, data)| (*mime_type, data.as_slice()))
+            .collect();
+        // held while writing, so the capture can't see the change before it's recorded
+        let mut cleared = CLEARED.lock().unwrap();
+        let res = if types.is_empty() {
+            clear()
+        } else {
+            write(&types)
+        };
+        if res.is_none() {
+            tracing::warn!(name = "clipboard_writer", "couldn't clear the clipboard");
+        }
+        *cleared = res;
+    });
+    let pending = PendingClear { timer, written };
+    if let Some(previous) = PENDING_CLEAR.lock().unwrap().replace(pending) {
+        previous.timer.abort();
+    }
+}
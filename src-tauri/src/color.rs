@@ -0,0 +1,174 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref HEX: Regex = Regex::new(r"^#([0-9a-fA-F]{3}|[0-9a-fA-F]{6})$").unwrap();
+    static ref RGB: Regex =
+        Regex::new(r"(?i)^rgb\(\s*(\d{1,3})\s*,\s*(\d{1,3})\s*,\s*(\d{1,3})\s*\)$").unwrap();
+    static ref HSL: Regex = Regex::new(
+        r"(?i)^hsl\(\s*(\d{1,3}(?:\.\d+)?)\s*,\s*(\d{1,3}(?:\.\d+)?)%\s*,\s*(\d{1,3}(?:\.\d+)?)%\s*\)$"
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    pub fn to_rgb(&self) -> String {
+        format!("rgb({}, {}, {})", self.r, self.g, self.b)
+    }
+
+    pub fn to_hsl(&self) -> String {
+        let r = self.r as f64 / 255.0;
+        let g = self.g as f64 / 255.0;
+        let b = self.b as f64 / 255.0;
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+
+        let (h, s) = if d == 0.0 {
+            (0.0, 0.0)
+        } else {
+            let s = d / (1.0 - (2.0 * l - 1.0).abs());
+            let h = if max == r {
+                60.0 * (((g - b) / d).rem_euclid(6.0))
+            } else if max == g {
+                60.0 * ((b - r) / d + 2.0)
+            } else {
+                60.0 * ((r - g) / d + 4.0)
+            };
+            (h, s)
+        };
+
+        format!(
+            "hsl({}, {}%, {}%)",
+            h.round() as u32 % 360,
+            (s * 100.0).round() as u32,
+            (l * 100.0).round() as u32
+        )
+    }
+
+    // Returns the color formatted as one of: hex, rgb or hsl
+    pub fn format(&self, format: &str) -> Option<String> {
+        match format {
+            "hex" => Some(self.to_hex()),
+            "rgb" => Some(self.to_rgb()),
+            "hsl" => Some(self.to_hsl()),
+            _ => None,
+        }
+    }
+}
+
+fn from_hsl(h: f64, s: f64, l: f64) -> Color {
+    let h = h % 360.0;
+    let s = (s / 100.0).clamp(0.0, 1.0);
+    let l = (l / 100.0).clamp(0.0, 1.0);
+
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match h as u32 / 60 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    let channel = |v: f64| ((v + m) * 255.0).round() as u8;
+    Color {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+    }
+}
+
+pub fn parse(input: &str) -> Option<Color> {
+    let input = input.trim();
+
+    if let Some(caps) = HEX.captures(input) {
+        let hex = &caps[1];
+        let hex = if hex.len() == 3 {
+            hex.chars().flat_map(|c| [c, c]).collect::<String>()
+        } else {
+            hex.to_string()
+        };
+        let value = u32::from_str_radix(&hex, 16).ok()?;
+        return Some(Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        });
+    }
+
+    if let Some(caps) = RGB.captures(input) {
+        let channel = |i: usize| caps[i].parse::<u8>().ok();
+        return Some(Color {
+            r: channel(1)?,
+            g: channel(2)?,
+            b: channel(3)?,
+        });
+    }
+
+    if let Some(caps) = HSL.captures(input) {
+        let h = caps[1].parse::<f64>().ok()?;
+        let s = caps[2].parse::<f64>().ok()?;
+        let l = caps[3].parse::<f64>().ok()?;
+        if s > 100.0 || l > 100.0 {
+            return None;
+        }
+        return Some(from_hsl(h, s, l));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        let color = parse("#aabbcc").unwrap();
+        assert_eq!(
+            color,
+            Color {
+                r: 170,
+                g: 187,
+                b: 204
+            }
+        );
+        assert_eq!(parse("#abc"), Some(color));
+        assert_eq!(parse("#abcd"), None);
+        assert_eq!(parse("aabbcc"), None);
+    }
+
+    #[test]
+    fn test_parse_rgb() {
+        let color = parse("rgb(255, 0, 128)").unwrap();
+        assert_eq!(color.to_hex(), "#ff0080");
+        assert_eq!(parse("rgb(256, 0, 0)"), None);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let color = parse("hsl(210, 50%, 40%)").unwrap();
+        assert_eq!(color.to_rgb(), "rgb(51, 102, 153)");
+        assert_eq!(color.to_hsl(), "hsl(210, 50%, 40%)");
+        assert_eq!(parse("#ffffff").unwrap().to_hsl(), "hsl(0, 0%, 100%)");
+        assert_eq!(color.format("cmyk"), None);
+    }
+}
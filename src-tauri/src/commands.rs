@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{Local, TimeZone, Utc};
+
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 
@@ -5,14 +10,73 @@ use tauri::Manager;
 
 use scru128::Scru128Id;
 
+use crate::actions;
+use crate::activity;
+use crate::address;
+use crate::agent;
+use crate::agent::Agent;
+use crate::backup;
+use crate::batch;
+use crate::bundle;
+use crate::calendar;
+use crate::clipboard_writer;
+use crate::color;
+use crate::compact;
+use crate::contact;
+use crate::content_bus;
 use crate::content_type::process_command;
+use crate::diff;
+use crate::disk;
+use crate::error::{ErrorKind, StacksError};
+use crate::events;
+use crate::export;
+use crate::file_ref;
+use crate::hotkeys;
+use crate::hotkeys::Hotkey;
+use crate::http;
+use crate::import;
+use crate::links;
+use crate::materialize;
+use crate::notifications;
+use crate::paste;
+use crate::paste::{PasteRule, StackTransform, Transform};
+use crate::phone;
+use crate::plugins;
+use crate::privacy;
+use crate::prompts;
+use crate::qr;
+use crate::registry;
+use crate::rules;
+use crate::schedule;
+use crate::schedule::Schedule;
+use crate::semantic;
+use crate::sequential;
+use crate::share::ShareToken;
+use crate::shell;
+use crate::shutdown;
+use crate::snippet;
+use crate::split;
 use crate::spotlight;
 use crate::spotlight::Shortcut;
-use crate::state::SharedState;
+use crate::stack_settings;
+use crate::stack_settings::StackSettings;
+use crate::state::{SharedState, State};
+use crate::stats;
 use crate::store::{
-    InProgressStream, MimeType, Movement, Settings, StackLockStatus, StackSortOrder,
+    ContentMeta, GcStats, InProgressStream, ItemVersion, MimeType, Movement, Settings,
+    StackLockStatus, StackSortOrder, DEFAULT_THUMBNAIL_WIDTH,
 };
-use crate::ui::{generate_preview, with_meta, Item as UIItem, Nav, UI};
+use crate::theme;
+use crate::theme::Theme;
+use crate::timeline;
+use crate::touch_id;
+use crate::ui::{
+    chunks, generate_preview, preview_key, preview_shown, render_chunk, stack_tree,
+    truncate_content, truncation_note, with_meta, Item as UIItem, Nav, PreviewLimits, StackTree,
+    StreamPreview, FULL_PREVIEW_CHUNK_BYTES,
+};
+use crate::usage::Access;
+use crate::verify;
 use crate::view::View;
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -41,7 +105,7 @@ pub async fn store_pipe_stack_to_shell(
     exec_id: u32,
     stack_id: scru128::Scru128Id,
     command: String,
-) -> Result<(), ()> {
+) -> Result<(), StacksError> {
     let item_hashes = state.with_lock(|state| {
         state
             .view
@@ -63,7 +127,10 @@ pub async fn store_pipe_stack_to_shell(
             let content_str = match state.with_lock(|state| state.store.get_content_meta(&hash)) {
                 Some(meta) => match meta.mime_type {
                     MimeType::TextPlain => String::from_utf8_lossy(&content).to_string(),
-                    MimeType::ImagePng => "Image".to_string(),
+                    MimeType::FileRef => file_ref::parse(&content)
+                        .map(|file_ref| file_ref.path)
+                        .unwrap_or_default(),
+                    _ => "Image".to_string(),
                 },
                 None => continue,
             };
@@ -73,48 +140,36 @@ pub async fn store_pipe_stack_to_shell(
 
     let (cooked_command, content_type) = process_command(&command);
 
-    let home_dir = dirs::home_dir().expect("Could not fetch home directory");
-    let shell = match std::env::var("SHELL") {
-        Ok(val) => val,
-        Err(_) => String::from("/bin/sh"), // default to sh if no SHELL variable is set
-    };
-
-    let rc_file = match shell.as_str() {
-        "/bin/bash" => ".bashrc",
-        "/bin/zsh" => ".zshrc",
-        _ => "", // if the shell is neither bash nor zsh, don't source an rc file
-    };
-
-    let rc_path = home_dir.join(rc_file);
-    let rc_command = format!(
-        "source {}\n{}",
-        rc_path.to_str().unwrap_or(""),
-        cooked_command
-    );
-
-    let mut cmd = tokio::process::Command::new(shell)
-        .arg("-c")
-        .arg(rc_command)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
+    let mut cmd = shell::command(&cooked_command)
         .spawn()
-        .unwrap();
+        .map_err(|e| e.to_string())?;
+    let started = std::time::Instant::now();
 
-    let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin").unwrap();
-    let json_list_string = serde_json::to_string(&json_list).unwrap();
+    let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin")?;
+    let json_list_string = serde_json::to_string(&json_list).map_err(|e| e.to_string())?;
     tokio::spawn(async move {
-        stdin.write_all(json_list_string.as_bytes()).await.unwrap();
+        // the command may exit without reading its input
+        if let Err(e) = stdin.write_all(json_list_string.as_bytes()).await {
+            tracing::warn!(%e, "couldn't write to command stdin");
+        }
     });
 
-    let mut stdout = cmd.stdout.take().unwrap();
+    let mut stdout = cmd.stdout.take().ok_or("Failed to open stdout")?;
     let read_stdout = {
         let state = state.inner().clone();
         let app = app.clone();
 
         tokio::spawn(async move {
+            // the stream is kept, as far as it got, if the app exits
+            let _guard = shutdown::guard();
             let mut buffer = [0u8; 4096];
-            let size = stdout.read(&mut buffer).await.unwrap();
+            let size = match stdout.read(&mut buffer).await {
+                Ok(size) => size,
+                Err(e) => {
+                    tracing::error!("Error reading bytes from command stdout: {}", e);
+                    return;
+                }
+            };
 
             // stdout is empty
             if size == 0 {
@@ -127,26 +182,34 @@ pub async fn store_pipe_stack_to_shell(
                     MimeType::TextPlain,
                     content_type.clone().unwrap_or("Text".to_string()),
                 ),
-                Some("image/png") => (MimeType::ImagePng, "Image".to_string()),
                 Some("text/html") => (MimeType::TextPlain, "HTML".to_string()),
-                Some(mime_type) => {
-                    eprintln!("mime_type: {:?}", mime_type);
-                    todo!()
-                }
+                Some(mime) => match MimeType::from_image_mime(mime) {
+                    Some(mime_type) => (mime_type, "Image".to_string()),
+                    None => {
+                        tracing::warn!(mime, "unsupported command output");
+                        return;
+                    }
+                },
             };
 
+            let (mut previewer, tokenizer) = state.with_lock(|state| {
+                let limits = PreviewLimits::from_settings(state.store.settings_get());
+                let previewer = StreamPreview::new(limits, &state.ui.theme.syntax);
+                (previewer, state.store.tokenizer_get())
+            });
             let mut streamer = state.with_lock(|state| {
                 let stack = state.get_curr_stack();
                 let mut streamer = InProgressStream::new(stack, mime_type.clone(), content_type_2);
                 if mime_type == MimeType::TextPlain {
                     state.merge(&streamer.packet);
-                    app.emit_all("refresh-items", true).unwrap();
+                    events::emit(&app, "refresh-items", true);
                 }
                 streamer.append(&buffer[..size]);
                 streamer
             });
 
-            app.emit_all(
+            events::emit(
+                &app,
                 "pipe-stack-to-shell",
                 ExecStatus {
                     exec_id,
@@ -158,38 +221,52 @@ pub async fn store_pipe_stack_to_shell(
                     err: None,
                     code: None,
                 },
-            )
-            .unwrap();
+            );
 
             loop {
-                match stdout.read(&mut buffer).await {
+                let read = tokio::select! {
+                    read = stdout.read(&mut buffer) => read,
+                    _ = shutdown::requested() => break,
+                };
+                match read {
                     Ok(size) => {
                         if size == 0 {
                             break; // End of stream
                         }
                         streamer.append(&buffer[..size]);
+                        streamer.count_tiktokens(&buffer[..size], tokenizer);
 
                         if mime_type == MimeType::TextPlain {
-                            let preview = generate_preview(
-                                "dark",
-                                &Some(streamer.content.clone()),
+                            let (preview, truncated) = previewer.update(
+                                &streamer.content,
                                 &streamer.content_meta.mime_type,
                                 &streamer.content_meta.content_type,
-                                true,
                             );
-                            let content = String::from_utf8_lossy(&streamer.content);
                             let content = Content {
                                 mime_type: streamer.content_meta.mime_type.clone(),
                                 content_type: streamer.content_meta.content_type.clone(),
-                                terse: content.chars().take(100).collect(),
-                                tiktokens: 0,
-                                words: content.split_whitespace().count(),
-                                chars: content.chars().count(),
-                                preview,
+                                terse: streamer.content_meta.terse.clone(),
+                                tiktokens: streamer.content_meta.tiktokens,
+                                words: streamer.content_meta.stats.words,
+                                chars: streamer.content_meta.stats.chars,
+                                lines: streamer.content_meta.stats.lines,
+                                bytes: streamer.content_meta.stats.bytes,
+                                preview: preview.to_string(),
+                                truncated,
+                                truncated_from: None,
+                                thumbnail: None,
                             };
 
-                            app.emit_all("streaming", (streamer.packet.id, content))
-                                .unwrap();
+                            let scope = events::Scope {
+                                stack_id: streamer.packet.stack_id.as_ref(),
+                                mime_type: Some(&streamer.content_meta.mime_type),
+                            };
+                            events::emit_scoped(
+                                &app,
+                                "streaming",
+                                &scope,
+                                (streamer.packet.id, content),
+                            );
                         }
                     }
                     Err(e) => {
@@ -210,11 +287,12 @@ pub async fn store_pipe_stack_to_shell(
                         let hash = packet.hash.clone().unwrap();
                         let packet = state.store.update_content_type(hash.clone(), content_type);
                         state.merge(&packet);
-                        app.emit_all("content", &hash).unwrap();
+                        events::emit(&app, "content", &hash);
                     }
                 }
 
-                app.emit_all(
+                events::emit(
+                    &app,
                     "pipe-stack-to-shell",
                     ExecStatus {
                         exec_id,
@@ -226,22 +304,25 @@ pub async fn store_pipe_stack_to_shell(
                         err: None,
                         code: None,
                     },
-                )
-                .unwrap();
+                );
             });
-            app.emit_all("refresh-items", true).unwrap();
+            events::emit(&app, "refresh-items", true);
         })
     };
 
-    let mut stderr = cmd.stderr.take().unwrap();
+    let mut stderr = cmd.stderr.take().ok_or("Failed to open stderr")?;
     let mut buff = Vec::new();
-    stderr.read_to_end(&mut buff).await.unwrap();
+    // whatever was read before an error is kept
+    if let Err(e) = stderr.read_to_end(&mut buff).await {
+        tracing::error!("Error reading bytes from command stderr: {}", e);
+    }
     let stderr = buff;
     if !stderr.is_empty() {
         state.with_lock(|state| {
             let packet = state.store.add(&stderr, MimeType::TextPlain, stack_id);
             state.merge(&packet);
-            app.emit_all(
+            events::emit(
+                &app,
                 "pipe-stack-to-shell",
                 ExecStatus {
                     exec_id,
@@ -253,24 +334,33 @@ pub async fn store_pipe_stack_to_shell(
                     }),
                     code: None,
                 },
-            )
-            .unwrap();
+            );
         })
     }
 
-    let status = cmd.wait().await.unwrap();
+    let code = match cmd.wait().await {
+        Ok(status) => status.code(),
+        Err(e) => {
+            tracing::error!("Error waiting for command: {}", e);
+            None
+        }
+    };
 
-    let _ = read_stdout.await.expect("Task failed");
-    app.emit_all(
+    if let Err(e) = read_stdout.await {
+        tracing::error!("Error streaming command stdout: {}", e);
+    }
+    events::emit(
+        &app,
         "pipe-stack-to-shell",
         ExecStatus {
             exec_id,
             out: None,
             err: None,
-            code: status.code(),
+            code,
         },
-    )
-    .unwrap();
+    );
+    let settings = state.with_lock(|state| state.store.settings_get().unwrap_or_default());
+    notifications::command_finished(&app, &settings, &command, started.elapsed(), code);
 
     state.with_lock(|state| {
         let packet = state
@@ -283,11 +373,63 @@ pub async fn store_pipe_stack_to_shell(
         state.merge(&packet);
     });
 
-    app.emit_all("refresh-items", true).unwrap();
+    events::emit(&app, "refresh-items", true);
 
     Ok(())
 }
 
+// Schedules a command item to run periodically, with a cron expression, e.g. "0 9 * * 1-5". Each
+// run's output is added to the item's stack. Returns the schedule's id.
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_schedule_add(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    cron: String,
+) -> Result<scru128::Scru128Id, StacksError> {
+    schedule::Cron::parse(&cron).map_err(StacksError::invalid_input)?;
+    state.with_lock(|state| {
+        if !state.view.items.contains_key(&source_id) {
+            return Err(StacksError::not_found(&source_id));
+        }
+        let schedule = Schedule {
+            id: scru128::new(),
+            source_id,
+            cron: cron.trim().to_string(),
+            paused: false,
+            last_run: None,
+        };
+        state.store.schedule_set(&schedule);
+        Ok(schedule.id)
+    })
+}
+
+#[tauri::command]
+pub fn store_schedules_list(state: tauri::State<SharedState>) -> Vec<Schedule> {
+    state.with_lock(|state| state.store.schedules())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_schedule_pause(
+    state: tauri::State<SharedState>,
+    id: scru128::Scru128Id,
+    paused: bool,
+) -> Option<()> {
+    state.with_lock(|state| {
+        let mut schedule = state.store.schedules().into_iter().find(|s| s.id == id)?;
+        schedule.paused = paused;
+        state.store.schedule_set(&schedule);
+        Some(())
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_schedule_delete(state: tauri::State<SharedState>, id: scru128::Scru128Id) -> bool {
+    state.with_lock(|state| state.store.schedule_delete(&id))
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(state, app))]
 pub async fn store_pipe_to_command(
@@ -296,58 +438,53 @@ pub async fn store_pipe_to_command(
     exec_id: u32,
     source_id: scru128::Scru128Id,
     command: String,
-) -> Result<(), ()> {
+) -> Result<(), StacksError> {
     let (cache_path, hash, stack_id) = state.with_lock(|state| {
         let cache_path = state.store.cache_path.clone();
-        let item = state.view.items.get(&source_id).unwrap();
-        (cache_path, item.hash.clone(), item.stack_id)
-    });
+        let item = state
+            .view
+            .items
+            .get(&source_id)
+            .ok_or(StacksError::not_found(&source_id))?;
+        Ok::<_, StacksError>((cache_path, item.hash.clone(), item.stack_id))
+    })?;
 
     let (cooked_command, content_type) = process_command(&command);
 
-    let home_dir = dirs::home_dir().expect("Could not fetch home directory");
-    let shell = match std::env::var("SHELL") {
-        Ok(val) => val,
-        Err(_) => String::from("/bin/sh"), // default to sh if no SHELL variable is set
-    };
-
-    let rc_file = match shell.as_str() {
-        "/bin/bash" => ".bashrc",
-        "/bin/zsh" => ".zshrc",
-        _ => "", // if the shell is neither bash nor zsh, don't source an rc file
-    };
-
-    let rc_path = home_dir.join(rc_file);
-    let rc_command = format!(
-        "source {}\n{}",
-        rc_path.to_str().unwrap_or(""),
-        cooked_command
-    );
-
-    let mut cmd = tokio::process::Command::new(shell)
-        .arg("-c")
-        .arg(rc_command)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
+    // opened first, so a missing blob fails the command before it's run
+    let mut reader = cacache::Reader::open_hash(cache_path, hash)
+        .await
+        .map_err(|_| StacksError::content_not_found(&source_id))?;
+    let mut cmd = shell::command(&cooked_command)
         .spawn()
-        .unwrap();
+        .map_err(|e| e.to_string())?;
+    let started = std::time::Instant::now();
 
-    let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin").unwrap();
-    let mut reader = cacache::Reader::open_hash(cache_path, hash).await.unwrap();
+    let mut stdin = cmd.stdin.take().ok_or("Failed to open stdin")?;
     tokio::spawn(async move {
-        tokio::io::copy(&mut reader, &mut stdin).await.unwrap();
+        // the command may exit without reading its input
+        if let Err(e) = tokio::io::copy(&mut reader, &mut stdin).await {
+            tracing::warn!(%e, "couldn't write to command stdin");
+        }
     });
 
-    let mut stdout = cmd.stdout.take().unwrap();
+    let mut stdout = cmd.stdout.take().ok_or("Failed to open stdout")?;
 
     let read_stdout = {
         let state = state.inner().clone();
         let app = app.clone();
 
         tokio::spawn(async move {
+            // the stream is kept, as far as it got, if the app exits
+            let _guard = shutdown::guard();
             let mut buffer = [0u8; 4096];
-            let size = stdout.read(&mut buffer).await.unwrap();
+            let size = match stdout.read(&mut buffer).await {
+                Ok(size) => size,
+                Err(e) => {
+                    tracing::error!("Error reading bytes from command stdout: {}", e);
+                    return;
+                }
+            };
 
             // stdout is empty
             if size == 0 {
@@ -360,26 +497,34 @@ pub async fn store_pipe_to_command(
                     MimeType::TextPlain,
                     content_type.clone().unwrap_or("Text".to_string()),
                 ),
-                Some("image/png") => (MimeType::ImagePng, "Image".to_string()),
                 Some("text/html") => (MimeType::TextPlain, "HTML".to_string()),
-                Some(mime_type) => {
-                    eprintln!("mime_type: {:?}", mime_type);
-                    todo!()
-                }
+                Some(mime) => match MimeType::from_image_mime(mime) {
+                    Some(mime_type) => (mime_type, "Image".to_string()),
+                    None => {
+                        tracing::warn!(mime, "unsupported command output");
+                        return;
+                    }
+                },
             };
 
+            let (mut previewer, tokenizer) = state.with_lock(|state| {
+                let limits = PreviewLimits::from_settings(state.store.settings_get());
+                let previewer = StreamPreview::new(limits, &state.ui.theme.syntax);
+                (previewer, state.store.tokenizer_get())
+            });
             let mut streamer = state.with_lock(|state| {
                 let stack = state.get_curr_stack();
                 let mut streamer = InProgressStream::new(stack, mime_type.clone(), content_type_2);
                 if mime_type == MimeType::TextPlain {
                     state.merge(&streamer.packet);
-                    app.emit_all("refresh-items", true).unwrap();
+                    events::emit(&app, "refresh-items", true);
                 }
                 streamer.append(&buffer[..size]);
                 streamer
             });
 
-            app.emit_all(
+            events::emit(
+                &app,
                 "pipe-to-shell",
                 ExecStatus {
                     exec_id,
@@ -391,38 +536,52 @@ pub async fn store_pipe_to_command(
                     err: None,
                     code: None,
                 },
-            )
-            .unwrap();
+            );
 
             loop {
-                match stdout.read(&mut buffer).await {
+                let read = tokio::select! {
+                    read = stdout.read(&mut buffer) => read,
+                    _ = shutdown::requested() => break,
+                };
+                match read {
                     Ok(size) => {
                         if size == 0 {
                             break; // End of stream
                         }
                         streamer.append(&buffer[..size]);
+                        streamer.count_tiktokens(&buffer[..size], tokenizer);
 
                         if mime_type == MimeType::TextPlain {
-                            let preview = generate_preview(
-                                "dark",
-                                &Some(streamer.content.clone()),
+                            let (preview, truncated) = previewer.update(
+                                &streamer.content,
                                 &streamer.content_meta.mime_type,
                                 &streamer.content_meta.content_type,
-                                true,
                             );
-                            let content = String::from_utf8_lossy(&streamer.content);
                             let content = Content {
                                 mime_type: streamer.content_meta.mime_type.clone(),
                                 content_type: streamer.content_meta.content_type.clone(),
-                                terse: content.chars().take(100).collect(),
-                                tiktokens: 0,
-                                words: content.split_whitespace().count(),
-                                chars: content.chars().count(),
-                                preview,
+                                terse: streamer.content_meta.terse.clone(),
+                                tiktokens: streamer.content_meta.tiktokens,
+                                words: streamer.content_meta.stats.words,
+                                chars: streamer.content_meta.stats.chars,
+                                lines: streamer.content_meta.stats.lines,
+                                bytes: streamer.content_meta.stats.bytes,
+                                preview: preview.to_string(),
+                                truncated,
+                                truncated_from: None,
+                                thumbnail: None,
                             };
 
-                            app.emit_all("streaming", (streamer.packet.id, content))
-                                .unwrap();
+                            let scope = events::Scope {
+                                stack_id: streamer.packet.stack_id.as_ref(),
+                                mime_type: Some(&streamer.content_meta.mime_type),
+                            };
+                            events::emit_scoped(
+                                &app,
+                                "streaming",
+                                &scope,
+                                (streamer.packet.id, content),
+                            );
                         }
                     }
                     Err(e) => {
@@ -443,11 +602,12 @@ pub async fn store_pipe_to_command(
                         let hash = packet.hash.clone().unwrap();
                         let packet = state.store.update_content_type(hash.clone(), content_type);
                         state.merge(&packet);
-                        app.emit_all("content", &hash).unwrap();
+                        events::emit(&app, "content", &hash);
                     }
                 }
 
-                app.emit_all(
+                events::emit(
+                    &app,
                     "pipe-to-shell",
                     ExecStatus {
                         exec_id,
@@ -459,23 +619,26 @@ pub async fn store_pipe_to_command(
                         err: None,
                         code: None,
                     },
-                )
-                .unwrap();
+                );
             });
-            app.emit_all("refresh-items", true).unwrap();
+            events::emit(&app, "refresh-items", true);
         })
     };
 
-    let mut stderr = cmd.stderr.take().unwrap();
+    let mut stderr = cmd.stderr.take().ok_or("Failed to open stderr")?;
     let mut buff = Vec::new();
-    stderr.read_to_end(&mut buff).await.unwrap();
+    // whatever was read before an error is kept
+    if let Err(e) = stderr.read_to_end(&mut buff).await {
+        tracing::error!("Error reading bytes from command stderr: {}", e);
+    }
     let stderr = buff;
     if !stderr.is_empty() {
         state.with_lock(|state| {
             let stack_id = stack_id.unwrap_or_else(|| state.get_curr_stack());
             let packet = state.store.add(&stderr, MimeType::TextPlain, stack_id);
             state.merge(&packet);
-            app.emit_all(
+            events::emit(
+                &app,
                 "pipe-to-shell",
                 ExecStatus {
                     exec_id,
@@ -487,24 +650,33 @@ pub async fn store_pipe_to_command(
                     }),
                     code: None,
                 },
-            )
-            .unwrap();
+            );
         })
     }
 
-    let status = cmd.wait().await.unwrap();
+    let code = match cmd.wait().await {
+        Ok(status) => status.code(),
+        Err(e) => {
+            tracing::error!("Error waiting for command: {}", e);
+            None
+        }
+    };
 
-    let _ = read_stdout.await.expect("Task failed");
-    app.emit_all(
+    if let Err(e) = read_stdout.await {
+        tracing::error!("Error streaming command stdout: {}", e);
+    }
+    events::emit(
+        &app,
         "pipe-to-shell",
         ExecStatus {
             exec_id,
             out: None,
             err: None,
-            code: status.code(),
+            code,
         },
-    )
-    .unwrap();
+    );
+    let settings = state.with_lock(|state| state.store.settings_get().unwrap_or_default());
+    notifications::command_finished(&app, &settings, &command, started.elapsed(), code);
 
     state.with_lock(|state| {
         let stack_id = stack_id.unwrap_or_else(|| state.get_curr_stack());
@@ -518,7 +690,7 @@ pub async fn store_pipe_to_command(
         state.merge(&packet);
     });
 
-    app.emit_all("refresh-items", true).unwrap();
+    events::emit(&app, "refresh-items", true);
     Ok(())
 }
 
@@ -537,7 +709,7 @@ pub fn store_get_raw_content(
     state: tauri::State<SharedState>,
     hash: ssri::Integrity,
 ) -> Option<String> {
-    state.with_lock(|state| {
+    state.with_read(|state| {
         state
             .store
             .get_content(&hash)
@@ -553,55 +725,174 @@ pub struct Content {
     pub tiktokens: usize,
     pub words: usize,
     pub chars: usize,
+    pub lines: usize,
+    pub bytes: u64,
     pub preview: String,
+    // the preview only shows the start of the content: see store_get_content_full
+    pub truncated: bool,
+    // the clip's size before it was truncated to max_item_bytes, if it was
+    pub truncated_from: Option<u64>,
+    // path to a cached thumbnail, for images
+    pub thumbnail: Option<String>,
 }
 
-#[tauri::command]
-#[tracing::instrument(skip(state), fields(%hash = truncate_hash(&hash, 8)))]
-pub fn store_get_content(state: tauri::State<SharedState>, hash: ssri::Integrity) -> Content {
-    state.with_lock(|state| {
-        let content = state.store.get_content(&hash);
-        let meta = state.store.get_content_meta(&hash).unwrap();
-
-        let (words, chars) = match (&meta.mime_type, &content) {
-            (MimeType::TextPlain, Some(bytes)) => {
-                let str_slice = std::str::from_utf8(bytes).expect("Invalid UTF-8");
-                (
-                    str_slice.split_whitespace().count(),
-                    str_slice.chars().count(),
-                )
-            }
-            _ => (0, 0),
-        };
+// Generates the content's thumbnail, if it has one, without holding the lock
+fn thumbnail(state: &SharedState, hash: &ssri::Integrity) -> Option<PathBuf> {
+    state
+        .with_read(|state| state.store.thumbnail(hash, DEFAULT_THUMBNAIL_WIDTH))
+        .and_then(|thumbnail| thumbnail.generate())
+}
 
+fn content(
+    state: &State,
+    hash: &ssri::Integrity,
+    thumbnail: Option<PathBuf>,
+    limits: Option<PreviewLimits>,
+) -> Content {
+    let content = state.store.get_content(hash);
+    let meta = state.store.get_content_meta(hash).unwrap();
+
+    // text previews are slow to highlight, so they're rendered once, and cached on disk
+    let preview_key = (meta.mime_type == MimeType::TextPlain)
+        .then(|| preview_key(&state.ui.theme.syntax, &meta.content_type, limits.as_ref()));
+    // how much of large text the preview shows, if it doesn't show all of it
+    let shown = match (&limits, &content) {
+        (Some(limits), Some(data)) if meta.mime_type == MimeType::TextPlain => {
+            let shown = preview_shown(data, &meta.mime_type, &meta.content_type, limits);
+            (shown < data.len()).then(|| truncation_note(&data[..shown], &meta.stats))
+        }
+        _ => None,
+    };
+    let (content, mime_type, truncated) = match (limits, &thumbnail) {
+        // large images are slow to render, so the preview shows the thumbnail instead
+        (Some(_), Some(path)) if meta.mime_type.is_image() => {
+            (std::fs::read(path).ok(), MimeType::ImagePng, false)
+        }
+        (Some(limits), _) => {
+            let (content, truncated) = truncate_content(content, &meta.mime_type, &limits);
+            (content, meta.mime_type.clone(), truncated)
+        }
+        (None, _) => (content, meta.mime_type.clone(), false),
+    };
+    let cached = preview_key
+        .as_ref()
+        .and_then(|key| state.store.preview_get(hash, key));
+    let preview = cached.unwrap_or_else(|| {
         let preview = generate_preview(
-            &state.ui.theme_mode,
+            &state.ui.theme.syntax,
             &content,
-            &meta.mime_type,
+            &mime_type,
             &meta.content_type,
             false,
         );
+        let preview = match &shown {
+            Some(note) => format!("{}{}", preview, note),
+            None => preview,
+        };
+        if let (Some(key), Some(_)) = (&preview_key, &content) {
+            state.store.preview_set(hash, key, &preview);
+        }
+        preview
+    });
 
-        Content {
-            mime_type: meta.mime_type,
-            content_type: meta.content_type,
-            terse: meta.terse,
-            tiktokens: meta.tiktokens,
-            words,
-            chars,
-            preview,
+    Content {
+        mime_type: meta.mime_type,
+        content_type: meta.content_type,
+        terse: meta.terse,
+        tiktokens: meta.tiktokens,
+        words: meta.stats.words,
+        chars: meta.stats.chars,
+        lines: meta.stats.lines,
+        bytes: meta.stats.bytes,
+        preview,
+        truncated: truncated || shown.is_some(),
+        truncated_from: meta.truncated_from,
+        thumbnail: thumbnail.map(|path| path.to_string_lossy().to_string()),
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state), fields(%hash = truncate_hash(&hash, 8)))]
+pub fn store_get_content(state: tauri::State<SharedState>, hash: ssri::Integrity) -> Content {
+    let thumbnail = thumbnail(&state, &hash);
+    state.with_read(|state| {
+        let limits = PreviewLimits::from_settings(state.store.settings_get());
+        content(state, &hash, thumbnail, Some(limits))
+    })
+}
+
+// Like store_get_content, but previews all of the content, however large
+#[tauri::command]
+#[tracing::instrument(skip(state), fields(%hash = truncate_hash(&hash, 8)))]
+pub fn store_get_content_full(state: tauri::State<SharedState>, hash: ssri::Integrity) -> Content {
+    let thumbnail = thumbnail(&state, &hash);
+    state.with_read(|state| content(state, &hash, thumbnail, None))
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct PreviewChunk {
+    pub id: scru128::Scru128Id,
+    pub seq: usize,
+    pub html: String,
+    pub done: bool,
+}
+
+// Renders the rest of a large text item, which its preview leaves out, in chunks sent with the
+// preview-chunk event, so the webview never takes in all of it at once. Returns how many chunks
+// there are.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_render_full_preview(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    id: scru128::Scru128Id,
+) -> Result<usize, StacksError> {
+    let (content, meta, limits, theme_mode) = state.with_read(|state| {
+        let item = state
+            .view
+            .items
+            .get(&id)
+            .ok_or(StacksError::not_found(&id))?;
+        let meta = state
+            .store
+            .get_content_meta(&item.hash)
+            .ok_or(StacksError::content_not_found(&id))?;
+        let content = state
+            .store
+            .get_content(&item.hash)
+            .ok_or(StacksError::content_not_found(&id))?;
+        let limits = PreviewLimits::from_settings(state.store.settings_get());
+        Ok::<_, StacksError>((content, meta, limits, state.ui.theme.syntax.clone()))
+    })?;
+
+    let count = tauri::async_runtime::spawn_blocking(move || {
+        let shown = preview_shown(&content, &meta.mime_type, &meta.content_type, &limits);
+        let chunks = chunks(&content[shown..], FULL_PREVIEW_CHUNK_BYTES);
+        let count = chunks.len();
+        for (seq, chunk) in chunks.into_iter().enumerate() {
+            let chunk = PreviewChunk {
+                id,
+                seq,
+                html: render_chunk(&theme_mode, chunk, &meta.content_type),
+                done: seq + 1 == count,
+            };
+            events::emit(&app, "preview-chunk", chunk);
         }
+        count
     })
+    .await?;
+    Ok(count)
 }
 
 #[tauri::command]
 #[tracing::instrument(skip(state))]
 pub fn store_get_root(state: tauri::State<SharedState>) -> Vec<UIItem> {
-    state.with_lock(|state| {
+    state.with_read(|state| {
         state
             .view
             .root()
             .iter()
+            .filter(|item| !item.archived)
             .map(|item| with_meta(&state.store, item))
             .collect()
     })
@@ -610,7 +901,7 @@ pub fn store_get_root(state: tauri::State<SharedState>) -> Vec<UIItem> {
 #[tauri::command]
 #[tracing::instrument(skip(state))]
 pub fn store_nav_refresh(state: tauri::State<SharedState>) -> Nav {
-    state.with_lock(|state| state.ui.render(&state.store))
+    state.with_read(|state| state.ui.render(&state.store))
 }
 
 #[tauri::command]
@@ -637,6 +928,8 @@ pub fn store_nav_set_filter(
             "Images" => "Image",
             "Markdown" => "Markdown",
             "Source Code" => "Source Code",
+            "Snippets" => snippet::CONTENT_TYPE,
+            "Contacts" => contact::CONTENT_TYPE,
             _ => "All",
         };
         state.nav_set_filter(&filter, content_type);
@@ -707,33 +1000,121 @@ pub fn store_nav_select_right(state: tauri::State<SharedState>) -> Nav {
     })
 }
 
-use cocoa::base::nil;
-use cocoa::foundation::NSString;
-use objc::{msg_send, sel, sel_impl};
-
 pub fn write_to_clipboard(mime_type: &str, data: &[u8]) -> Option<i64> {
-    unsafe {
-        let nsdata: *mut objc::runtime::Object = msg_send![objc::class!(NSData), alloc];
-        let nsdata: *mut objc::runtime::Object =
-            msg_send![nsdata, initWithBytes:data.as_ptr() length:data.len()];
+    write_types_to_clipboard(&[(mime_type, data)])
+}
 
-        let pasteboard: *mut objc::runtime::Object =
-            msg_send![objc::class!(NSPasteboard), generalPasteboard];
+// Writes multiple representations of the same content to the clipboard, e.g. HTML along with a
+// plain text fallback
+pub fn write_types_to_clipboard(types: &[(&str, &[u8])]) -> Option<i64> {
+    // whatever's copied now isn't to be cleared for the sensitive item before it
+    clipboard_writer::cancel_clear();
+    clipboard_writer::write(types)
+}
 
-        let png_type = NSString::alloc(nil).init_str(mime_type);
+// Copies an item to the clipboard, applying the item's stack paste transform and the paste rules
+// for the app the item is about to be pasted into. Returns the clipboard's new change number.
+pub fn copy_to_clipboard(state: &State, source_id: &Scru128Id) -> Option<i64> {
+    let item = state.view.items.get(source_id)?;
+    let meta = state.store.get_content_meta(&item.hash).unwrap();
+    let content = state.store.get_content(&item.hash).unwrap();
+    let settings = state.store.settings_get().unwrap_or_default();
+
+    // apply the stack's default paste transform, if it has one
+    let transform = settings
+        .stack_transforms
+        .unwrap_or_default()
+        .into_iter()
+        .find(|t| Some(t.stack_id) == item.stack_id);
+    let content = transform
+        .and_then(|t| paste::transform_content(&t.transform, &content, &meta.mime_type))
+        .unwrap_or(content);
+
+    let rules = settings.paste_rules.unwrap_or_default();
+    let bundle_id = spotlight::get_previous_app_bundle_id();
+    let format = paste::format_for(&rules, bundle_id.as_deref());
+    let types = paste::representations(&format, &state.ui.theme.syntax, &meta, &content);
+    let types: Vec<_> = types
+        .iter()
+        .map(|(mime_type, data)| (*mime_type, data.as_slice()))
+        .collect();
+
+    let change_num = write_types_to_clipboard(&types);
+    let clear_after = settings.clear_sensitive_after_secs.filter(|secs| *secs > 0);
+    if let Some(secs) = clear_after.filter(|_| state.store.is_sensitive(source_id)) {
+        let restore = previous_clip(state, source_id).unwrap_or_default();
+        clipboard_writer::clear_after(std::time::Duration::from_secs(secs), change_num, restore);
+    }
+    change_num
+}
 
-        let i: i64 = msg_send![pasteboard, clearContents];
-        let success: bool = msg_send![pasteboard, setData: nsdata forType: png_type];
+// The most recently touched item which isn't sensitive, other than the one being copied, as its
+// representations, to restore to the clipboard once a sensitive item's cleared from it
+fn previous_clip(state: &State, source_id: &Scru128Id) -> Option<Vec<(&'static str, Vec<u8>)>> {
+    let item = state
+        .view
+        .items
+        .values()
+        .filter(|item| !item.is_stack && item.id != *source_id)
+        .filter(|item| !state.store.is_sensitive(&item.id))
+        .max_by_key(|item| item.last_touched)?;
+    let meta = state.store.get_content_meta(&item.hash)?;
+    let content = state.store.get_content(&item.hash)?;
+    Some(paste::representations(
+        &paste::PasteFormat::Plain,
+        &state.ui.theme.syntax,
+        &meta,
+        &content,
+    ))
+}
 
-        // After the data is set, release the nsdata object to prevent a memory leak.
-        let () = msg_send![nsdata, release];
-        let () = msg_send![png_type, release];
+// Writes an item's content to a file, returning its path, so the frontend can start a native drag
+// of the item into other apps
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_materialize_item(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Result<String, StacksError> {
+    let (meta, content) = state
+        .with_lock(|state| {
+            let item = state.view.items.get(&source_id)?;
+            let meta = state.store.get_content_meta(&item.hash)?;
+            let content = state.store.get_content(&item.hash)?;
+            Some((meta, content))
+        })
+        .ok_or(StacksError::not_found(&source_id))?;
+    let path = materialize::materialize(&materialize::dir(), &meta, &content)
+        .map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().to_string())
+}
 
-        if !success {
-            return None;
-        }
-        Some(i)
-    }
+// Copies the item's content as it's stored, without any stack transform or paste rule, in each
+// representation it supports: text, image or file URL
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_copy_item(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Option<()> {
+    state.with_lock(|state| {
+        let item = state.view.items.get(&source_id)?;
+        let meta = state.store.get_content_meta(&item.hash)?;
+        let content = state.store.get_content(&item.hash)?;
+        let types = paste::representations(
+            &paste::PasteFormat::Plain,
+            &state.ui.theme.syntax,
+            &meta,
+            &content,
+        );
+        let types: Vec<_> = types
+            .iter()
+            .map(|(mime_type, data)| (*mime_type, data.as_slice()))
+            .collect();
+        let _change_num = write_types_to_clipboard(&types);
+        state.record_usage(source_id, Access::Copy);
+        Some(())
+    })
 }
 
 #[tauri::command]
@@ -743,16 +1124,9 @@ pub fn store_copy_to_clipboard(
     source_id: scru128::Scru128Id,
 ) -> Option<()> {
     state.with_lock(|state| {
-        if let Some(item) = state.view.items.get(&source_id) {
-            let meta = state.store.get_content_meta(&item.hash).unwrap();
-
-            let mime_type = match &meta.mime_type {
-                MimeType::TextPlain => "public.utf8-plain-text",
-                MimeType::ImagePng => "public.png",
-            };
-            let content = state.store.get_content(&item.hash).unwrap();
-
-            let _change_num = write_to_clipboard(mime_type, &content);
+        if state.view.items.contains_key(&source_id) {
+            let _change_num = copy_to_clipboard(state, &source_id);
+            state.record_usage(source_id, Access::Copy);
             Some(())
         } else {
             None
@@ -761,377 +1135,2026 @@ pub fn store_copy_to_clipboard(
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_new_note(
-    app: tauri::AppHandle,
+#[tracing::instrument(skip(state))]
+pub fn store_copy_color_as(
     state: tauri::State<SharedState>,
-    content: String,
-    stack_id: Option<scru128::Scru128Id>,
-) {
+    source_id: scru128::Scru128Id,
+    format: String,
+) -> Option<String> {
     state.with_lock(|state| {
-        let stack_id = stack_id.unwrap_or_else(|| state.get_curr_stack());
-
-        let packet = state
-            .store
-            .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+        let item = state.view.items.get(&source_id)?;
+        let content = state.store.get_content(&item.hash)?;
+        let color = color::parse(&String::from_utf8_lossy(&content))?;
+        let converted = color.format(&format)?;
+        let _change_num = write_to_clipboard("public.utf8-plain-text", converted.as_bytes());
+        Some(converted)
+    })
+}
 
-        let id = packet.id;
-        state.merge(&packet);
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_copy_contact_field(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    field: String,
+) -> Option<String> {
+    state.with_lock(|state| {
+        let item = state.view.items.get(&source_id)?;
+        let contact = state.store.contact_get(&item.hash)?;
+        let value = contact.field(&field)?;
+        let _change_num = write_to_clipboard("public.utf8-plain-text", value.as_bytes());
+        Some(value)
+    })
+}
 
-        let focus = state.view.get_focus_for_id(&id);
-        state.ui.select(focus);
+fn item_addresses(state: &State, source_id: &Scru128Id) -> Vec<address::Address> {
+    state
+        .view
+        .items
+        .get(source_id)
+        .and_then(|item| state.store.get_content(&item.hash))
+        .map(|content| address::find(&String::from_utf8_lossy(&content)))
+        .unwrap_or_default()
+}
 
-        state.skip_change_num = write_to_clipboard("public.utf8-plain-text", content.as_bytes());
-    });
-    app.emit_all("refresh-items", true).unwrap();
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_addresses(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Vec<address::Address> {
+    state.with_lock(|state| item_addresses(state, &source_id))
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app))]
-pub fn store_win_move(app: tauri::AppHandle) {
-    let win = app.get_window("main").unwrap();
-    // use tauri_plugin_positioner::{Position, WindowExt};
-    // let _ = win.move_window(Position::TopRight);
-    win.set_size(tauri::PhysicalSize::new(1954, 978)).unwrap();
-    win.set_position(tauri::PhysicalPosition::new(722, 678))
-        .unwrap();
+#[tracing::instrument(skip(state))]
+pub fn store_copy_address(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    index: usize,
+) -> Option<String> {
+    state.with_lock(|state| {
+        let address = item_addresses(state, &source_id).into_iter().nth(index)?;
+        let _change_num =
+            write_to_clipboard("public.utf8-plain-text", address.normalized.as_bytes());
+        Some(address.normalized)
+    })
 }
 
+// Adds a map link for the address as a new item in the item's stack. provider is one of: apple
+// or google
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_edit_note(
+pub fn store_address_map_link(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
-    content: String,
-) {
-    state.with_lock(|state| {
-        let meta = state
-            .view
-            .items
-            .get(&source_id)
-            .and_then(|source| state.store.get_content_meta(&source.hash));
-        if meta.is_none() {
-            tracing::warn!("source or meta not found");
-            return;
-        }
-        let meta = meta.unwrap();
+    index: usize,
+    provider: String,
+) -> Option<Scru128Id> {
+    let id = state.with_lock(|state| {
+        let address = item_addresses(state, &source_id).into_iter().nth(index)?;
+        let link = address.map_link(&provider)?;
+        let stack_id = state.view.items.get(&source_id)?.stack_id?;
 
-        let packet = state.store.update(
-            source_id,
-            Some(content.as_bytes()),
-            MimeType::TextPlain,
-            None,
-        );
+        let packet = state
+            .store
+            .add(link.as_bytes(), MimeType::TextPlain, stack_id);
         state.merge(&packet);
+        Some(packet.id)
+    })?;
+    events::emit(&app, "refresh-items", true);
+    Some(id)
+}
 
-        if let Some(hash) = packet.hash {
-            if meta.content_type != "Text" {
-                let packet = state.store.update_content_type(hash, meta.content_type);
-                state.merge(&packet);
-            }
-        }
+fn item_phones(state: &State, source_id: &Scru128Id) -> Vec<phone::Phone> {
+    state
+        .view
+        .items
+        .get(source_id)
+        .and_then(|item| state.store.get_content(&item.hash))
+        .map(|content| phone::find(&String::from_utf8_lossy(&content)))
+        .unwrap_or_default()
+}
 
-        let focus = state.view.get_focus_for_id(&source_id);
-        state.ui.select(focus);
-        state.skip_change_num = write_to_clipboard("public.utf8-plain-text", content.as_bytes());
-    });
-    app.emit_all("refresh-items", true).unwrap();
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_phone_numbers(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Vec<phone::Phone> {
+    state.with_lock(|state| item_phones(state, &source_id))
 }
 
+// Copies the number in E.164 form
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_touch(
-    app: tauri::AppHandle,
+#[tracing::instrument(skip(state))]
+pub fn store_copy_phone(
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
-) {
+    index: usize,
+) -> Option<String> {
     state.with_lock(|state| {
-        let packet = state.store.update_touch(source_id);
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+        let e164 = item_phones(state, &source_id)
+            .into_iter()
+            .nth(index)?
+            .e164?;
+        let _change_num = write_to_clipboard("public.utf8-plain-text", e164.as_bytes());
+        Some(e164)
+    })
 }
 
+// Dials the number. via is one of: tel, facetime or facetime-audio
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_set_content_type(
+pub fn store_phone_dial(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
-    hash: ssri::Integrity,
-    content_type: String,
-) {
-    state.with_lock(|state| {
-        let packet = state.store.update_content_type(
-            hash.clone(),
-            if content_type == "Plain Text" {
-                "Text".to_string()
-            } else {
-                content_type
-            },
-        );
-        state.merge(&packet);
-    });
-    app.emit_all("content", hash).unwrap();
+    source_id: scru128::Scru128Id,
+    index: usize,
+    via: String,
+) -> Result<(), StacksError> {
+    let link = state
+        .with_lock(|state| {
+            let phone = item_phones(state, &source_id).into_iter().nth(index)?;
+            phone.dial_link(&via)
+        })
+        .ok_or(StacksError::invalid_input("no dialable number"))?;
+    tauri::api::shell::open(&app.shell_scope(), &link, None).map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_set_theme_mode(app: tauri::AppHandle, state: tauri::State<SharedState>, mode: String) {
-    state.with_lock(|state| {
-        state.ui.theme_mode = mode;
-    });
-    app.emit_all("refresh-items", true).unwrap();
+#[tracing::instrument(skip(state))]
+pub fn store_item_source(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Option<String> {
+    state.with_lock(|state| state.store.source_get(&source_id))
 }
 
+// Sensitive items, e.g. passwords, can be cleared from the clipboard after they're pasted: see
+// clear_sensitive_after_secs. Transient clips are marked sensitive as they're captured.
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_delete(
+pub fn store_set_sensitive(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
-    id: scru128::Scru128Id,
+    source_id: scru128::Scru128Id,
+    sensitive: bool,
 ) {
-    state.with_lock(|state| {
-        let packet = state.store.delete(id);
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+    // marking an item sensitive doesn't change it, so items in read-only stacks can be too
+    state.with_lock(|state| state.store.sensitive_set(source_id, sensitive));
+    events::emit(&app, "refresh-items", true);
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_undo(app: tauri::AppHandle, state: tauri::State<SharedState>) {
-    state.with_lock(|state| {
-        if let Some(item) = state.view.undo.clone() {
-            state.store.remove_packet(&item.last_touched);
-            let mut view = View::new();
-            state.store.scan().for_each(|p| view.merge(&p));
-            let mut ui = UI::new(&view);
-            ui.select(view.get_focus_for_id(&item.id));
-            state.view = view;
-            state.ui = ui;
-        }
-    });
-    app.emit_all("refresh-items", true).unwrap();
+#[tracing::instrument(skip(state))]
+pub fn store_item_sensitive(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> bool {
+    state.with_read(|state| state.store.is_sensitive(&source_id))
 }
 
-//
-// Settings related commands
-
+// The text captures from a source, e.g. a URL or file path, with the changes between each
 #[tauri::command]
 #[tracing::instrument(skip(state))]
-pub fn store_settings_save(state: tauri::State<SharedState>, settings: Settings) {
+pub fn store_timeline(state: tauri::State<SharedState>, source: String) -> Vec<timeline::Version> {
     state.with_lock(|state| {
-        state.store.settings_save(settings);
-    });
+        let captures = state
+            .store
+            .source_items(&source)
+            .into_iter()
+            .filter_map(|id| {
+                let item = state.view.items.get(&id)?;
+                let meta = state.store.get_content_meta(&item.hash)?;
+                if meta.mime_type != MimeType::TextPlain {
+                    return None;
+                }
+                let content = state.store.get_content(&item.hash)?;
+                Some((
+                    id,
+                    item.hash.clone(),
+                    String::from_utf8_lossy(&content).to_string(),
+                ))
+            })
+            .collect();
+        timeline::build(captures)
+    })
 }
 
+// Compares two text items, as a line diff unless words are asked for
 #[tauri::command]
 #[tracing::instrument(skip(state))]
-pub fn store_settings_get(state: tauri::State<SharedState>) -> Option<Settings> {
-    state.with_lock(|state| state.store.settings_get())
+pub fn store_diff_items(
+    state: tauri::State<SharedState>,
+    id_a: Scru128Id,
+    id_b: Scru128Id,
+    granularity: Option<diff::Granularity>,
+) -> Result<diff::Diff, StacksError> {
+    Ok(state
+        .with_lock(|state| diff::items(state, &id_a, &id_b, granularity.unwrap_or_default()))?)
 }
 
-//
-// Stack related commands
+// How many items were copied in each hour or day of the range, by source and mime type
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_activity_timeline(
+    state: tauri::State<SharedState>,
+    range: activity::Range,
+    bucket: activity::Bucket,
+) -> Vec<activity::Period> {
+    state.with_lock(|state| activity::timeline(state, range, bucket))
+}
+
+// Relative dates, e.g. "tomorrow", are resolved against when the item was copied
+fn item_event(state: &State, source_id: &Scru128Id) -> Option<calendar::Event> {
+    let item = state.view.items.get(source_id)?;
+    let content = state.store.get_content(&item.hash)?;
+    let copied_at = Local
+        .timestamp_millis_opt(item.id.timestamp() as i64)
+        .single()?;
+    calendar::parse(&String::from_utf8_lossy(&content), copied_at.naive_local())
+}
 
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_event_get(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Option<calendar::Event> {
+    state.with_lock(|state| item_event(state, &source_id))
+}
+
+// Opens the event as an .ics file, which the calendar app offers to import
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_add_to_stack(
+pub fn store_event_add_to_calendar(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
-    stack_id: scru128::Scru128Id,
     source_id: scru128::Scru128Id,
-) {
-    state.with_lock(|state| {
-        let packet = state
-            .store
-            .fork(source_id, None, MimeType::TextPlain, Some(stack_id));
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+) -> Result<(), StacksError> {
+    let event = state
+        .with_lock(|state| item_event(state, &source_id))
+        .ok_or(StacksError::invalid_input("item isn't an event"))?;
+    let ics = event.to_ics(&format!("{}@stacks", source_id), Utc::now());
+
+    let path = std::env::temp_dir().join(format!("{}.ics", source_id));
+    std::fs::write(&path, ics).map_err(|e| e.to_string())?;
+    tauri::api::shell::open(&app.shell_scope(), path.to_string_lossy(), None)
+        .map_err(|e| e.to_string())?;
+    Ok(())
 }
 
+// Adds the event, as ICS, as a new item in the event's stack
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_new_stack(app: tauri::AppHandle, state: tauri::State<SharedState>, name: String) {
-    state.with_lock(|state| {
+pub fn store_event_to_ics(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Option<Scru128Id> {
+    let id = state.with_lock(|state| {
+        let event = item_event(state, &source_id)?;
+        let stack_id = state.view.items.get(&source_id)?.stack_id?;
+        let ics = event.to_ics(&format!("{}@stacks", source_id), Utc::now());
+
         let packet = state
             .store
-            .add_stack(name.as_bytes(), StackLockStatus::Unlocked);
+            .add(ics.as_bytes(), MimeType::TextPlain, stack_id);
         state.merge(&packet);
-        state.ui.select(None); // focus first
-    });
-    app.emit_all("refresh-items", true).unwrap();
+        Some(packet.id)
+    })?;
+    events::emit(&app, "refresh-items", true);
+    Some(id)
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_add_to_new_stack(
-    app: tauri::AppHandle,
+#[tracing::instrument(skip(state))]
+pub fn store_paste_with_transform(
     state: tauri::State<SharedState>,
-    name: String,
     source_id: scru128::Scru128Id,
-) {
+    transform: Transform,
+) -> Option<String> {
     state.with_lock(|state| {
-        let stack_packet = state
-            .store
-            .add_stack(name.as_bytes(), StackLockStatus::Locked);
-        state.merge(&stack_packet);
+        let item = state.view.items.get(&source_id)?;
+        let meta = state.store.get_content_meta(&item.hash)?;
+        if meta.mime_type != MimeType::TextPlain {
+            return None;
+        }
+        let content = state.store.get_content(&item.hash)?;
+        let transformed = paste::transform(&transform, &String::from_utf8_lossy(&content))?;
+        // the transformed variant is transient: don't capture it back into the store
+        state.skip_change_num =
+            write_to_clipboard("public.utf8-plain-text", transformed.as_bytes());
+        state.record_usage(source_id, Access::Paste);
+        Some(transformed)
+    })
+}
 
-        let item_packet =
-            state
-                .store
-                .fork(source_id, None, MimeType::TextPlain, Some(stack_packet.id));
-        state.merge(&item_packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_clipboard_at(
+    state: tauri::State<SharedState>,
+    timestamp: u64,
+    restore: bool,
+) -> Option<ContentMeta> {
+    state.with_lock(|state| {
+        let packet = state.store.clipboard_at(timestamp)?;
+        let hash = packet.hash?;
+        let meta = state.store.get_content_meta(&hash)?;
+
+        if restore {
+            let content = state.store.get_content(&hash)?;
+            let types = paste::representations(
+                &paste::PasteFormat::Plain,
+                &state.ui.theme.syntax,
+                &meta,
+                &content,
+            );
+            let types: Vec<_> = types
+                .iter()
+                .map(|(mime_type, data)| (*mime_type, data.as_slice()))
+                .collect();
+            // we don't set skip_change_num, so the restored clip is captured to the top of the
+            // current stack, as if the user had just copied it again
+            let _change_num = write_types_to_clipboard(&types);
+        }
+
+        Some(meta)
+    })
 }
 
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_move_up(
+pub fn store_new_note(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
-    source_id: scru128::Scru128Id,
+    content: String,
+    stack_id: Option<scru128::Scru128Id>,
 ) {
     state.with_lock(|state| {
-        let packet = state.store.update_move(source_id, Movement::Up);
+        let stack_id = stack_id.unwrap_or_else(|| state.get_curr_stack());
+
+        let packet = state
+            .store
+            .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+
+        let id = packet.id;
         state.merge(&packet);
+
+        let focus = state.view.get_focus_for_id(&id);
+        state.ui.select(focus);
+
+        state.skip_change_num = write_to_clipboard("public.utf8-plain-text", content.as_bytes());
     });
-    app.emit_all("refresh-items", true).unwrap();
+    events::emit(&app, "refresh-items", true);
 }
 
+// Adds an item to another stack without copying it: the item shows in both stacks, and edits to
+// it show in both. Unlinking removes it from the linked stack only.
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_mark_as_cross_stream(
+pub fn store_link_item(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
     stack_id: scru128::Scru128Id,
+    linked: bool,
 ) {
     state.with_lock(|state| {
-        let packet = state.store.mark_as_cross_stream(stack_id);
+        if state.is_read_only(&stack_id) {
+            return;
+        }
+        let packet = state.store.link(source_id, stack_id, linked);
         state.merge(&packet);
     });
-    app.emit_all("refresh-items", true).unwrap();
+    events::emit(&app, "refresh-items", true);
 }
 
+// Attaches a note to an item, e.g. why it was saved. Notes are matched by the filter, like the
+// item's content. An empty note removes it.
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_move_down(
+pub fn store_set_note(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
+    text: String,
 ) {
     state.with_lock(|state| {
-        let packet = state.store.update_move(source_id, Movement::Down);
-        state.merge(&packet);
+        if !state.is_read_only(&source_id) {
+            state.store.note_set(source_id, &text);
+        }
     });
-    app.emit_all("refresh-items", true).unwrap();
+    events::emit(&app, "refresh-items", true);
 }
 
+// Concatenates the text items, in the order given, into a new item in the current stack. Items
+// which aren't text are skipped. Returns the new item's id.
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_stack_lock(
+pub fn store_merge_items(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
-    source_id: scru128::Scru128Id,
-) {
-    state.with_lock(|state| {
-        let packet = state
-            .store
-            .update_stack_lock_status(source_id, StackLockStatus::Locked);
+    ids: Vec<scru128::Scru128Id>,
+    separator: Option<String>,
+    delete_sources: bool,
+) -> Option<scru128::Scru128Id> {
+    let separator = separator.unwrap_or_else(|| "\n".to_string());
+    let id = state.with_lock(|state| {
+        let mut merged = Vec::new();
+        let mut sources = Vec::new();
+        for id in &ids {
+            let Some(item) = state.view.items.get(id) else {
+                continue;
+            };
+            let is_text = state
+                .store
+                .get_content_meta(&item.hash)
+                .is_some_and(|meta| meta.mime_type == MimeType::TextPlain);
+            if item.is_stack || !is_text {
+                continue;
+            }
+            let Some(content) = state.store.get_content(&item.hash) else {
+                continue;
+            };
+            merged.push(String::from_utf8_lossy(&content).to_string());
+            sources.push(*id);
+        }
+        if merged.is_empty() {
+            return None;
+        }
+
+        let stack_id = state.get_curr_stack();
+        let packet = state.store.add(
+            merged.join(&separator).as_bytes(),
+            MimeType::TextPlain,
+            stack_id,
+        );
         state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+
+        if delete_sources {
+            sources.retain(|id| !state.is_read_only(id));
+            for source_id in sources {
+                let packet = state.store.delete(source_id);
+                state.merge(&packet);
+            }
+        }
+
+        let focus = state.view.get_focus_for_id(&packet.id);
+        state.ui.select(focus);
+        Some(packet.id)
+    })?;
+    events::emit(&app, "refresh-items", true);
+    Some(id)
 }
 
+// Adds an item per segment of a text item to the target stack, which defaults to the item's own
+// stack. Segments are added in order, so sequential paste pastes them first to last.
 #[tauri::command]
 #[tracing::instrument(skip(app, state))]
-pub fn store_stack_unlock(
+pub fn store_split_item(
     app: tauri::AppHandle,
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
-) {
-    state.with_lock(|state| {
-        let packet = state
+    delimiter: split::Delimiter,
+    stack_id: Option<scru128::Scru128Id>,
+) -> Result<Vec<scru128::Scru128Id>, StacksError> {
+    let ids = state.with_lock(|state| {
+        let item = state
+            .view
+            .items
+            .get(&source_id)
+            .ok_or(StacksError::not_found(&source_id))?;
+        let meta = state
             .store
-            .update_stack_lock_status(source_id, StackLockStatus::Unlocked);
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+            .get_content_meta(&item.hash)
+            .ok_or(StacksError::content_not_found(&source_id))?;
+        if meta.mime_type != MimeType::TextPlain {
+            return Err(StacksError::invalid_input("only text items can be split"));
+        }
+        let stack_id = stack_id.or(item.stack_id).ok_or(StacksError::new(
+            ErrorKind::NotFound,
+            "target stack not found",
+        ))?;
+        if state.is_read_only(&stack_id) {
+            return Err(StacksError::read_only(&stack_id));
+        }
+        let content = state
+            .store
+            .get_content(&item.hash)
+            .ok_or(StacksError::content_not_found(&source_id))?;
+        let segments = split::split(&String::from_utf8_lossy(&content), &delimiter)
+            .map_err(|e| e.to_string())?;
+
+        let mut ids = Vec::new();
+        for segment in segments {
+            let packet = state
+                .store
+                .add(segment.as_bytes(), MimeType::TextPlain, stack_id);
+            state.merge(&packet);
+            ids.push(packet.id);
+        }
+        Ok(ids)
+    })?;
+    events::emit(&app, "refresh-items", true);
+    Ok(ids)
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_stack_sort_manual(
-    app: tauri::AppHandle,
+#[tracing::instrument(skip(state))]
+pub fn store_snippet_fields(
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
-) {
+) -> Option<Vec<String>> {
     state.with_lock(|state| {
-        let packet = state
-            .store
-            .update_stack_sort_order(source_id, StackSortOrder::Manual);
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+        let item = state.view.items.get(&source_id)?;
+        let content = state.store.get_content(&item.hash)?;
+        Some(snippet::fields(&String::from_utf8_lossy(&content)))
+    })
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app, state))]
-pub fn store_stack_sort_auto(
-    app: tauri::AppHandle,
+#[tracing::instrument(skip(state))]
+pub fn store_expand_snippet(
     state: tauri::State<SharedState>,
     source_id: scru128::Scru128Id,
-) {
+    fields: HashMap<String, String>,
+    copy: bool,
+) -> Option<snippet::Expanded> {
     state.with_lock(|state| {
-        let packet = state
+        let item = state.view.items.get(&source_id)?;
+        let content = state.store.get_content(&item.hash)?;
+        let template = String::from_utf8_lossy(&content);
+
+        let now = chrono::Local::now();
+        let clipboard = state
             .store
-            .update_stack_sort_order(source_id, StackSortOrder::Auto);
-        state.merge(&packet);
-    });
-    app.emit_all("refresh-items", true).unwrap();
+            .clipboard_at(now.timestamp_millis() as u64)
+            .and_then(|packet| packet.hash)
+            .and_then(|hash| {
+                let meta = state.store.get_content_meta(&hash)?;
+                if meta.mime_type != MimeType::TextPlain {
+                    return None;
+                }
+                state.store.get_content(&hash)
+            })
+            .map(|content| String::from_utf8_lossy(&content).into_owned());
+
+        let expanded = snippet::expand(&template, &fields, clipboard.as_deref(), now);
+
+        if copy {
+            // the expansion is transient: don't capture it back into the store
+            state.skip_change_num =
+                write_to_clipboard("public.utf8-plain-text", expanded.text.as_bytes());
+        }
+
+        Some(expanded)
+    })
 }
 
-//
-// Spotlight related commands
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_calc_copy(state: tauri::State<SharedState>) -> Option<String> {
+    state.with_lock(|state| {
+        let result = state.ui.calc.clone()?;
+        let _change_num = write_to_clipboard("public.utf8-plain-text", result.as_bytes());
+        Some(result)
+    })
+}
 
 #[tauri::command]
-#[tracing::instrument(skip(state, app))]
-pub fn spotlight_update_shortcut(
-    state: tauri::State<'_, SharedState>,
-    app: tauri::AppHandle,
-    shortcut: Shortcut,
-) {
+#[tracing::instrument(skip(app, state))]
+pub fn store_calc_persist(app: tauri::AppHandle, state: tauri::State<SharedState>) {
     state.with_lock(|state| {
-        let mut settings = state.store.settings_get().unwrap_or_default();
-        settings.activation_shortcut = Some(shortcut.clone());
-        state.store.settings_save(settings);
+        if let Some(result) = state.ui.calc.clone() {
+            let stack_id = state.get_curr_stack();
+            let packet = state
+                .store
+                .add(result.as_bytes(), MimeType::TextPlain, stack_id);
+            state.merge(&packet);
+        }
     });
-    let window = app.get_window("main").unwrap();
-    spotlight::register_shortcut(&window, &shortcut.to_macos_shortcut()).unwrap();
+    events::emit(&app, "refresh-items", true);
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(state))]
-pub fn spotlight_get_shortcut(state: tauri::State<'_, SharedState>) -> Shortcut {
+#[tracing::instrument(skip(app))]
+pub fn store_win_move(app: tauri::AppHandle) {
+    let win = app.get_window("main").unwrap();
+    // use tauri_plugin_positioner::{Position, WindowExt};
+    // let _ = win.move_window(Position::TopRight);
+    win.set_size(tauri::PhysicalSize::new(1954, 978)).unwrap();
+    win.set_position(tauri::PhysicalPosition::new(722, 678))
+        .unwrap();
+}
+
+// Stores the edited content as a new version of the item, keeping the item's content type.
+// Earlier versions stay in the store: see store_item_history. Only text items can be edited.
+pub fn edit_item(state: &mut State, source_id: Scru128Id, content: &[u8]) -> Option<()> {
+    let meta = state
+        .view
+        .items
+        .get(&source_id)
+        .and_then(|source| state.store.get_content_meta(&source.hash));
+    if meta.is_none() {
+        tracing::warn!("source or meta not found");
+        return None;
+    }
+    let meta = meta.unwrap();
+    if meta.mime_type != MimeType::TextPlain {
+        tracing::warn!("only text items can be edited");
+        return None;
+    }
+    store_version(state, source_id, content, meta)
+}
+
+// Stores content as the newest version of the item, with the mime and content type in meta
+fn store_version(
+    state: &mut State,
+    source_id: Scru128Id,
+    content: &[u8],
+    meta: ContentMeta,
+) -> Option<()> {
+    if state.is_read_only(&source_id) {
+        tracing::warn!("item is read-only");
+        return None;
+    }
+
+    let packet = state
+        .store
+        .update(source_id, Some(content), meta.mime_type, None);
+    state.merge(&packet);
+
+    if let Some(hash) = packet.hash {
+        if meta.content_type != "Text" {
+            let packet = state.store.update_content_type(hash, meta.content_type);
+            state.merge(&packet);
+        }
+    }
+
+    let focus = state.view.get_focus_for_id(&source_id);
+    state.ui.select(focus);
+    Some(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_edit_note(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    content: String,
+) {
     state.with_lock(|state| {
-        let settings = state.store.settings_get();
-        settings
-            .and_then(|s| s.activation_shortcut)
-            .unwrap_or(Shortcut {
-                ctrl: true,
-                shift: false,
-                alt: false,
-                command: false,
-            })
-    })
+        if edit_item(state, source_id, content.as_bytes()).is_some() {
+            state.skip_change_num =
+                write_to_clipboard("public.utf8-plain-text", content.as_bytes());
+        }
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+// Like store_edit_note, but the edit isn't also copied to the clipboard
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_edit_item(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    content: String,
+) -> Option<()> {
+    state.with_lock(|state| edit_item(state, source_id, content.as_bytes()))?;
+    events::emit(&app, "refresh-items", true);
+    Some(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_item_history(
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Vec<ItemVersion> {
+    state.with_lock(|state| state.store.history(&source_id))
+}
+
+// Restores an earlier version by storing its content as the newest version, so the restore can
+// itself be undone from the history
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_item_history_restore(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    version_id: scru128::Scru128Id,
+) -> Option<()> {
+    state.with_lock(|state| {
+        let version = state
+            .store
+            .history(&source_id)
+            .into_iter()
+            .find(|version| version.id == version_id)?;
+        let content = state.store.get_content(&version.hash)?;
+        // restored as the version's own mime type, so images stay images
+        let meta = state.store.get_content_meta(&version.hash)?;
+        store_version(state, source_id, &content, meta)
+    })?;
+    events::emit(&app, "refresh-items", true);
+    Some(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_touch(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_touch(source_id);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_set_content_type(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    hash: ssri::Integrity,
+    content_type: String,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_content_type(
+            hash.clone(),
+            if content_type == "Plain Text" {
+                "Text".to_string()
+            } else {
+                content_type
+            },
+        );
+        state.merge(&packet);
+    });
+    events::emit(&app, "content", hash);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_set_theme_mode(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    mode: String,
+) -> Theme {
+    let theme = state.with_lock(|state| {
+        state.ui.theme_mode = mode;
+        let settings = state.store.settings_get().unwrap_or_default();
+        state.ui.theme = theme::active(&settings, &state.ui.theme_mode);
+        state.ui.theme.clone()
+    });
+    events::emit(&app, "refresh-items", true);
+    theme
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_themes(state: tauri::State<SharedState>) -> Vec<Theme> {
+    state.with_lock(|state| theme::all(&state.store.settings_get().unwrap_or_default()))
+}
+
+// Chooses the theme previews are rendered with, by name, or None to follow the app's mode
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_set_theme(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    name: Option<String>,
+) -> Result<Theme, StacksError> {
+    let theme = state.with_lock(|state| {
+        let mut settings = state.store.settings_get().unwrap_or_default();
+        if let Some(name) = &name {
+            if !theme::all(&settings)
+                .iter()
+                .any(|theme| &theme.name == name)
+            {
+                return Err(StacksError::new(
+                    ErrorKind::NotFound,
+                    format!("no such theme: {}", name),
+                ));
+            }
+        }
+        settings.preview_theme = name;
+        state.ui.theme = theme::active(&settings, &state.ui.theme_mode);
+        state.store.settings_save(settings);
+        Ok(state.ui.theme.clone())
+    })?;
+    events::emit(&app, "theme", &theme);
+    events::emit(&app, "refresh-items", true);
+    Ok(theme)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_delete(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if state.is_read_only(&id) {
+            return;
+        }
+        let packet = state.store.delete(id);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+// Applies the ops for a multi-select action, with a single refresh. If any op can't be applied,
+// none are.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_batch(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    ops: Vec<batch::Op>,
+) -> Result<usize, batch::BatchError> {
+    let applied = state.with_lock(|state| batch::apply(state, &ops))?;
+    events::emit(&app, "refresh-items", true);
+    Ok(applied)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_undo(app: tauri::AppHandle, state: tauri::State<SharedState>) {
+    state.with_lock(|state| {
+        if let Some(item) = state.view.undo.clone() {
+            state.store.remove_packet(&item.last_touched);
+            let mut view = View::new();
+            state.store.scan().for_each(|p| view.merge(&p));
+            let mut ui = state.ui.renew(&view);
+            ui.select(view.get_focus_for_id(&item.id));
+            state.view = view;
+            state.ui = ui;
+        }
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+//
+// Settings related commands
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_settings_save(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    settings: Settings,
+) {
+    let tokenizer_changed = state.with_lock(|state| {
+        let before = state.store.tokenizer_get();
+        state.store.settings_save(settings);
+        state.refresh_rank();
+        state.store.tokenizer_get() != before
+    });
+    if tokenizer_changed {
+        content_bus::recount_tiktokens(app.clone(), state.inner().clone());
+    }
+
+    // pick up any changes to the HTTP server's configuration
+    http::start(app, state.inner().clone());
+}
+
+// Where the HTTP server is listening, which may not be the configured port if it was taken. The
+// server only runs with http_enabled set: otherwise it isn't listening anywhere.
+#[tauri::command]
+pub fn store_server_info() -> Option<serde_json::Value> {
+    serde_json::to_value(http::info()).ok()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_settings_get(state: tauri::State<SharedState>) -> Option<Settings> {
+    state.with_read(|state| state.store.settings_get())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_paste_rules_get(state: tauri::State<SharedState>) -> Vec<PasteRule> {
+    state.with_read(|state| {
+        state
+            .store
+            .settings_get()
+            .and_then(|s| s.paste_rules)
+            .unwrap_or_default()
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_paste_rules_set(state: tauri::State<SharedState>, rules: Vec<PasteRule>) {
+    state.with_lock(|state| {
+        let mut settings = state.store.settings_get().unwrap_or_default();
+        settings.paste_rules = Some(rules);
+        state.store.settings_save(settings);
+    });
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_stack_set_paste_transform(
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    transform: Option<Transform>,
+) {
+    state.with_lock(|state| {
+        let mut settings = state.store.settings_get().unwrap_or_default();
+        let mut transforms = settings.stack_transforms.unwrap_or_default();
+        transforms.retain(|t| t.stack_id != stack_id);
+        if let Some(transform) = transform {
+            transforms.push(StackTransform {
+                stack_id,
+                transform,
+            });
+        }
+        settings.stack_transforms = Some(transforms);
+        state.store.settings_save(settings);
+    });
+}
+
+//
+// Stack related commands
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_add_to_stack(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if state.is_read_only(&stack_id) {
+            return;
+        }
+        let packet = state
+            .store
+            .fork(source_id, None, MimeType::TextPlain, Some(stack_id));
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_new_stack(app: tauri::AppHandle, state: tauri::State<SharedState>, name: String) {
+    state.with_lock(|state| {
+        let packet = state
+            .store
+            .add_stack(name.as_bytes(), StackLockStatus::Unlocked);
+        state.merge(&packet);
+        state.ui.select(None); // focus first
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_add_to_new_stack(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    name: String,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let stack_packet = state
+            .store
+            .add_stack(name.as_bytes(), StackLockStatus::Locked);
+        state.merge(&stack_packet);
+
+        let item_packet =
+            state
+                .store
+                .fork(source_id, None, MimeType::TextPlain, Some(stack_packet.id));
+        state.merge(&item_packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+// All the stacks, as a tree of nested stacks, e.g. to pick where to move a stack
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_stack_tree(state: tauri::State<SharedState>) -> Vec<StackTree> {
+    state.with_lock(|state| stack_tree(&state.store, &state.view))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_nest(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    parent_id: Option<scru128::Scru128Id>,
+) {
+    state.with_lock(|state| {
+        let into_read_only = parent_id.is_some_and(|id| state.is_read_only(&id));
+        if into_read_only || state.is_read_only(&source_id) {
+            return;
+        }
+        let packet = state.store.nest_stack(source_id, parent_id);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+pub fn store_stack_settings_get(
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) -> StackSettings {
+    state.with_lock(|state| state.store.stack_settings_get(&stack_id))
+}
+
+// A new retention policy is applied straight away, rather than at the next periodic check
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_settings_set(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    settings: StackSettings,
+) {
+    state.with_lock(|state| {
+        state.store.stack_settings_set(stack_id, &settings);
+        stack_settings::enforce_retention(state, privacy::now());
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_move_up(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if state.is_read_only(&source_id) {
+            return;
+        }
+        let packet = state.store.update_move(source_id, Movement::Up);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_mark_as_cross_stream(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.mark_as_cross_stream(stack_id);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_move_down(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        if state.is_read_only(&source_id) {
+            return;
+        }
+        let packet = state.store.update_move(source_id, Movement::Down);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+// A read-only stack is also protected from having its items deleted or edited. It stays read-only
+// until it's unlocked with store_stack_unlock.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_lock(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+    read_only: Option<bool>,
+) {
+    state.with_lock(|state| {
+        if state.is_read_only(&source_id) {
+            return;
+        }
+        let lock_status = if read_only.unwrap_or(false) {
+            StackLockStatus::ReadOnly
+        } else {
+            StackLockStatus::Locked
+        };
+        let packet = state.store.update_stack_lock_status(source_id, lock_status);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+fn unlock_requires_auth(state: &State, source_id: &Scru128Id) -> bool {
+    let read_only = state
+        .view
+        .items
+        .get(source_id)
+        .is_some_and(|item| item.read_only);
+    let enabled = state
+        .store
+        .settings_get()
+        .and_then(|settings| settings.unlock_requires_auth)
+        .unwrap_or(false);
+    read_only && enabled && cfg!(target_os = "macos")
+}
+
+// Unlocking a read-only stack asks the user to authenticate first, if unlock_requires_auth is set
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_stack_unlock(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Result<(), StacksError> {
+    let mut authenticated = false;
+    loop {
+        if !authenticated && state.with_lock(|state| unlock_requires_auth(state, &source_id)) {
+            authenticated = tauri::async_runtime::spawn_blocking(|| {
+                touch_id::authenticate("unlock a read-only stack")
+            })
+            .await?;
+            if !authenticated {
+                return Err(StacksError::permission_denied("authentication failed"));
+            }
+        }
+
+        // the stack may have become read-only while the lock was released: if so, the user is
+        // asked to authenticate after all
+        let unlocked = state.with_lock(|state| {
+            if !authenticated && unlock_requires_auth(state, &source_id) {
+                return false;
+            }
+            let packet = state
+                .store
+                .update_stack_lock_status(source_id, StackLockStatus::Unlocked);
+            state.merge(&packet);
+            true
+        });
+        if unlocked {
+            break;
+        }
+    }
+    events::emit(&app, "refresh-items", true);
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_archive(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_stack_archived(source_id, true);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_restore(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state.store.update_stack_archived(source_id, false);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_stack_list_archived(state: tauri::State<SharedState>) -> Vec<UIItem> {
+    state.with_lock(|state| {
+        let mut archived: Vec<_> = state
+            .view
+            .items
+            .values()
+            .filter(|item| item.archived)
+            .collect();
+        archived.sort_by_key(|item| std::cmp::Reverse(item.last_touched));
+        archived
+            .iter()
+            .map(|item| with_meta(&state.store, item))
+            .collect()
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_nav_show_archived(state: tauri::State<SharedState>, show_archived: bool) -> Nav {
+    state.with_lock(|state| {
+        let view = state.view.clone();
+        state.ui.set_show_archived(&view, show_archived);
+        state.ui.render(&state.store)
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_check_links(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) -> usize {
+    let stack_links = state.with_lock(|state| links::stack_links(state, &stack_id));
+    let count = stack_links.len();
+    links::spawn_check(app, state.inner().clone(), stack_links);
+    count
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_open_links(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    force: bool,
+) -> Result<usize, StacksError> {
+    let stack_links = state.with_lock(|state| links::stack_links(state, &stack_id));
+    if stack_links.len() > links::OPEN_ALL_CONFIRM_THRESHOLD && !force {
+        return Err(StacksError::new(
+            ErrorKind::ConfirmationRequired,
+            format!(
+                "This stack has {} links: confirm to open them all",
+                stack_links.len()
+            ),
+        ));
+    }
+    for link in &stack_links {
+        tauri::api::shell::open(&app.shell_scope(), &link.url, None).map_err(|e| e.to_string())?;
+    }
+    Ok(stack_links.len())
+}
+
+// Creates a read-only token for the stack, for use with the HTTP server
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_stack_share(
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    expires_in_secs: Option<u64>,
+) -> Option<ShareToken> {
+    state.with_lock(|state| {
+        if !state.view.items.get(&stack_id)?.is_stack {
+            return None;
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let share = ShareToken::new(stack_id, now, expires_in_secs);
+
+        let mut tokens = state.store.share_tokens_get();
+        tokens.retain(|t| !t.is_expired(now));
+        tokens.push(share.clone());
+        state.store.share_tokens_save(tokens);
+        Some(share)
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_stack_shares(
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) -> Vec<ShareToken> {
+    state.with_lock(|state| {
+        state
+            .store
+            .share_tokens_get()
+            .into_iter()
+            .filter(|t| t.stack_id == stack_id)
+            .collect()
+    })
+}
+
+// Revokes every token for the stack, returning how many were revoked
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_revoke_share(state: tauri::State<SharedState>, stack_id: scru128::Scru128Id) -> usize {
+    state.with_lock(|state| {
+        let mut tokens = state.store.share_tokens_get();
+        let count = tokens.len();
+        tokens.retain(|t| t.stack_id != stack_id);
+        let revoked = count - tokens.len();
+        state.store.share_tokens_save(tokens);
+        revoked
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_sequential_paste_arm(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+) -> Option<sequential::Progress> {
+    state.with_lock(|state| sequential::arm(&app, state, &stack_id))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_sequential_paste_disarm(app: tauri::AppHandle, state: tauri::State<SharedState>) {
+    state.with_lock(|state| sequential::disarm(&app, state));
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_sequential_paste_progress(
+    state: tauri::State<SharedState>,
+) -> Option<sequential::Progress> {
+    state.with_lock(|state| state.sequential_paste.as_ref().map(|s| s.progress()))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_sort_manual(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state
+            .store
+            .update_stack_sort_order(source_id, StackSortOrder::Manual);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_stack_sort_auto(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) {
+    state.with_lock(|state| {
+        let packet = state
+            .store
+            .update_stack_sort_order(source_id, StackSortOrder::Auto);
+        state.merge(&packet);
+    });
+    events::emit(&app, "refresh-items", true);
+}
+
+//
+// Spotlight related commands
+
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub fn spotlight_update_shortcut(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    shortcut: Shortcut,
+) {
+    state.with_lock(|state| {
+        let mut settings = state.store.settings_get().unwrap_or_default();
+        settings.activation_shortcut = Some(shortcut.clone());
+        state.store.settings_save(settings);
+    });
+    spotlight::register_shortcut(&app, &shortcut.to_macos_shortcut()).unwrap();
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn spotlight_get_shortcut(state: tauri::State<'_, SharedState>) -> Shortcut {
+    state.with_lock(|state| {
+        let settings = state.store.settings_get();
+        settings
+            .and_then(|s| s.activation_shortcut)
+            .unwrap_or_default()
+    })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_agent_get(state: tauri::State<'_, SharedState>) -> Agent {
+    state.with_lock(|state| agent::get(state))
+}
+
+// Sets whether Stacks starts when the user logs in, and whether it starts without the spotlight
+// window. The latter applies from the next start.
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub fn store_agent_set(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    agent: Agent,
+) -> Result<(), StacksError> {
+    Ok(state.with_lock(|state| agent::set(&app, state, agent))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_hotkeys_get(state: tauri::State<'_, SharedState>) -> Vec<Hotkey> {
+    state.with_lock(|state| hotkeys::get(state))
+}
+
+// Saves and registers the hotkeys. Hotkeys which conflict aren't saved; those another app has
+// taken are saved, but the error says which couldn't be registered.
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub fn store_hotkeys_set(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    hotkeys: Vec<Hotkey>,
+) -> Result<(), StacksError> {
+    state.with_lock(|state| hotkeys::set(state, hotkeys.clone()))?;
+    Ok(hotkeys::register(&app, &hotkeys)?)
+}
+
+// Binds quick paste to the modifiers and 1 to 9, e.g. "Cmd" for Cmd+1..9, which paste the nth item
+// of the stack Stacks is on, as filtered. Without modifiers, quick paste is unbound.
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub fn store_set_quick_paste(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    modifiers: Option<String>,
+) -> Result<(), StacksError> {
+    let hotkeys = state.with_lock(|state| {
+        hotkeys::set_quick_paste(state, modifiers)?;
+        Ok::<_, StacksError>(hotkeys::get(state))
+    })?;
+    Ok(hotkeys::register(&app, &hotkeys)?)
+}
+
+// Sets the hotkey which opens Stacks on the stack, or, with paste, pastes its top item. Without an
+// accelerator, the stack's hotkey is removed.
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub fn store_set_stack_hotkey(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    stack_id: Scru128Id,
+    accelerator: Option<String>,
+    paste: Option<bool>,
+) -> Result<(), StacksError> {
+    let hotkeys = state.with_lock(|state| {
+        hotkeys::set_stack(state, stack_id, accelerator, paste.unwrap_or(false))?;
+        Ok::<_, StacksError>(hotkeys::get(state))
+    })?;
+    Ok(hotkeys::register(&app, &hotkeys)?)
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Diagnostics {
+    pub version: String,
+    pub items: usize,
+    pub packets: usize,
+    // per-route metrics for the HTTP server
+    pub http: HashMap<String, http::RouteMetrics>,
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_diagnostics(app: tauri::AppHandle, state: tauri::State<SharedState>) -> Diagnostics {
+    Diagnostics {
+        version: app.package_info().version.to_string(),
+        items: state.with_lock(|state| state.view.items.len()),
+        packets: state.with_lock(|state| state.store.packet_count()),
+        http: http::metrics(),
+    }
+}
+
+fn start_privacy(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    mode: privacy::Mode,
+    minutes: u64,
+) -> privacy::Privacy {
+    let privacy = privacy::Privacy::new(mode, privacy::now(), minutes);
+    state.with_lock(|state| state.privacy = Some(privacy));
+    events::emit(app, "privacy", Some(privacy));
+    privacy
+}
+
+// Stops capturing clipboard changes for the given number of minutes
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_pause_capture_for(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    minutes: u64,
+) -> privacy::Privacy {
+    start_privacy(&app, &state, privacy::Mode::Pause, minutes)
+}
+
+// For the given number of minutes, clips are captured but purged after expire_after_secs,
+// defaulting to ten minutes
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_capture_expiring_for(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    minutes: u64,
+    expire_after_secs: Option<u64>,
+) -> privacy::Privacy {
+    let after_secs = expire_after_secs.unwrap_or(privacy::DEFAULT_EXPIRE_AFTER_SECS);
+    start_privacy(&app, &state, privacy::Mode::Expire { after_secs }, minutes)
+}
+
+// Ends the privacy mode early. Clips captured while it was on still expire.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_privacy_end(app: tauri::AppHandle, state: tauri::State<SharedState>) {
+    state.with_lock(|state| state.privacy = None);
+    events::emit(&app, "privacy", None::<privacy::Privacy>);
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_privacy_get(state: tauri::State<SharedState>) -> Option<privacy::Privacy> {
+    let now = privacy::now();
+    state.with_lock(|state| state.privacy.filter(|p| p.is_active(now)))
+}
+
+// Walks the whole store, so it's run in the background rather than with each refresh
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub async fn store_stats(
+    state: tauri::State<'_, SharedState>,
+) -> Result<stats::StoreStats, StacksError> {
+    let state = state.inner().clone();
+    Ok(tauri::async_runtime::spawn(async move { stats::compute(&state).await }).await?)
+}
+
+// Checks every blob the store's packets refer to, emitting verify-progress as it goes. With
+// repair, metadata is re-derived where it can be, and packets whose blobs are gone are quarantined.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_verify(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    repair: bool,
+) -> Result<verify::VerifyReport, StacksError> {
+    verify::run(&app, state.inner(), repair).await
+}
+
+// Drops packets which no longer affect the store, e.g. those of deleted items, after backing up
+// the log. Runs regardless of the size threshold automatic compaction waits for.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_compact(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+) -> Result<compact::CompactStats, StacksError> {
+    Ok(compact::run(&app, state.inner(), true).await?)
+}
+
+// Takes a snapshot of the store now, whenever the last one was taken
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub async fn store_backup(
+    state: tauri::State<'_, SharedState>,
+) -> Result<backup::Backup, StacksError> {
+    let backup = backup::run(state.inner(), true).await?;
+    backup.ok_or_else(|| "no snapshot was taken".into())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_backups(state: tauri::State<SharedState>) -> Vec<backup::Backup> {
+    state.with_lock(|state| {
+        let settings = state.store.settings_get();
+        backup::list(&backup::target_dir(&state.store.path, settings.as_ref()))
+    })
+}
+
+// Replaces the store's packets with those of the snapshot at path. The store as it was is
+// snapshotted first, so a restore can itself be undone.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_restore_backup(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    path: String,
+) -> Result<usize, StacksError> {
+    Ok(backup::restore_from(&app, state.inner(), std::path::Path::new(&path)).await?)
+}
+
+// The k items whose text is nearest in meaning to the query, nearest first. Items are only
+// found once they've been embedded, in the background, with semantic_search on.
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub async fn store_semantic_search(
+    state: tauri::State<'_, SharedState>,
+    query: String,
+    k: usize,
+) -> Result<Vec<semantic::Match>, StacksError> {
+    Ok(semantic::search(state.inner(), &query, k).await?)
+}
+
+// Sends the item's content to the configured LLM with the prompt template, or the name of a
+// built-in action, streaming the response into a new item. Returns the new item's id.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_run_llm_action(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    id: scru128::Scru128Id,
+    prompt_template: String,
+) -> Result<scru128::Scru128Id, StacksError> {
+    Ok(actions::run(&app, state.inner(), id, &prompt_template).await?)
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct QrItem {
+    pub id: scru128::Scru128Id,
+    pub content: Content,
+}
+
+// Renders the text item as a QR code, in a new image item next to it, which is returned to preview
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_generate_qr(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    id: scru128::Scru128Id,
+) -> Result<QrItem, StacksError> {
+    let (id, hash) = state.with_lock(|state| {
+        let id = qr::generate(state, &id)?;
+        let hash = state
+            .view
+            .items
+            .get(&id)
+            .ok_or(StacksError::not_found(&id))?
+            .hash
+            .clone();
+        Ok::<_, StacksError>((id, hash))
+    })?;
+    let thumbnail = thumbnail(&state, &hash);
+    let item = state.with_read(|state| {
+        let limits = PreviewLimits::from_settings(state.store.settings_get());
+        QrItem {
+            id,
+            content: content(state, &hash, thumbnail, Some(limits)),
+        }
+    });
+    events::emit(&app, "refresh-items", true);
+    Ok(item)
+}
+
+// The rules clips are routed with as they're captured: see rules
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_rules_get(state: tauri::State<SharedState>) -> Result<Vec<rules::Rule>, StacksError> {
+    Ok(state.with_lock(|state| rules::load(&rules::path(state)))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_rules_set(
+    state: tauri::State<SharedState>,
+    rules: Vec<rules::Rule>,
+) -> Result<(), StacksError> {
+    Ok(state.with_lock(|state| rules::set(state, &rules))?)
+}
+
+// The user's plugins, and whether each may fetch URLs: see plugins
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_plugins(state: tauri::State<SharedState>) -> Vec<plugins::PluginInfo> {
+    state.with_lock(|state| plugins::list(state))
+}
+
+// The actions which apply to the item, built-in and the user's: see registry
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_list_actions(
+    state: tauri::State<SharedState>,
+    id: scru128::Scru128Id,
+) -> Result<Vec<registry::ActionInfo>, StacksError> {
+    Ok(state.with_lock(|state| registry::list_item(state, &id))?)
+}
+
+// Runs the named action on the item. Returns the id of the item the action added, if any.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_run_action(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    id: scru128::Scru128Id,
+    action: String,
+) -> Result<Option<scru128::Scru128Id>, StacksError> {
+    Ok(registry::run(&app, state.inner(), id, &action).await?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_actions_get(
+    state: tauri::State<SharedState>,
+) -> Result<Vec<registry::ActionDef>, StacksError> {
+    Ok(state.with_lock(|state| registry::load(&registry::path(state)))?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_actions_set(
+    state: tauri::State<SharedState>,
+    actions: Vec<registry::ActionDef>,
+) -> Result<(), StacksError> {
+    Ok(state.with_lock(|state| registry::save(&registry::path(state), &actions))?)
+}
+
+// Stores the LLM API key in the system keychain, or removes it if key is None
+#[tauri::command]
+#[tracing::instrument(skip(key))]
+pub fn store_llm_key_set(key: Option<String>) -> Result<(), StacksError> {
+    Ok(actions::key_set(key.as_deref())?)
+}
+
+// Fills the prompt template with the input items' content, then copies it, or sends it to the LLM
+// and streams the response into a new item, in the first input's stack. Returns the new item's id.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_compose_prompt(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    template_id: scru128::Scru128Id,
+    input_ids: Vec<scru128::Scru128Id>,
+    target: prompts::Target,
+) -> Result<Option<scru128::Scru128Id>, StacksError> {
+    let (prompt, stack_id) = state.with_lock(|state| {
+        let prompt = prompts::compose_items(state, &template_id, &input_ids)?;
+        // the response goes to the first input's stack, or the template's if there are no inputs
+        let first = input_ids.first().unwrap_or(&template_id);
+        let stack_id = state.view.items.get(first).and_then(|item| item.stack_id);
+        Ok::<_, StacksError>((prompt, stack_id))
+    })?;
+    match target {
+        prompts::Target::Copy => {
+            let _change_num = write_to_clipboard("public.utf8-plain-text", prompt.as_bytes());
+            Ok(None)
+        }
+        prompts::Target::Llm => {
+            let stack_id =
+                stack_id.ok_or(StacksError::new(ErrorKind::NotFound, "stack not found"))?;
+            let id =
+                actions::run_prompt(&app, state.inner(), &prompt, stack_id, template_id).await?;
+            Ok(Some(id))
+        }
+    }
+}
+
+// Imports another clipboard manager's history into a new stack, emitting import-progress
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub async fn store_import_from(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, SharedState>,
+    tool: import::Tool,
+    path: String,
+) -> Result<import::ImportReport, StacksError> {
+    Ok(import::run(&app, state.inner(), tool, std::path::Path::new(&path)).await?)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_disk_status(state: tauri::State<SharedState>) -> Option<disk::DiskStatus> {
+    state.with_lock(|state| state.disk_status.clone())
+}
+
+// Removes unreferenced content, then rechecks free disk space
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn store_gc(app: tauri::AppHandle, state: tauri::State<SharedState>) -> GcStats {
+    let stats = state.with_lock(|state| state.store.gc());
+    tracing::info!(?stats, "gc");
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn(async move {
+        disk::refresh(&app, &state).await;
+    });
+    stats
+}
+
+// Limits the events sent to the calling window. A filter of None sends it every event.
+#[tauri::command]
+#[tracing::instrument(skip(window))]
+pub fn store_events_subscribe(window: tauri::Window, filter: Option<events::Filter>) {
+    match filter {
+        Some(filter) => events::subscribe(window.label(), filter),
+        None => events::unsubscribe(window.label()),
+    }
+}
+
+// Writes the stack out as a bundle, to share it. The bundle is written to path, or to the
+// Downloads directory if no path is given; returns the bundle's path.
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_export_stack(
+    state: tauri::State<SharedState>,
+    stack_id: scru128::Scru128Id,
+    path: Option<String>,
+) -> Result<String, StacksError> {
+    let bundle = state
+        .with_lock(|state| bundle::export(&state.store, &state.view, &stack_id))
+        .ok_or(StacksError::stack_not_found(&stack_id))?;
+    let path = match path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let dir = dirs::download_dir()
+                .or_else(dirs::home_dir)
+                .ok_or("no directory to export to")?;
+            let stem: String = bundle
+                .name
+                .chars()
+                .map(|c| if c == '/' || c == ':' { '-' } else { c })
+                .collect();
+            dir.join(format!("{}.{}", stem, bundle::FILE_EXTENSION))
+        }
+    };
+    let data = serde_json::to_vec_pretty(&bundle).map_err(|e| e.to_string())?;
+    std::fs::write(&path, data).map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+// Exports the items, or the stack's items, as Markdown, CSV or JSON. The export is written to
+// path, or to the Downloads directory if no path is given; returns the export's path.
+#[tauri::command]
+#[tracing::instrument(skip(state))]
+pub fn store_export_items(
+    state: tauri::State<SharedState>,
+    ids: Option<Vec<scru128::Scru128Id>>,
+    stack_id: Option<scru128::Scru128Id>,
+    format: export::Format,
+    path: Option<String>,
+) -> Result<String, StacksError> {
+    let (export, data) = state.with_lock(|state| {
+        let export = export::collect(state, ids.as_deref(), stack_id)?;
+        let data = export::render(&export, format);
+        Ok::<_, String>((export, data))
+    })?;
+    let path = match path {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let dir = dirs::download_dir()
+                .or_else(dirs::home_dir)
+                .ok_or("no directory to export to")?;
+            let stem: String = export
+                .title
+                .unwrap_or("Stacks export".to_string())
+                .chars()
+                .map(|c| if c == '/' || c == ':' { '-' } else { c })
+                .collect();
+            dir.join(format!("{}.{}", stem, format.extension()))
+        }
+    };
+    std::fs::write(&path, data).map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+// Imports a bundle, from a file or an http(s) URL, as a new stack
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub async fn store_import_stack(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    path_or_url: String,
+) -> Result<scru128::Scru128Id, StacksError> {
+    let data = bundle::fetch(&path_or_url).await?;
+    let (bundle, contents) = bundle::parse(&data)?;
+    let stack_id = state.with_lock(|state| {
+        let stack_id = bundle::import(state, &bundle, &contents);
+        let focus = state.view.get_focus_for_id(&stack_id);
+        state.ui.select(focus);
+        stack_id
+    });
+    events::emit(&app, "refresh-items", true);
+    Ok(stack_id)
+}
+
+// Opens an always-on-top window pinned to a stack, or focuses it if it's already open. The
+// window is only sent events for that stack; returns the window's label.
+#[tauri::command]
+#[tracing::instrument(skip(state, app))]
+pub async fn store_open_stack_window(
+    state: tauri::State<'_, SharedState>,
+    app: tauri::AppHandle,
+    stack_id: scru128::Scru128Id,
+) -> Result<String, StacksError> {
+    let name = state
+        .with_lock(|state| {
+            let stack = state
+                .view
+                .items
+                .get(&stack_id)
+                .filter(|item| item.is_stack)?;
+            let content = state.store.get_content(&stack.hash)?;
+            Some(String::from_utf8_lossy(&content).to_string())
+        })
+        .ok_or(StacksError::stack_not_found(&stack_id))?;
+
+    let label = format!("stack-{}", stack_id);
+    if let Some(window) = app.get_window(&label) {
+        window.set_focus()?;
+        return Ok(label);
+    }
+
+    let url = tauri::WindowUrl::App(format!("index.html?stack={}", stack_id).into());
+    let window = tauri::WindowBuilder::new(&app, &label, url)
+        .title(name)
+        .always_on_top(true)
+        .inner_size(400.0, 600.0)
+        .build()?;
+
+    events::subscribe(
+        &label,
+        events::Filter {
+            stack_id: Some(stack_id),
+            ..Default::default()
+        },
+    );
+    let closed = label.clone();
+    window.on_window_event(move |event| {
+        if let tauri::WindowEvent::Destroyed = event {
+            events::unsubscribe(&closed);
+        }
+    });
+    Ok(label)
 }
 
 #[tauri::command]
 #[tracing::instrument(skip(app))]
 pub fn spotlight_hide(app: tauri::AppHandle) {
-    let window = app.get_window("main").unwrap();
-    spotlight::hide(&window).unwrap();
+    if let Some(window) = app.get_window(spotlight::WINDOW_LABEL) {
+        spotlight::hide(&window).unwrap();
+    }
+}
+
+#[tauri::command]
+pub fn spotlight_accessibility_trusted() -> bool {
+    spotlight::is_accessibility_trusted()
+}
+
+// Copies the item, hides Stacks, returning focus to the previously active app, and pastes the
+// item there. Without accessibility access the item is only copied.
+#[tauri::command]
+#[tracing::instrument(skip(app, state))]
+pub fn spotlight_paste_to_frontmost(
+    app: tauri::AppHandle,
+    state: tauri::State<SharedState>,
+    source_id: scru128::Scru128Id,
+) -> Result<(), StacksError> {
+    Ok(paste_to_frontmost(&app, state.inner(), source_id)?)
+}
+
+pub fn paste_to_frontmost(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    source_id: Scru128Id,
+) -> Result<(), StacksError> {
+    state.with_lock(|state| {
+        if !state.view.items.contains_key(&source_id) {
+            return Err(StacksError::not_found(&source_id));
+        }
+        let _change_num = copy_to_clipboard(state, &source_id);
+        state.record_usage(source_id, Access::Paste);
+        Ok(())
+    })?;
+
+    if let Some(window) = app.get_window(spotlight::WINDOW_LABEL) {
+        spotlight::hide(&window).map_err(|e| format!("{:?}", e))?;
+    }
+
+    if !spotlight::is_accessibility_trusted() {
+        return Err(StacksError::permission_denied(
+            "accessibility access is required to paste",
+        ));
+    }
+
+    // give the previous app a moment to become active before the keystroke is sent to it
+    tauri::async_runtime::spawn(async {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        spotlight::send_paste_keystroke();
+    });
+    Ok(())
 }
@@ -0,0 +1,358 @@
+// The packet log is append-only, so it grows with every edit, delete and touch. Compaction drops
+// the packets which no longer affect what's shown: everything about items which have since been
+// deleted, and touches superseded by a later touch. Packets keep their ids. Every packet is
+// written to a backup in the store's directory before any is dropped; the most recent backups are
+// kept.
+//
+// Whether a packet affects the view isn't always obvious, e.g. adding content which is already in
+// the stack touches the existing item instead, so the view is rebuilt without the dropped packets
+// and compared with the current one. Deleted items in stacks which come out differently are kept.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::Serialize;
+
+use crate::error::StacksError;
+use crate::events;
+use crate::state::SharedState;
+use crate::store::{Packet, PacketType};
+use crate::view::View;
+
+pub const DEFAULT_THRESHOLD_PACKETS: usize = 100_000;
+pub const BACKUPS_DIR: &str = "backups";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// past the threshold, the log is only compacted automatically if this fraction of it can go
+const MIN_DROPPABLE_FRACTION: usize = 10;
+// rounds of keeping deleted items back, before giving up on them altogether
+const MAX_ROUNDS: usize = 10;
+// backups of the packet log kept; older ones are removed as new ones are written
+const KEEP_BACKUPS: usize = 5;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct CompactStats {
+    pub packets: usize,
+    pub removed: usize,
+    pub backup: Option<PathBuf>,
+}
+
+// The item a packet is about
+fn item_id(packet: &Packet) -> Scru128Id {
+    match packet.packet_type {
+        PacketType::Add => packet.id,
+        _ => packet.source_id.unwrap_or(packet.id),
+    }
+}
+
+// What compaction has to leave as it was. An item's touched history is allowed to shrink.
+type Shown = (
+    ssri::Integrity,
+    Option<Scru128Id>,
+    Vec<Scru128Id>,
+    Vec<Scru128Id>,
+    Scru128Id,
+    [bool; 6],
+);
+
+fn shown(view: &View) -> HashMap<Scru128Id, Shown> {
+    view.items
+        .iter()
+        .map(|(id, item)| {
+            let flags = [
+                item.is_stack,
+                item.ordered,
+                item.locked,
+                item.read_only,
+                item.archived,
+                item.cross_stream,
+            ];
+            let shown = (
+                item.hash.clone(),
+                item.stack_id,
+                view.children(item),
+                item.linked.clone(),
+                item.last_touched,
+                flags,
+            );
+            (*id, shown)
+        })
+        .collect()
+}
+
+fn replay<'a>(packets: impl Iterator<Item = &'a Packet>) -> View {
+    let mut view = View::new();
+    packets.for_each(|p| view.merge(p));
+    view
+}
+
+// Returns the ids of the packets which can be dropped without changing the view
+pub fn droppable(packets: &[Packet]) -> HashSet<Scru128Id> {
+    let stacks: HashSet<Scru128Id> = packets
+        .iter()
+        .filter(|p| p.packet_type == PacketType::Add && p.stack_id.is_none())
+        .map(|p| p.id)
+        .collect();
+    // forks copy their source when they're merged, so a forked item's packets are kept
+    let forked: HashSet<Scru128Id> = packets
+        .iter()
+        .filter(|p| p.packet_type == PacketType::Fork)
+        .filter_map(|p| p.source_id)
+        .collect();
+    let mut deleted: HashMap<Scru128Id, Option<Scru128Id>> = packets
+        .iter()
+        .filter(|p| p.packet_type == PacketType::Delete)
+        .filter_map(|p| p.source_id)
+        .filter(|id| !stacks.contains(id) && !forked.contains(id))
+        .map(|id| (id, None))
+        .collect();
+    for packet in packets {
+        if packet.packet_type == PacketType::Add {
+            if let Some(stack_id) = deleted.get_mut(&packet.id) {
+                *stack_id = packet.stack_id;
+            }
+        }
+    }
+
+    // a touch can go if the next packet about the same item is another touch
+    let mut superseded = HashSet::new();
+    let mut last_touch: HashMap<Scru128Id, Scru128Id> = HashMap::new();
+    for packet in packets {
+        let id = item_id(packet);
+        let previous = last_touch.remove(&id);
+        if packet.packet_type == PacketType::Touch {
+            superseded.extend(previous);
+            last_touch.insert(id, packet.id);
+        }
+    }
+
+    let current = shown(&replay(packets.iter()));
+    for _ in 0..MAX_ROUNDS {
+        let drop: HashSet<Scru128Id> = packets
+            .iter()
+            .filter(|p| superseded.contains(&p.id) || deleted.contains_key(&item_id(p)))
+            .map(|p| p.id)
+            .collect();
+        let compacted = shown(&replay(packets.iter().filter(|p| !drop.contains(&p.id))));
+
+        let mut changed: HashSet<Scru128Id> = HashSet::new();
+        for (id, was) in &current {
+            match compacted.get(id) {
+                Some(now) if now == was => {}
+                _ => {
+                    changed.insert(*id);
+                    changed.extend(was.1);
+                }
+            }
+        }
+        for (id, now) in &compacted {
+            if !current.contains_key(id) {
+                changed.insert(*id);
+                changed.extend(now.1);
+            }
+        }
+        if changed.is_empty() {
+            return drop;
+        }
+        let before = deleted.len();
+        deleted.retain(|id, stack_id| {
+            !changed.contains(id) && !stack_id.is_some_and(|stack_id| changed.contains(&stack_id))
+        });
+        if deleted.len() == before {
+            break;
+        }
+    }
+    HashSet::new()
+}
+
+// Writes every packet, as a line of JSON, to a new file in the backups directory, then removes
+// all but the KEEP_BACKUPS most recent backups
+pub fn backup(dir: &Path, packets: &[Packet]) -> std::io::Result<PathBuf> {
+    let dir = dir.join(BACKUPS_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("packets-{}.jsonl", scru128::new_string()));
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+    for packet in packets {
+        serde_json::to_writer(&mut file, packet)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner()?.sync_all()?;
+    rotate(&dir);
+    Ok(path)
+}
+
+// backups are named for a scru128 id, so sorting their names sorts them oldest first
+fn rotate(dir: &Path) {
+    let mut backups: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("packets-") && name.ends_with(".jsonl"))
+            })
+            .collect(),
+        Err(e) => {
+            tracing::warn!(name = "compact", %e, "couldn't list backups");
+            return;
+        }
+    };
+    backups.sort();
+    let excess = backups.len().saturating_sub(KEEP_BACKUPS);
+    for path in &backups[..excess] {
+        if let Err(e) = std::fs::remove_file(path) {
+            tracing::warn!(name = "compact", ?path, %e, "couldn't remove backup");
+        }
+    }
+}
+
+// Compacts the log. Unless forced, the log is only compacted once it's past the threshold, and a
+// good part of it can go.
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    force: bool,
+) -> Result<CompactStats, StacksError> {
+    let (dir, packets, generation, threshold) = state.with_lock(|state| {
+        let threshold = state
+            .store
+            .settings_get()
+            .and_then(|settings| settings.compact_threshold_packets)
+            .unwrap_or(DEFAULT_THRESHOLD_PACKETS);
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let generation = state.store.generation();
+        (
+            PathBuf::from(&state.store.path),
+            packets,
+            generation,
+            threshold,
+        )
+    });
+    if !force && packets.len() < threshold {
+        return Ok(CompactStats {
+            packets: packets.len(),
+            ..Default::default()
+        });
+    }
+
+    // the compacted view is built here, off the lock, and swapped in once the packets are gone
+    let (packets, drop, view) = tauri::async_runtime::spawn_blocking(move || {
+        let drop = droppable(&packets);
+        let view = replay(packets.iter().filter(|p| !drop.contains(&p.id)));
+        (packets, drop, view)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut stats = CompactStats {
+        packets: packets.len(),
+        ..Default::default()
+    };
+    if drop.is_empty() || (!force && drop.len() * MIN_DROPPABLE_FRACTION < packets.len()) {
+        return Ok(stats);
+    }
+
+    let path = backup(&dir, &packets).map_err(|e| e.to_string())?;
+    state.with_lock(|state| {
+        // anything added, or removed, e.g. by an undo, since the scan wasn't considered, so it's
+        // left for the next run
+        if state.store.generation() != generation {
+            return Err(StacksError::busy("the store changed while compacting"));
+        }
+        state.store.remove_packets(&drop)?;
+        stats.removed = drop.len();
+        state.set_view(view);
+        Ok(())
+    })?;
+    stats.backup = Some(path);
+    tracing::info!(name = "compact", ?stats);
+    events::emit(app, "refresh-items", true);
+    Ok(stats)
+}
+
+pub fn spawn(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+            if let Err(e) = run(&app, &state, false).await {
+                tracing::error!(name = "compact", %e, "failed to compact");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use crate::store::{MimeType, StackLockStatus};
+
+    #[test]
+    fn test_droppable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let apply = |state: &mut State, packet: Packet| {
+            state.merge(&packet);
+            packet
+        };
+        let stack = state.store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        let stack = apply(&mut state, stack);
+        let kept = state.store.add(b"kept", MimeType::TextPlain, stack.id);
+        let kept = apply(&mut state, kept);
+        let gone = state.store.add(b"gone", MimeType::TextPlain, stack.id);
+        let gone = apply(&mut state, gone);
+        let first_touch = state.store.update_touch(kept.id);
+        let first_touch = apply(&mut state, first_touch);
+        let touch = state.store.update_touch(kept.id);
+        apply(&mut state, touch);
+        let delete = state.store.delete(gone.id);
+        let delete = apply(&mut state, delete);
+        // the stack's last activity, so the deleted item isn't the last thing it saw
+        let later = state.store.add(b"later", MimeType::TextPlain, stack.id);
+        apply(&mut state, later);
+
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let drop = droppable(&packets);
+        let expected: HashSet<Scru128Id> = [gone.id, first_touch.id, delete.id].into();
+        assert_eq!(drop, expected);
+        let compacted = replay(packets.iter().filter(|p| !drop.contains(&p.id)));
+        assert_eq!(shown(&compacted), shown(&state.view));
+
+        // a deleted item which was the stack's last activity is kept, so stacks stay in order
+        let gone = state
+            .store
+            .add(b"gone again", MimeType::TextPlain, stack.id);
+        let gone = apply(&mut state, gone);
+        let delete = state.store.delete(gone.id);
+        apply(&mut state, delete);
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let drop = droppable(&packets);
+        assert!(!drop.contains(&gone.id));
+        let compacted = replay(packets.iter().filter(|p| !drop.contains(&p.id)));
+        assert_eq!(shown(&compacted), shown(&state.view));
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = crate::store::Store::new(dir.path().to_str().unwrap());
+        let packet = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        let path = backup(dir.path(), &[packet.clone()]).unwrap();
+        let line = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(serde_json::from_str::<Packet>(line.trim()).unwrap(), packet);
+
+        // only the most recent backups are kept
+        for _ in 0..KEEP_BACKUPS {
+            backup(dir.path(), &[packet.clone()]).unwrap();
+        }
+        let backups = std::fs::read_dir(dir.path().join(BACKUPS_DIR)).unwrap();
+        assert_eq!(backups.count(), KEEP_BACKUPS);
+        assert!(!path.exists());
+    }
+}
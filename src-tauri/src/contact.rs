@@ -0,0 +1,171 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::phone;
+
+// Items with this content type have their fields parsed into a Contact
+pub const CONTENT_TYPE: &str = "Contact";
+
+// signatures are short: anything longer is more likely prose which happens to contain an email
+const MAX_SIGNATURE_LINES: usize = 8;
+
+lazy_static! {
+    static ref EMAIL: Regex = Regex::new(r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+").unwrap();
+    static ref NAME: Regex =
+        Regex::new(r"^\p{Lu}[\p{L}'.-]*(?:\s+\p{Lu}[\p{L}'.-]*){1,3}$").unwrap();
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Contact {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub phone: Option<String>,
+}
+
+impl Contact {
+    // Returns the named field: one of name, email or phone
+    pub fn field(&self, field: &str) -> Option<String> {
+        match field {
+            "name" => self.name.clone(),
+            "email" => self.email.clone(),
+            "phone" => self.phone.clone(),
+            _ => None,
+        }
+    }
+}
+
+fn parse_vcard(input: &str) -> Option<Contact> {
+    // continuation lines start with whitespace and belong to the previous line
+    let mut lines: Vec<String> = Vec::new();
+    for line in input.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().push_str(rest),
+            _ => lines.push(line.trim_end().to_string()),
+        }
+    }
+
+    let mut contact = Contact::default();
+    let mut structured_name = None;
+    for line in &lines {
+        let (key, value) = match line.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        // strip parameters, e.g. EMAIL;TYPE=work
+        let key = key.split(';').next().unwrap_or("").to_uppercase();
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.as_str() {
+            "FN" => contact.name = Some(value.to_string()),
+            "N" => {
+                // family;given;additional;prefix;suffix
+                let parts: Vec<&str> = value.split(';').collect();
+                let given = parts.get(1).copied().unwrap_or("");
+                let name = [given, parts[0]]
+                    .iter()
+                    .filter(|s| !s.is_empty())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if !name.is_empty() {
+                    structured_name = Some(name);
+                }
+            }
+            "EMAIL" if contact.email.is_none() => contact.email = Some(value.to_string()),
+            "TEL" if contact.phone.is_none() => {
+                contact.phone = Some(value.trim_start_matches("tel:").to_string())
+            }
+            _ => {}
+        }
+    }
+
+    if contact.name.is_none() {
+        contact.name = structured_name;
+    }
+    Some(contact)
+}
+
+fn parse_signature(input: &str) -> Option<Contact> {
+    let lines: Vec<&str> = input
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.len() < 2 || lines.len() > MAX_SIGNATURE_LINES {
+        return None;
+    }
+
+    let name = lines.iter().find(|line| NAME.is_match(line))?;
+    let email = EMAIL.find(input).map(|m| m.as_str().to_string());
+    let phone = phone::find(input)
+        .into_iter()
+        .next()
+        .map(|phone| phone.text);
+    if email.is_none() && phone.is_none() {
+        return None;
+    }
+
+    Some(Contact {
+        name: Some(name.to_string()),
+        email,
+        phone,
+    })
+}
+
+// Parses a vCard, or contact-like text such as an email signature
+pub fn parse(input: &str) -> Option<Contact> {
+    let input = input.trim();
+    let is_vcard = input
+        .get(..11)
+        .is_some_and(|start| start.eq_ignore_ascii_case("BEGIN:VCARD"));
+    if is_vcard {
+        parse_vcard(input)
+    } else {
+        parse_signature(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vcard() {
+        let vcard = "BEGIN:VCARD\r\nVERSION:3.0\r\nN:Lovelace;Ada;;;\r\nEMAIL;TYPE=work:ada@example.com\r\nTEL;TYPE=cell:+44 20 7946\r\n 0958\r\nEND:VCARD\r\n";
+        assert_eq!(
+            parse(vcard),
+            Some(Contact {
+                name: Some("Ada Lovelace".to_string()),
+                email: Some("ada@example.com".to_string()),
+                phone: Some("+44 20 79460958".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        let signature =
+            "Thanks,\nAda Lovelace\nAnalytical Engines Ltd\nada@example.com | +1 (555) 010-9999";
+        let contact = parse(signature).unwrap();
+        assert_eq!(contact.field("name"), Some("Ada Lovelace".to_string()));
+        assert_eq!(contact.field("email"), Some("ada@example.com".to_string()));
+        assert_eq!(
+            contact.field("phone"),
+            Some("+1 (555) 010-9999".to_string())
+        );
+        assert_eq!(contact.field("fax"), None);
+    }
+
+    #[test]
+    fn test_not_a_contact() {
+        assert_eq!(parse("ada@example.com"), None);
+        assert_eq!(parse("Ada Lovelace\nno way to reach her"), None);
+        assert_eq!(
+            parse("meeting at 10:30 in room 4\nbring the 2023-10-01 report"),
+            None
+        );
+    }
+}
@@ -1,7 +1,6 @@
 use tokio::sync::broadcast;
 
-use tauri::Manager;
-
+use crate::events;
 use crate::state::SharedState;
 use crate::store::{count_tiktokens, MimeType};
 
@@ -21,21 +20,31 @@ pub fn spawn_tiktokens(app: tauri::AppHandle, state: SharedState) {
                 Ok(content_meta) => {
                     if content_meta.mime_type == MimeType::TextPlain {
                         let hash = content_meta.hash.clone();
+                        let tokenizer = state.with_lock(|state| state.store.tokenizer_get());
                         let tiktokens = tokio::task::spawn_blocking(move || {
-                            let content =
-                                cacache::read_hash_sync(&cache_path, &content_meta.hash).unwrap();
+                            let hash = &content_meta.hash;
+                            let content = match cacache::read_hash_sync(&cache_path, hash) {
+                                Ok(content) => content,
+                                Err(e) => {
+                                    tracing::warn!(name = "content_bus::tiktokens", %hash, %e, "content missing");
+                                    return None;
+                                }
+                            };
                             let content = String::from_utf8_lossy(&content);
-                            let tiktokens = count_tiktokens(&content);
-                        tracing::info!(name = "content_bus::tiktokens", hash = %content_meta.hash, tiktokens = tiktokens);
-                        tiktokens
+                            let tiktokens = count_tiktokens(&content, tokenizer);
+                            tracing::info!(name = "content_bus::tiktokens", %hash, tiktokens);
+                            Some(tiktokens)
                         })
-                        .await
-                        .unwrap();
+                        .await;
+                        // a missing blob, or a panic counting it, skips the item, not the rest
+                        let Ok(Some(tiktokens)) = tiktokens else {
+                            continue;
+                        };
 
                         state.with_lock(|state| {
                             state.store.update_tiktokens(hash.clone(), tiktokens);
                         });
-                        app.emit_all("content", hash).unwrap();
+                        events::emit(&app, "content", hash);
                     }
                 }
                 Err(broadcast::error::RecvError::Lagged(skipped)) => {
@@ -52,3 +61,49 @@ pub fn spawn_tiktokens(app: tauri::AppHandle, state: SharedState) {
         }
     });
 }
+
+// Counts the tokens of all text content again, e.g. after the tokenizer setting has changed
+pub fn recount_tiktokens(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        let tokenizer = state.with_lock(|state| state.store.tokenizer_get());
+        let (cache_path, hashes) = state.with_lock(|state| {
+            let mut seen = std::collections::HashSet::new();
+            let hashes: Vec<_> = state
+                .view
+                .items
+                .values()
+                .filter(|item| !item.is_stack && seen.insert(item.hash.clone()))
+                .filter(|item| {
+                    state
+                        .store
+                        .get_content_meta(&item.hash)
+                        .is_some_and(|meta| meta.mime_type == MimeType::TextPlain)
+                })
+                .map(|item| item.hash.clone())
+                .collect();
+            (state.store.cache_path.clone(), hashes)
+        });
+        tracing::info!(
+            name = "content_bus::tiktokens",
+            count = hashes.len(),
+            ?tokenizer,
+            "recount"
+        );
+        for hash in hashes {
+            let cache_path = cache_path.clone();
+            let counted = hash.clone();
+            let tiktokens = tokio::task::spawn_blocking(move || {
+                let content = cacache::read_hash_sync(&cache_path, &counted).ok()?;
+                let content = String::from_utf8_lossy(&content);
+                Some(count_tiktokens(&content, tokenizer))
+            })
+            .await
+            .ok()
+            .flatten();
+            if let Some(tiktokens) = tiktokens {
+                state.with_lock(|state| state.store.update_tiktokens(hash.clone(), tiktokens));
+            }
+        }
+        events::emit(&app, "refresh-items", true);
+    });
+}
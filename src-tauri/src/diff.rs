@@ -0,0 +1,146 @@
+// Compares two text items, e.g. two versions of copied code or config. A line diff is rendered as
+// a unified diff, through the preview pipeline, so it's highlighted as the Diff content type is. A
+// word diff marks what was deleted and inserted within the text itself.
+
+use maud::html;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::error::StacksError;
+use crate::state::State;
+use crate::store::MimeType;
+use crate::ui;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    #[default]
+    Lines,
+    Words,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Diff {
+    pub html: String,
+    // lines, or words, inserted and deleted
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+pub fn render(
+    theme_mode: &str,
+    (a_name, a): (&str, &str),
+    (b_name, b): (&str, &str),
+    granularity: Granularity,
+) -> Diff {
+    let diff = match granularity {
+        Granularity::Lines => TextDiff::from_lines(a, b),
+        Granularity::Words => TextDiff::from_words(a, b),
+    };
+    let (mut insertions, mut deletions) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            // whitespace between words is a change of its own, which isn't worth counting
+            _ if granularity == Granularity::Words && change.value().trim().is_empty() => {}
+            ChangeTag::Insert => insertions += 1,
+            ChangeTag::Delete => deletions += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    let html = match granularity {
+        Granularity::Lines => {
+            let unified = diff
+                .unified_diff()
+                .context_radius(3)
+                .header(a_name, b_name)
+                .to_string();
+            ui::generate_preview(
+                theme_mode,
+                &Some(unified.into_bytes()),
+                &MimeType::TextPlain,
+                &"Diff".to_string(),
+                false,
+            )
+        }
+        Granularity::Words => {
+            let pre = html! {
+                pre.preview.diff style="margin: 0; white-space: pre-wrap; overflow-x: hidden" {
+                    @for change in diff.iter_all_changes() {
+                        @match change.tag() {
+                            ChangeTag::Equal => (change.value()),
+                            ChangeTag::Delete => del style="background: rgba(255, 80, 80, 0.25)" { (change.value()) },
+                            ChangeTag::Insert => ins style="background: rgba(80, 200, 80, 0.25); text-decoration: none" { (change.value()) },
+                        }
+                    }
+                }
+            };
+            pre.into_string()
+        }
+    };
+    Diff {
+        html,
+        insertions,
+        deletions,
+    }
+}
+
+fn text(state: &State, id: &Scru128Id) -> Result<String, StacksError> {
+    let item = state
+        .view
+        .items
+        .get(id)
+        .filter(|item| !item.is_stack)
+        .ok_or(StacksError::not_found(id))?;
+    let meta = state.store.get_content_meta(&item.hash);
+    if !meta.is_some_and(|meta| meta.mime_type == MimeType::TextPlain) {
+        return Err(StacksError::invalid_input(format!("not text: {}", id)));
+    }
+    let content = state
+        .store
+        .get_content(&item.hash)
+        .ok_or(StacksError::content_not_found(id))?;
+    Ok(String::from_utf8_lossy(&content).to_string())
+}
+
+// Diffs item a against item b
+pub fn items(
+    state: &State,
+    a: &Scru128Id,
+    b: &Scru128Id,
+    granularity: Granularity,
+) -> Result<Diff, StacksError> {
+    let (a_text, b_text) = (text(state, a)?, text(state, b)?);
+    Ok(render(
+        &state.ui.theme.syntax,
+        (&a.to_string(), &a_text),
+        (&b.to_string(), &b_text),
+        granularity,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let a = "port = 8080\nhost = \"localhost\"\ndebug = false\n";
+        let b = "port = 9090\nhost = \"localhost\"\ndebug = false\n";
+
+        let lines = render("dark", ("a", a), ("b", b), Granularity::Lines);
+        assert_eq!((lines.insertions, lines.deletions), (1, 1));
+        assert!(lines.html.contains("8080"));
+        assert!(lines.html.contains("9090"));
+
+        let words = render("dark", ("a", a), ("b", b), Granularity::Words);
+        assert_eq!((words.insertions, words.deletions), (1, 1));
+        assert!(words.html.contains(">8080</del>"));
+        assert!(words.html.contains(">9090</ins>"));
+        assert!(words.html.contains("&quot;localhost&quot;"));
+
+        let same = render("dark", ("a", a), ("b", a), Granularity::Words);
+        assert_eq!((same.insertions, same.deletions), (0, 0));
+    }
+}
@@ -0,0 +1,107 @@
+// Watches free space on the volume holding the store. When it runs low, large clips are no longer
+// captured until space is freed, e.g. by running store_gc.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::events;
+use crate::state::SharedState;
+
+pub const DEFAULT_LOW_DISK_BYTES: u64 = 1 << 30;
+// while disk space is low, clips larger than this aren't captured
+pub const LARGE_ITEM_BYTES: usize = 1 << 20;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DiskStatus {
+    pub free: u64,
+    pub threshold: u64,
+    pub low: bool,
+}
+
+impl DiskStatus {
+    pub fn new(free: u64, threshold: u64) -> Self {
+        Self {
+            free,
+            threshold,
+            low: free < threshold,
+        }
+    }
+}
+
+// Parses the available space, in bytes, from the output of `df -Pk <path>`
+pub fn parse_df(output: &str) -> Option<u64> {
+    let line = output.lines().nth(1)?;
+    let available: u64 = line.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available * 1024)
+}
+
+pub async fn free_space(path: &str) -> Option<u64> {
+    let output = tokio::process::Command::new("df")
+        .args(["-Pk", path])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_df(&String::from_utf8_lossy(&output.stdout))
+}
+
+pub async fn check(state: &SharedState) -> Option<DiskStatus> {
+    let (path, threshold) = state.with_lock(|state| {
+        let threshold = state
+            .store
+            .settings_get()
+            .and_then(|settings| settings.low_disk_threshold_bytes)
+            .unwrap_or(DEFAULT_LOW_DISK_BYTES);
+        (state.store.path.clone(), threshold)
+    });
+    let free = free_space(&path).await?;
+    Some(DiskStatus::new(free, threshold))
+}
+
+// Checks free space, emitting disk-status when it crosses the threshold
+pub async fn refresh(app: &tauri::AppHandle, state: &SharedState) {
+    let Some(status) = check(state).await else {
+        return;
+    };
+    let changed = state.with_lock(|state| {
+        let changed = state.disk_status.as_ref().map(|s| s.low) != Some(status.low);
+        state.disk_status = Some(status.clone());
+        changed
+    });
+    if changed {
+        if status.low {
+            tracing::warn!(name = "disk", ?status, "low disk space");
+        }
+        events::emit(app, "disk-status", &status);
+    }
+}
+
+pub fn spawn_monitor(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            refresh(&app, &state).await;
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      /dev/disk3s5    482797652 398204344  61247068    87% /System/Volumes/Data\n";
+        assert_eq!(parse_df(output), Some(61247068 * 1024));
+        assert_eq!(parse_df("Filesystem 1024-blocks Used Available\n"), None);
+
+        assert!(DiskStatus::new(10, 20).low);
+        assert!(!DiskStatus::new(20, 20).low);
+    }
+}
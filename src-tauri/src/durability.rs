@@ -0,0 +1,103 @@
+// How hard the store works to get writes onto disk before carrying on. sled, and SQLite's
+// write-ahead log, hand writes to the OS as they're made, and sync them to disk on their own
+// schedule, so the machine crashing, rather than just the app, can lose the last few clips.
+//
+//   os: sync when sled, SQLite and the OS get to it. The default, and the least disk churn.
+//   periodic: sync every PERIODIC_INTERVAL there've been writes, so a burst of clips, e.g. while
+//     copying one thing after another, is committed to disk together.
+//   every_write: sync after each packet is written. The safest, and the most disk churn.
+//
+// With periodic and every_write, the content a packet refers to is synced along with the packet.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use ssri::Integrity;
+
+use crate::state::SharedState;
+
+pub const PERIODIC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    #[default]
+    Os,
+    Periodic,
+    EveryWrite,
+}
+
+// Syncs writes made since the last tick, for periodic durability. The state is only locked when
+// there are writes to sync.
+pub fn spawn(state: SharedState) {
+    let unsynced = state.with_read(|state| state.store.unsynced());
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(PERIODIC_INTERVAL).await;
+            if unsynced.load(Ordering::Relaxed) {
+                state.with_lock(|state| state.store.sync_pending());
+            }
+        }
+    });
+}
+
+// Where cacache keeps the content: content-v2/<algorithm>/<hex[..2]>/<hex[2..4]>/<hex[4..]>
+pub fn blob_path(cache_path: &str, hash: &Integrity) -> PathBuf {
+    let (algorithm, hex) = hash.to_hex();
+    Path::new(cache_path)
+        .join("content-v2")
+        .join(algorithm.to_string())
+        .join(&hex[..2])
+        .join(&hex[2..4])
+        .join(&hex[4..])
+}
+
+pub fn sync_blob(cache_path: &str, hash: &Integrity) -> std::io::Result<()> {
+    std::fs::File::open(blob_path(cache_path, hash))?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::{MimeType, Settings, StackLockStatus, Store};
+
+    #[test]
+    fn test_sync_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::new(dir.path().to_str().unwrap());
+        let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+
+        let set = |store: &mut Store, durability| {
+            store.settings_save(Settings {
+                durability: Some(durability),
+                ..Default::default()
+            })
+        };
+
+        // the default leaves syncing to the OS
+        let packet = store.add(b"one", MimeType::TextPlain, stack.id);
+        assert!(!store.sync_pending());
+        // content is synced where cacache wrote it
+        let hash = packet.hash.unwrap();
+        assert_eq!(
+            std::fs::read(blob_path(&store.cache_path, &hash)).unwrap(),
+            b"one"
+        );
+        sync_blob(&store.cache_path, &hash).unwrap();
+
+        set(&mut store, Durability::Periodic);
+        store.add(b"two", MimeType::TextPlain, stack.id);
+        store.add(b"three", MimeType::TextPlain, stack.id);
+        assert!(store.unsynced().load(Ordering::Relaxed));
+        assert!(store.sync_pending());
+        assert!(!store.sync_pending());
+
+        // each write is synced as it's made
+        set(&mut store, Durability::EveryWrite);
+        store.add(b"four", MimeType::TextPlain, stack.id);
+        assert!(!store.sync_pending());
+    }
+}
@@ -0,0 +1,143 @@
+// The error commands return to the frontend, so the UI can tell an item which has gone from a
+// read-only stack, or a busy store, and react: e.g. offer to unlock the stack, or to try again.
+// Errors the UI reacts to are made where they happen, with their kind, e.g. StacksError::read_only,
+// and kept as they're passed up, with ?. Other errors are strings, which are internal errors.
+//
+//     {"kind": "permission_denied", "message": "read-only: 0u1l..", "recoverable": false,
+//      "context": "0u1l.."}
+
+use scru128::Scru128Id;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    // the store is busy, e.g. it changed while it was being compacted: try again
+    Busy,
+    InvalidInput,
+    // the request needs the user's confirmation, e.g. to open a lot of links at once
+    ConfirmationRequired,
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn is_recoverable(&self) -> bool {
+        matches!(self, ErrorKind::Busy | ErrorKind::ConfirmationRequired)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StacksError {
+    pub kind: ErrorKind,
+    pub message: String,
+    // whether the same request may succeed if it's made again, later or once confirmed
+    pub recoverable: bool,
+    // e.g. the id of the item which wasn't found
+    pub context: Option<String>,
+}
+
+impl StacksError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        StacksError {
+            kind,
+            message: message.into(),
+            recoverable: kind.is_recoverable(),
+            context: None,
+        }
+    }
+
+    fn about(mut self, id: &Scru128Id) -> Self {
+        self.context = Some(id.to_string());
+        self
+    }
+
+    pub fn not_found(id: &Scru128Id) -> Self {
+        Self::new(ErrorKind::NotFound, format!("item not found: {}", id)).about(id)
+    }
+
+    pub fn content_not_found(id: &Scru128Id) -> Self {
+        Self::new(ErrorKind::NotFound, format!("content not found: {}", id)).about(id)
+    }
+
+    pub fn stack_not_found(id: &Scru128Id) -> Self {
+        Self::new(ErrorKind::NotFound, format!("stack not found: {}", id)).about(id)
+    }
+
+    pub fn read_only(id: &Scru128Id) -> Self {
+        Self::new(ErrorKind::PermissionDenied, format!("read-only: {}", id)).about(id)
+    }
+
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::PermissionDenied, message)
+    }
+
+    pub fn busy(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Busy, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidInput, message)
+    }
+}
+
+impl std::fmt::Display for StacksError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for StacksError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+}
+
+impl From<&str> for StacksError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+// e.g. a window which couldn't be created, or a task which panicked
+impl From<tauri::Error> for StacksError {
+    fn from(e: tauri::Error) -> Self {
+        Self::new(ErrorKind::Internal, e.to_string())
+    }
+}
+
+// for callers which report errors as strings, e.g. HTTP responses
+impl From<StacksError> for String {
+    fn from(e: StacksError) -> Self {
+        e.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from() {
+        let id = scru128::new();
+        let err = StacksError::not_found(&id);
+        assert_eq!(err.kind, ErrorKind::NotFound);
+        assert_eq!(err.message, format!("item not found: {}", id));
+        assert_eq!(err.context, Some(id.to_string()));
+        assert!(!err.recoverable);
+
+        let err = StacksError::read_only(&id);
+        assert_eq!(err.kind, ErrorKind::PermissionDenied);
+        assert_eq!(String::from(err), format!("read-only: {}", id));
+        let err = StacksError::busy("the store changed while compacting");
+        assert!(err.recoverable);
+        assert_eq!(err.context, None);
+
+        // strings aren't guessed at, whatever they say
+        let err = StacksError::from(format!("item not found: {}", id));
+        assert_eq!(err.kind, ErrorKind::Internal);
+        assert_eq!(err.context, None);
+        assert!(!err.recoverable);
+    }
+}
@@ -0,0 +1,168 @@
+// Events sent to the webviews. Windows can subscribe with a filter so they're only sent the events
+// they render: windows without a subscription are sent everything. Events are also broadcast to
+// listeners outside the webviews, e.g. WebSocket clients, which filter them themselves.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::broadcast;
+
+use crate::store::MimeType;
+
+lazy_static! {
+    static ref SUBSCRIPTIONS: Mutex<HashMap<String, Filter>> = Mutex::new(HashMap::new());
+    static ref BROADCAST: broadcast::Sender<Event> = broadcast::channel(256).0;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub topic: String,
+    pub stack_id: Option<Scru128Id>,
+    pub mime_type: Option<MimeType>,
+    pub payload: serde_json::Value,
+}
+
+impl Event {
+    pub fn scope(&self) -> Scope<'_> {
+        Scope {
+            stack_id: self.stack_id.as_ref(),
+            mime_type: self.mime_type.as_ref(),
+        }
+    }
+}
+
+// Listens to every event emitted from now on. A listener which falls behind misses events, rather
+// than holding them up.
+pub fn listen() -> broadcast::Receiver<Event> {
+    BROADCAST.subscribe()
+}
+
+// Each field narrows the filter. Events which aren't about a particular stack, or mime type, pass
+// the stack_id and mime_types checks.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Filter {
+    pub topics: Option<Vec<String>>,
+    pub stack_id: Option<Scru128Id>,
+    pub mime_types: Option<Vec<MimeType>>,
+}
+
+// What an event is about, for filtering
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scope<'a> {
+    pub stack_id: Option<&'a Scru128Id>,
+    pub mime_type: Option<&'a MimeType>,
+}
+
+impl Filter {
+    pub fn matches(&self, topic: &str, scope: &Scope) -> bool {
+        if let Some(topics) = &self.topics {
+            if !topics.iter().any(|t| t == topic) {
+                return false;
+            }
+        }
+        if let (Some(want), Some(got)) = (&self.stack_id, scope.stack_id) {
+            if want != got {
+                return false;
+            }
+        }
+        if let (Some(want), Some(got)) = (&self.mime_types, scope.mime_type) {
+            if !want.contains(got) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn subscribe(window: &str, filter: Filter) {
+    SUBSCRIPTIONS
+        .lock()
+        .unwrap()
+        .insert(window.to_string(), filter);
+}
+
+pub fn unsubscribe(window: &str) {
+    SUBSCRIPTIONS.lock().unwrap().remove(window);
+}
+
+pub fn emit<S: Serialize + Clone>(app: &tauri::AppHandle, topic: &str, payload: S) {
+    emit_scoped(app, topic, &Scope::default(), payload)
+}
+
+// Emitting never fails: a window which can't be sent the event, e.g. one which is closing, is
+// logged and skipped, so one window can't stop the rest being sent it, or end a stream.
+pub fn emit_scoped<S: Serialize + Clone>(
+    app: &tauri::AppHandle,
+    topic: &str,
+    scope: &Scope,
+    payload: S,
+) {
+    if BROADCAST.receiver_count() > 0 {
+        if let Ok(payload) = serde_json::to_value(payload.clone()) {
+            let _ = BROADCAST.send(Event {
+                topic: topic.to_string(),
+                stack_id: scope.stack_id.copied(),
+                mime_type: scope.mime_type.cloned(),
+                payload,
+            });
+        }
+    }
+
+    let subscriptions = SUBSCRIPTIONS.lock().unwrap();
+    for label in app.windows().keys() {
+        let wanted = subscriptions
+            .get(label)
+            .map_or(true, |filter| filter.matches(topic, scope));
+        if wanted {
+            if let Err(e) = app.emit_to(label, topic, payload.clone()) {
+                tracing::warn!(name = "events", %e, window = label, topic, "couldn't emit");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matches() {
+        let stack_id = scru128::new();
+        let other_id = scru128::new();
+        let unscoped = Scope::default();
+        let in_stack = Scope {
+            stack_id: Some(&stack_id),
+            mime_type: Some(&MimeType::TextPlain),
+        };
+        let in_other = Scope {
+            stack_id: Some(&other_id),
+            mime_type: Some(&MimeType::ImagePng),
+        };
+
+        let everything = Filter::default();
+        assert!(everything.matches("refresh-items", &unscoped));
+        assert!(everything.matches("streaming", &in_other));
+
+        let streaming = Filter {
+            topics: Some(vec!["streaming".to_string()]),
+            stack_id: Some(stack_id),
+            mime_types: None,
+        };
+        assert!(streaming.matches("streaming", &in_stack));
+        assert!(streaming.matches("streaming", &unscoped));
+        assert!(!streaming.matches("streaming", &in_other));
+        assert!(!streaming.matches("refresh-items", &in_stack));
+
+        let text = Filter {
+            topics: None,
+            stack_id: None,
+            mime_types: Some(vec![MimeType::TextPlain]),
+        };
+        assert!(text.matches("content", &in_stack));
+        assert!(!text.matches("content", &in_other));
+    }
+}
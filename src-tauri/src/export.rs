@@ -0,0 +1,227 @@
+// Exports items as a document: Markdown, with source code fenced by its language, CSV, or JSON.
+// Items are given by id, or as a stack, in the order the stack shows them. Unlike a bundle, an
+// export is meant to be read, or loaded into other tools, rather than imported back into Stacks,
+// so images are only noted where they were.
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::error::StacksError;
+use crate::file_ref;
+use crate::state::State;
+use crate::store::MimeType;
+use crate::ui;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Markdown,
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Row {
+    pub id: Scru128Id,
+    // milliseconds since the epoch
+    pub created_at: u64,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    // the item's text, or the path of a file reference. None for images.
+    pub content: Option<String>,
+    pub terse: String,
+}
+
+pub struct Export {
+    // the stack's name, when a stack was exported
+    pub title: Option<String>,
+    pub rows: Vec<Row>,
+}
+
+// Collects the items given by id, or the items of the stack. Ids which aren't items are skipped.
+pub fn collect(
+    state: &State,
+    ids: Option<&[Scru128Id]>,
+    stack_id: Option<Scru128Id>,
+) -> Result<Export, StacksError> {
+    let (title, ids) = match (ids, stack_id) {
+        (Some(ids), _) => (None, ids.to_vec()),
+        (None, Some(stack_id)) => {
+            let stack = state
+                .view
+                .items
+                .get(&stack_id)
+                .filter(|item| item.is_stack)
+                .ok_or(StacksError::stack_not_found(&stack_id))?;
+            let name = state
+                .store
+                .get_content(&stack.hash)
+                .map(|name| String::from_utf8_lossy(&name).to_string());
+            (name, state.view.children(stack))
+        }
+        (None, None) => return Err(StacksError::invalid_input("no items to export")),
+    };
+
+    let rows = ids
+        .iter()
+        .filter_map(|id| {
+            let item = state.view.items.get(id).filter(|item| !item.is_stack)?;
+            let meta = state.store.get_content_meta(&item.hash)?;
+            let content = match meta.mime_type {
+                MimeType::TextPlain => state
+                    .store
+                    .get_content(&item.hash)
+                    .map(|content| String::from_utf8_lossy(&content).to_string()),
+                MimeType::FileRef => state
+                    .store
+                    .get_content(&item.hash)
+                    .and_then(|content| file_ref::parse(&content))
+                    .map(|file_ref| file_ref.path),
+                _ => None,
+            };
+            Some(Row {
+                id: item.id,
+                created_at: item.id.timestamp(),
+                mime_type: meta.mime_type,
+                content_type: meta.content_type,
+                content,
+                terse: meta.terse,
+            })
+        })
+        .collect();
+    Ok(Export { title, rows })
+}
+
+// A fence longer than any run of backticks in the content, so the content can't close it
+fn fence(content: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in content.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    "`".repeat((longest + 1).max(3))
+}
+
+pub fn markdown(export: &Export) -> String {
+    let mut blocks = Vec::new();
+    if let Some(title) = &export.title {
+        blocks.push(format!("# {}", title.trim()));
+    }
+    for row in &export.rows {
+        let block = match (&row.mime_type, &row.content) {
+            (MimeType::FileRef, Some(path)) => format!("[{}](<{}>)", row.terse, path),
+            (_, Some(content)) => match ui::file_extension(&row.content_type) {
+                Some(language) => {
+                    let fence = fence(content);
+                    format!("{}{}\n{}\n{}", fence, language, content.trim_end(), fence)
+                }
+                None => content.trim_end().to_string(),
+            },
+            (_, None) => format!("*{}*", row.terse),
+        };
+        blocks.push(block);
+    }
+    let mut markdown = blocks.join("\n\n");
+    markdown.push('\n');
+    markdown
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn csv(export: &Export) -> String {
+    let mut csv = String::from("id,created_at,mime_type,content_type,content\r\n");
+    for row in &export.rows {
+        let fields = [
+            row.id.to_string(),
+            row.created_at.to_string(),
+            row.mime_type.as_str().to_string(),
+            row.content_type.clone(),
+            row.content.clone().unwrap_or_else(|| row.terse.clone()),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+pub fn render(export: &Export, format: Format) -> String {
+    match format {
+        Format::Markdown => markdown(export),
+        Format::Csv => csv(export),
+        Format::Json => serde_json::to_string_pretty(&export.rows).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StackLockStatus;
+
+    #[test]
+    fn test_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack = state
+            .store
+            .add_stack(b"Research", StackLockStatus::Unlocked);
+        state.merge(&stack);
+        let note = state
+            .store
+            .add(b"Notes, \"quoted\"", MimeType::TextPlain, stack.id);
+        state.merge(&note);
+        let code = state.store.add(
+            b"fn main() {\n    println!(\"```\");\n}\n",
+            MimeType::TextPlain,
+            stack.id,
+        );
+        state.merge(&code);
+        let content_type = state
+            .store
+            .update_content_type(code.hash.clone().unwrap(), "Rust".to_string());
+        state.merge(&content_type);
+
+        let export = collect(&state, None, Some(stack.id)).unwrap();
+        assert_eq!(export.title.as_deref(), Some("Research"));
+        assert_eq!(export.rows.len(), 2);
+        assert_eq!(
+            markdown(&export),
+            "# Research\n\n````rs\nfn main() {\n    println!(\"```\");\n}\n````\n\nNotes, \"quoted\"\n"
+        );
+
+        let export = collect(&state, Some(&[note.id, stack.id]), None).unwrap();
+        assert_eq!(export.title, None);
+        assert_eq!(
+            csv(&export),
+            format!(
+                "id,created_at,mime_type,content_type,content\r\n{},{},text/plain,Text,\"Notes, \"\"quoted\"\"\"\r\n",
+                note.id,
+                note.id.timestamp()
+            )
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(&export, Format::Json)).unwrap();
+        assert_eq!(json[0]["content"], "Notes, \"quoted\"");
+
+        assert!(collect(&state, None, Some(note.id)).is_err());
+    }
+}
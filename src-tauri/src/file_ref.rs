@@ -0,0 +1,128 @@
+// Files copied in Finder, or dropped onto Stacks, are captured as references: the item holds the
+// file's path and size rather than its contents, and pasting it pastes the file itself.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::events;
+use crate::paste;
+use crate::state::SharedState;
+use crate::store::MimeType;
+
+pub const CONTENT_TYPE: &str = "File";
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct FileRef {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+}
+
+impl FileRef {
+    // Resolves a file URL, or absolute path, to a reference to the existing file
+    pub fn resolve(url_or_path: &str) -> Option<FileRef> {
+        let url = paste::file_url(url_or_path)?;
+        let path = paste::url_decode(url.strip_prefix("file://")?)?;
+        let name = Path::new(&path).file_name()?.to_string_lossy().to_string();
+        let size = std::fs::metadata(&path).ok()?.len();
+        Some(FileRef { path, name, size })
+    }
+
+    pub fn url(&self) -> String {
+        paste::path_url(&self.path)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+// File references are stored as JSON
+pub fn parse(content: &[u8]) -> Option<FileRef> {
+    serde_json::from_slice(content).ok()
+}
+
+// Finder puts a copied file's URL on the pasteboard along with its name, as text. Apps can also
+// include the URL of the document copied text came from: that's the clip's source, not a file.
+pub fn from_pasteboard(file_url: &str, text: Option<&str>) -> Option<FileRef> {
+    let file_ref = FileRef::resolve(file_url)?;
+    match text.map(str::trim) {
+        Some(text) if text != file_ref.name && text != file_ref.path => None,
+        _ => Some(file_ref),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64;
+    let mut unit = "";
+    for u in UNITS {
+        size /= 1000.0;
+        unit = u;
+        if size < 1000.0 {
+            break;
+        }
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+// Adds files dropped onto a window to the current stack
+pub fn add_dropped(app: &tauri::AppHandle, state: &SharedState, paths: &[PathBuf]) {
+    let added = state.with_lock(|state| {
+        let stack_id = state.get_curr_stack();
+        let mut added = 0;
+        for path in paths {
+            let Some(file_ref) = FileRef::resolve(&path.to_string_lossy()) else {
+                continue;
+            };
+            let packet = state
+                .store
+                .add(&file_ref.to_bytes(), MimeType::FileRef, stack_id);
+            state.merge(&packet);
+            added += 1;
+        }
+        added
+    });
+    tracing::info!(name = "file_ref", added, "dropped");
+    if added > 0 {
+        events::emit(app, "refresh-items", true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_pasteboard() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report 2023.pdf");
+        std::fs::write(&path, "%PDF").unwrap();
+        let file_ref = FileRef::resolve(path.to_str().unwrap()).unwrap();
+        assert_eq!(file_ref.name, "report 2023.pdf");
+        assert_eq!(file_ref.size, 4);
+        assert!(file_ref.url().ends_with("/report%202023.pdf"));
+        assert_eq!(parse(&file_ref.to_bytes()), Some(file_ref.clone()));
+
+        let url = file_ref.url();
+        assert_eq!(from_pasteboard(&url, None), Some(file_ref.clone()));
+        assert_eq!(
+            from_pasteboard(&url, Some("report 2023.pdf")),
+            Some(file_ref)
+        );
+        // text copied from the document, with its URL as the source
+        assert_eq!(from_pasteboard(&url, Some("Quarterly results")), None);
+        assert_eq!(from_pasteboard("file:///no/such/file", None), None);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(999), "999 bytes");
+        assert_eq!(format_size(1_500), "1.5 KB");
+        assert_eq!(format_size(2_340_000_000), "2.3 GB");
+    }
+}
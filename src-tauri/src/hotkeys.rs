@@ -0,0 +1,536 @@
+// Global hotkeys, beyond the spotlight's activation shortcut: copying or pasting one of the most
+// recent clips, without opening Stacks, pausing capture, quick access to a stack, opening Stacks
+// on it or pasting its top item, and quick paste, pasting the nth item of the stack Stacks is on,
+// as it's filtered, e.g. with Cmd+1..9. Hotkeys are kept in settings, as accelerators, e.g.
+// "CmdOrCtrl+Shift+V", and are registered when they're set, and at startup. Quick paste's hotkeys
+// are only registered while Stacks' window is shown, so the rest of the time they're left to other
+// apps.
+//
+//     [{"accelerator": "Cmd+Shift+V", "action": {"type": "paste", "nth": 2}}]
+
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use tauri::{GlobalShortcutManager, Manager};
+
+use crate::commands;
+use crate::error::StacksError;
+use crate::events;
+use crate::spotlight;
+use crate::spotlight::Shortcut;
+use crate::state::{SharedState, State};
+use crate::tray;
+use crate::usage::Access;
+
+lazy_static! {
+    // the accelerators registered by register, to unregister when hotkeys change
+    static ref REGISTERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static ref QUICK_PASTE: Mutex<QuickPaste> = Mutex::new(QuickPaste::default());
+}
+
+#[derive(Debug, Default)]
+struct QuickPaste {
+    hotkeys: Vec<Hotkey>,
+    // whether Stacks' window is shown, and so whether the hotkeys should be registered
+    visible: bool,
+    registered: Vec<String>,
+}
+
+impl QuickPaste {
+    // Registers the hotkeys if the window is shown, or unregisters them if it isn't. Returns the
+    // hotkeys which couldn't be registered.
+    fn sync(&mut self, app: &tauri::AppHandle) -> Vec<String> {
+        let mut manager = app.global_shortcut_manager();
+        let mut errors = Vec::new();
+        if !self.visible {
+            for accelerator in self.registered.drain(..) {
+                let _ = manager.unregister(&accelerator);
+            }
+            return errors;
+        }
+        for hotkey in &self.hotkeys {
+            if self.registered.contains(&hotkey.accelerator) {
+                continue;
+            }
+            let (handle, action) = (app.clone(), hotkey.action.clone());
+            match manager.register(&hotkey.accelerator, move || run(&handle, &action)) {
+                Ok(()) => self.registered.push(hotkey.accelerator.clone()),
+                Err(e) => errors.push(format!("{}: {}", hotkey.accelerator, e)),
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    // the nth most recent clip: 1 is the latest, 2 the one before it
+    Copy { nth: usize },
+    // copies the clip, then pastes it into the frontmost app
+    Paste { nth: usize },
+    ToggleCapture,
+    // opens Stacks on the stack, or, with paste, pastes its top item
+    Stack { stack_id: Scru128Id, paste: bool },
+    // pastes the nth item of the focused stack, as shown, with the current filter
+    QuickPaste { nth: usize },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hotkey {
+    pub accelerator: String,
+    pub action: Action,
+}
+
+const MODIFIERS: [&str; 4] = ["shift", "control", "alt", "super"];
+
+// CmdOrCtrl is Command on macOS, and Control elsewhere
+const CMD_OR_CTRL: &str = if cfg!(target_os = "macos") {
+    "super"
+} else {
+    "control"
+};
+
+// The accelerator's modifiers, in a fixed order, and its key, so accelerators which are spelled
+// differently, e.g. "Cmd+Shift+V" and "shift+command+v", compare equal
+pub fn normalize(accelerator: &str) -> Result<String, String> {
+    let mut modifiers = Vec::new();
+    let mut key = None;
+    for part in accelerator
+        .split('+')
+        .map(|part| part.trim().to_lowercase())
+    {
+        let modifier = match part.as_str() {
+            "shift" => "shift",
+            "ctrl" | "control" => "control",
+            "alt" | "option" => "alt",
+            "super" | "cmd" | "command" | "meta" => "super",
+            "cmdorctrl" | "commandorcontrol" | "cmdorcontrol" | "commandorctrl" => CMD_OR_CTRL,
+            "" => return Err(format!("empty key in {:?}", accelerator)),
+            _ => {
+                if key.replace(part).is_some() {
+                    return Err(format!("more than one key in {:?}", accelerator));
+                }
+                continue;
+            }
+        };
+        if !modifiers.contains(&modifier) {
+            modifiers.push(modifier);
+        }
+    }
+    let key = key.ok_or_else(|| format!("no key in {:?}", accelerator))?;
+    let mut parts: Vec<_> = MODIFIERS
+        .into_iter()
+        .filter(|modifier| modifiers.contains(modifier))
+        .collect();
+    parts.push(&key);
+    Ok(parts.join("+"))
+}
+
+// Hotkeys which can't be registered: accelerators which don't parse, or which are taken by
+// another hotkey, or by the activation shortcut
+pub fn conflicts(hotkeys: &[Hotkey], activation: &Shortcut) -> Vec<String> {
+    let mut taken = vec![normalize(&activation.to_macos_shortcut()).unwrap()];
+    let mut conflicts = Vec::new();
+    for hotkey in hotkeys {
+        match normalize(&hotkey.accelerator) {
+            Ok(normalized) if taken.contains(&normalized) => {
+                conflicts.push(format!("{} is already in use", hotkey.accelerator))
+            }
+            Ok(normalized) => taken.push(normalized),
+            Err(e) => conflicts.push(e),
+        }
+        if let Action::Copy { nth: 0 } | Action::Paste { nth: 0 } | Action::QuickPaste { nth: 0 } =
+            hotkey.action
+        {
+            conflicts.push(format!("{}: nth starts at 1", hotkey.accelerator));
+        }
+    }
+    conflicts
+}
+
+pub fn get(state: &State) -> Vec<Hotkey> {
+    state
+        .store
+        .settings_get()
+        .and_then(|settings| settings.hotkeys)
+        .unwrap_or_default()
+}
+
+// Saves the hotkeys, unless any of them conflict
+pub fn set(state: &mut State, hotkeys: Vec<Hotkey>) -> Result<(), StacksError> {
+    let mut settings = state.store.settings_get().unwrap_or_default();
+    let conflicts = conflicts(
+        &hotkeys,
+        &settings.activation_shortcut.clone().unwrap_or_default(),
+    );
+    if !conflicts.is_empty() {
+        return Err(StacksError::invalid_input(conflicts.join(", ")));
+    }
+    settings.hotkeys = Some(hotkeys);
+    state.store.settings_save(settings);
+    Ok(())
+}
+
+// Sets the stack's hotkey, in place of the one it had, or removes it
+pub fn set_stack(
+    state: &mut State,
+    stack_id: Scru128Id,
+    accelerator: Option<String>,
+    paste: bool,
+) -> Result<(), StacksError> {
+    if !state
+        .view
+        .items
+        .get(&stack_id)
+        .is_some_and(|item| item.is_stack)
+    {
+        return Err(StacksError::stack_not_found(&stack_id));
+    }
+    let mut hotkeys: Vec<_> = get(state)
+        .into_iter()
+        .filter(
+            |hotkey| !matches!(hotkey.action, Action::Stack { stack_id: id, .. } if id == stack_id),
+        )
+        .collect();
+    if let Some(accelerator) = accelerator {
+        hotkeys.push(Hotkey {
+            accelerator,
+            action: Action::Stack { stack_id, paste },
+        });
+    }
+    set(state, hotkeys)
+}
+
+// Binds quick paste to the modifiers and 1 to 9, e.g. "Cmd" for Cmd+1..9, in place of the
+// accelerators it was bound to, or unbinds it
+pub fn set_quick_paste(state: &mut State, modifiers: Option<String>) -> Result<(), StacksError> {
+    let mut hotkeys: Vec<_> = get(state)
+        .into_iter()
+        .filter(|hotkey| !matches!(hotkey.action, Action::QuickPaste { .. }))
+        .collect();
+    if let Some(modifiers) = modifiers {
+        hotkeys.extend((1..=9).map(|nth| Hotkey {
+            accelerator: format!("{}+{}", modifiers, nth),
+            action: Action::QuickPaste { nth },
+        }));
+    }
+    set(state, hotkeys)
+}
+
+// The nth item of the focused item's stack, or of the first stack, in the UI's view, so it's
+// ordered and filtered as the user sees it
+pub fn quick_paste_item(state: &State, nth: usize) -> Option<Scru128Id> {
+    let view = &state.ui.view;
+    let stack_id = match &state.ui.focused {
+        Some(focus) if focus.item.is_stack => Some(focus.item.id),
+        Some(focus) => focus.item.stack_id,
+        None => None,
+    }
+    .or_else(|| view.root().first().map(|stack| stack.id))?;
+    let stack = view.items.get(&stack_id)?;
+    view.children(stack)
+        .into_iter()
+        .filter(|id| view.items.get(id).is_some_and(|item| !item.is_stack))
+        .nth(nth.checked_sub(1)?)
+}
+
+// The stack's first item, in the stack's order
+pub fn top_item(state: &State, stack_id: &Scru128Id) -> Option<Scru128Id> {
+    let stack = state.view.items.get(stack_id)?;
+    state
+        .view
+        .children(stack)
+        .into_iter()
+        .find(|id| state.view.items.get(id).is_some_and(|item| !item.is_stack))
+}
+
+// The nth most recently touched clip
+pub fn nth_recent(state: &State, nth: usize) -> Option<Scru128Id> {
+    tray::recent_items(state)
+        .get(nth.checked_sub(1)?)
+        .map(|item| item.id)
+}
+
+fn paste() {
+    if !spotlight::is_accessibility_trusted() {
+        tracing::warn!(
+            name = "hotkeys",
+            "accessibility access is required to paste"
+        );
+        return;
+    }
+    // let the hotkey's modifiers be released, so they aren't sent with the paste
+    tauri::async_runtime::spawn(async {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        spotlight::send_paste_keystroke();
+    });
+}
+
+fn run(app: &tauri::AppHandle, action: &Action) {
+    let state = app.state::<SharedState>();
+    match action {
+        Action::Copy { nth } | Action::Paste { nth } => {
+            let copied = state.with_lock(|state| {
+                let id = nth_recent(state, *nth)?;
+                let change_num = commands::copy_to_clipboard(state, &id)?;
+                let access = match action {
+                    Action::Paste { .. } => Access::Paste,
+                    _ => Access::Copy,
+                };
+                state.record_usage(id, access);
+                Some(change_num)
+            });
+            if copied.is_some() && matches!(action, Action::Paste { .. }) {
+                paste();
+            }
+        }
+        Action::ToggleCapture => tray::toggle_capture(app),
+        Action::Stack {
+            stack_id,
+            paste: true,
+        } => {
+            let copied = state.with_lock(|state| {
+                let id = top_item(state, stack_id)?;
+                let change_num = commands::copy_to_clipboard(state, &id)?;
+                state.record_usage(id, Access::Paste);
+                Some(change_num)
+            });
+            if copied.is_some() {
+                paste();
+            }
+        }
+        Action::QuickPaste { nth } => {
+            let Some(id) = state.with_lock(|state| quick_paste_item(state, *nth)) else {
+                return;
+            };
+            if let Err(e) = commands::paste_to_frontmost(app, &state, id) {
+                tracing::warn!(name = "hotkeys", %e, "couldn't paste");
+            }
+        }
+        Action::Stack { stack_id, .. } => {
+            // clear any filter, so the stack is shown, and focus it
+            state.with_lock(|state| {
+                state.ui.reset(state.view.clone());
+                state.nav_select(stack_id);
+            });
+            spotlight::show(&spotlight::window(app)).unwrap();
+            events::emit(app, "refresh-items", true);
+        }
+    }
+}
+
+// Registers the hotkeys, in place of those registered before. Hotkeys which can't be registered,
+// e.g. as another app has taken them, are skipped, and returned as errors.
+pub fn register(app: &tauri::AppHandle, hotkeys: &[Hotkey]) -> Result<(), String> {
+    let mut manager = app.global_shortcut_manager();
+    let mut registered = REGISTERED.lock().unwrap();
+    for accelerator in registered.drain(..) {
+        let _ = manager.unregister(&accelerator);
+    }
+    let (quick_paste, hotkeys): (Vec<_>, Vec<_>) = hotkeys
+        .iter()
+        .cloned()
+        .partition(|hotkey| matches!(hotkey.action, Action::QuickPaste { .. }));
+    let mut errors = {
+        let mut state = QUICK_PASTE.lock().unwrap();
+        for accelerator in state.registered.drain(..) {
+            let _ = manager.unregister(&accelerator);
+        }
+        state.hotkeys = quick_paste;
+        state.sync(app)
+    };
+    for hotkey in hotkeys {
+        let (handle, action) = (app.clone(), hotkey.action.clone());
+        match manager.register(&hotkey.accelerator, move || run(&handle, &action)) {
+            Ok(()) => registered.push(hotkey.accelerator.clone()),
+            Err(e) => errors.push(format!("{}: {}", hotkey.accelerator, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+// Registers quick paste's hotkeys as Stacks' window is shown, and unregisters them as it's hidden.
+// This is handed off, as the window is hidden from within a quick paste hotkey's handler, which
+// can't unregister its own hotkey.
+pub fn set_visible(app: &tauri::AppHandle, visible: bool) {
+    QUICK_PASTE.lock().unwrap().visible = visible;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        for e in QUICK_PASTE.lock().unwrap().sync(&app) {
+            tracing::warn!(name = "hotkeys", %e, "couldn't register quick paste");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::MimeType;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Cmd+Shift+V"), Ok("shift+super+v".to_string()));
+        assert_eq!(normalize(" shift + command + v "), normalize("Cmd+Shift+V"));
+        assert_eq!(
+            normalize("CmdOrCtrl+Alt+1"),
+            normalize("CommandOrControl+Option+1")
+        );
+        assert!(normalize("Cmd+Shift").is_err());
+        assert!(normalize("Cmd+V+C").is_err());
+        assert!(normalize("Cmd++").is_err());
+    }
+
+    #[test]
+    fn test_cmd_or_ctrl() {
+        // the default hotkeys use Control on Linux
+        let expected = if cfg!(target_os = "macos") {
+            "shift+super+v"
+        } else {
+            "shift+control+v"
+        };
+        assert_eq!(normalize("CmdOrCtrl+Shift+V"), Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let hotkey = |accelerator: &str, action| Hotkey {
+            accelerator: accelerator.to_string(),
+            action,
+        };
+        let activation = Shortcut::default();
+        let hotkeys = vec![
+            hotkey("Cmd+Shift+C", Action::Copy { nth: 2 }),
+            hotkey("Cmd+Shift+V", Action::Paste { nth: 2 }),
+            hotkey("Cmd+Shift+P", Action::ToggleCapture),
+        ];
+        assert!(conflicts(&hotkeys, &activation).is_empty());
+
+        let hotkeys = vec![
+            hotkey("Cmd+Shift+V", Action::Paste { nth: 2 }),
+            hotkey("shift+command+v", Action::ToggleCapture),
+            // the default activation shortcut
+            hotkey("Ctrl+Space", Action::Copy { nth: 1 }),
+            hotkey("Cmd+1", Action::Copy { nth: 0 }),
+        ];
+        assert_eq!(
+            conflicts(&hotkeys, &activation),
+            vec![
+                "shift+command+v is already in use",
+                "Ctrl+Space is already in use",
+                "Cmd+1: nth starts at 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack_id = state.get_curr_stack();
+        let ids: Vec<_> = [b"one", b"two"]
+            .into_iter()
+            .map(|content| {
+                let packet = state.store.add(content, MimeType::TextPlain, stack_id);
+                state.merge(&packet);
+                packet.id
+            })
+            .collect();
+        assert_eq!(nth_recent(&state, 1), Some(ids[1]));
+        assert_eq!(nth_recent(&state, 2), Some(ids[0]));
+        assert!(nth_recent(&state, 3).is_none());
+        assert!(nth_recent(&state, 0).is_none());
+
+        let hotkeys = vec![Hotkey {
+            accelerator: "Cmd+Shift+V".to_string(),
+            action: Action::Paste { nth: 2 },
+        }];
+        set(&mut state, hotkeys.clone()).unwrap();
+        assert_eq!(get(&state), hotkeys);
+
+        let mut conflicting = hotkeys.clone();
+        conflicting.push(Hotkey {
+            accelerator: "Shift+Cmd+V".to_string(),
+            action: Action::ToggleCapture,
+        });
+        assert!(set(&mut state, conflicting).is_err());
+        assert_eq!(get(&state), hotkeys);
+
+        // a stack has one hotkey at most
+        assert_eq!(top_item(&state, &stack_id), Some(ids[1]));
+        set_stack(&mut state, stack_id, Some("Cmd+1".to_string()), false).unwrap();
+        set_stack(&mut state, stack_id, Some("Cmd+2".to_string()), true).unwrap();
+        let stack_hotkey = Hotkey {
+            accelerator: "Cmd+2".to_string(),
+            action: Action::Stack {
+                stack_id,
+                paste: true,
+            },
+        };
+        assert_eq!(get(&state), vec![hotkeys[0].clone(), stack_hotkey]);
+        assert!(set_stack(&mut state, stack_id, Some("Cmd+Shift+V".to_string()), false).is_err());
+        assert!(set_stack(&mut state, ids[0], Some("Cmd+3".to_string()), false).is_err());
+        set_stack(&mut state, stack_id, None, false).unwrap();
+        assert_eq!(get(&state), hotkeys);
+    }
+
+    #[test]
+    fn test_quick_paste() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack_id = state.get_curr_stack();
+        let ids: Vec<_> = [b"apple", b"grape", b"peach"]
+            .into_iter()
+            .map(|content| {
+                let packet = state.store.add(content, MimeType::TextPlain, stack_id);
+                state.merge(&packet);
+                packet.id
+            })
+            .collect();
+        assert_eq!(quick_paste_item(&state, 1), Some(ids[2]));
+        assert_eq!(quick_paste_item(&state, 3), Some(ids[0]));
+        assert_eq!(quick_paste_item(&state, 4), None);
+
+        // as filtered
+        state.nav_set_filter("ap", "All");
+        assert_eq!(quick_paste_item(&state, 1), Some(ids[1]));
+        assert_eq!(quick_paste_item(&state, 2), Some(ids[0]));
+
+        set_quick_paste(&mut state, Some("Cmd".to_string())).unwrap();
+        set_quick_paste(&mut state, Some("Cmd+Alt".to_string())).unwrap();
+        let hotkeys = get(&state);
+        assert_eq!(hotkeys.len(), 9);
+        assert_eq!(hotkeys[0].accelerator, "Cmd+Alt+1");
+        assert_eq!(hotkeys[8].action, Action::QuickPaste { nth: 9 });
+        // modifiers only: Ctrl+Space+1 has two keys
+        let err = set_quick_paste(&mut state, Some("Ctrl+Space".to_string())).unwrap_err();
+        assert!(err.message.contains("more than one key"), "{}", err);
+        set_quick_paste(&mut state, None).unwrap();
+        assert!(get(&state).is_empty());
+
+        // taken by another hotkey
+        set(
+            &mut state,
+            vec![Hotkey {
+                accelerator: "Cmd+Shift+2".to_string(),
+                action: Action::Copy { nth: 1 },
+            }],
+        )
+        .unwrap();
+        let err = set_quick_paste(&mut state, Some("Cmd+Shift".to_string())).unwrap_err();
+        assert_eq!(err.message, "Cmd+Shift+2 is already in use");
+    }
+}
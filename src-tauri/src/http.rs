@@ -1,29 +1,175 @@
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use tauri::Manager;
+use std::sync::{Arc, Mutex};
 
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Error, Method, Request, Response, Server, StatusCode};
 
+use lazy_static::lazy_static;
+use tokio::sync::oneshot;
+
 use tracing::error;
 
-use crate::state::SharedState;
-use crate::store::{infer_mime_type, InProgressStream, MimeType};
-use crate::ui::generate_preview;
+use crate::batch;
+use crate::capture;
+use crate::commands;
+use crate::events;
+use crate::ingest;
+use crate::paste;
+use crate::share;
+use crate::share::AuthError;
+use crate::state::{SharedState, State};
+use crate::store::{
+    infer_mime_type, write_blob, InProgressStream, MimeType, Settings, Tokenizer,
+    DEFAULT_THUMBNAIL_WIDTH,
+};
+use crate::ui::{with_meta, PreviewLimits, StreamPreview};
+use crate::upload;
+use crate::ws;
+
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq)]
+pub struct RouteMetrics {
+    pub requests: u64,
+    // responses with a 4xx or 5xx status
+    pub errors: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<HashMap<String, RouteMetrics>> = Mutex::new(HashMap::new());
+}
+
+// Returns a snapshot of the per-route metrics
+pub fn metrics() -> HashMap<String, RouteMetrics> {
+    METRICS.lock().unwrap().clone()
+}
+
+// Metrics are keyed by route, rather than path, so ids and share tokens don't each get a counter
+// (or end up in the logs)
+fn route(method: &Method, path: &str) -> String {
+    let route = match path {
+        "/" => "/",
+        "/metrics" => "/metrics",
+        "/ingest" => "/ingest",
+        "/capture" => "/capture",
+        "/items" => "/items",
+        "/ws" => "/ws",
+        "/batch" => "/batch",
+        _ => match path.strip_prefix("/share/") {
+            Some(share) if share.contains('/') => "/share/:token/:id",
+            Some(_) => "/share/:token",
+            None if scru128::Scru128Id::from_str(&path[1..]).is_ok() => "/:id",
+            None if thumbnail_id(path).is_some() => "/:id/thumb",
+            None => "unknown",
+        },
+    };
+    format!("{} {}", method, route)
+}
+
+async fn handle_logged(
+    req: Request<Body>,
+    context: Context,
+    client: String,
+) -> Result<Response<Body>, Error> {
+    let start = std::time::Instant::now();
+    let method = req.method().clone();
+    let route = route(&method, req.uri().path());
+
+    let api_token = context.api_token.as_deref().map(String::as_str);
+    let res = handle(req, context.state, context.app_handle, api_token).await;
+
+    let status = match &res {
+        Ok(res) => res.status(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    tracing::info!(
+        name = "http::request",
+        %method,
+        route,
+        status = status.as_u16(),
+        latency_ms,
+        %client,
+    );
+
+    let mut metrics = METRICS.lock().unwrap();
+    let metrics = metrics.entry(route).or_default();
+    metrics.requests += 1;
+    if status.is_client_error() || status.is_server_error() {
+        metrics.errors += 1;
+    }
+    metrics.total_latency_ms += latency_ms;
+    metrics.max_latency_ms = metrics.max_latency_ms.max(latency_ms);
+
+    res
+}
 
 async fn handle(
     req: Request<Body>,
     state: SharedState,
     app_handle: tauri::AppHandle,
+    api_token: Option<&str>,
 ) -> Result<Response<Body>, Error> {
     let path = req.uri().path();
     let id = path
         .strip_prefix("/")
         .and_then(|id| scru128::Scru128Id::from_str(id).ok());
 
+    if needs_api_token(path) {
+        // the token couldn't be read, so no one could have it: see ServerInfo::error
+        let Some(api_token) = api_token else {
+            return Ok(status(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "API token unavailable",
+            ));
+        };
+        if !is_authorized(&req, api_token) {
+            return Ok(status(StatusCode::UNAUTHORIZED, "Unauthorized"));
+        }
+    }
+
+    if let Some(id) = thumbnail_id(path) {
+        if req.method() != Method::GET {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"));
+        }
+        let width = match query_param(req.uri().query(), "w") {
+            None => DEFAULT_THUMBNAIL_WIDTH,
+            Some(w) => match w.parse() {
+                Ok(width) => width,
+                Err(_) => return Ok(status(StatusCode::BAD_REQUEST, "Bad Request")),
+            },
+        };
+        return get_thumbnail(id, width, state).await;
+    }
+
+    if let Some(share) = path.strip_prefix("/share/") {
+        if req.method() != Method::GET {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"));
+        }
+        return get_shared(share, state).await;
+    }
+
     match (req.method(), id) {
         (&Method::GET, Some(id)) => get(id, state).await,
+        (&Method::PUT, Some(id)) => put(id, req, state, app_handle).await,
         (&Method::POST, None) if path == "/" => post(req, state.clone(), app_handle.clone()).await,
+        (&Method::POST, None) if path == "/ingest" => post_ingest(req, state, app_handle).await,
+        (&Method::POST, None) if path == "/capture" => post_capture(req, state, app_handle).await,
+        (&Method::GET, None) if path == "/items" => Ok(get_items(req.uri().query(), state)),
+        (&Method::POST, None) if path == "/batch" => post_batch(req, state, app_handle).await,
+        (&Method::GET, None) if path == "/ws" => Ok(ws::upgrade(req, state, app_handle)),
+        (&Method::GET, None) if path == "/metrics" => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&metrics()).unwrap()))
+            .unwrap()),
         _ => Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body(Body::from("Not Found"))
@@ -31,8 +177,128 @@ async fn handle(
     }
 }
 
+fn thumbnail_id(path: &str) -> Option<scru128::Scru128Id> {
+    let id = path.strip_prefix('/')?.strip_suffix("/thumb")?;
+    scru128::Scru128Id::from_str(id).ok()
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// Query parameters are form encoded: spaces as +, everything else percent encoded
+fn query_value(query: Option<&str>, name: &str) -> Option<String> {
+    paste::url_decode(&query_param(query, name)?.replace('+', " "))
+}
+
+// Every route takes the API token, other than shared stacks, which take their share token: see
+// get_shared
+fn needs_api_token(path: &str) -> bool {
+    !path.starts_with("/share/")
+}
+
+fn is_authorized(req: &Request<Body>, api_token: &str) -> bool {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // browsers can't set headers on WebSocket requests, so there the token can be a parameter
+    let token = match req.uri().path() {
+        "/ws" => bearer.or(query_param(req.uri().query(), "token")),
+        _ => bearer,
+    };
+    token.is_some_and(|token| token == api_token)
+}
+
+fn status(code: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+// Resolves /share/<token>[/<id>] to the shared stack, and the item asked for, if it's in the
+// stack. Unknown, and revoked, tokens are unauthorized; expired ones are gone
+fn resolve_share(
+    state: &State,
+    path: &str,
+    now: u64,
+) -> Result<(scru128::Scru128Id, Option<scru128::Scru128Id>), StatusCode> {
+    let (token, id) = match path.split_once('/') {
+        Some((token, id)) => match scru128::Scru128Id::from_str(id) {
+            Ok(id) => (token, Some(id)),
+            Err(_) => return Err(StatusCode::NOT_FOUND),
+        },
+        None => (path, None),
+    };
+
+    let stack_id = match share::authorize(&state.store.share_tokens_get(), token, now) {
+        Ok(stack_id) => stack_id,
+        Err(AuthError::Unknown) => return Err(StatusCode::UNAUTHORIZED),
+        Err(AuthError::Expired) => return Err(StatusCode::GONE),
+    };
+    if let Some(id) = id {
+        let in_stack = state
+            .view
+            .items
+            .get(&id)
+            .is_some_and(|item| item.stack_id == Some(stack_id));
+        if !in_stack {
+            return Err(StatusCode::NOT_FOUND);
+        }
+    }
+    Ok((stack_id, id))
+}
+
+// Shared stacks are read-only: /share/<token> lists the stack's items, and
+// /share/<token>/<id> returns an item's content
+async fn get_shared(path: &str, state: SharedState) -> Result<Response<Body>, Error> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let (stack_id, id) = match state.with_read(|state| resolve_share(state, path, now)) {
+        Ok(share) => share,
+        Err(StatusCode::UNAUTHORIZED) => {
+            return Ok(status(StatusCode::UNAUTHORIZED, "Unauthorized"))
+        }
+        Err(StatusCode::GONE) => return Ok(status(StatusCode::GONE, "Share Expired")),
+        Err(_) => return Ok(status(StatusCode::NOT_FOUND, "Not Found")),
+    };
+
+    match id {
+        Some(id) => get(id, state).await,
+        None => {
+            let items = state.with_read(|state| {
+                let stack = state.view.items.get(&stack_id)?;
+                let items: Vec<_> = state
+                    .view
+                    .children(stack)
+                    .iter()
+                    .filter_map(|id| state.view.items.get(id))
+                    .map(|item| with_meta(&state.store, item))
+                    .collect();
+                Some(items)
+            });
+            match items {
+                Some(items) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(serde_json::to_string(&items).unwrap()))
+                    .unwrap()),
+                None => Ok(status(StatusCode::NOT_FOUND, "Not Found")),
+            }
+        }
+    }
+}
+
 async fn get(id: scru128::Scru128Id, state: SharedState) -> Result<Response<Body>, Error> {
-    let (item, meta) = state.with_lock(|state| {
+    let (item, meta) = state.with_read(|state| {
         let item = state.view.items.get(&id).cloned();
         let meta = item
             .as_ref()
@@ -40,19 +306,25 @@ async fn get(id: scru128::Scru128Id, state: SharedState) -> Result<Response<Body
         (item, meta)
     });
 
+    // a file reference's content is the reference, not the file it refers to, which could be
+    // anywhere on disk
     match item {
         Some(item) => {
-            let cache_path = state.with_lock(|state| state.store.cache_path.clone());
-            let reader = cacache::Reader::open_hash(cache_path, item.hash)
-                .await
-                .unwrap();
+            let cache_path = state.with_read(|state| state.store.cache_path.clone());
+            let reader = match cacache::Reader::open_hash(cache_path, item.hash).await {
+                Ok(reader) => reader,
+                Err(e) => {
+                    error!("failed to open content of {}: {}", id, e);
+                    return Ok(status(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal Server Error",
+                    ));
+                }
+            };
             let stream = Body::wrap_stream(tokio_util::io::ReaderStream::new(reader));
 
             let content_type = match meta {
-                Some(meta) => match meta.mime_type {
-                    MimeType::TextPlain => "text/plain",
-                    MimeType::ImagePng => "image/png",
-                },
+                Some(meta) => meta.mime_type.as_str(),
                 None => "application/octet-stream",
             };
 
@@ -69,23 +341,201 @@ async fn get(id: scru128::Scru128Id, state: SharedState) -> Result<Response<Body
     }
 }
 
+// /<id>/thumb?w=<width> returns a PNG thumbnail of an image item, generated on first request
+async fn get_thumbnail(
+    id: scru128::Scru128Id,
+    width: u32,
+    state: SharedState,
+) -> Result<Response<Body>, Error> {
+    let thumbnail = state.with_read(|state| {
+        let item = state.view.items.get(&id)?;
+        state.store.thumbnail(&item.hash, width)
+    });
+    // generated off the lock, as large images are slow to scale
+    let path = match thumbnail {
+        Some(thumbnail) => tokio::task::spawn_blocking(move || thumbnail.generate())
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let png = match path {
+        Some(path) => tokio::fs::read(path).await.ok(),
+        None => None,
+    };
+    match png {
+        Some(png) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "image/png")
+            .body(Body::from(png))
+            .unwrap()),
+        None => Ok(status(StatusCode::NOT_FOUND, "Not Found")),
+    }
+}
+
 async fn post(
     req: Request<Body>,
     state: SharedState,
     app_handle: tauri::AppHandle,
 ) -> Result<Response<Body>, Error> {
+    // text is streamed in as it arrives: anything else is buffered, and stored by what it is
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    match content_type {
+        Some(content_type) if content_type.starts_with("multipart/form-data") => {
+            return post_multipart(req, &content_type, state, app_handle).await
+        }
+        Some(content_type) if !upload::is_text(&content_type) => {
+            let max = state.with_read(|state| upload::max_bytes(&state.store));
+            let Some(body) = read_limited(req.into_body(), max).await? else {
+                return Ok(status(StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"));
+            };
+            let res = upload::add(&state, body, Some(content_type), None).await;
+            return Ok(uploaded(
+                &app_handle,
+                res.map(|id| id.into_iter().collect()),
+            ));
+        }
+        _ => {}
+    }
+
+    let (previewer, tokenizer) = state.with_lock(|state| {
+        let limits = PreviewLimits::from_settings(state.store.settings_get());
+        let previewer = StreamPreview::new(limits, &state.ui.theme.syntax);
+        (previewer, state.store.tokenizer_get())
+    });
     let mut streamer = state.with_lock(|state| {
         let stack = state.get_curr_stack();
         state.ui.select(None); // focus first
         let (mime_type, content_type) = infer_mime_type("".as_bytes(), MimeType::TextPlain);
         let streamer = InProgressStream::new(stack, mime_type, content_type);
         state.merge(&streamer.packet);
-        app_handle.emit_all("refresh-items", true).unwrap();
+        events::emit(&app_handle, "refresh-items", true);
         streamer
     });
 
-    let mut bytes_stream = req.into_body();
+    let stack_id = streamer.packet.stack_id;
+    stream_in(
+        &mut streamer,
+        req.into_body(),
+        stack_id,
+        previewer,
+        tokenizer,
+        &app_handle,
+    )
+    .await;
+
+    if let Err(e) = end_stream(&mut streamer, &state).await {
+        error!("failed to store stream: {}", e);
+        return Ok(status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+        ));
+    }
+    events::emit(&app_handle, "refresh-items", true);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(streamer.packet.id.to_string()))
+        .unwrap())
+}
+
+// PUT /<id> replaces the item's content, as a new version of the item. With ?append=true, the
+// body is streamed onto the end of the item's content instead, e.g. to tail a log into it.
+async fn put(
+    id: scru128::Scru128Id,
+    req: Request<Body>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Result<Response<Body>, Error> {
+    let append = query_param(req.uri().query(), "append") == Some("true");
+    let found = state.with_lock(|state| {
+        let item = state.view.items.get(&id).filter(|item| !item.is_stack)?;
+        let meta = state.store.get_content_meta(&item.hash)?;
+        let content = state.store.get_content(&item.hash)?;
+        Some((item.stack_id, meta, content, state.is_read_only(&id)))
+    });
+    let Some((stack_id, meta, content, read_only)) = found else {
+        return Ok(status(StatusCode::NOT_FOUND, "Not Found"));
+    };
+    if read_only {
+        return Ok(status(StatusCode::FORBIDDEN, "Read Only"));
+    }
+    if meta.mime_type != MimeType::TextPlain {
+        return Ok(status(
+            StatusCode::CONFLICT,
+            "Only text items can be changed",
+        ));
+    }
+
+    if !append {
+        let body = hyper::body::to_bytes(req.into_body()).await?;
+        if state
+            .with_lock(|state| commands::edit_item(state, id, &body))
+            .is_none()
+        {
+            return Ok(status(StatusCode::NOT_FOUND, "Not Found"));
+        }
+        events::emit(&app_handle, "refresh-items", true);
+        return Ok(status(StatusCode::OK, ""));
+    }
 
+    let (previewer, tokenizer) = state.with_lock(|state| {
+        let limits = PreviewLimits::from_settings(state.store.settings_get());
+        let previewer = StreamPreview::new(limits, &state.ui.theme.syntax);
+        (previewer, state.store.tokenizer_get())
+    });
+    let mut streamer = InProgressStream::append_to(id, meta, content);
+    state.with_lock(|state| state.merge(&streamer.packet));
+    events::emit(&app_handle, "refresh-items", true);
+
+    stream_in(
+        &mut streamer,
+        req.into_body(),
+        stack_id,
+        previewer,
+        tokenizer,
+        &app_handle,
+    )
+    .await;
+
+    if let Err(e) = end_stream(&mut streamer, &state).await {
+        error!("failed to store stream: {}", e);
+        return Ok(status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal Server Error",
+        ));
+    }
+    events::emit(&app_handle, "refresh-items", true);
+    Ok(status(StatusCode::OK, ""))
+}
+
+// Writes what was streamed in to the content store, without the state lock, then stores the item
+async fn end_stream(streamer: &mut InProgressStream, state: &SharedState) -> Result<(), String> {
+    let cache_path = state.with_read(|state| state.store.cache_path.clone());
+    let content = std::mem::take(&mut streamer.content);
+    let (hash, content) = write_blob(cache_path, content).await?;
+    streamer.content = content;
+    state.with_lock(|state| {
+        let packet = streamer.end_stream_written(&mut state.store, hash);
+        state.merge(&packet);
+        state.store.insert_packet(&packet);
+    });
+    Ok(())
+}
+
+// Streams the body into the item, sending its content to the webviews as each chunk arrives
+async fn stream_in(
+    streamer: &mut InProgressStream,
+    mut bytes_stream: Body,
+    stack_id: Option<scru128::Scru128Id>,
+    mut previewer: StreamPreview,
+    tokenizer: Tokenizer,
+    app_handle: &tauri::AppHandle,
+) {
     #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
     pub struct Content {
         pub mime_type: MimeType,
@@ -94,6 +544,8 @@ async fn post(
         pub tiktokens: usize,
         pub words: usize,
         pub chars: usize,
+        pub lines: usize,
+        pub bytes: u64,
         pub preview: String,
     }
 
@@ -101,66 +553,681 @@ async fn post(
         match chunk {
             Ok(chunk) => {
                 streamer.append(&chunk);
-                let preview = generate_preview(
-                    "dark",
-                    &Some(streamer.content.clone()),
-                    &MimeType::TextPlain,
-                    &"Text".to_string(),
-                    true,
-                );
+                streamer.count_tiktokens(&chunk, tokenizer);
+                let (preview, _) =
+                    previewer.update(&streamer.content, &MimeType::TextPlain, &"Text".to_string());
 
-                let content = String::from_utf8_lossy(&streamer.content);
                 let content = Content {
                     mime_type: MimeType::TextPlain,
                     content_type: "Text".to_string(),
-                    terse: content.chars().take(100).collect(),
-                    tiktokens: 0,
-                    words: content.split_whitespace().count(),
-                    chars: content.chars().count(),
-                    preview,
+                    terse: streamer.content_meta.terse.clone(),
+                    tiktokens: streamer.content_meta.tiktokens,
+                    words: streamer.content_meta.stats.words,
+                    chars: streamer.content_meta.stats.chars,
+                    lines: streamer.content_meta.stats.lines,
+                    bytes: streamer.content_meta.stats.bytes,
+                    preview: preview.to_string(),
                 };
 
-                app_handle
-                    .emit_all("streaming", (streamer.packet.id, content))
-                    .unwrap();
+                let scope = events::Scope {
+                    stack_id: stack_id.as_ref(),
+                    mime_type: Some(&streamer.content_meta.mime_type),
+                };
+                events::emit_scoped(
+                    app_handle,
+                    "streaming",
+                    &scope,
+                    (streamer.item_id(), content),
+                );
             }
             Err(e) => {
-                tracing::error!("Error reading bytes from HTTP POST: {}", e);
+                tracing::error!("Error reading bytes from HTTP request: {}", e);
             }
         }
     }
+}
 
-    state.with_lock(|state| {
-        let packet = streamer.end_stream(&mut state.store);
-        state.merge(&packet);
-        state.store.insert_packet(&packet);
+// Each part of a multipart upload, e.g. from `curl -F file=@shot.png`, is added as an item
+async fn post_multipart(
+    req: Request<Body>,
+    content_type: &str,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Result<Response<Body>, Error> {
+    let Ok(boundary) = multer::parse_boundary(content_type) else {
+        return Ok(status(StatusCode::BAD_REQUEST, "Bad Request"));
+    };
+    let max = state.with_read(|state| upload::max_bytes(&state.store));
+    let limit = max.map_or(multer::SizeLimit::new(), |max| {
+        multer::SizeLimit::new().per_field(max as u64)
+    });
+    let constraints = multer::Constraints::new().size_limit(limit);
+    let mut multipart = multer::Multipart::with_constraints(req.into_body(), boundary, constraints);
+    let mut ids = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(_) => return Ok(status(StatusCode::BAD_REQUEST, "Bad Request")),
+        };
+        let file_name = field.file_name().map(str::to_string);
+        let declared = field.content_type().map(|mime| mime.to_string());
+        let content = match field.bytes().await {
+            Ok(content) => content,
+            Err(multer::Error::FieldSizeExceeded { .. }) => {
+                return Ok(status(StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large"))
+            }
+            Err(_) => return Ok(status(StatusCode::BAD_REQUEST, "Bad Request")),
+        };
+        let res = upload::add(&state, content.to_vec(), declared, file_name).await;
+        match res {
+            Ok(id) => ids.extend(id),
+            Err(e) => return Ok(uploaded(&app_handle, Err(e))),
+        }
+    }
+    Ok(uploaded(&app_handle, Ok(ids)))
+}
+
+// Reads the body, or, once it's larger than max, none of it
+async fn read_limited(mut body: Body, max: Option<usize>) -> Result<Option<Vec<u8>>, Error> {
+    use hyper::body::HttpBody;
+
+    let mut content = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if max.is_some_and(|max| content.len() + chunk.len() > max) {
+            return Ok(None);
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(Some(content))
+}
+
+// Responds with the ids of the items added, one per line
+fn uploaded(
+    app_handle: &tauri::AppHandle,
+    res: Result<Vec<scru128::Scru128Id>, String>,
+) -> Response<Body> {
+    let ids = match res {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("failed to store upload: {}", e);
+            return status(StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error");
+        }
+    };
+    if ids.is_empty() {
+        return status(StatusCode::NO_CONTENT, "");
+    }
+    events::emit(app_handle, "refresh-items", true);
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(ids.join("\n")))
+        .unwrap()
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ListedItem {
+    pub id: scru128::Scru128Id,
+    pub stack_id: Option<scru128::Scru128Id>,
+    pub stack: Option<String>,
+    pub content_type: String,
+    pub terse: String,
+}
+
+// GET /items lists items, most recently touched first, as JSON. Query parameters, all optional:
+// q: a filter, as typed into Stacks; stack: a stack's name; limit: defaults to 20
+fn get_items(query: Option<&str>, state: SharedState) -> Response<Body> {
+    let filter = query_value(query, "q").unwrap_or_default();
+    let stack = query_value(query, "stack");
+    let limit = query_value(query, "limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(20);
+
+    let items = state.with_read(|state| {
+        let store = &state.store;
+        let stack_name = |id: &scru128::Scru128Id| {
+            let stack = state.view.items.get(id)?;
+            let name = store.get_content(&stack.hash)?;
+            Some(String::from_utf8_lossy(&name).to_string())
+        };
+        let matches = (!filter.is_empty()).then(|| store.query(&filter, ""));
+        let mut items: Vec<_> = state
+            .view
+            .items
+            .values()
+            .filter(|item| !item.is_stack)
+            .filter(|item| match &matches {
+                Some(matches) => matches.contains(&item.hash),
+                None => true,
+            })
+            .collect();
+        items.sort_by(|a, b| b.last_touched.cmp(&a.last_touched));
+        items
+            .into_iter()
+            .filter_map(|item| {
+                let meta = store.get_content_meta(&item.hash)?;
+                let name = item.stack_id.as_ref().and_then(stack_name);
+                if stack.is_some() && name != stack {
+                    return None;
+                }
+                Some(ListedItem {
+                    id: item.id,
+                    stack_id: item.stack_id,
+                    stack: name,
+                    content_type: meta.content_type,
+                    terse: meta.terse,
+                })
+            })
+            .take(limit)
+            .collect::<Vec<_>>()
     });
-    app_handle.emit_all("refresh-items", true).unwrap();
 
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&items).unwrap()))
+        .unwrap()
+}
+
+// POST /ingest?topic=<topic> takes a JSON payload for the topic: see docs/ingest.md
+async fn post_ingest(
+    req: Request<Body>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Result<Response<Body>, Error> {
+    let Some(topic) = query_param(req.uri().query(), "topic").and_then(ingest::Topic::parse) else {
+        return Ok(status(StatusCode::BAD_REQUEST, "Unknown topic"));
+    };
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let id = match topic {
+        ingest::Topic::Shell => {
+            let Ok(payload) = serde_json::from_slice::<ingest::ShellPayload>(&body) else {
+                return Ok(status(StatusCode::BAD_REQUEST, "Bad Request"));
+            };
+            state.with_lock(|state| ingest::add_shell(state, &payload))
+        }
+    };
+    let Some(id) = id else {
+        return Ok(status(StatusCode::NO_CONTENT, ""));
+    };
+    events::emit(&app_handle, "refresh-items", true);
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .body(Body::from(id.to_string()))
+        .unwrap())
+}
+
+// POST /capture takes a selection from the browser extension, as JSON:
+// {"content": .., "url": .., "title": .., "selection_html": ..}
+async fn post_capture(
+    req: Request<Body>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Result<Response<Body>, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let Ok(selection) = serde_json::from_slice::<capture::Capture>(&body) else {
+        return Ok(status(StatusCode::BAD_REQUEST, "Bad Request"));
+    };
+    let Some(id) = state.with_lock(|state| capture::add(state, &selection)) else {
+        return Ok(status(StatusCode::NO_CONTENT, ""));
+    };
+    events::emit(&app_handle, "refresh-items", true);
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .body(Body::from(streamer.packet.id.to_string()))
+        .body(Body::from(id.to_string()))
         .unwrap())
 }
 
-pub fn start(app_handle: tauri::AppHandle, state: SharedState) {
-    tauri::async_runtime::spawn(async move {
-        let addr = ([127, 0, 0, 1], 9146).into();
-
-        let make_svc = make_service_fn(move |_conn| {
-            let state = state.clone();
-            let app_handle = app_handle.clone();
-            async move {
-                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
-                    handle(req, state.clone(), app_handle.clone())
-                }))
+// POST /batch takes a JSON list of ops, applied together: see batch::Op. The response is the
+// number of ops applied, or the op which couldn't be.
+async fn post_batch(
+    req: Request<Body>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Result<Response<Body>, Error> {
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+    let Ok(ops) = serde_json::from_slice::<Vec<batch::Op>>(&body) else {
+        return Ok(status(StatusCode::BAD_REQUEST, "Bad Request"));
+    };
+    let (code, body) = match state.with_lock(|state| batch::apply(state, &ops)) {
+        Ok(applied) => {
+            events::emit(&app_handle, "refresh-items", true);
+            (StatusCode::OK, serde_json::json!({ "applied": applied }))
+        }
+        Err(e) => (StatusCode::CONFLICT, serde_json::to_value(e).unwrap()),
+    };
+    Ok(Response::builder()
+        .status(code)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap())
+}
+
+// The socket is in a directory only the user can open, so it's never reachable by others, even
+// before its own permissions are set
+pub const UNIX_SOCKET_DIR: &str = "socket";
+pub const UNIX_SOCKET_FILE: &str = "stacks.sock";
+
+// Where the server is listening is written to this file in the store's directory, so clients
+// can find it, e.g. when the configured port was taken
+pub const DISCOVERY_FILE: &str = "server.json";
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ServerInfo {
+    pub addr: Option<SocketAddr>,
+    pub unix_socket: Option<PathBuf>,
+    // why the routes which take the API token are unavailable, e.g. its file couldn't be written
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // None when the server only listens on the Unix socket
+    pub addr: Option<SocketAddr>,
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_settings(settings: Option<&Settings>, dir: &Path) -> Self {
+        if !settings.and_then(|s| s.http_enabled).unwrap_or(false) {
+            return Config {
+                addr: None,
+                unix_socket: None,
+            };
+        }
+        // only loopback addresses, e.g. 127.0.0.1 or ::1: the server isn't meant for the network
+        let ip = settings
+            .and_then(|s| s.http_bind_address.as_ref())
+            .and_then(|addr| addr.parse::<IpAddr>().ok())
+            .filter(|ip| {
+                if !ip.is_loopback() {
+                    tracing::warn!("ignoring http_bind_address {}: not a loopback address", ip);
+                }
+                ip.is_loopback()
+            })
+            .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+        let port = settings.and_then(|s| s.http_port).unwrap_or(9146);
+        let unix_socket = settings
+            .and_then(|s| s.http_unix_socket)
+            .unwrap_or(false)
+            .then(|| dir.join(UNIX_SOCKET_DIR).join(UNIX_SOCKET_FILE));
+        // the server always listens somewhere
+        let tcp = settings.and_then(|s| s.http_tcp).unwrap_or(true) || unix_socket.is_none();
+        Config {
+            addr: tcp.then_some(SocketAddr::new(ip, port)),
+            unix_socket,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.addr.is_some() || self.unix_socket.is_some()
+    }
+}
+
+// What each request is handled with
+#[derive(Clone)]
+struct Context {
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+    // None if it couldn't be read: only shared stacks are served
+    api_token: Option<Arc<String>>,
+    // the store's directory
+    dir: PathBuf,
+}
+
+struct ServerHandle {
+    config: Config,
+    shutdown: oneshot::Sender<()>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+lazy_static! {
+    static ref SERVER: Mutex<Option<ServerHandle>> = Mutex::new(None);
+    static ref INFO: Mutex<ServerInfo> = Mutex::new(ServerInfo::default());
+}
+
+// Where the server is currently listening
+pub fn info() -> ServerInfo {
+    INFO.lock().unwrap().clone()
+}
+
+// Updates where the server is listening, and the discovery file to match. The file is removed
+// once the server isn't listening anywhere.
+fn set_info(dir: &Path, update: impl FnOnce(&mut ServerInfo)) {
+    let mut info = INFO.lock().unwrap();
+    update(&mut info);
+    let path = dir.join(DISCOVERY_FILE);
+    let res = if *info == ServerInfo::default() {
+        std::fs::remove_file(&path).or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        })
+    } else {
+        std::fs::write(&path, serde_json::to_vec(&*info).unwrap())
+    };
+    if let Err(e) = res {
+        error!("failed to update {}: {}", path.display(), e);
+    }
+}
+
+async fn serve_tcp(addr: SocketAddr, context: Context, shutdown: impl Future<Output = ()>) {
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let context = context.clone();
+        let client = conn.remote_addr().to_string();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                handle_logged(req, context.clone(), client.clone())
+            }))
+        }
+    });
+
+    let dir = context.dir.clone();
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server,
+        // the port is taken, e.g. by another app: fall back to a free port. Clients find it
+        // through the discovery file.
+        Err(e) => {
+            error!("failed to bind {}: {}, trying a free port", addr, e);
+            match Server::try_bind(&SocketAddr::new(addr.ip(), 0)) {
+                Ok(server) => server,
+                Err(e) => {
+                    error!("failed to bind a free port: {}", e);
+                    return;
+                }
             }
-        });
+        }
+    };
+    let server = server.serve(make_svc);
+    let addr = server.local_addr();
+    tracing::info!(name = "http::start", %addr, "listening");
+    set_info(&dir, |info| info.addr = Some(addr));
 
-        let server = Server::bind(&addr).serve(make_svc);
+    if let Err(e) = server.with_graceful_shutdown(shutdown).await {
+        error!("server error: {}", e);
+    }
+    set_info(&dir, |info| info.addr = None);
+}
+
+// The socket is created readable and writable only by the user, so other users on the machine
+// can't connect to it
+async fn serve_unix(path: PathBuf, context: Context, shutdown: impl Future<Output = ()>) {
+    if let Some(parent) = path.parent() {
+        let private = std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            // in case it was created with other permissions
+            .and_then(|_| std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700)));
+        if let Err(e) = private {
+            error!("failed to create {}: {}", parent.display(), e);
+            return;
+        }
+    }
+    // a socket left behind by a previous run
+    let _ = std::fs::remove_file(&path);
+    let listener = match tokio::net::UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("failed to bind {}: {}", path.display(), e);
+            return;
+        }
+    };
+    if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+        error!("failed to restrict {}: {}", path.display(), e);
+        return;
+    }
 
-        if let Err(e) = server.await {
-            error!("server error: {}", e);
+    let accept = hyper::server::accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _)| stream)))
+    });
+    let dir = context.dir.clone();
+    let make_svc = make_service_fn(move |_: &tokio::net::UnixStream| {
+        let context = context.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                handle_logged(req, context.clone(), "unix".to_string())
+            }))
         }
     });
+    tracing::info!(name = "http::start", path = %path.display(), "listening");
+    set_info(&dir, |info| info.unix_socket = Some(path.clone()));
+    let server = Server::builder(accept)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown);
+
+    if let Err(e) = server.await {
+        error!("server error: {}", e);
+    }
+    set_info(&dir, |info| info.unix_socket = None);
+    let _ = std::fs::remove_file(&path);
+}
+
+// Starts the server, or restarts it if its configuration has changed since it was started. The
+// previous server is shut down gracefully: in-flight requests are allowed to complete.
+pub fn start(app_handle: tauri::AppHandle, state: SharedState) {
+    let dir = state.with_lock(|state| PathBuf::from(&state.store.path));
+    let config =
+        state.with_lock(|state| Config::from_settings(state.store.settings_get().as_ref(), &dir));
+    let mut server = SERVER.lock().unwrap();
+    // already running as configured, or still disabled
+    let unchanged = match server.as_ref() {
+        Some(server) => server.config == config,
+        None => !config.is_enabled(),
+    };
+    if unchanged {
+        return;
+    }
+    let previous = server.take().map(|previous| {
+        let _ = previous.shutdown.send(());
+        previous.task
+    });
+    // disabled: the previous server, if there was one, is shutting down
+    if !config.is_enabled() {
+        set_info(&dir, |info| info.error = None);
+        return;
+    }
+
+    let (api_token, token_error) = match share::api_token(&dir) {
+        Ok(token) => (Some(Arc::new(token)), None),
+        Err(e) => {
+            let e = format!("failed to read the API token: {}", e);
+            error!("{}", e);
+            (None, Some(e))
+        }
+    };
+    set_info(&dir, |info| info.error = token_error);
+
+    let context = Context {
+        state,
+        app_handle,
+        api_token,
+        dir,
+    };
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let shutdown_rx = async {
+        let _ = shutdown_rx.await;
+    }
+    .shared();
+    let listen = config.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        // the previous server may be holding the address we're about to bind to
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+
+        let tcp = async {
+            if let Some(addr) = listen.addr {
+                serve_tcp(addr, context.clone(), shutdown_rx.clone()).await;
+            }
+        };
+        let unix = async {
+            if let Some(path) = listen.unix_socket.clone() {
+                serve_unix(path, context.clone(), shutdown_rx.clone()).await;
+            }
+        };
+        futures::join!(tcp, unix);
+    });
+
+    *server = Some(ServerHandle {
+        config,
+        shutdown,
+        task,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_authorized() {
+        let token = "secret";
+        let request = |uri: &str, bearer: Option<&str>| {
+            let mut req = Request::builder().uri(uri);
+            if let Some(bearer) = bearer {
+                req = req.header("Authorization", format!("Bearer {}", bearer));
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        // shared stacks are checked against their own tokens
+        assert!(!needs_api_token("/share/abc/03BCJ0B0JQ9B3V7VSQ4NJUPHM"));
+        for path in [
+            "/",
+            "/03BCJ0B0JQ9B3V7VSQ4NJUPHM",
+            "/items",
+            "/metrics",
+            "/capture",
+        ] {
+            assert!(needs_api_token(path), "{}", path);
+            assert!(!is_authorized(&request(path, None), token), "{}", path);
+            assert!(
+                !is_authorized(&request(path, Some("nope")), token),
+                "{}",
+                path
+            );
+            assert!(
+                is_authorized(&request(path, Some(token)), token),
+                "{}",
+                path
+            );
+        }
+
+        // only the WebSocket takes the token as a parameter
+        assert!(is_authorized(&request("/ws?token=secret", None), token));
+        assert!(!is_authorized(&request("/items?token=secret", None), token));
+    }
+
+    #[test]
+    fn test_resolve_share() {
+        use crate::store::StackLockStatus;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(dir.path().to_str().unwrap(), sender);
+        let shared = state.store.add_stack(b"Shared", StackLockStatus::Unlocked);
+        state.merge(&shared);
+        let other = state.store.add_stack(b"Other", StackLockStatus::Unlocked);
+        state.merge(&other);
+        let item = state.store.add(b"shared", MimeType::TextPlain, shared.id);
+        state.merge(&item);
+        let private = state.store.add(b"private", MimeType::TextPlain, other.id);
+        state.merge(&private);
+
+        let share = share::ShareToken::new(shared.id, 1_000, Some(60));
+        state.store.share_tokens_save(vec![share.clone()]);
+
+        let path = |id: scru128::Scru128Id| format!("{}/{}", share.token, id);
+        assert_eq!(
+            resolve_share(&state, &share.token, 1_000),
+            Ok((shared.id, None))
+        );
+        assert_eq!(
+            resolve_share(&state, &path(item.id), 1_000),
+            Ok((shared.id, Some(item.id)))
+        );
+        // the token only grants access to its own stack
+        assert_eq!(
+            resolve_share(&state, &path(private.id), 1_000),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            resolve_share(&state, &path(item.id), 61_000),
+            Err(StatusCode::GONE)
+        );
+        assert_eq!(
+            resolve_share(&state, "nope", 1_000),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+
+        // revoked
+        state.store.share_tokens_save(vec![]);
+        assert_eq!(
+            resolve_share(&state, &path(item.id), 1_000),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = Path::new("/tmp/store");
+        // the server is off until it's enabled
+        assert!(!Config::from_settings(None, dir).is_enabled());
+        let disabled = Settings {
+            http_unix_socket: Some(true),
+            ..Default::default()
+        };
+        assert!(!Config::from_settings(Some(&disabled), dir).is_enabled());
+
+        let enabled = Settings {
+            http_enabled: Some(true),
+            ..Default::default()
+        };
+        let config = Config::from_settings(Some(&enabled), dir);
+        assert_eq!(config.addr, Some(SocketAddr::from(([127, 0, 0, 1], 9146))));
+        assert_eq!(config.unix_socket, None);
+
+        let socket_only = Settings {
+            http_unix_socket: Some(true),
+            http_tcp: Some(false),
+            ..enabled.clone()
+        };
+        let config = Config::from_settings(Some(&socket_only), dir);
+        assert_eq!(config.addr, None);
+        assert_eq!(
+            config.unix_socket,
+            Some(dir.join(UNIX_SOCKET_DIR).join(UNIX_SOCKET_FILE))
+        );
+
+        let ipv6 = Settings {
+            http_bind_address: Some("::1".into()),
+            ..enabled.clone()
+        };
+        assert_eq!(
+            Config::from_settings(Some(&ipv6), dir).addr,
+            Some(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 9146)))
+        );
+        for addr in ["0.0.0.0", "192.168.1.2", "::"] {
+            let public = Settings {
+                http_bind_address: Some(addr.into()),
+                ..enabled.clone()
+            };
+            assert_eq!(
+                Config::from_settings(Some(&public), dir).addr,
+                Some(SocketAddr::from(([127, 0, 0, 1], 9146))),
+                "{}",
+                addr
+            );
+        }
+
+        // with nowhere else to listen, TCP stays on
+        let nowhere = Settings {
+            http_tcp: Some(false),
+            ..enabled
+        };
+        assert!(Config::from_settings(Some(&nowhere), dir).addr.is_some());
+    }
 }
@@ -0,0 +1,479 @@
+// Imports clipboard history from other clipboard managers into a new stack. Items keep when they
+// were copied: their ids are made with the original timestamps, so they sort among each other as
+// they did in the tool they came from. The stack's id is older than any of its items, so it's
+// merged before them when the store is loaded.
+//
+// Maccy's history is read straight from its Core Data store, and CopyQ's from a tab's data file,
+// which is a Qt data stream. Content which is already in the history is imported once.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::events;
+use crate::state::SharedState;
+use crate::store::{MimeType, StackLockStatus, IMAGE_TYPES};
+
+// Core Data stores times as seconds since 2001-01-01
+const CORE_DATA_EPOCH_SECS: f64 = 978_307_200.0;
+const PROGRESS_EVERY: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tool {
+    Maccy,
+    CopyQ,
+}
+
+impl Tool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Maccy => "Maccy",
+            Tool::CopyQ => "CopyQ",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub content: Vec<u8>,
+    pub mime_type: MimeType,
+    // milliseconds since the epoch, if the tool keeps it
+    pub copied_at: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub stack_id: Option<Scru128Id>,
+    pub imported: usize,
+    // entries which held nothing that could be stored, e.g. only file URLs or rich text, or which
+    // held content already imported
+    pub skipped: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub imported: usize,
+    pub total: usize,
+}
+
+// Reads Maccy's Storage.sqlite. Each history item has a content row per pasteboard type it was
+// copied with: plain text is preferred, then images in the order they're captured in.
+pub fn read_maccy(path: &Path) -> Result<Vec<Entry>, String> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT i.Z_PK, i.ZLASTCOPIEDAT, c.ZTYPE, c.ZVALUE
+             FROM ZHISTORYITEM i JOIN ZHISTORYITEMCONTENT c ON c.ZITEM = i.Z_PK
+             ORDER BY i.Z_PK",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<f64>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<Vec<u8>>>(3)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    // (item, copied_at, content rows)
+    let mut items: Vec<(i64, Option<f64>, Vec<(String, Vec<u8>)>)> = Vec::new();
+    for row in rows {
+        let (pk, copied_at, kind, value) = row.map_err(|e| e.to_string())?;
+        if items.last().map(|item| item.0) != Some(pk) {
+            items.push((pk, copied_at, Vec::new()));
+        }
+        if let (Some(kind), Some(value)) = (kind, value) {
+            items.last_mut().unwrap().2.push((kind, value));
+        }
+    }
+
+    Ok(items
+        .into_iter()
+        .map(|(_, copied_at, contents)| {
+            let copied_at = copied_at.map(|secs| ((secs + CORE_DATA_EPOCH_SECS) * 1000.0) as u64);
+            // nothing which could be stored is left empty, and skipped
+            let (content, mime_type) =
+                pick_pasteboard(contents).unwrap_or((Vec::new(), MimeType::TextPlain));
+            Entry {
+                content,
+                mime_type,
+                copied_at,
+            }
+        })
+        .collect())
+}
+
+fn pick_pasteboard(contents: Vec<(String, Vec<u8>)>) -> Option<(Vec<u8>, MimeType)> {
+    let find = |kind: &str| contents.iter().find(|(k, _)| k == kind).map(|(_, v)| v);
+    if let Some(text) = find("public.utf8-plain-text") {
+        return Some((text.clone(), MimeType::TextPlain));
+    }
+    IMAGE_TYPES
+        .iter()
+        .find_map(|(kind, mime_type)| find(kind).map(|image| (image.clone(), mime_type.clone())))
+}
+
+// Qt's QDataStream encoding, as CopyQ writes it: big-endian, with byte arrays, and strings, as
+// UTF-16, prefixed by their length in bytes
+struct DataStream<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DataStream<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("unexpected end of data".to_string());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.take(1)?[0] != 0)
+    }
+
+    // A byte array, or string; null ones are empty
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap());
+        if len == u32::MAX {
+            return Ok(&[]);
+        }
+        self.take(len as usize)
+    }
+
+    // CopyQ has written MIME types both as strings and as UTF-8 byte arrays. They're ASCII, so
+    // UTF-16 is told apart by its zero bytes.
+    fn mime(&mut self) -> Result<String, String> {
+        let bytes = self.bytes()?;
+        if bytes.len() % 2 == 0 && bytes.contains(&0) {
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                .collect();
+            return Ok(String::from_utf16_lossy(&units));
+        }
+        Ok(String::from_utf8_lossy(bytes).to_string())
+    }
+}
+
+// CopyQ abbreviates common MIME type prefixes to a digit
+fn decompress_mime(mime: &str) -> String {
+    let prefix = match mime.chars().next() {
+        Some('0') => "application/x-copyq-",
+        Some('1') => "text/",
+        Some('2') => "application/",
+        Some('3') => "image/",
+        _ => return mime.to_string(),
+    };
+    format!("{}{}", prefix, &mime[1..])
+}
+
+// An item is a map of MIME types to data. Data CopyQ compressed, or kept in a separate file
+// because it's large, isn't read.
+fn read_copyq_item(stream: &mut DataStream) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let version = stream.i32()?;
+    if version != -1 && version != -2 {
+        return Err(format!("unsupported item format: {}", version));
+    }
+    let len = stream.i32()?;
+    let mut data = Vec::new();
+    for _ in 0..len {
+        let mime = decompress_mime(&stream.mime()?);
+        let compressed = stream.bool()?;
+        let bytes = stream.bytes()?;
+        if !compressed && !mime.starts_with("FILE:") {
+            data.push((mime, bytes.to_vec()));
+        }
+    }
+    Ok(data)
+}
+
+// Reads a CopyQ tab's data file, e.g. ~/.config/copyq/copyq_tab_JmNsaXBib2FyZA==.dat for the
+// clipboard tab. Items are listed newest first. Plain text is preferred, then images. CopyQ
+// doesn't keep when items were copied.
+pub fn read_copyq(path: &Path) -> Result<Vec<Entry>, String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let mut stream = DataStream { data: &data };
+    let count = stream.i32()?;
+    if count < 0 {
+        return Err("not a CopyQ tab: synced, and encrypted, tabs can't be imported".to_string());
+    }
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let item = read_copyq_item(&mut stream)
+            .map_err(|e| format!("not a CopyQ tab, or synced, or encrypted: {}", e))?;
+        let find = |mime: &str| item.iter().find(|(m, _)| m == mime).map(|(_, data)| data);
+        let picked = find("text/plain")
+            .map(|text| (text.clone(), MimeType::TextPlain))
+            .or_else(|| {
+                item.iter().find_map(|(mime, data)| {
+                    MimeType::from_image_mime(mime).map(|mime_type| (data.clone(), mime_type))
+                })
+            });
+        let (content, mime_type) = picked.unwrap_or((Vec::new(), MimeType::TextPlain));
+        entries.push(Entry {
+            content,
+            mime_type,
+            copied_at: None,
+        });
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+pub fn read(tool: Tool, path: &Path) -> Result<Vec<Entry>, String> {
+    match tool {
+        Tool::Maccy => read_maccy(path),
+        Tool::CopyQ => read_copyq(path),
+    }
+}
+
+// Drops entries with nothing to store, and all but the newest copy of content which is in the
+// history more than once, returning how many were dropped
+pub fn storable(entries: Vec<Entry>) -> (Vec<Entry>, usize) {
+    let total = entries.len();
+    let mut seen = HashSet::new();
+    let mut kept: Vec<Entry> = entries
+        .into_iter()
+        .rev()
+        .filter(|entry| {
+            !entry.content.is_empty() && seen.insert(ssri::Integrity::from(&entry.content))
+        })
+        .collect();
+    kept.reverse();
+    let skipped = total - kept.len();
+    (kept, skipped)
+}
+
+// Gives each entry an id at the time it was copied. Entries the tool didn't keep a time for are
+// placed just before the oldest entry that has one, or now, in the order they were read.
+pub fn ids(entries: &[Entry], now: u64) -> Vec<Scru128Id> {
+    let mut next = entries
+        .iter()
+        .filter_map(|entry| entry.copied_at)
+        .min()
+        .unwrap_or(now)
+        .saturating_sub(entries.len() as u64);
+    entries
+        .iter()
+        .map(|entry| {
+            let timestamp = entry.copied_at.unwrap_or_else(|| {
+                next += 1;
+                next
+            });
+            let fresh = scru128::new();
+            Scru128Id::from_fields(
+                timestamp,
+                fresh.counter_hi(),
+                fresh.counter_lo(),
+                fresh.entropy(),
+            )
+        })
+        .collect()
+}
+
+// Imports the history at path into a new stack, emitting import-progress as it goes
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    tool: Tool,
+    path: &Path,
+) -> Result<ImportReport, String> {
+    let read_path = path.to_path_buf();
+    let entries = tauri::async_runtime::spawn_blocking(move || read(tool, &read_path))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let mut report = ImportReport::default();
+    let (entries, skipped) = storable(entries);
+    report.skipped = skipped;
+    if entries.is_empty() {
+        return Ok(report);
+    }
+    let ids = ids(&entries, crate::privacy::now());
+    let oldest = ids.iter().min().unwrap();
+    let fresh = scru128::new();
+    let stack_id = Scru128Id::from_fields(
+        oldest.timestamp().saturating_sub(1),
+        fresh.counter_hi(),
+        fresh.counter_lo(),
+        fresh.entropy(),
+    );
+
+    let total = entries.len();
+    let name = format!("Imported from {}", tool.name());
+    state.with_lock(|state| {
+        let stack =
+            state
+                .store
+                .add_stack_with_id(stack_id, name.as_bytes(), StackLockStatus::Unlocked);
+        state.merge(&stack);
+        // the UI's view is refreshed once, at the end, rather than for each item
+        for (i, (entry, id)) in entries.iter().zip(&ids).enumerate() {
+            let packet =
+                state
+                    .store
+                    .add_with_id(*id, &entry.content, entry.mime_type.clone(), stack_id);
+            state.view.merge(&packet);
+            if (i + 1) % PROGRESS_EVERY == 0 || i + 1 == total {
+                let progress = Progress {
+                    imported: i + 1,
+                    total,
+                };
+                events::emit(app, "import-progress", progress);
+            }
+        }
+        // bring the stack to the top, rather than leaving it where its oldest item sorts
+        let touch = state.store.update_touch(stack_id);
+        state.merge(&touch);
+    });
+    report.stack_id = Some(stack_id);
+    report.imported = total;
+    tracing::info!(name = "import", tool = tool.name(), ?path, ?report);
+    events::emit(app, "refresh-items", true);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_maccy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Storage.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ZHISTORYITEM (Z_PK INTEGER PRIMARY KEY, ZLASTCOPIEDAT TIMESTAMP);
+             CREATE TABLE ZHISTORYITEMCONTENT (Z_PK INTEGER PRIMARY KEY, ZITEM INTEGER,
+                ZTYPE VARCHAR, ZVALUE BLOB);
+             INSERT INTO ZHISTORYITEM VALUES (1, 700000000.5), (2, 700000010.0);
+             INSERT INTO ZHISTORYITEMCONTENT VALUES
+                (1, 1, 'public.html', X'3c623e68693c2f623e'),
+                (2, 1, 'public.utf8-plain-text', X'6869'),
+                (3, 2, 'public.png', X'89504e47'),
+                (4, 3, 'public.file-url', X'66696c653a2f2f2f');
+             INSERT INTO ZHISTORYITEM VALUES (3, NULL);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let entries = read_maccy(&path).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    content: b"hi".to_vec(),
+                    mime_type: MimeType::TextPlain,
+                    copied_at: Some(1_678_307_200_500),
+                },
+                Entry {
+                    content: b"\x89PNG".to_vec(),
+                    mime_type: MimeType::ImagePng,
+                    copied_at: Some(1_678_307_210_000),
+                },
+                Entry {
+                    content: Vec::new(),
+                    mime_type: MimeType::TextPlain,
+                    copied_at: None,
+                },
+            ]
+        );
+    }
+
+    fn qt_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend((bytes.len() as u32).to_be_bytes());
+        out.extend(bytes);
+    }
+
+    fn qt_string(out: &mut Vec<u8>, s: &str) {
+        let utf16: Vec<u8> = s.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        qt_bytes(out, &utf16);
+    }
+
+    #[test]
+    fn test_read_copyq_and_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("copyq_tab_JmNsaXBib2FyZA==.dat");
+        let mut tab = Vec::new();
+        tab.extend(4i32.to_be_bytes());
+        // the newest item: plain text, and HTML, with MIME types as UTF-8
+        tab.extend((-2i32).to_be_bytes());
+        tab.extend(2i32.to_be_bytes());
+        for (mime, data) in [("1html", "<b>newest</b>"), ("1plain", "newest")] {
+            qt_bytes(&mut tab, mime.as_bytes());
+            tab.push(0);
+            qt_bytes(&mut tab, data.as_bytes());
+        }
+        // an image, with its MIME type as a string
+        tab.extend((-1i32).to_be_bytes());
+        tab.extend(1i32.to_be_bytes());
+        qt_string(&mut tab, "3png");
+        tab.push(0);
+        qt_bytes(&mut tab, b"\x89PNG");
+        // compressed data isn't read
+        tab.extend((-2i32).to_be_bytes());
+        tab.extend(1i32.to_be_bytes());
+        qt_bytes(&mut tab, b"1plain");
+        tab.push(1);
+        qt_bytes(&mut tab, b"\0\0\0\x05x");
+        // the oldest item
+        tab.extend((-2i32).to_be_bytes());
+        tab.extend(1i32.to_be_bytes());
+        qt_bytes(&mut tab, b"1plain");
+        tab.push(0);
+        qt_bytes(&mut tab, b"oldest");
+        std::fs::write(&path, &tab).unwrap();
+
+        let entries = read_copyq(&path).unwrap();
+        let contents: Vec<(&[u8], &MimeType)> = entries
+            .iter()
+            .map(|e| (e.content.as_slice(), &e.mime_type))
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                (&b"oldest"[..], &MimeType::TextPlain),
+                (b"", &MimeType::TextPlain),
+                (b"\x89PNG", &MimeType::ImagePng),
+                (b"newest", &MimeType::TextPlain),
+            ]
+        );
+
+        std::fs::write(&path, &tab[..tab.len() - 3]).unwrap();
+        assert!(read_copyq(&path).is_err());
+        std::fs::write(&path, r#"[{"text": "newest"}]"#).unwrap();
+        assert!(read_copyq(&path).is_err());
+
+        // the empty item is skipped, as is the older copy of repeated content
+        let mut repeated = entries.clone();
+        repeated.push(repeated[0].clone());
+        let (kept, skipped) = storable(repeated);
+        let contents: Vec<&[u8]> = kept.iter().map(|e| e.content.as_slice()).collect();
+        assert_eq!(contents, vec![&b"\x89PNG"[..], b"newest", b"oldest"]);
+        assert_eq!(skipped, 2);
+
+        let ids = ids(&entries, 1_000_000);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id.timestamp() < 1_000_000));
+
+        let mut entries = entries;
+        entries[1].copied_at = Some(5_000);
+        let ids = super::ids(&entries, 1_000_000);
+        assert_eq!(ids[1].timestamp(), 5_000);
+        assert!(ids[0].timestamp() < 5_000);
+    }
+}
@@ -0,0 +1,177 @@
+// Sources other than the clipboard can push items to Stacks through the HTTP server, by topic.
+// The shell topic takes each command run in the terminal, from a shell hook, and adds it to a
+// dedicated Shell stack along with its exit code and working directory. See docs/ingest.md for
+// the payload format, and example hooks.
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::state::State;
+use crate::store::{MimeType, StackLockStatus};
+
+pub const SHELL_STACK: &str = "Shell";
+
+// Shell commands are highlighted as shell script
+const SHELL_CONTENT_TYPE: &str = "Shell";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShellRun {
+    pub exit_code: i32,
+    pub cwd: String,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ShellPayload {
+    pub command: String,
+    #[serde(flatten)]
+    pub run: ShellRun,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topic {
+    Shell,
+}
+
+impl Topic {
+    pub fn parse(topic: &str) -> Option<Self> {
+        match topic {
+            "shell" => Some(Topic::Shell),
+            _ => None,
+        }
+    }
+}
+
+// The Shell stack is created locked, so clipboard captures don't land in it. It's kept by id, so
+// it can be renamed, and another stack named Shell isn't mistaken for it.
+fn shell_stack(state: &mut State) -> Scru128Id {
+    let existing = state
+        .store
+        .shell_stack_get()
+        .filter(|id| state.view.items.get(id).is_some_and(|item| item.is_stack));
+    if let Some(id) = existing {
+        return id;
+    }
+    let packet = state
+        .store
+        .add_stack(SHELL_STACK.as_bytes(), StackLockStatus::Locked);
+    state.merge(&packet);
+    state.store.shell_stack_set(packet.id);
+    packet.id
+}
+
+// Adds the command to the Shell stack, returning its item's id: a command which was run before is
+// moved to the top, with how it ran this time. Blank commands are ignored.
+pub fn add_shell(state: &mut State, payload: &ShellPayload) -> Option<Scru128Id> {
+    let command = payload.command.trim();
+    if command.is_empty() {
+        return None;
+    }
+    let stack_id = shell_stack(state);
+    let packet = state
+        .store
+        .add(command.as_bytes(), MimeType::TextPlain, stack_id);
+    let id = state.merge_add(&packet);
+    state.store.shell_run_set(id, &payload.run);
+
+    // a content type applies wherever the content is held, so it's only set for commands which
+    // are only in the Shell stack, and haven't been given one
+    let hash = packet.hash?;
+    let is_text = state
+        .store
+        .get_content_meta(&hash)
+        .is_some_and(|meta| meta.content_type == "Text");
+    let elsewhere = state
+        .view
+        .items
+        .values()
+        .any(|item| item.hash == hash && item.stack_id != Some(stack_id));
+    if is_text && !elsewhere {
+        let packet = state
+            .store
+            .update_content_type(hash, SHELL_CONTENT_TYPE.to_string());
+        state.merge(&packet);
+    }
+    Some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let payload: ShellPayload = serde_json::from_str(
+            r#"{"command": "cargo test", "exit_code": 101, "cwd": "/src/stacks"}"#,
+        )
+        .unwrap();
+        let id = add_shell(&mut state, &payload).unwrap();
+        assert_eq!(
+            state.store.shell_run_get(&id),
+            Some(ShellRun {
+                exit_code: 101,
+                cwd: "/src/stacks".to_string(),
+                duration_ms: None,
+            })
+        );
+
+        // later commands land in the same stack, which clipboard captures skip
+        let payload = ShellPayload {
+            command: "ls".to_string(),
+            run: payload.run.clone(),
+        };
+        let second = add_shell(&mut state, &payload).unwrap();
+        let stack_id = state.view.items[&id].stack_id.unwrap();
+        assert_eq!(state.view.items[&second].stack_id, Some(stack_id));
+        assert!(state.view.items[&stack_id].locked);
+        assert_ne!(state.get_curr_stack(), stack_id);
+
+        // a command run again is the same item, with how it last ran
+        let rerun = ShellPayload {
+            command: "cargo test".to_string(),
+            run: ShellRun {
+                exit_code: 0,
+                cwd: "/src/stacks".to_string(),
+                duration_ms: Some(5230),
+            },
+        };
+        assert_eq!(add_shell(&mut state, &rerun), Some(id));
+        assert_eq!(state.store.shell_run_get(&id), Some(rerun.run));
+
+        // the stack is found by id, not name
+        let other = state
+            .store
+            .add_stack(SHELL_STACK.as_bytes(), StackLockStatus::Unlocked);
+        state.merge(&other);
+        assert_eq!(shell_stack(&mut state), stack_id);
+
+        // only content which is only in the Shell stack is given the Shell content type
+        let copied = state
+            .store
+            .add(b"git status", MimeType::TextPlain, other.id);
+        state.merge(&copied);
+        let payload = ShellPayload {
+            command: "git status".to_string(),
+            run: payload.run.clone(),
+        };
+        add_shell(&mut state, &payload).unwrap();
+        let content_type = |state: &State, id: &Scru128Id| {
+            let hash = &state.view.items[id].hash;
+            state.store.get_content_meta(hash).unwrap().content_type
+        };
+        assert_eq!(content_type(&state, &copied.id), "Text");
+        assert_eq!(content_type(&state, &second), SHELL_CONTENT_TYPE);
+
+        let blank = ShellPayload {
+            command: " ".to_string(),
+            run: payload.run,
+        };
+        assert_eq!(add_shell(&mut state, &blank), None);
+    }
+}
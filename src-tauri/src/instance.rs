@@ -0,0 +1,178 @@
+// Only one instance of Stacks runs on a store: a second would race the first on the store's files,
+// with two capture loops. The running instance holds an flock on a lockfile in the store's
+// directory, and listens on a socket beside it. An instance which can't take the lock asks the
+// running instance, over the socket, to show its window, and exits. The OS drops the lock when
+// the process exits, however it exits, so there's no stale lockfile to take over.
+//
+// The socket takes a line, "ping" or "show", and answers "ok".
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+use crate::spotlight;
+
+pub const LOCK_FILE: &str = "stacks.lock";
+pub const SOCKET_FILE: &str = "instance.sock";
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    // the locked lockfile, held until release
+    static ref LOCK: Mutex<Option<std::fs::File>> = Mutex::new(None);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // another instance is running on the store
+    Running,
+    Io(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+fn lock_path(dir: &Path) -> PathBuf {
+    dir.join(LOCK_FILE)
+}
+
+fn socket_path(dir: &Path) -> PathBuf {
+    dir.join(SOCKET_FILE)
+}
+
+// Sends the request to the running instance, returning whether it answered
+pub fn send(dir: &Path, request: &str) -> bool {
+    let send = || -> std::io::Result<String> {
+        let mut stream = UnixStream::connect(socket_path(dir))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        writeln!(stream, "{}", request)?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(response)
+    };
+    send().is_ok_and(|response| response.trim() == "ok")
+}
+
+// Takes the store's lock, unless another instance is running on the store
+pub fn acquire(dir: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path(dir))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() == std::io::ErrorKind::WouldBlock {
+            return Err(Error::Running);
+        }
+        return Err(e.into());
+    }
+    file.set_len(0)?;
+    writeln!(file, "{}", std::process::id())?;
+    *LOCK.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+// Removes the socket, and drops the lock, on shutdown. The lockfile is left: removing it could
+// let a new instance lock a new file while another waits on the old one.
+pub fn release(dir: &Path) {
+    let _ = std::fs::remove_file(socket_path(dir));
+    LOCK.lock().unwrap().take();
+}
+
+fn respond(app: &tauri::AppHandle, request: &str) -> &'static str {
+    match request.trim() {
+        "ping" => "ok",
+        "show" => {
+            let window = spotlight::window(app);
+            match spotlight::show(&window) {
+                Ok(()) => "ok",
+                Err(_) => "error",
+            }
+        }
+        _ => "unknown",
+    }
+}
+
+// Answers other instances. The socket is only readable and writable by the user.
+pub fn listen(app: tauri::AppHandle, dir: &Path) {
+    let path = socket_path(dir);
+    tauri::async_runtime::spawn(async move {
+        let _ = std::fs::remove_file(&path);
+        let listener = match tokio::net::UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(name = "instance", %e, "couldn't listen");
+                return;
+            }
+        };
+        if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)) {
+            tracing::error!(name = "instance", %e, "couldn't restrict the socket");
+            return;
+        }
+        while let Ok((stream, _)) = listener.accept().await {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut request = String::new();
+                if tokio::io::BufReader::new(reader)
+                    .read_line(&mut request)
+                    .await
+                    .is_ok()
+                {
+                    let response = respond(&app, &request);
+                    let _ = writer.write_all(format!("{}\n", response).as_bytes()).await;
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_acquire() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        acquire(dir).unwrap();
+        let pid = std::fs::read_to_string(lock_path(dir)).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        // the lock is held, whether or not the holder answers
+        assert_eq!(acquire(dir), Err(Error::Running));
+
+        let listener = UnixListener::bind(socket_path(dir)).unwrap();
+        let answer = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            assert_eq!(request, "ping\n");
+            (&stream).write_all(b"ok\n").unwrap();
+        });
+        assert!(send(dir, "ping"));
+        answer.join().unwrap();
+
+        release(dir);
+        assert!(!socket_path(dir).exists());
+        acquire(dir).unwrap();
+        release(dir);
+    }
+}
@@ -0,0 +1,116 @@
+use futures::StreamExt;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::events;
+use crate::state::{SharedState, State};
+
+// opening more links than this at once requires the caller to confirm
+pub const OPEN_ALL_CONFIRM_THRESHOLD: usize = 10;
+
+const CONCURRENT_CHECKS: usize = 8;
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct LinkStatus {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub checked_at: u64,
+}
+
+impl LinkStatus {
+    pub fn is_dead(&self) -> bool {
+        !matches!(self.status, Some(status) if status < 400)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub id: Scru128Id,
+    pub hash: ssri::Integrity,
+    pub url: String,
+}
+
+// Returns the Link items in the given stack, in display order
+pub fn stack_links(state: &State, stack_id: &Scru128Id) -> Vec<Link> {
+    let stack = match state.view.items.get(stack_id) {
+        Some(stack) => stack,
+        None => return Vec::new(),
+    };
+    state
+        .view
+        .children(stack)
+        .iter()
+        .filter_map(|id| {
+            let item = state.view.items.get(id)?;
+            let meta = state.store.get_content_meta(&item.hash)?;
+            if meta.content_type != "Link" {
+                return None;
+            }
+            let content = state.store.get_content(&item.hash)?;
+            Some(Link {
+                id: item.id,
+                hash: item.hash.clone(),
+                url: String::from_utf8_lossy(&content).trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[tracing::instrument(skip(client))]
+pub async fn check(client: &reqwest::Client, url: &str) -> LinkStatus {
+    let mut res = client.head(url).send().await;
+    // not every server supports HEAD
+    if let Ok(r) = &res {
+        if r.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED {
+            res = client.get(url).send().await;
+        }
+    }
+
+    let checked_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    match res {
+        Ok(r) => LinkStatus {
+            status: Some(r.status().as_u16()),
+            error: None,
+            checked_at,
+        },
+        Err(e) => LinkStatus {
+            status: None,
+            error: Some(e.to_string()),
+            checked_at,
+        },
+    }
+}
+
+pub fn spawn_check(app: tauri::AppHandle, state: SharedState, links: Vec<Link>) {
+    tauri::async_runtime::spawn(async move {
+        tracing::info!(name = "links::check", count = links.len(), "checking links");
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        futures::stream::iter(links)
+            .for_each_concurrent(CONCURRENT_CHECKS, |link| {
+                let client = &client;
+                let state = &state;
+                let app = &app;
+                async move {
+                    let status = check(client, &link.url).await;
+                    if status.is_dead() {
+                        tracing::warn!(name = "links::check", url = %link.url, ?status, "dead link");
+                    }
+                    state.with_lock(|state| {
+                        state.store.link_status_set(link.hash.clone(), status.clone());
+                    });
+                    events::emit(app, "link-status", (link.id, status));
+                }
+            })
+            .await;
+
+        events::emit(&app, "refresh-items", true);
+    });
+}
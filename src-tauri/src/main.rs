@@ -4,30 +4,87 @@
 
 use std::sync::Arc;
 
-use tauri::CustomMenuItem;
 use tauri::Manager;
-use tauri::SystemTrayMenu;
 
 use tracing::info;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
+mod actions;
+mod activity;
+mod address;
+mod agent;
+mod backup;
+mod batch;
+mod bundle;
+mod calc;
+mod calendar;
+mod capture;
 mod clipboard;
+#[cfg(target_os = "linux")]
+mod clipboard_linux;
+#[cfg(target_os = "macos")]
+mod clipboard_macos;
+mod clipboard_writer;
+mod color;
 mod commands;
+mod compact;
+mod contact;
 mod content_bus;
 mod content_type;
+mod diff;
+mod disk;
+mod durability;
+mod error;
+mod events;
+mod export;
+mod file_ref;
+mod hotkeys;
+mod import;
+mod ingest;
+mod instance;
+mod links;
+mod materialize;
+mod migrate;
+mod notifications;
+mod packet_store;
+mod paste;
+mod phone;
+mod plugins;
+mod privacy;
+mod prompts;
 mod publish;
+mod qr;
+mod registry;
+mod rules;
+mod schedule;
+mod search;
+mod semantic;
+mod sequential;
+mod share;
+mod shell;
+mod shutdown;
+mod snippet;
+mod split;
 mod spotlight;
+mod stack_settings;
 mod state;
+mod stats;
 mod store;
+mod theme;
+mod timeline;
+mod touch_id;
+mod tray;
 mod ui;
+mod url_actions;
+mod usage;
 mod util;
+mod verify;
 mod view;
 
-use crate::spotlight::Shortcut;
-
-#[cfg(debug_assertions)]
 mod http;
+mod upload;
+mod ws;
 
 #[cfg(test)]
 mod store_tests;
@@ -38,7 +95,7 @@ mod ui_tests;
 #[cfg(test)]
 mod view_tests;
 
-use state::{SharedState, State};
+use state::{SharedState, State, StateLock};
 
 #[tokio::main]
 async fn main() {
@@ -62,16 +119,7 @@ async fn main() {
     let config = context.config();
     let version = &config.package.version.clone().unwrap();
 
-    let menu = SystemTrayMenu::new()
-        .add_item(CustomMenuItem::new("".to_string(), "Stacks").disabled())
-        .add_item(CustomMenuItem::new("".to_string(), format!("Version {}", version)).disabled())
-        .add_native_item(tauri::SystemTrayMenuItem::Separator)
-        .add_item(CustomMenuItem::new(
-            "check-updates".to_string(),
-            "Check for Updates...",
-        ))
-        .add_native_item(tauri::SystemTrayMenuItem::Separator)
-        .add_item(CustomMenuItem::new("quit".to_string(), "Quit"));
+    let menu = tray::initial_menu(version);
     let system_tray = tauri::SystemTray::new().with_menu(menu);
 
     tauri::Builder::default()
@@ -83,26 +131,30 @@ async fn main() {
                     state.with_lock(|state| {
                         state.ui.is_visible = *is_focused;
                     });
+                    if event.window().label() == spotlight::WINDOW_LABEL {
+                        hotkeys::set_visible(&event.window().app_handle(), *is_focused);
+                    }
+                }
+                if let tauri::WindowEvent::FileDrop(tauri::FileDropEvent::Dropped(paths)) =
+                    event.event()
+                {
+                    let app = event.window().app_handle();
+                    let state = app.state::<SharedState>();
+                    file_ref::add_dropped(&app, &state, paths);
                 }
             });
         })
         .system_tray(system_tray)
         .on_system_tray_event(|app, event| {
             if let tauri::SystemTrayEvent::MenuItemClick { id, .. } = event {
-                match id.as_str() {
-                    "check-updates" => {
-                        app.trigger_global("tauri://update", None);
-                    }
-                    "quit" => {
-                        app.exit(0);
-                    }
-                    _ => {}
-                }
+                tray::handle_click(app, &id);
             }
         })
         .invoke_handler(tauri::generate_handler![
             commands::store_win_move,
             commands::store_get_content,
+            commands::store_get_content_full,
+            commands::store_render_full_preview,
             commands::store_get_raw_content,
             commands::store_get_root,
             commands::store_nav_refresh,
@@ -115,44 +167,134 @@ async fn main() {
             commands::store_nav_select_down_stack,
             commands::store_nav_select_left,
             commands::store_nav_select_right,
+            commands::store_copy_item,
+            commands::store_materialize_item,
             commands::store_copy_to_clipboard,
+            commands::store_paste_with_transform,
+            commands::store_clipboard_at,
+            commands::store_copy_color_as,
+            commands::store_copy_contact_field,
+            commands::store_addresses,
+            commands::store_copy_address,
+            commands::store_address_map_link,
+            commands::store_phone_numbers,
+            commands::store_copy_phone,
+            commands::store_phone_dial,
+            commands::store_event_get,
+            commands::store_event_add_to_calendar,
+            commands::store_event_to_ics,
             commands::store_delete,
+            commands::store_batch,
             commands::store_undo,
             commands::store_new_note,
+            commands::store_set_note,
+            commands::store_link_item,
+            commands::store_merge_items,
+            commands::store_split_item,
+            commands::store_snippet_fields,
+            commands::store_expand_snippet,
+            commands::store_calc_copy,
+            commands::store_calc_persist,
             commands::store_edit_note,
+            commands::store_edit_item,
+            commands::store_item_history,
+            commands::store_item_history_restore,
             commands::store_move_up,
             commands::store_touch,
             commands::store_move_down,
             commands::store_stack_lock,
             commands::store_stack_unlock,
             commands::store_stack_sort_auto,
+            commands::store_stack_check_links,
+            commands::store_stack_open_links,
+            commands::store_stack_share,
+            commands::store_stack_shares,
+            commands::store_revoke_share,
+            commands::store_stack_archive,
+            commands::store_stack_restore,
+            commands::store_stack_list_archived,
+            commands::store_nav_show_archived,
+            commands::store_sequential_paste_arm,
+            commands::store_sequential_paste_disarm,
+            commands::store_sequential_paste_progress,
             commands::store_stack_sort_manual,
             commands::store_settings_save,
             commands::store_settings_get,
+            commands::store_paste_rules_get,
+            commands::store_paste_rules_set,
+            commands::store_stack_set_paste_transform,
             commands::store_set_theme_mode,
+            commands::store_set_theme,
+            commands::store_themes,
             commands::store_pipe_to_command,
             commands::store_pipe_stack_to_shell,
+            commands::store_schedule_add,
+            commands::store_schedules_list,
+            commands::store_schedule_pause,
+            commands::store_schedule_delete,
             commands::store_set_content_type,
             commands::store_add_to_stack,
             commands::store_add_to_new_stack,
             commands::store_new_stack,
+            commands::store_stack_nest,
+            commands::store_stack_tree,
+            commands::store_export_stack,
+            commands::store_export_items,
+            commands::store_import_stack,
+            commands::store_stack_settings_get,
+            commands::store_stack_settings_set,
             commands::store_mark_as_cross_stream,
             commands::spotlight_update_shortcut,
             commands::spotlight_get_shortcut,
+            commands::store_hotkeys_get,
+            commands::store_hotkeys_set,
+            commands::store_set_stack_hotkey,
+            commands::store_set_quick_paste,
             commands::spotlight_hide,
+            commands::store_agent_get,
+            commands::store_agent_set,
+            commands::store_diagnostics,
+            commands::store_server_info,
+            commands::store_events_subscribe,
+            commands::store_open_stack_window,
+            commands::store_disk_status,
+            commands::store_stats,
+            commands::store_verify,
+            commands::store_compact,
+            commands::store_backup,
+            commands::store_backups,
+            commands::store_restore_backup,
+            commands::store_import_from,
+            commands::store_semantic_search,
+            commands::store_run_llm_action,
+            commands::store_llm_key_set,
+            commands::store_compose_prompt,
+            commands::store_generate_qr,
+            commands::store_list_actions,
+            commands::store_run_action,
+            commands::store_actions_get,
+            commands::store_actions_set,
+            commands::store_plugins,
+            commands::store_rules_get,
+            commands::store_rules_set,
+            commands::store_gc,
+            commands::store_pause_capture_for,
+            commands::store_capture_expiring_for,
+            commands::store_privacy_end,
+            commands::store_privacy_get,
+            commands::store_item_source,
+            commands::store_set_sensitive,
+            commands::store_item_sensitive,
+            commands::store_timeline,
+            commands::store_diff_items,
+            commands::store_activity_timeline,
+            commands::spotlight_accessibility_trusted,
+            commands::spotlight_paste_to_frontmost,
         ])
         .setup(|app| {
+            #[cfg(target_os = "macos")]
             app.set_activation_policy(tauri::ActivationPolicy::Accessory);
 
-            let window = app.get_window("main").unwrap();
-
-            #[cfg(debug_assertions)]
-            if std::env::var("STACK_DEVTOOLS").is_ok() {
-                window.open_devtools();
-                use tauri_plugin_positioner::{Position, WindowExt};
-                let _ = window.move_window(Position::Center);
-            }
-
             let db_path = match std::env::var("STACK_DB_PATH") {
                 Ok(path) => path,
                 Err(_) => {
@@ -162,40 +304,90 @@ async fn main() {
             };
             info!(db_path, "let's go");
 
-            let (packet_sender, packet_receiver) = std::sync::mpsc::channel();
+            // with another instance running on the store, show it, rather than race it
+            let dir = std::path::Path::new(&db_path);
+            match instance::acquire(dir) {
+                Ok(()) => {}
+                Err(instance::Error::Running) => {
+                    info!("already running");
+                    instance::send(dir, "show");
+                    std::process::exit(0);
+                }
+                Err(e) => tracing::warn!(?e, "couldn't take the lockfile"),
+            }
+            instance::listen(app.handle(), dir);
+
+            let (packet_sender, packet_receiver) = publish::channel();
 
-            let state = State::new(&db_path, packet_sender);
-            let mutex = tracing_mutex_span::TracingMutexSpan::new("SharedState", state);
-            let state: SharedState = Arc::new(mutex);
+            // a store which can't be opened, e.g. from a newer build, is left alone, rather than
+            // risk writing to it
+            let state = match State::open(&db_path, packet_sender) {
+                Ok(state) => state,
+                Err(e) => {
+                    tracing::error!(%e, "couldn't open the store");
+                    tauri::api::dialog::MessageDialogBuilder::new("Stacks can't open its store", e)
+                        .kind(tauri::api::dialog::MessageDialogKind::Error)
+                        .show(|_| std::process::exit(1));
+                    return Ok(());
+                }
+            };
+            let state: SharedState = Arc::new(StateLock::new("SharedState", state));
             app.manage(state.clone());
 
-            publish::spawn(state.clone(), packet_receiver);
+            publish::spawn(app.handle(), state.clone(), packet_receiver);
             content_bus::spawn_tiktokens(app.handle(), state.clone());
+            disk::spawn_monitor(app.handle(), state.clone());
+            tray::spawn(app.handle(), state.clone());
+            privacy::spawn(app.handle(), state.clone());
+            stack_settings::spawn(app.handle(), state.clone());
+            schedule::spawn(app.handle(), state.clone());
+            compact::spawn(app.handle(), state.clone());
+            backup::spawn(state.clone());
+            semantic::spawn(state.clone());
+            durability::spawn(state.clone());
 
-            // start HTTP api if in debug mode
-            #[cfg(debug_assertions)]
-            {
-                http::start(app.handle().clone(), state.clone());
-            }
+            // start the HTTP api, if it's enabled
+            http::start(app.handle().clone(), state.clone());
 
             clipboard::start(app.handle(), &state);
+            shutdown::spawn_signals(app.handle());
 
             let shortcut = state.with_lock(|state| {
                 let settings = state.store.settings_get();
                 settings
                     .and_then(|s| s.activation_shortcut)
-                    .unwrap_or(Shortcut {
-                        ctrl: true,
-                        shift: false,
-                        alt: false,
-                        command: false,
-                    })
+                    .unwrap_or_default()
+            });
+            spotlight::register_shortcut(&app.handle(), &shortcut.to_macos_shortcut()).unwrap();
+
+            // as a background agent, the window is created once it's asked for
+            let background = state.with_lock(|state| {
+                agent::start(&app.handle(), state);
+                agent::get(state).background_agent
             });
-            spotlight::init(&window).unwrap();
-            spotlight::register_shortcut(&window, &shortcut.to_macos_shortcut()).unwrap();
+            if !background {
+                let window = spotlight::window(&app.handle());
+
+                #[cfg(debug_assertions)]
+                if std::env::var("STACK_DEVTOOLS").is_ok() {
+                    window.open_devtools();
+                    use tauri_plugin_positioner::{Position, WindowExt};
+                    let _ = window.move_window(Position::Center);
+                }
+            }
+
+            let hotkeys = state.with_lock(|state| hotkeys::get(state));
+            if let Err(e) = hotkeys::register(&app.handle(), &hotkeys) {
+                tracing::warn!(name = "hotkeys", %e, "couldn't register hotkeys");
+            }
 
             Ok(())
         })
-        .run(context)
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                shutdown::run(&app.state::<SharedState>());
+            }
+        });
 }
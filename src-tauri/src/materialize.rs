@@ -0,0 +1,97 @@
+// Items are written out as files so they can be dragged from Stacks into other apps. Files are
+// named for the item's content type, e.g. Markdown.md, in a directory per content hash, so
+// materializing the same content again reuses the file.
+
+use std::path::{Path, PathBuf};
+
+use crate::file_ref;
+use crate::store::{ContentMeta, MimeType};
+use crate::ui;
+
+const DIR_NAME: &str = "stacks-materialized";
+
+pub fn file_name(meta: &ContentMeta) -> String {
+    let extension = if meta.mime_type.is_image() {
+        meta.mime_type.as_str().trim_start_matches("image/")
+    } else {
+        match meta.content_type.as_str() {
+            "Markdown" => "md",
+            content_type => ui::file_extension(content_type).unwrap_or("txt"),
+        }
+    };
+    let stem: String = meta
+        .content_type
+        .chars()
+        .map(|c| if c == '/' || c == ':' { '-' } else { c })
+        .collect();
+    format!("{}.{}", stem, extension)
+}
+
+pub fn dir() -> PathBuf {
+    std::env::temp_dir().join(DIR_NAME)
+}
+
+// Returns the path of a file holding the content: a file reference's own path, otherwise a file
+// written under dir
+pub fn materialize(dir: &Path, meta: &ContentMeta, content: &[u8]) -> std::io::Result<PathBuf> {
+    if meta.mime_type == MimeType::FileRef {
+        let file_ref = file_ref::parse(content).filter(|f| Path::new(&f.path).exists());
+        return file_ref.map(|f| PathBuf::from(f.path)).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "referenced file not found")
+        });
+    }
+
+    let dir = dir.join(meta.hash.to_hex().1);
+    let path = dir.join(file_name(meta));
+    if !path.exists() {
+        std::fs::create_dir_all(&dir)?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &path)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(mime_type: MimeType, content_type: &str, content: &[u8]) -> ContentMeta {
+        ContentMeta {
+            hash: ssri::Integrity::from(content),
+            mime_type,
+            content_type: content_type.to_string(),
+            terse: "".to_string(),
+            tiktokens: 0,
+            stats: Default::default(),
+            truncated_from: None,
+        }
+    }
+
+    #[test]
+    fn test_materialize() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let markdown = meta(MimeType::TextPlain, "Markdown", b"# hi");
+        let path = materialize(dir.path(), &markdown, b"# hi").unwrap();
+        assert!(path.ends_with("Markdown.md"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"# hi");
+        assert_eq!(materialize(dir.path(), &markdown, b"# hi").unwrap(), path);
+
+        let names = [
+            (MimeType::TextPlain, "Rust", "Rust.rs"),
+            (MimeType::TextPlain, "Link", "Link.txt"),
+            (MimeType::ImageJpeg, "Image", "Image.jpeg"),
+        ];
+        for (mime_type, content_type, name) in names {
+            assert_eq!(file_name(&meta(mime_type, content_type, b"")), name);
+        }
+
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, "hi").unwrap();
+        let file_ref = file_ref::FileRef::resolve(file.to_str().unwrap()).unwrap();
+        let content = file_ref.to_bytes();
+        let meta = meta(MimeType::FileRef, file_ref::CONTENT_TYPE, &content);
+        assert_eq!(materialize(dir.path(), &meta, &content).unwrap(), file);
+    }
+}
@@ -0,0 +1,170 @@
+// The store's schema version, and the steps which bring an older store up to date. Steps run in
+// order at startup, each bumping the version once it's done, so a step which fails is retried on
+// the next start rather than skipped. The packet log is backed up before the first step runs.
+//
+// A change to how packets or side data are encoded should add a step here, rather than relying on
+// old data failing to decode and being silently dropped.
+
+use std::path::Path;
+
+use crate::compact;
+use crate::store::{analyze, ContentStats, Store};
+
+pub struct Migration {
+    // the schema version the store is at once the step has run
+    pub version: u32,
+    pub name: &'static str,
+    pub run: fn(&mut Store) -> Result<(), String>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "rewrite_v3_packets",
+        run: rewrite_v3_packets,
+    },
+    Migration {
+        version: 2,
+        name: "content_stats",
+        run: content_stats,
+    },
+    Migration {
+        version: 3,
+        name: "reindex",
+        run: reindex,
+    },
+    Migration {
+        version: 4,
+        name: "index_origins",
+        run: reindex,
+    },
+];
+
+pub fn current_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+// Packets from before content types and lock statuses were added are decoded as PacketV3 on every
+// read. Writing them back encodes them as current packets.
+fn rewrite_v3_packets(store: &mut Store) -> Result<(), String> {
+    let packets: Vec<_> = store.scan().collect();
+    for packet in &packets {
+        store.insert_packet(packet);
+    }
+    Ok(())
+}
+
+// Content meta from before stats were added is decoded as ContentMetaV1, with no stats. This
+// analyzes its content, and writes it back with them.
+fn content_stats(store: &mut Store) -> Result<(), String> {
+    let metas: Vec<_> = store.scan_content_meta().into_values().collect();
+    for meta in metas {
+        if meta.stats != ContentStats::default() {
+            continue;
+        }
+        if let Some(content) = store.get_content(&meta.hash) {
+            store.update_content_stats(meta.hash, analyze(&content, &meta.mime_type));
+        }
+    }
+    Ok(())
+}
+
+// The search index is recreated when it's opened with a new schema, e.g. once content's hash was
+// indexed, so its document could be deleted, and once origins were: this fills it again
+fn reindex(store: &mut Store) -> Result<(), String> {
+    store.reindex();
+    Ok(())
+}
+
+// A store at a newer version than this build's was written by a newer build, which may encode it
+// in ways this one can't read
+pub fn check(version: u32) -> Result<(), String> {
+    let current = current_version();
+    if version > current {
+        return Err(format!(
+            "the store is at schema version {}, newer than this build's {}: update Stacks to \
+             open it",
+            version, current
+        ));
+    }
+    Ok(())
+}
+
+// Brings the store up to the current version, returning the names of the steps which ran
+pub fn run(store: &mut Store) -> Result<Vec<&'static str>, String> {
+    let version = store.schema_version_get();
+    let current = current_version();
+    if version == current {
+        return Ok(Vec::new());
+    }
+    check(version)?;
+    // a new store has nothing to migrate
+    if version == 0 && store.packet_count() == 0 {
+        store.schema_version_save(current);
+        return Ok(Vec::new());
+    }
+
+    let packets: Vec<_> = store.scan().collect();
+    let backup = compact::backup(Path::new(&store.path), &packets).map_err(|e| e.to_string())?;
+    tracing::info!(name = "migrate", from = version, to = current, ?backup);
+
+    let mut ran = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        (migration.run)(store).map_err(|e| format!("{}: {}", migration.name, e))?;
+        store.schema_version_save(migration.version);
+        ran.push(migration.name);
+    }
+    Ok(ran)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MimeType, StackLockStatus};
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::new(dir.path().to_str().unwrap());
+        // a new store starts at the current version
+        assert_eq!(store.schema_version_get(), current_version());
+
+        let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        let item = store.add(b"hello", MimeType::TextPlain, stack.id);
+        store.schema_version_save(0);
+        assert_eq!(
+            run(&mut store).unwrap(),
+            vec![
+                "rewrite_v3_packets",
+                "content_stats",
+                "reindex",
+                "index_origins"
+            ]
+        );
+        assert_eq!(store.schema_version_get(), current_version());
+        assert_eq!(store.scan().collect::<Vec<_>>(), vec![stack, item.clone()]);
+        let backups = std::fs::read_dir(dir.path().join(compact::BACKUPS_DIR)).unwrap();
+        assert_eq!(backups.count(), 1);
+
+        assert_eq!(run(&mut store).unwrap(), Vec::<&str>::new());
+
+        // content meta without stats has them filled in
+        let hash = item.hash.clone().unwrap();
+        store.update_content_stats(hash.clone(), ContentStats::default());
+        store.schema_version_save(1);
+        assert_eq!(
+            run(&mut store).unwrap(),
+            vec!["content_stats", "reindex", "index_origins"]
+        );
+        let stats = store.get_content_meta(&hash).unwrap().stats;
+        assert_eq!((stats.words, stats.chars, stats.bytes), (1, 5, 5));
+
+        store.schema_version_save(current_version() + 1);
+        assert!(run(&mut store).is_err());
+        assert_eq!(store.schema_version_get(), current_version() + 1);
+
+        // and isn't opened at all
+        drop(store);
+        assert!(Store::open(dir.path().to_str().unwrap()).is_err());
+    }
+}
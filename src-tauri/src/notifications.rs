@@ -0,0 +1,96 @@
+// Desktop notifications, sent from the backend: when a rule with a notify action matches a clip,
+// when a command piped to the shell finishes after a while, when the cross.stream stack is
+// synced, and when a clip is too large to capture. Each category can be turned off in settings;
+// syncs are off by default, as they happen with every change to the stack.
+
+use std::time::Duration;
+
+use tauri::api::notification::Notification;
+
+use crate::store::Settings;
+
+pub const DEFAULT_COMMAND_AFTER_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Rule,
+    Command,
+    Sync,
+    Capture,
+}
+
+pub fn enabled(settings: &Settings, category: Category) -> bool {
+    match category {
+        Category::Rule => settings.notify_rules.unwrap_or(true),
+        Category::Command => settings.notify_commands.unwrap_or(true),
+        Category::Sync => settings.notify_sync.unwrap_or(false),
+        Category::Capture => settings.notify_capture.unwrap_or(true),
+    }
+}
+
+// Whether a command which took this long is worth a notification
+pub fn command_is_long(settings: &Settings, elapsed: Duration) -> bool {
+    let after = settings
+        .notify_command_after_secs
+        .unwrap_or(DEFAULT_COMMAND_AFTER_SECS);
+    elapsed >= Duration::from_secs(after)
+}
+
+pub fn notify(
+    app: &tauri::AppHandle,
+    settings: &Settings,
+    category: Category,
+    title: &str,
+    body: &str,
+) {
+    if !enabled(settings, category) {
+        return;
+    }
+    let identifier = &app.config().tauri.bundle.identifier;
+    if let Err(e) = Notification::new(identifier).title(title).body(body).show() {
+        tracing::warn!(name = "notifications", ?category, %e, "couldn't notify");
+    }
+}
+
+// Notifies that the command finished, if it took long enough
+pub fn command_finished(
+    app: &tauri::AppHandle,
+    settings: &Settings,
+    command: &str,
+    elapsed: Duration,
+    code: Option<i32>,
+) {
+    if !command_is_long(settings, elapsed) {
+        return;
+    }
+    let status = match code {
+        Some(0) => "finished".to_string(),
+        Some(code) => format!("failed with {}", code),
+        None => "was stopped".to_string(),
+    };
+    let body = format!("{} {} after {}s", command, status, elapsed.as_secs());
+    notify(app, settings, Category::Command, "Command finished", &body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enabled() {
+        let mut settings = Settings::default();
+        assert!(enabled(&settings, Category::Rule));
+        assert!(enabled(&settings, Category::Command));
+        assert!(!enabled(&settings, Category::Sync));
+
+        settings.notify_commands = Some(false);
+        settings.notify_sync = Some(true);
+        assert!(!enabled(&settings, Category::Command));
+        assert!(enabled(&settings, Category::Sync));
+
+        assert!(!command_is_long(&settings, Duration::from_secs(9)));
+        assert!(command_is_long(&settings, Duration::from_secs(10)));
+        settings.notify_command_after_secs = Some(0);
+        assert!(command_is_long(&settings, Duration::ZERO));
+    }
+}
@@ -0,0 +1,419 @@
+// Storage for the packet log. sled is the default; SQLite keeps the log in a single file, which is
+// easy to back up and can be queried directly.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::OptionalExtension;
+use scru128::Scru128Id;
+
+use crate::store::{deserialize_packet, Packet};
+
+// packets are read back from SQLite in pages of this size
+const PAGE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Sled,
+    Sqlite,
+}
+
+impl Backend {
+    // STACK_DB_BACKEND=sqlite opts in to the SQLite backend
+    pub fn from_env() -> Self {
+        match std::env::var("STACK_DB_BACKEND") {
+            Ok(name) if name.eq_ignore_ascii_case("sqlite") => Backend::Sqlite,
+            _ => Backend::Sled,
+        }
+    }
+}
+
+pub trait PacketStore: Send + Sync {
+    fn insert(&mut self, packet: &Packet) -> Result<(), String>;
+    fn remove(&mut self, id: &Scru128Id) -> Result<Option<Packet>, String>;
+    // removes the packets together: if any can't be, none are
+    fn remove_all(&mut self, ids: &HashSet<Scru128Id>) -> Result<(), String>;
+    // all packets, oldest first
+    fn scan(&self) -> Box<dyn Iterator<Item = Packet> + '_>;
+    // packets with an id at or before upper, newest first
+    fn scan_rev(&self, upper: &Scru128Id) -> Box<dyn Iterator<Item = Packet> + '_>;
+
+    fn count(&self) -> usize {
+        self.scan().count()
+    }
+
+    // writes anything pending to disk
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub fn open(path: &Path, db: &sled::Db, backend: Backend) -> Result<Box<dyn PacketStore>, String> {
+    let sled = SledPacketStore {
+        tree: db.open_tree("packets").map_err(|e| e.to_string())?,
+    };
+    match backend {
+        Backend::Sled => Ok(Box::new(sled)),
+        Backend::Sqlite => {
+            let mut sqlite = SqlitePacketStore::new(&path.join("packets.sqlite"))?;
+            sqlite.migrate_from(&sled)?;
+            Ok(Box::new(sqlite))
+        }
+    }
+}
+
+pub struct SledPacketStore {
+    tree: sled::Tree,
+}
+
+impl PacketStore for SledPacketStore {
+    fn insert(&mut self, packet: &Packet) -> Result<(), String> {
+        let encoded: Vec<u8> = bincode::serialize(&packet).map_err(|e| e.to_string())?;
+        self.tree
+            .insert(packet.id.to_bytes(), encoded)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &Scru128Id) -> Result<Option<Packet>, String> {
+        let removed = self.tree.remove(id.to_bytes()).map_err(|e| e.to_string())?;
+        Ok(removed.and_then(|value| deserialize_packet(&value)))
+    }
+
+    fn remove_all(&mut self, ids: &HashSet<Scru128Id>) -> Result<(), String> {
+        let mut batch = sled::Batch::default();
+        ids.iter().for_each(|id| batch.remove(&id.to_bytes()));
+        self.tree.apply_batch(batch).map_err(|e| e.to_string())
+    }
+
+    fn scan(&self) -> Box<dyn Iterator<Item = Packet> + '_> {
+        Box::new(
+            self.tree
+                .iter()
+                .filter_map(|item| item.ok().and_then(|(_, value)| deserialize_packet(&value))),
+        )
+    }
+
+    fn scan_rev(&self, upper: &Scru128Id) -> Box<dyn Iterator<Item = Packet> + '_> {
+        Box::new(
+            self.tree
+                .range(..=upper.to_bytes())
+                .rev()
+                .filter_map(|item| item.ok().and_then(|(_, value)| deserialize_packet(&value))),
+        )
+    }
+
+    fn count(&self) -> usize {
+        self.tree.len()
+    }
+}
+
+pub struct SqlitePacketStore {
+    // a connection can't be shared between threads, and the state is read from several at once
+    conn: Mutex<rusqlite::Connection>,
+}
+
+fn sqlite_error(e: rusqlite::Error) -> String {
+    format!("sqlite: {}", e)
+}
+
+fn insert_row(conn: &rusqlite::Connection, packet: &Packet) -> Result<(), String> {
+    let encoded: Vec<u8> = bincode::serialize(&packet).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO packets (id, packet_type, stack_id, packet)
+        VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![
+            packet.id.to_bytes().to_vec(),
+            format!("{:?}", packet.packet_type),
+            packet.stack_id.map(|id| id.to_bytes().to_vec()),
+            encoded,
+        ],
+    )
+    .map_err(sqlite_error)?;
+    Ok(())
+}
+
+impl SqlitePacketStore {
+    pub fn new(path: &Path) -> Result<Self, String> {
+        let conn = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        // journal_mode reports the mode it switched to
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .map_err(sqlite_error)?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .map_err(sqlite_error)?;
+        // packet_type and stack_id are stored alongside the encoded packet so they can be queried
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS packets (
+                id BLOB PRIMARY KEY,
+                packet_type TEXT NOT NULL,
+                stack_id BLOB,
+                packet BLOB NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS packets_stack_id ON packets (stack_id);",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // The first time the database is opened, the sled packet log is copied into it, in a single
+    // transaction, so switching backends keeps the store's items. user_version records that it
+    // has been; sled's copy is left as it was, to switch back to.
+    fn migrate_from(&mut self, sled: &SledPacketStore) -> Result<(), String> {
+        let conn = self.conn.get_mut().map_err(|e| e.to_string())?;
+        let version: i64 = conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .map_err(sqlite_error)?;
+        if version >= 1 {
+            return Ok(());
+        }
+        let tx = conn.transaction().map_err(sqlite_error)?;
+        let mut count = 0;
+        for packet in sled.scan() {
+            insert_row(&tx, &packet)?;
+            count += 1;
+        }
+        tx.pragma_update(None, "user_version", 1)
+            .map_err(sqlite_error)?;
+        tx.commit().map_err(sqlite_error)?;
+        tracing::info!(
+            name = "packet_store",
+            count,
+            "migrated sled packets to sqlite"
+        );
+        Ok(())
+    }
+
+    // Returns the raw (id, packet) rows for a page
+    fn page(&self, sql: &str, bound: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, String> {
+        let conn = self.conn.lock().map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare_cached(sql).map_err(sqlite_error)?;
+        let rows = stmt
+            .query_map(rusqlite::params![bound, PAGE_SIZE], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(sqlite_error)?;
+        Ok(rows.flatten().collect())
+    }
+
+    // A page, or, if it couldn't be read, none: the error is logged, and the scan ends
+    fn page_or_log(&self, sql: &str, bound: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.page(sql, bound).unwrap_or_else(|e| {
+            tracing::error!(name = "packet_store", %e, "couldn't read packets");
+            Vec::new()
+        })
+    }
+
+    // Walks the table a page at a time, continuing from the bound
+    fn paged<'a>(
+        &'a self,
+        sql: &'static str,
+        bound: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = Packet> + 'a> {
+        let mut bound = bound;
+        let mut page = Vec::new().into_iter();
+        let mut done = false;
+        Box::new(std::iter::from_fn(move || loop {
+            if let Some((id, value)) = page.next() {
+                bound = id;
+                match deserialize_packet(&value) {
+                    Some(packet) => return Some(packet),
+                    None => continue,
+                }
+            }
+            if done {
+                return None;
+            }
+            let rows = self.page_or_log(sql, &bound);
+            done = rows.len() < PAGE_SIZE;
+            page = rows.into_iter();
+        }))
+    }
+}
+
+impl PacketStore for SqlitePacketStore {
+    fn insert(&mut self, packet: &Packet) -> Result<(), String> {
+        let conn = self.conn.get_mut().map_err(|e| e.to_string())?;
+        insert_row(conn, packet)
+    }
+
+    fn remove(&mut self, id: &Scru128Id) -> Result<Option<Packet>, String> {
+        let id = id.to_bytes().to_vec();
+        let removed: Option<Vec<u8>> = self
+            .conn
+            .get_mut()
+            .map_err(|e| e.to_string())?
+            .query_row(
+                "DELETE FROM packets WHERE id = ?1 RETURNING packet",
+                [&id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+        Ok(removed.and_then(|value| deserialize_packet(&value)))
+    }
+
+    fn remove_all(&mut self, ids: &HashSet<Scru128Id>) -> Result<(), String> {
+        let conn = self.conn.get_mut().map_err(|e| e.to_string())?;
+        let tx = conn.transaction().map_err(sqlite_error)?;
+        for id in ids {
+            tx.execute(
+                "DELETE FROM packets WHERE id = ?1",
+                [id.to_bytes().to_vec()],
+            )
+            .map_err(sqlite_error)?;
+        }
+        tx.commit().map_err(sqlite_error)
+    }
+
+    fn scan(&self) -> Box<dyn Iterator<Item = Packet> + '_> {
+        self.paged(
+            "SELECT id, packet FROM packets WHERE id > ?1 ORDER BY id LIMIT ?2",
+            Vec::new(),
+        )
+    }
+
+    fn scan_rev(&self, upper: &Scru128Id) -> Box<dyn Iterator<Item = Packet> + '_> {
+        let upper = upper.to_bytes().to_vec();
+        let first = self.page_or_log(
+            "SELECT id, packet FROM packets WHERE id <= ?1 ORDER BY id DESC LIMIT ?2",
+            &upper,
+        );
+        // the first page includes upper itself; the following pages continue strictly below
+        let rest = match first.last() {
+            Some((id, _)) if first.len() == PAGE_SIZE => Some(self.paged(
+                "SELECT id, packet FROM packets WHERE id < ?1 ORDER BY id DESC LIMIT ?2",
+                id.clone(),
+            )),
+            _ => None,
+        };
+        Box::new(
+            first
+                .into_iter()
+                .filter_map(|(_, value)| deserialize_packet(&value))
+                .chain(rest.into_iter().flatten()),
+        )
+    }
+
+    fn count(&self) -> usize {
+        let count = self
+            .conn
+            .lock()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| {
+                conn.query_row("SELECT COUNT(*) FROM packets", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .map_err(sqlite_error)
+            });
+        match count {
+            Ok(count) => count as usize,
+            Err(e) => {
+                tracing::error!(name = "packet_store", %e, "couldn't count packets");
+                0
+            }
+        }
+    }
+
+    // moves the write-ahead log into the database file
+    fn flush(&self) -> Result<(), String> {
+        self.conn
+            .lock()
+            .map_err(|e| e.to_string())?
+            .query_row("PRAGMA wal_checkpoint(FULL)", [], |_| Ok(()))
+            .map_err(sqlite_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::store::PacketType;
+
+    fn packet(stack_id: Option<Scru128Id>) -> Packet {
+        Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Add,
+            source_id: None,
+            hash: None,
+            stack_id,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+        }
+    }
+
+    #[test]
+    fn test_backends() {
+        for backend in [Backend::Sled, Backend::Sqlite] {
+            let dir = tempfile::tempdir().unwrap();
+            let db = sled::open(dir.path().join("sled")).unwrap();
+            let mut store = open(dir.path(), &db, backend).unwrap();
+
+            let stack = packet(None);
+            store.insert(&stack).unwrap();
+            // enough packets to span more than one page
+            let items: Vec<Packet> = (0..PAGE_SIZE + 10)
+                .map(|_| packet(Some(stack.id)))
+                .collect();
+            items.iter().for_each(|p| store.insert(p).unwrap());
+            assert_eq!(store.count(), items.len() + 1, "{:?}", backend);
+
+            let scanned: Vec<Packet> = store.scan().collect();
+            assert_eq!(scanned[0], stack, "{:?}", backend);
+            assert_eq!(scanned[1..], items[..], "{:?}", backend);
+
+            let upper = items[PAGE_SIZE + 5].id;
+            let rev: Vec<Scru128Id> = store.scan_rev(&upper).map(|p| p.id).collect();
+            let expected: Vec<Scru128Id> = std::iter::once(stack.id)
+                .chain(items[..=PAGE_SIZE + 5].iter().map(|p| p.id))
+                .rev()
+                .collect();
+            assert_eq!(rev, expected, "{:?}", backend);
+
+            assert_eq!(store.remove(&items[0].id), Ok(Some(items[0].clone())));
+            assert_eq!(store.remove(&items[0].id), Ok(None));
+            assert_eq!(store.count(), items.len(), "{:?}", backend);
+
+            let removed: HashSet<Scru128Id> = items[1..4].iter().map(|p| p.id).collect();
+            store.remove_all(&removed).unwrap();
+            assert_eq!(store.count(), items.len() - 3, "{:?}", backend);
+
+            store.flush().unwrap();
+            drop(store);
+            let store = open(dir.path(), &db, backend).unwrap();
+            assert_eq!(store.count(), items.len() - 3, "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn test_migrate_to_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = sled::open(dir.path().join("sled")).unwrap();
+        let mut sled = open(dir.path(), &db, Backend::Sled).unwrap();
+        let stack = packet(None);
+        let item = packet(Some(stack.id));
+        sled.insert(&stack).unwrap();
+        sled.insert(&item).unwrap();
+
+        // the sled log is copied over the first time
+        let mut sqlite = open(dir.path(), &db, Backend::Sqlite).unwrap();
+        assert_eq!(
+            sqlite.scan().collect::<Vec<_>>(),
+            vec![stack.clone(), item.clone()]
+        );
+
+        // and only the first time: a packet removed since isn't brought back
+        sqlite.remove(&item.id).unwrap();
+        drop(sqlite);
+        let sqlite = open(dir.path(), &db, Backend::Sqlite).unwrap();
+        assert_eq!(sqlite.scan().collect::<Vec<_>>(), vec![stack]);
+        assert_eq!(sled.count(), 2);
+    }
+}
@@ -0,0 +1,371 @@
+use base64::{engine::general_purpose, Engine as _};
+use lazy_static::lazy_static;
+use regex::Regex;
+use scru128::Scru128Id;
+
+use crate::file_ref;
+use crate::store::{ContentMeta, MimeType};
+use crate::ui;
+
+lazy_static! {
+    static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PasteFormat {
+    // only the plain text representation
+    Plain,
+    // plain text, additionally tagged as Markdown for apps which understand it
+    Markdown,
+    // Markdown is rendered to HTML, with plain text as a fallback
+    Html,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct PasteRule {
+    pub bundle_id: String,
+    pub format: PasteFormat,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    StripFormatting,
+    Trim,
+    Lowercase,
+    Uppercase,
+    UrlEncode,
+    UrlDecode,
+    Base64Encode,
+    Base64Decode,
+}
+
+// The transform applied by default when pasting items from a given stack
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub struct StackTransform {
+    pub stack_id: Scru128Id,
+    pub transform: Transform,
+}
+
+fn strip_formatting(input: &str) -> String {
+    let stripped = TAG.replace_all(input, "");
+    stripped
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn url_encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn url_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// Returns None if the transform can't be applied, e.g. decoding invalid base64
+pub fn transform(transform: &Transform, input: &str) -> Option<String> {
+    match transform {
+        Transform::StripFormatting => Some(strip_formatting(input)),
+        Transform::Trim => Some(input.trim().to_string()),
+        Transform::Lowercase => Some(input.to_lowercase()),
+        Transform::Uppercase => Some(input.to_uppercase()),
+        Transform::UrlEncode => Some(url_encode(input)),
+        Transform::UrlDecode => url_decode(input),
+        Transform::Base64Encode => Some(general_purpose::STANDARD.encode(input)),
+        Transform::Base64Decode => general_purpose::STANDARD
+            .decode(input.trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok()),
+    }
+}
+
+// Applies a transform to text content, as it's pasted or captured. Returns None for content which
+// isn't text, or which the transform can't be applied to.
+pub fn transform_content(
+    with: &Transform,
+    content: &[u8],
+    mime_type: &MimeType,
+) -> Option<Vec<u8>> {
+    if *mime_type != MimeType::TextPlain {
+        return None;
+    }
+    transform(with, &String::from_utf8_lossy(content)).map(String::into_bytes)
+}
+
+pub fn format_for(rules: &[PasteRule], bundle_id: Option<&str>) -> PasteFormat {
+    bundle_id
+        .and_then(|bundle_id| {
+            rules
+                .iter()
+                .find(|rule| rule.bundle_id.eq_ignore_ascii_case(bundle_id))
+        })
+        .map(|rule| rule.format.clone())
+        .unwrap_or(PasteFormat::Plain)
+}
+
+// If the text is the path of an existing file, or a file URL, returns it as a file URL
+pub fn file_url(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.contains('\n') {
+        return None;
+    }
+    let path = match text.strip_prefix("file://") {
+        Some(encoded) => url_decode(encoded)?,
+        None => text.to_string(),
+    };
+    if !path.starts_with('/') || !std::path::Path::new(&path).exists() {
+        return None;
+    }
+    Some(path_url(&path))
+}
+
+pub fn path_url(path: &str) -> String {
+    let encoded: Vec<String> = path.split('/').map(url_encode).collect();
+    format!("file://{}", encoded.join("/"))
+}
+
+// Transcodes an image to PNG
+pub fn to_png(content: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(content).ok()?;
+    let mut png = Vec::new();
+    image
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .ok()?;
+    Some(png)
+}
+
+// Returns the pasteboard types, and their data, to write for the given content
+pub fn representations(
+    format: &PasteFormat,
+    theme_mode: &str,
+    meta: &ContentMeta,
+    content: &[u8],
+) -> Vec<(&'static str, Vec<u8>)> {
+    if meta.mime_type == MimeType::FileRef {
+        // the file itself, with its path as a plain text fallback: if the file's gone, just the path
+        if let Some(file_ref) = file_ref::parse(content) {
+            if !std::path::Path::new(&file_ref.path).exists() {
+                return vec![("public.utf8-plain-text", file_ref.path.into_bytes())];
+            }
+            return vec![
+                ("public.file-url", file_ref.url().into_bytes()),
+                ("public.utf8-plain-text", file_ref.path.into_bytes()),
+            ];
+        }
+    }
+
+    if meta.mime_type.is_image() {
+        let mut types = vec![(meta.mime_type.pasteboard_type(), content.to_vec())];
+        // not every app reads JPEG, TIFF, GIF or WebP, so offer a PNG as well
+        if meta.mime_type != MimeType::ImagePng {
+            if let Some(png) = to_png(content) {
+                types.push(("public.png", png));
+            }
+        }
+        return types;
+    }
+
+    let mut types = vec![("public.utf8-plain-text", content.to_vec())];
+    match format {
+        PasteFormat::Plain => {}
+        PasteFormat::Markdown => {
+            types.push(("net.daringfireball.markdown", content.to_vec()));
+        }
+        PasteFormat::Html => {
+            if meta.content_type == "Markdown" {
+                let html = ui::markdown_to_html(theme_mode, &content.to_vec());
+                types.push(("public.html", html.into_bytes()));
+            }
+        }
+    }
+    types
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(content_type: &str) -> ContentMeta {
+        ContentMeta {
+            hash: ssri::Integrity::from(""),
+            mime_type: MimeType::TextPlain,
+            content_type: content_type.to_string(),
+            terse: "".to_string(),
+            tiktokens: 0,
+            stats: Default::default(),
+            truncated_from: None,
+        }
+    }
+
+    #[test]
+    fn test_format_for() {
+        let rules = vec![
+            PasteRule {
+                bundle_id: "com.apple.Terminal".to_string(),
+                format: PasteFormat::Plain,
+            },
+            PasteRule {
+                bundle_id: "md.obsidian".to_string(),
+                format: PasteFormat::Markdown,
+            },
+        ];
+        assert_eq!(
+            format_for(&rules, Some("md.obsidian")),
+            PasteFormat::Markdown
+        );
+        assert_eq!(
+            format_for(&rules, Some("com.apple.terminal")),
+            PasteFormat::Plain
+        );
+        assert_eq!(
+            format_for(&rules, Some("com.apple.mail")),
+            PasteFormat::Plain
+        );
+        assert_eq!(format_for(&rules, None), PasteFormat::Plain);
+    }
+
+    #[test]
+    fn test_transform() {
+        let t = |tr, s| transform(&tr, s);
+        assert_eq!(
+            t(Transform::StripFormatting, "<b>Fish &amp; chips</b>"),
+            Some("Fish & chips".to_string())
+        );
+        assert_eq!(t(Transform::Trim, "  hi \n"), Some("hi".to_string()));
+        assert_eq!(
+            t(Transform::Uppercase, "straße"),
+            Some("STRASSE".to_string())
+        );
+        assert_eq!(
+            t(Transform::UrlEncode, "a b&c=é"),
+            Some("a%20b%26c%3D%C3%A9".to_string())
+        );
+        assert_eq!(
+            t(Transform::UrlDecode, "a%20b%26c%3D%C3%A9"),
+            Some("a b&c=é".to_string())
+        );
+        assert_eq!(t(Transform::UrlDecode, "100%"), None);
+        assert_eq!(t(Transform::Base64Encode, "hi"), Some("aGk=".to_string()));
+        assert_eq!(t(Transform::Base64Decode, "aGk=\n"), Some("hi".to_string()));
+        assert_eq!(t(Transform::Base64Decode, "not base64!"), None);
+    }
+
+    #[test]
+    fn test_representations() {
+        let types = representations(&PasteFormat::Html, "dark", &meta("Markdown"), b"# hi");
+        let names: Vec<_> = types.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["public.utf8-plain-text", "public.html"]);
+
+        // only Markdown is rendered to HTML
+        let types = representations(&PasteFormat::Html, "dark", &meta("Text"), b"# hi");
+        assert_eq!(types.len(), 1);
+    }
+
+    #[test]
+    fn test_representations_image() {
+        let mut gif = Vec::new();
+        image::DynamicImage::new_rgb8(2, 2)
+            .write_to(
+                &mut std::io::Cursor::new(&mut gif),
+                image::ImageOutputFormat::Gif,
+            )
+            .unwrap();
+        let meta = ContentMeta {
+            mime_type: MimeType::ImageGif,
+            ..meta("Image")
+        };
+
+        let types = representations(&PasteFormat::Html, "dark", &meta, &gif);
+        let names: Vec<_> = types.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["com.compuserve.gif", "public.png"]);
+        assert!(types[1].1.starts_with(b"\x89PNG"));
+
+        // PNGs are written as is
+        let meta = ContentMeta {
+            mime_type: MimeType::ImagePng,
+            ..meta
+        };
+        let types = representations(&PasteFormat::Html, "dark", &meta, &types[1].1);
+        assert_eq!(types.len(), 1);
+    }
+
+    #[test]
+    fn test_representations_file_ref() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a file.txt");
+        std::fs::write(&path, "hi").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let file_ref = file_ref::FileRef {
+            path: path.clone(),
+            name: "a file.txt".to_string(),
+            size: 2,
+        };
+        let meta = ContentMeta {
+            mime_type: MimeType::FileRef,
+            ..meta(file_ref::CONTENT_TYPE)
+        };
+        let types = representations(&PasteFormat::Html, "dark", &meta, &file_ref.to_bytes());
+        assert_eq!(
+            types,
+            vec![
+                ("public.file-url", path_url(&path).into_bytes()),
+                ("public.utf8-plain-text", path.clone().into_bytes()),
+            ]
+        );
+
+        // once the file's gone, only its path is offered
+        std::fs::remove_file(&path).unwrap();
+        let types = representations(&PasteFormat::Html, "dark", &meta, &file_ref.to_bytes());
+        assert_eq!(types, vec![("public.utf8-plain-text", path.into_bytes())]);
+    }
+
+    #[test]
+    fn test_file_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a file.txt");
+        std::fs::write(&path, "hi").unwrap();
+        let path = path.to_str().unwrap();
+
+        let url = file_url(&format!("{}\n", path)).unwrap();
+        assert!(url.starts_with("file:///"));
+        assert!(url.ends_with("/a%20file.txt"));
+        assert_eq!(file_url(&url), Some(url.clone()));
+
+        assert_eq!(file_url("/no/such/file"), None);
+        assert_eq!(file_url("relative.txt"), None);
+        // text which happens to be a path is only pasted as text
+        let types = representations(&PasteFormat::Plain, "dark", &meta("Text"), path.as_bytes());
+        let names: Vec<_> = types.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["public.utf8-plain-text"]);
+    }
+}
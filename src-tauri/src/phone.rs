@@ -0,0 +1,123 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Items which are entirely a phone number have this content type
+pub const CONTENT_TYPE: &str = "Phone";
+
+lazy_static! {
+    static ref PHONE: Regex = Regex::new(r"[+(]?\b\d[\d \t().-]{5,}\d\b").unwrap();
+    static ref DATE: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Phone {
+    // the number as it appears in the text
+    pub text: String,
+    // None if the number's country can't be determined
+    pub e164: Option<String>,
+}
+
+impl Phone {
+    // via is one of: tel, facetime or facetime-audio
+    pub fn dial_link(&self, via: &str) -> Option<String> {
+        let number = self.e164.as_ref()?;
+        match via {
+            "tel" | "facetime" | "facetime-audio" => Some(format!("{}:{}", via, number)),
+            _ => None,
+        }
+    }
+}
+
+// Normalizes to E.164. Numbers without a country code, e.g. (555) 010-9999, could be from
+// anywhere, so they aren't normalized: dialing a guess could call the wrong number.
+pub fn normalize(input: &str) -> Option<String> {
+    let input = input.trim();
+    let digits: String = input.chars().filter(|c| c.is_ascii_digit()).collect();
+
+    let international = if input.starts_with('+') {
+        digits
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        rest.to_string()
+    } else {
+        return None;
+    };
+
+    if international.len() < 8 || international.len() > 15 || international.starts_with('0') {
+        return None;
+    }
+    Some(format!("+{}", international))
+}
+
+// Returns the phone numbers found in the text, in the order they appear
+pub fn find(input: &str) -> Vec<Phone> {
+    PHONE
+        .find_iter(input)
+        .map(|m| m.as_str().trim())
+        .filter(|text| {
+            let digits = text.chars().filter(|c| c.is_ascii_digit()).count();
+            (7..=15).contains(&digits) && !DATE.is_match(text)
+        })
+        .map(|text| Phone {
+            text: text.to_string(),
+            e164: normalize(text),
+        })
+        .collect()
+}
+
+// Returns true if the text is a single, dialable, phone number and nothing else
+pub fn is_phone(input: &str) -> bool {
+    let input = input.trim();
+    match find(input).as_slice() {
+        [phone] => phone.text == input && phone.e164.is_some(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize("+1 (555) 010-9999"),
+            Some("+15550109999".to_string())
+        );
+        assert_eq!(
+            normalize("+44 20 7946 0958"),
+            Some("+442079460958".to_string())
+        );
+        assert_eq!(
+            normalize("0044 20 7946 0958"),
+            Some("+442079460958".to_string())
+        );
+        // without a country code, the number's region is unknown
+        assert_eq!(normalize("020 7946 0958"), None);
+        assert_eq!(normalize("(555) 010-9999"), None);
+        assert_eq!(normalize("1-555-010-9999"), None);
+        assert_eq!(normalize("98765 43210"), None);
+        assert_eq!(normalize("12345"), None);
+    }
+
+    #[test]
+    fn test_find() {
+        let found = find("call +1 (555) 010-9999 or 555.010.1234, not before 2023-10-01");
+        let e164: Vec<_> = found.iter().map(|p| p.e164.as_deref()).collect();
+        assert_eq!(e164, vec![Some("+15550109999"), None]);
+    }
+
+    #[test]
+    fn test_dial_link() {
+        let phone = &find("+1 555-010-9999")[0];
+        assert_eq!(phone.dial_link("tel"), Some("tel:+15550109999".to_string()));
+        assert_eq!(
+            phone.dial_link("facetime"),
+            Some("facetime:+15550109999".to_string())
+        );
+        assert_eq!(phone.dial_link("skype"), None);
+        assert_eq!(find("555-010-9999")[0].dial_link("tel"), None);
+        assert!(is_phone(" +1 555-010-9999\n"));
+        assert!(!is_phone("+1 555-010-9999 ext"));
+        assert!(!is_phone("555-010-9999"));
+    }
+}
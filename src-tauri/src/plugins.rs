@@ -0,0 +1,416 @@
+// Plugins are Lua scripts, in the plugins directory in the store's, which react to new clips
+// without the app being rebuilt. A plugin defines on_clip(item), which is called with each new clip,
+// and is given a small API, as the stacks table:
+//
+//     stacks.get(id) -> item, or nil
+//     stacks.add(content, stack_id?) -> id, adding text to the stack, or to the clip's
+//     stacks.note(id, text), setting the item's note
+//     stacks.emit(name, payload), emitting the plugin event
+//     stacks.fetch(url) -> body, if the user has allowed the plugin to: see plugins_fetch_allowed
+//
+// e.g. to note the Jira issues a clip mentions:
+//
+//     function on_clip(item)
+//       local issues = {}
+//       for issue in (item.content or ""):gmatch("%u+%-%d+") do table.insert(issues, issue) end
+//       if #issues > 0 then stacks.note(item.id, table.concat(issues, " ")) end
+//     end
+//
+// Plugins are loaded afresh for each clip, so edits apply to the next one. Only Lua's string, table,
+// math and utf8 libraries are loaded: plugins can't reach the file system or run commands. A plugin
+// is stopped once it's run for TIMEOUT, or used MEMORY_LIMIT, and it isn't called for sensitive
+// clips, or clips due to expire, e.g. one-time codes and those copied in privacy mode.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mlua::{
+    Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, SerializeOptions, StdLib, VmState,
+};
+use scru128::Scru128Id;
+use serde::Serialize;
+
+use crate::events;
+use crate::state::{SharedState, State};
+use crate::store::MimeType;
+
+pub const DIR_NAME: &str = "plugins";
+
+// how long a plugin can run for, per clip
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+// the time is checked every this many instructions
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// plugin, event name, payload
+pub type Emit = Arc<dyn Fn(&str, &str, serde_json::Value) + Send + Sync>;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PluginEvent {
+    pub plugin: String,
+    pub name: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PluginInfo {
+    pub name: String,
+    pub fetch_allowed: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct Item {
+    id: String,
+    stack_id: Option<String>,
+    mime_type: MimeType,
+    content_type: String,
+    // text items only
+    content: Option<String>,
+    note: Option<String>,
+}
+
+pub fn dir(state: &State) -> PathBuf {
+    Path::new(&state.store.path).join(DIR_NAME)
+}
+
+// The plugins' names, from their file names, and their source, by name
+pub fn load(dir: &Path) -> Vec<(String, String)> {
+    let mut plugins: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((name, std::fs::read_to_string(&path).ok()?))
+        })
+        .collect();
+    plugins.sort();
+    plugins
+}
+
+pub fn list(state: &State) -> Vec<PluginInfo> {
+    let allowed = fetch_allowed(state);
+    load(&dir(state))
+        .into_iter()
+        .map(|(name, _)| PluginInfo {
+            fetch_allowed: allowed.contains(&name),
+            name,
+        })
+        .collect()
+}
+
+fn fetch_allowed(state: &State) -> Vec<String> {
+    state
+        .store
+        .settings_get()
+        .and_then(|settings| settings.plugins_fetch_allowed)
+        .unwrap_or_default()
+}
+
+fn item(state: &State, id: &Scru128Id) -> Option<Item> {
+    let item = state.view.items.get(id).filter(|item| !item.is_stack)?;
+    let meta = state.store.get_content_meta(&item.hash)?;
+    let content = (meta.mime_type == MimeType::TextPlain)
+        .then(|| state.store.get_content(&item.hash))
+        .flatten()
+        .map(|content| String::from_utf8_lossy(&content).to_string());
+    Some(Item {
+        id: id.to_string(),
+        stack_id: item.stack_id.map(|id| id.to_string()),
+        mime_type: meta.mime_type,
+        content_type: meta.content_type,
+        content,
+        note: state.store.note_get(id),
+    })
+}
+
+fn error(message: String) -> mlua::Error {
+    mlua::Error::RuntimeError(message)
+}
+
+fn parse_id(id: &str) -> mlua::Result<Scru128Id> {
+    id.parse().map_err(|_| error(format!("not an id: {}", id)))
+}
+
+fn to_lua<'lua, T: Serialize>(lua: &'lua Lua, value: &T) -> mlua::Result<mlua::Value<'lua>> {
+    // nil, rather than a null which is truthy
+    lua.to_value_with(value, SerializeOptions::new().serialize_none_to_null(false))
+}
+
+// Runs the plugin's on_clip for the clip, stopping it once it's run for timeout
+pub fn run(
+    state: &SharedState,
+    emit: &Emit,
+    name: &str,
+    source: &str,
+    clip_id: Scru128Id,
+    timeout: Duration,
+) -> mlua::Result<()> {
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::default(),
+    )?;
+    lua.set_memory_limit(MEMORY_LIMIT)?;
+    let deadline = Instant::now() + timeout;
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(HOOK_INSTRUCTIONS),
+            ..Default::default()
+        },
+        move |_, _| {
+            if Instant::now() >= deadline {
+                return Err(error(format!("timed out after {:?}", timeout)));
+            }
+            Ok(VmState::Continue)
+        },
+    );
+    let (clip, allowed) = state.with_lock(|state| {
+        (
+            item(state, &clip_id),
+            fetch_allowed(state).iter().any(|allowed| allowed == name),
+        )
+    });
+    let api = lua.create_table()?;
+
+    let s = state.clone();
+    api.set(
+        "get",
+        lua.create_function(move |lua, id: String| {
+            let id = parse_id(&id)?;
+            to_lua(lua, &s.with_lock(|state| item(state, &id)))
+        })?,
+    )?;
+
+    let s = state.clone();
+    let clip_stack = clip.as_ref().and_then(|clip| clip.stack_id.clone());
+    api.set(
+        "add",
+        lua.create_function(move |_, (content, stack_id): (String, Option<String>)| {
+            let stack_id = stack_id
+                .or(clip_stack.clone())
+                .ok_or(error("no stack to add to".to_string()))?;
+            let stack_id = parse_id(&stack_id)?;
+            s.with_lock(|state| {
+                if !state
+                    .view
+                    .items
+                    .get(&stack_id)
+                    .is_some_and(|item| item.is_stack)
+                {
+                    return Err(error(format!("not a stack: {}", stack_id)));
+                }
+                if state.is_read_only(&stack_id) {
+                    return Err(error(format!("read-only: {}", stack_id)));
+                }
+                let packet = state
+                    .store
+                    .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+                state.merge(&packet);
+                Ok(packet.id.to_string())
+            })
+        })?,
+    )?;
+
+    let s = state.clone();
+    api.set(
+        "note",
+        lua.create_function(move |_, (id, text): (String, String)| {
+            let id = parse_id(&id)?;
+            s.with_lock(|state| {
+                if state.is_read_only(&id) {
+                    return Err(error(format!("read-only: {}", id)));
+                }
+                state.store.note_set(id, &text);
+                Ok(())
+            })
+        })?,
+    )?;
+
+    let (e, plugin) = (emit.clone(), name.to_string());
+    api.set(
+        "emit",
+        lua.create_function(move |lua, (event, payload): (String, mlua::Value)| {
+            let payload: serde_json::Value = lua.from_value(payload)?;
+            e(&plugin, &event, payload);
+            Ok(())
+        })?,
+    )?;
+
+    let plugin = name.to_string();
+    api.set(
+        "fetch",
+        lua.create_function(move |_, url: String| {
+            if !allowed {
+                return Err(error(format!(
+                    "{} isn't allowed to fetch: see plugins_fetch_allowed",
+                    plugin
+                )));
+            }
+            reqwest::blocking::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .and_then(|client| client.get(&url).send())
+                .and_then(|res| res.error_for_status())
+                .and_then(|res| res.text())
+                .map_err(mlua::Error::external)
+        })?,
+    )?;
+
+    lua.globals().set("stacks", api)?;
+    lua.load(source).set_name(name).exec()?;
+    if let (Some(on_clip), Some(clip)) =
+        (lua.globals().get::<_, Option<Function>>("on_clip")?, clip)
+    {
+        on_clip.call::<_, ()>(to_lua(&lua, &clip)?)?;
+    }
+    Ok(())
+}
+
+// Runs each plugin's on_clip for the new clip, in the background
+pub fn on_clip(app: &tauri::AppHandle, state: &SharedState, clip_id: Scru128Id) {
+    let plugins = state.with_read(|state| {
+        if state.store.is_sensitive(&clip_id) || state.store.has_expiry(&clip_id) {
+            return Vec::new();
+        }
+        load(&dir(state))
+    });
+    if plugins.is_empty() {
+        return;
+    }
+    let (app, state) = (app.clone(), state.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+        let emit: Emit = {
+            let app = app.clone();
+            Arc::new(move |plugin, name, payload| {
+                let event = PluginEvent {
+                    plugin: plugin.to_string(),
+                    name: name.to_string(),
+                    payload,
+                };
+                events::emit(&app, "plugin", event);
+            })
+        };
+        for (name, source) in plugins {
+            if let Err(e) = run(&state, &emit, &name, &source, clip_id, TIMEOUT) {
+                tracing::warn!(name = "plugins", plugin = %name, %e, "plugin failed");
+            }
+        }
+        events::emit(&app, "refresh-items", true);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use crate::state::StateLock;
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+        let stack_id = state.get_curr_stack();
+        let packet = state.store.add(
+            b"PROJ-123 is blocked on OPS-7",
+            MimeType::TextPlain,
+            stack_id,
+        );
+        state.merge(&packet);
+        let clip_id = packet.id;
+
+        let plugins = dir.path().join(DIR_NAME);
+        std::fs::create_dir(&plugins).unwrap();
+        std::fs::write(
+            plugins.join("jira.lua"),
+            r#"
+            function on_clip(item)
+              local issues = {}
+              for issue in (item.content or ""):gmatch("%u+%-%d+") do
+                table.insert(issues, issue)
+              end
+              if #issues > 0 then
+                stacks.note(item.id, table.concat(issues, " "))
+                local id = stacks.add("issues: " .. #issues)
+                stacks.emit("tagged", { id = item.id, issues = issues, added = stacks.get(id).content })
+              end
+            end
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            plugins.join("fetch.lua"),
+            r#"stacks.fetch("https://example.com")"#,
+        )
+        .unwrap();
+        std::fs::write(plugins.join("notes.txt"), "not a plugin").unwrap();
+        assert_eq!(
+            load(&plugins)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["fetch", "jira"]
+        );
+
+        let state: SharedState = Arc::new(StateLock::new("SharedState", state));
+        let emitted = Arc::new(Mutex::new(Vec::new()));
+        let emit: Emit = {
+            let emitted = emitted.clone();
+            Arc::new(move |plugin, name, payload| {
+                emitted
+                    .lock()
+                    .unwrap()
+                    .push((plugin.to_string(), name.to_string(), payload))
+            })
+        };
+
+        let (_, source) = &load(&plugins)[1];
+        run(&state, &emit, "jira", source, clip_id, TIMEOUT).unwrap();
+        state.with_lock(|state| {
+            assert_eq!(
+                state.store.note_get(&clip_id).as_deref(),
+                Some("PROJ-123 OPS-7")
+            );
+        });
+        assert_eq!(
+            emitted.lock().unwrap().clone(),
+            vec![(
+                "jira".to_string(),
+                "tagged".to_string(),
+                serde_json::json!({
+                    "id": clip_id.to_string(),
+                    "issues": ["PROJ-123", "OPS-7"],
+                    "added": "issues: 2",
+                })
+            )]
+        );
+
+        // fetching needs the user's consent
+        let (_, source) = &load(&plugins)[0];
+        let err = run(&state, &emit, "fetch", source, clip_id, TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("isn't allowed to fetch"));
+
+        // nor can plugins reach the file system
+        let err = run(
+            &state,
+            &emit,
+            "io",
+            r#"io.open("/etc/passwd")"#,
+            clip_id,
+            TIMEOUT,
+        );
+        assert!(err.is_err());
+
+        // a plugin which never returns is stopped
+        let timeout = Duration::from_millis(100);
+        let err = run(&state, &emit, "loop", "while true do end", clip_id, timeout).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+}
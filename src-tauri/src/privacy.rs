@@ -0,0 +1,112 @@
+// Timed privacy modes, for copying sensitive data: either capture is paused, or clips are captured
+// but purged, along with their content, once they expire.
+
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::backup;
+use crate::events;
+use crate::state::{SharedState, State};
+
+pub const DEFAULT_EXPIRE_AFTER_SECS: u64 = 600;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "mode")]
+pub enum Mode {
+    // clipboard changes aren't captured
+    Pause,
+    // clips are captured, and purged this many seconds later
+    Expire { after_secs: u64 },
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Privacy {
+    pub mode: Mode,
+    // unix timestamp, in milliseconds, when the mode ends
+    pub until: u64,
+}
+
+impl Privacy {
+    pub fn new(mode: Mode, now: u64, minutes: u64) -> Self {
+        Self {
+            mode,
+            until: now + minutes * 60_000,
+        }
+    }
+
+    pub fn is_active(&self, now: u64) -> bool {
+        now < self.until
+    }
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Purges the items whose expiry has passed, returning their ids
+pub fn purge_expired(state: &mut State, now: u64) -> Vec<Scru128Id> {
+    let due = state.store.expiries_due(now);
+    if due.is_empty() {
+        return due;
+    }
+    for id in &due {
+        state.store.purge(id);
+        state.store.expiry_clear(id);
+    }
+    let stats = state.store.gc();
+    tracing::info!(name = "privacy", purged = due.len(), ?stats, "expired");
+    state.rebuild_view();
+    due
+}
+
+pub fn spawn(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let now = now();
+            let (purged, ended, backups) = state.with_lock(|state| {
+                let purged = purge_expired(state, now);
+                let ended = state.privacy.is_some_and(|p| !p.is_active(now));
+                if ended {
+                    state.privacy = None;
+                }
+                let settings = state.store.settings_get();
+                let backups = backup::target_dir(&state.store.path, settings.as_ref());
+                (purged, ended, backups)
+            });
+            if !purged.is_empty() {
+                events::emit(&app, "refresh-items", true);
+                // snapshots don't keep what was purged either
+                let pruned =
+                    tauri::async_runtime::spawn_blocking(move || backup::prune(&backups, &purged))
+                        .await;
+                if let Ok(Err(e)) = pruned {
+                    tracing::error!(name = "privacy", %e, "failed to prune snapshots");
+                }
+            }
+            if ended {
+                events::emit(&app, "privacy", None::<Privacy>);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_privacy() {
+        let privacy = Privacy::new(Mode::Pause, 1_000, 5);
+        assert_eq!(privacy.until, 301_000);
+        assert!(privacy.is_active(300_999));
+        assert!(!privacy.is_active(301_000));
+    }
+}
@@ -0,0 +1,138 @@
+// A Prompts stack holds prompt templates. Composing a prompt fills a template with the content of
+// the selected items: {content} takes all of them, separated by blank lines, and {1}, {2}, ...
+// each in turn. A template without placeholders has the items appended. The composed prompt is
+// copied, or sent to the LLM, as an action is: see actions.
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::error::StacksError;
+use crate::stack_settings::StackKind;
+use crate::state::State;
+use crate::store::MimeType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Copy,
+    Llm,
+}
+
+pub fn compose(template: &str, inputs: &[String]) -> String {
+    let all = inputs.join("\n\n");
+    let mut composed = template.replace("{content}", &all);
+    let mut placed = composed != template;
+    for (i, input) in inputs.iter().enumerate() {
+        let placeholder = format!("{{{}}}", i + 1);
+        if composed.contains(&placeholder) {
+            composed = composed.replace(&placeholder, input);
+            placed = true;
+        }
+    }
+    if !placed && !all.is_empty() {
+        composed = format!("{}\n\n{}", composed.trim_end(), all);
+    }
+    composed
+}
+
+fn text(state: &State, id: &Scru128Id) -> Result<String, StacksError> {
+    let item = state
+        .view
+        .items
+        .get(id)
+        .filter(|item| !item.is_stack)
+        .ok_or(StacksError::not_found(id))?;
+    let meta = state
+        .store
+        .get_content_meta(&item.hash)
+        .ok_or(StacksError::content_not_found(id))?;
+    if meta.mime_type != MimeType::TextPlain {
+        return Err(StacksError::invalid_input(format!("not text: {}", id)));
+    }
+    let content = state
+        .store
+        .get_content(&item.hash)
+        .ok_or(StacksError::content_not_found(id))?;
+    Ok(String::from_utf8_lossy(&content).to_string())
+}
+
+// Fills the template, which has to be an item in a Prompts stack, with the inputs' content
+pub fn compose_items(
+    state: &State,
+    template_id: &Scru128Id,
+    input_ids: &[Scru128Id],
+) -> Result<String, StacksError> {
+    let in_prompts = state
+        .view
+        .items
+        .get(template_id)
+        .and_then(|item| item.stack_id)
+        .is_some_and(|stack_id| {
+            state.store.stack_settings_get(&stack_id).kind == StackKind::Prompts
+        });
+    if !in_prompts {
+        return Err(StacksError::invalid_input(format!(
+            "not a prompt template: {}",
+            template_id
+        )));
+    }
+    let template = text(state, template_id)?;
+    let inputs = input_ids
+        .iter()
+        .map(|id| text(state, id))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(compose(&template, &inputs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack_settings::StackSettings;
+    use crate::store::StackLockStatus;
+
+    #[test]
+    fn test_compose() {
+        let inputs = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(
+            compose("Compare:\n{content}", &inputs),
+            "Compare:\none\n\ntwo"
+        );
+        assert_eq!(compose("{2} before {1}", &inputs), "two before one");
+        assert_eq!(compose("Summarize\n", &inputs), "Summarize\n\none\n\ntwo");
+        assert_eq!(compose("Just this", &[]), "Just this");
+        let many: Vec<String> = (1..=10).map(|i| i.to_string()).collect();
+        assert_eq!(compose("{10} {1}", &many), "10 1");
+    }
+
+    #[test]
+    fn test_compose_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let prompts = state.store.add_stack(b"Prompts", StackLockStatus::Unlocked);
+        state.merge(&prompts);
+        let template = state
+            .store
+            .add(b"Review this:\n{1}", MimeType::TextPlain, prompts.id);
+        state.merge(&template);
+        let input = state
+            .store
+            .add(b"fn main() {}", MimeType::TextPlain, prompts.id);
+        state.merge(&input);
+
+        // only items in a Prompts stack are templates
+        assert!(compose_items(&state, &template.id, &[input.id]).is_err());
+        let settings = StackSettings {
+            kind: StackKind::Prompts,
+            ..Default::default()
+        };
+        state.store.stack_settings_set(prompts.id, &settings);
+        assert_eq!(
+            compose_items(&state, &template.id, &[input.id]),
+            Ok("Review this:\nfn main() {}".to_string())
+        );
+        assert!(compose_items(&state, &template.id, &[prompts.id]).is_err());
+    }
+}
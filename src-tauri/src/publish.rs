@@ -1,12 +1,23 @@
 use std::collections::HashMap;
-use std::sync::mpsc::Receiver;
+use tokio::sync::watch;
 use tracing::{error, info};
 
+use crate::notifications;
 use crate::state::SharedState;
 use crate::ui;
 use crate::store;
 use crate::view;
 
+// Only the latest view matters to the publisher, so views are sent over a watch channel, which
+// holds a single value: if the publisher falls behind, intermediate views are coalesced rather
+// than queued. Each view carries a sequence number so the publisher can tell how many it skipped.
+pub type ViewSender = watch::Sender<(u64, view::View)>;
+pub type ViewReceiver = watch::Receiver<(u64, view::View)>;
+
+pub fn channel() -> (ViewSender, ViewReceiver) {
+    watch::channel((0, view::View::new()))
+}
+
 // tracks previously published state
 struct PreviousPublish {
     items: Vec<(view::Item, store::ContentMeta)>,
@@ -60,7 +71,7 @@ fn generate(state: &SharedState, item: &view::Item, content_meta: &store::Conten
     let (content, theme_mode) = state.with_lock(|state| {
         (
             state.store.get_content(&item.hash),
-            state.ui.theme_mode.clone(),
+            state.ui.theme.syntax.clone(),
         )
     });
     ui::generate_preview(
@@ -73,15 +84,21 @@ fn generate(state: &SharedState, item: &view::Item, content_meta: &store::Conten
 }
 
 #[tracing::instrument(skip_all)]
-fn process(state: &SharedState, view: &view::View, previous: &mut PreviousPublish) {
-    let (token, items) = state.with_lock(|state| {
-        let settings = state.store.settings_get();
+fn process(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    view: &view::View,
+    previous: &mut PreviousPublish,
+) {
+    let (token, items, settings) = state.with_lock(|state| {
+        let settings = state.store.settings_get().unwrap_or_default();
         let token = settings
-            .and_then(|s| s.cross_stream_access_token)
+            .cross_stream_access_token
+            .clone()
             .filter(|t| t.len() == 64);
 
         if token.is_none() {
-            return (token, Vec::new());
+            return (token, Vec::new(), settings);
         }
 
         let id = view
@@ -106,7 +123,7 @@ fn process(state: &SharedState, view: &view::View, previous: &mut PreviousPublis
             Vec::new()
         };
 
-        (token, items)
+        (token, items, settings)
     });
 
     let token = match token {
@@ -137,16 +154,33 @@ fn process(state: &SharedState, view: &view::View, previous: &mut PreviousPublis
         .join("");
 
     match post(&token, &previews) {
-        Ok(_) => previous.items = items,
+        Ok(_) => {
+            let body = format!("{} items synced to cross.stream", items.len());
+            notifications::notify(
+                app,
+                &settings,
+                notifications::Category::Sync,
+                "Synced",
+                &body,
+            );
+            previous.items = items;
+        }
         Err(_) => {}
     }
 }
 
-pub fn spawn(state: SharedState, packet_receiver: Receiver<view::View>) {
+pub fn spawn(app: tauri::AppHandle, state: SharedState, mut packet_receiver: ViewReceiver) {
     std::thread::spawn(move || {
         let mut previous = PreviousPublish::new();
-        for view in packet_receiver {
-            process(&state, &view, &mut previous)
+        let mut last_seq = 0;
+        while tauri::async_runtime::block_on(packet_receiver.changed()).is_ok() {
+            let (seq, view) = packet_receiver.borrow_and_update().clone();
+            let skipped = seq.saturating_sub(last_seq + 1);
+            if skipped > 0 {
+                tracing::warn!(name = "publish", skipped = skipped, "channel lagged");
+            }
+            last_seq = seq;
+            process(&app, &state, &view, &mut previous)
         }
     });
 }
@@ -0,0 +1,105 @@
+// Renders a text item as a QR code, e.g. to move a URL or wifi credentials to a phone. The code is
+// stored as a PNG image item, next to the item it was made from, which becomes its source.
+
+use image::{GrayImage, Luma};
+use qrcode::{Color, QrCode};
+use scru128::Scru128Id;
+
+use crate::error::StacksError;
+use crate::state::State;
+use crate::store::MimeType;
+
+// the action's name: see registry
+pub const ACTION: &str = "qr";
+
+// pixels per module, and modules of blank border around the code, which scanners need
+const SCALE: u32 = 8;
+const QUIET_ZONE: u32 = 4;
+
+pub fn png(text: &str) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| e.to_string())?;
+    let width = code.width() as u32;
+    let size = (width + QUIET_ZONE * 2) * SCALE;
+    let colors = code.to_colors();
+    let image = GrayImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x / SCALE, y / SCALE);
+        let dark = (QUIET_ZONE..QUIET_ZONE + width).contains(&x)
+            && (QUIET_ZONE..QUIET_ZONE + width).contains(&y)
+            && colors[((y - QUIET_ZONE) * width + (x - QUIET_ZONE)) as usize] == Color::Dark;
+        Luma([if dark { 0 } else { 255 }])
+    });
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(png.into_inner())
+}
+
+// Adds the QR code for the text item to the item's stack. Returns the code's item: if the stack
+// already held it, the existing item is moved to the top.
+pub fn generate(state: &mut State, id: &Scru128Id) -> Result<Scru128Id, StacksError> {
+    let item = state
+        .view
+        .items
+        .get(id)
+        .filter(|item| !item.is_stack)
+        .ok_or(StacksError::not_found(id))?;
+    let stack_id = item.stack_id.ok_or(StacksError::not_found(id))?;
+    if state.is_read_only(&stack_id) {
+        return Err(StacksError::read_only(&stack_id));
+    }
+    let meta = state.store.get_content_meta(&item.hash);
+    if !meta.is_some_and(|meta| meta.mime_type == MimeType::TextPlain) {
+        return Err(StacksError::invalid_input(
+            "only text can be rendered as a QR code",
+        ));
+    }
+    let content = state
+        .store
+        .get_content(&item.hash)
+        .ok_or(StacksError::content_not_found(id))?;
+    let png = png(String::from_utf8_lossy(&content).trim())?;
+
+    let packet = state.store.add(&png, MimeType::ImagePng, stack_id);
+    let qr_id = state.merge_add(&packet);
+    state.store.source_set(qr_id, &id.to_string());
+    Ok(qr_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png() {
+        let png = png("WIFI:T:WPA;S:home;P:hunter2;;").unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_luma8();
+        let (width, height) = image.dimensions();
+        assert_eq!(width, height);
+        assert_eq!(width % SCALE, 0);
+        // the quiet zone is blank, and the finder pattern's corner, just inside it, is dark
+        let edge = QUIET_ZONE * SCALE;
+        assert_eq!(image.get_pixel(edge - 1, edge - 1), &Luma([255]));
+        assert_eq!(image.get_pixel(edge, edge), &Luma([0]));
+
+        assert!(png(&"x".repeat(8000)).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(dir.path().to_str().unwrap(), sender);
+        let stack_id = state.get_curr_stack();
+        let packet = state
+            .store
+            .add(b"https://example.com", MimeType::TextPlain, stack_id);
+        state.merge(&packet);
+
+        let qr_id = generate(&mut state, &packet.id).unwrap();
+        assert_eq!(state.view.items[&qr_id].stack_id, Some(stack_id));
+        assert_eq!(state.store.source_get(&qr_id), Some(packet.id.to_string()));
+        // the same code again is the same item
+        assert_eq!(generate(&mut state, &packet.id), Ok(qr_id));
+    }
+}
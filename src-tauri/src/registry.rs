@@ -0,0 +1,349 @@
+// Actions items can be run through: the built-in ones, and the user's, which are declared in
+// actions.json, in the store's directory. A user action is a shell command, which is given the
+// item's content on stdin, and whose output is added as a new item, replaces the item's content, is
+// copied, or is discarded. An action applies to the mime types and content types it lists, or to
+// every item, if it lists none.
+//
+//     [{"name": "jq", "content_types": ["JSON"], "command": "jq .", "output": "replace"}]
+
+use std::path::{Path, PathBuf};
+use std::process::Output as ProcessOutput;
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::commands::{edit_item, write_to_clipboard};
+use crate::error::StacksError;
+use crate::events;
+use crate::qr;
+use crate::shell;
+use crate::state::{SharedState, State};
+use crate::store::{ContentMeta, MimeType};
+use crate::url_actions;
+
+pub const FILE_NAME: &str = "actions.json";
+
+// a user command which hasn't finished by then, e.g. it's waiting on input, is killed
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Output {
+    #[default]
+    Item,
+    Replace,
+    Copy,
+    None,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActionDef {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // e.g. "text/plain", or "image/*"
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    #[serde(default)]
+    pub content_types: Vec<String>,
+    pub command: String,
+    #[serde(default)]
+    pub output: Output,
+}
+
+impl ActionDef {
+    pub fn applies(&self, meta: &ContentMeta) -> bool {
+        let mime_type = meta.mime_type.as_str();
+        let mime_matches = self.mime_types.is_empty()
+            || self
+                .mime_types
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => mime_type.starts_with(prefix),
+                    None => pattern == mime_type,
+                });
+        let content_type_matches =
+            self.content_types.is_empty() || self.content_types.contains(&meta.content_type);
+        mime_matches && content_type_matches
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub builtin: bool,
+}
+
+pub fn path(state: &State) -> PathBuf {
+    Path::new(&state.store.path).join(FILE_NAME)
+}
+
+// The user's actions. Without an actions file, there are none.
+pub fn load(path: &Path) -> Result<Vec<ActionDef>, String> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("{}: {}", FILE_NAME, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn save(path: &Path, actions: &[ActionDef]) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(actions).unwrap();
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+fn builtin(meta: &ContentMeta) -> Vec<ActionInfo> {
+    let mut names = Vec::new();
+    if meta.content_type == "Link" {
+        names.extend(url_actions::Action::ALL.iter().map(|action| action.name()));
+    }
+    if meta.mime_type == MimeType::TextPlain {
+        names.push(qr::ACTION);
+    }
+    names
+        .into_iter()
+        .map(|name| ActionInfo {
+            name: name.to_string(),
+            description: None,
+            builtin: true,
+        })
+        .collect()
+}
+
+// The actions which apply to the item: the built-in ones, then the user's. A user action can't
+// replace a built-in one.
+pub fn list(meta: &ContentMeta, actions: &[ActionDef]) -> Vec<ActionInfo> {
+    let mut infos = builtin(meta);
+    for action in actions.iter().filter(|action| action.applies(meta)) {
+        if !infos.iter().any(|info| info.name == action.name) {
+            infos.push(ActionInfo {
+                name: action.name.clone(),
+                description: action.description.clone(),
+                builtin: false,
+            });
+        }
+    }
+    infos
+}
+
+fn item_meta(state: &State, id: &Scru128Id) -> Result<ContentMeta, StacksError> {
+    let item = state
+        .view
+        .items
+        .get(id)
+        .filter(|item| !item.is_stack)
+        .ok_or(StacksError::not_found(id))?;
+    state
+        .store
+        .get_content_meta(&item.hash)
+        .ok_or(StacksError::content_not_found(id))
+}
+
+pub fn list_item(state: &State, id: &Scru128Id) -> Result<Vec<ActionInfo>, StacksError> {
+    let meta = item_meta(state, id)?;
+    Ok(list(&meta, &load(&path(state))?))
+}
+
+// Runs the named action on the item. Returns the id of the item the action added, if any.
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    id: Scru128Id,
+    name: &str,
+) -> Result<Option<Scru128Id>, StacksError> {
+    let (meta, actions) = state
+        .with_lock(|state| Ok::<_, StacksError>((item_meta(state, &id)?, load(&path(state))?)))?;
+    if !builtin(&meta).iter().any(|info| info.name == name) {
+        let action = actions
+            .into_iter()
+            .find(|action| action.name == name && action.applies(&meta))
+            .ok_or(format!("no action {} for: {}", name, id))?;
+        return run_command(app, state, id, &meta, &action).await;
+    }
+    if name == qr::ACTION {
+        let new_id = state.with_lock(|state| qr::generate(state, &id))?;
+        events::emit(app, "refresh-items", true);
+        return Ok(Some(new_id));
+    }
+    let action = url_actions::Action::from_name(name).unwrap();
+    url_actions::run(app, state, id, action).await
+}
+
+async fn run_command(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    id: Scru128Id,
+    meta: &ContentMeta,
+    action: &ActionDef,
+) -> Result<Option<Scru128Id>, StacksError> {
+    let (content, stack_id) = state.with_lock(|state| {
+        let item = state
+            .view
+            .items
+            .get(&id)
+            .ok_or(StacksError::not_found(&id))?;
+        let content = state
+            .store
+            .get_content(&item.hash)
+            .ok_or(StacksError::content_not_found(&id))?;
+        Ok::<_, StacksError>((content, item.stack_id))
+    })?;
+
+    let mut cmd = shell::command(&action.command);
+    cmd.env("STACKS_ITEM_ID", id.to_string())
+        .env("STACKS_MIME_TYPE", meta.mime_type.as_str())
+        .env("STACKS_CONTENT_TYPE", &meta.content_type);
+    let output = wait_with_timeout(cmd, content, COMMAND_TIMEOUT)
+        .await
+        .map_err(|e| format!("{}: {}", action.name, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{}: {}",
+            action.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    let new_id = match action.output {
+        Output::None => None,
+        Output::Copy => {
+            write_to_clipboard("public.utf8-plain-text", &output.stdout);
+            None
+        }
+        Output::Replace => {
+            if meta.mime_type != MimeType::TextPlain {
+                return Err(StacksError::invalid_input(format!(
+                    "only text can be replaced: {}",
+                    id
+                )));
+            }
+            state
+                .with_lock(|state| edit_item(state, id, &output.stdout))
+                .ok_or(format!("couldn't replace: {}", id))?;
+            None
+        }
+        Output::Item => {
+            let stack_id = stack_id.ok_or(StacksError::not_found(&id))?;
+            let new_id = state.with_lock(|state| {
+                if state.is_read_only(&stack_id) {
+                    return Err(StacksError::read_only(&stack_id));
+                }
+                let packet = state
+                    .store
+                    .add(&output.stdout, MimeType::TextPlain, stack_id);
+                // the output may already be in the stack, which is touched instead: the source
+                // goes on that item
+                let new_id = state.merge_add(&packet);
+                state.store.source_set(new_id, &id.to_string());
+                Ok(new_id)
+            })?;
+            Some(new_id)
+        }
+    };
+    events::emit(app, "refresh-items", true);
+    Ok(new_id)
+}
+
+// Runs the command with input on its stdin, killing it if it hasn't finished within timeout
+async fn wait_with_timeout(
+    mut cmd: tokio::process::Command,
+    input: Vec<u8>,
+    timeout: Duration,
+) -> Result<ProcessOutput, String> {
+    let mut child = cmd.kill_on_drop(true).spawn().map_err(|e| e.to_string())?;
+    let mut stdin = child.stdin.take().ok_or("failed to open stdin")?;
+    tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+    // the child is dropped, and so killed, if it times out
+    tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| format!("timed out after {:?}", timeout))?
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(mime_type: MimeType, content_type: &str) -> ContentMeta {
+        ContentMeta {
+            hash: ssri::Integrity::from(content_type),
+            mime_type,
+            content_type: content_type.to_string(),
+            terse: "".to_string(),
+            tiktokens: 0,
+            stats: Default::default(),
+            truncated_from: None,
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        assert_eq!(load(&path), Ok(Vec::new()));
+
+        let actions: Vec<ActionDef> = serde_json::from_str(
+            r#"[
+                {"name": "jq", "content_types": ["JSON"], "command": "jq .", "output": "replace"},
+                {"name": "ocr", "mime_types": ["image/*"], "command": "tesseract - -"},
+                {"name": "open", "command": "echo shadowed"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(actions[1].output, Output::Item);
+        save(&path, &actions).unwrap();
+        assert_eq!(load(&path), Ok(actions.clone()));
+
+        let names = |meta: &ContentMeta| -> Vec<String> {
+            list(meta, &actions)
+                .into_iter()
+                .map(|info| info.name)
+                .collect()
+        };
+        assert_eq!(
+            names(&meta(MimeType::TextPlain, "JSON")),
+            vec!["qr", "jq", "open"]
+        );
+        assert_eq!(
+            names(&meta(MimeType::ImagePng, "Image")),
+            vec!["ocr", "open"]
+        );
+        // the built-in open comes first, and isn't replaced
+        let link = list(&meta(MimeType::TextPlain, "Link"), &actions);
+        let open: Vec<_> = link.iter().filter(|info| info.name == "open").collect();
+        assert_eq!(open.len(), 1);
+        assert!(open[0].builtin);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn test_wait_with_timeout() {
+        let command = |script: &str| {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.args(["-c", script])
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped());
+            cmd
+        };
+        tauri::async_runtime::block_on(async {
+            let output = wait_with_timeout(command("cat"), b"hi".to_vec(), COMMAND_TIMEOUT)
+                .await
+                .unwrap();
+            assert_eq!(output.stdout, b"hi");
+
+            let timeout = Duration::from_millis(100);
+            let err = wait_with_timeout(command("sleep 5"), Vec::new(), timeout)
+                .await
+                .unwrap_err();
+            assert!(err.contains("timed out"), "{}", err);
+        });
+    }
+}
@@ -0,0 +1,310 @@
+// Rules route clips as they're captured, before they're added: each rule whose pattern matches the
+// clip runs its actions, in order, e.g. to move clips from a password manager somewhere else, or
+// to note that a clip looks like an API key. A pattern matches text clips' content with a regex,
+// the clip's mime type, and where it came from, and matches every clip if it's empty. Rules are
+// kept in rules.json, in the store's directory, and compiled once, when they're first applied, and
+// again after they're saved.
+//
+//     [{"name": "keys", "pattern": {"regex": "^sk-"}, "actions": [{"type": "delete"}]}]
+
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::paste;
+use crate::paste::Transform;
+use crate::state::State;
+use crate::store::MimeType;
+
+pub const FILE_NAME: &str = "rules.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Pattern {
+    #[serde(default)]
+    pub regex: Option<String>,
+    // e.g. "image/png", or "image/*"
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    // a regex, matched against the URL or file path the clip came from, or the app
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Move { stack_id: Scru128Id },
+    // adds the tag to the clip's note
+    Tag { tag: String },
+    Transform { transform: Transform },
+    Delete,
+    // emits the rule-notify event
+    Notify { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub pattern: Pattern,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub rule: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outcome {
+    pub content: Vec<u8>,
+    pub stack_id: Option<Scru128Id>,
+    pub tags: Vec<String>,
+    pub notifications: Vec<Notification>,
+    pub delete: bool,
+}
+
+// A rule, with its pattern's regexes compiled
+#[derive(Debug, Clone)]
+pub struct Compiled {
+    pub rule: Rule,
+    regex: Option<Regex>,
+    source: Option<Regex>,
+}
+
+impl Compiled {
+    // None if one of the rule's regexes doesn't compile, as the rule can't match
+    pub fn new(rule: Rule) -> Option<Self> {
+        let compile = |pattern: &Option<String>| match pattern {
+            Some(pattern) => Regex::new(pattern).ok().map(Some),
+            None => Some(None),
+        };
+        Some(Compiled {
+            regex: compile(&rule.pattern.regex)?,
+            source: compile(&rule.pattern.source)?,
+            rule,
+        })
+    }
+
+    pub fn matches(&self, content: &[u8], mime_type: &MimeType, source: Option<&str>) -> bool {
+        let regex = self.regex.as_ref().map_or(true, |regex| {
+            *mime_type == MimeType::TextPlain && regex.is_match(&String::from_utf8_lossy(content))
+        });
+        let mime = self
+            .rule
+            .pattern
+            .mime_type
+            .as_ref()
+            .map_or(true, |pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => mime_type.as_str().starts_with(prefix),
+                None => pattern == mime_type.as_str(),
+            });
+        let source = self.source.as_ref().map_or(true, |pattern| {
+            source.is_some_and(|source| pattern.is_match(source))
+        });
+        regex && mime && source
+    }
+}
+
+pub fn compile(rules: Vec<Rule>) -> Vec<Compiled> {
+    rules.into_iter().filter_map(Compiled::new).collect()
+}
+
+// The rules, compiled, which are loaded when they're first needed
+pub fn cached(state: &mut State) -> &[Compiled] {
+    if state.rules.is_none() {
+        let rules = load(&path(state)).unwrap_or_else(|e| {
+            tracing::warn!(%e, "rules not applied");
+            Vec::new()
+        });
+        state.rules = Some(compile(rules));
+    }
+    state.rules.as_deref().unwrap_or_default()
+}
+
+// Runs the rules which match the clip. A rule which deletes the clip stops the rest from running.
+pub fn apply(
+    rules: &[Compiled],
+    content: Vec<u8>,
+    mime_type: &MimeType,
+    source: Option<&str>,
+) -> Outcome {
+    let mut outcome = Outcome {
+        content,
+        ..Default::default()
+    };
+    for compiled in rules {
+        if !compiled.matches(&outcome.content, mime_type, source) {
+            continue;
+        }
+        let rule = &compiled.rule;
+        for action in &rule.actions {
+            match action {
+                Action::Move { stack_id } => outcome.stack_id = Some(*stack_id),
+                Action::Tag { tag } => outcome.tags.push(tag.clone()),
+                Action::Transform { transform } if *mime_type == MimeType::TextPlain => {
+                    let text = String::from_utf8_lossy(&outcome.content);
+                    if let Some(transformed) = paste::transform(transform, &text) {
+                        outcome.content = transformed.into_bytes();
+                    }
+                }
+                Action::Transform { .. } => {}
+                Action::Delete => {
+                    outcome.delete = true;
+                    return outcome;
+                }
+                Action::Notify { message } => outcome.notifications.push(Notification {
+                    rule: rule.name.clone(),
+                    message: message.clone(),
+                }),
+            }
+        }
+    }
+    outcome
+}
+
+// The note, with the tags it doesn't have yet added to it
+pub fn tag_note(note: Option<String>, tags: &[String]) -> String {
+    let note = note.unwrap_or_default();
+    let mut tagged = note.trim().to_string();
+    for tag in tags {
+        if !tagged.split_whitespace().any(|word| word == tag) {
+            tagged = format!("{} {}", tagged, tag);
+        }
+    }
+    tagged.trim().to_string()
+}
+
+pub fn path(state: &State) -> PathBuf {
+    Path::new(&state.store.path).join(FILE_NAME)
+}
+
+// Without a rules file, there are no rules
+pub fn load(path: &Path) -> Result<Vec<Rule>, String> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("{}: {}", FILE_NAME, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Saves the rules, and has them compiled again when they're next applied
+pub fn set(state: &mut State, rules: &[Rule]) -> Result<(), String> {
+    save(&path(state), rules)?;
+    state.rules = None;
+    Ok(())
+}
+
+// Rules with a regex which doesn't compile aren't saved
+pub fn save(path: &Path, rules: &[Rule]) -> Result<(), String> {
+    for rule in rules {
+        let patterns = [&rule.pattern.regex, &rule.pattern.source];
+        for pattern in patterns.into_iter().flatten() {
+            Regex::new(pattern).map_err(|e| format!("{}: {}", rule.name, e))?;
+        }
+    }
+    let data = serde_json::to_vec_pretty(rules).unwrap();
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let stack_id = scru128::new();
+        let rules: Vec<Rule> = serde_json::from_value(serde_json::json!([
+            {
+                "name": "jira",
+                "pattern": {"regex": "[A-Z]+-\\d+"},
+                "actions": [
+                    {"type": "move", "stack_id": stack_id},
+                    {"type": "tag", "tag": "jira"},
+                    {"type": "transform", "transform": "trim"},
+                ],
+            },
+            {
+                "name": "keys",
+                "pattern": {"regex": "^sk-"},
+                "actions": [{"type": "notify", "message": "API key"}, {"type": "delete"}],
+            },
+            {
+                "name": "screenshots",
+                "pattern": {"mime_type": "image/*", "source": "Screenshot"},
+                "actions": [{"type": "tag", "tag": "screenshot"}],
+            },
+        ]))
+        .unwrap();
+        let rules = compile(rules);
+
+        let outcome = apply(
+            &rules,
+            b"  PROJ-1 is done \n".to_vec(),
+            &MimeType::TextPlain,
+            None,
+        );
+        assert_eq!(outcome.content, b"PROJ-1 is done");
+        assert_eq!(outcome.stack_id, Some(stack_id));
+        assert_eq!(outcome.tags, vec!["jira"]);
+        assert!(!outcome.delete);
+
+        let outcome = apply(&rules, b"sk-123".to_vec(), &MimeType::TextPlain, None);
+        assert!(outcome.delete);
+        assert_eq!(outcome.notifications[0].message, "API key");
+
+        let outcome = apply(
+            &rules,
+            b"png".to_vec(),
+            &MimeType::ImagePng,
+            Some("Screenshot"),
+        );
+        assert_eq!(outcome.tags, vec!["screenshot"]);
+        // a regex only matches text
+        let outcome = apply(&rules, b"sk-123".to_vec(), &MimeType::ImagePng, None);
+        assert_eq!(
+            outcome,
+            Outcome {
+                content: b"sk-123".to_vec(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        assert_eq!(load(&path), Ok(Vec::new()));
+
+        let mut rules = vec![Rule {
+            name: "all".to_string(),
+            pattern: Pattern::default(),
+            actions: vec![Action::Tag {
+                tag: "clip".to_string(),
+            }],
+        }];
+        save(&path, &rules).unwrap();
+        assert_eq!(load(&path), Ok(rules.clone()));
+
+        rules[0].pattern.source = Some("(".to_string());
+        assert!(save(&path, &rules).is_err());
+        assert_eq!(load(&path).unwrap()[0].pattern.source, None);
+        // nor are they applied
+        assert!(compile(rules).is_empty());
+    }
+
+    #[test]
+    fn test_tag_note() {
+        let tags = vec!["jira".to_string(), "work".to_string()];
+        assert_eq!(tag_note(None, &tags), "jira work");
+        assert_eq!(
+            tag_note(Some("from standup".to_string()), &tags),
+            "from standup jira work"
+        );
+        assert_eq!(tag_note(Some("work".to_string()), &tags), "work jira");
+    }
+}
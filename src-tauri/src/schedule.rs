@@ -0,0 +1,254 @@
+// Command items can be scheduled to run periodically, e.g. to keep a stack of `df -h` snapshots.
+// Each run's output is added as a new item in the command's stack. Schedules use cron
+// expressions: minute, hour, day of month, month and day of week, in local time.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, TimeZone, Timelike};
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::content_type::process_command;
+use crate::events;
+use crate::shell;
+use crate::state::SharedState;
+use crate::store::MimeType;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schedule {
+    pub id: Scru128Id,
+    // the command item to run
+    pub source_id: Scru128Id,
+    pub cron: String,
+    pub paused: bool,
+    // unix timestamp, in milliseconds
+    pub last_run: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // whether the day of month, and day of week, fields were restricted, rather than *
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+// Parses one field, e.g. "*/15" or "1-5,7", into a bitmask of the values it matches
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|e| e.to_string())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("invalid step: {}", part));
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u32>().map_err(|e| e.to_string())?,
+                    end.parse::<u32>().map_err(|e| e.to_string())?,
+                ),
+                None => {
+                    let value = range.parse::<u32>().map_err(|e| e.to_string())?;
+                    // a single value with a step runs from the value to the end of the range
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("out of range: {}", part));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err("expected 5 fields: minute hour day month weekday".to_string());
+        };
+        // Sunday can be 0 or 7
+        let mut dow = parse_field(days_of_week, 0, 7)?;
+        if dow & (1 << 7) != 0 {
+            dow = (dow | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days_of_month: parse_field(days_of_month, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            days_of_week: dow,
+            dom_restricted: days_of_month != "*",
+            dow_restricted: days_of_week != "*",
+        })
+    }
+
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let has = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let dom = has(self.days_of_month, time.day());
+        let dow = has(self.days_of_week, time.weekday().num_days_from_sunday());
+        // as with cron, when both are restricted, matching either is enough
+        let day = match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        };
+        has(self.minutes, time.minute())
+            && has(self.hours, time.hour())
+            && has(self.months, time.month())
+            && day
+    }
+}
+
+impl Schedule {
+    // Whether the schedule should run at now, a unix timestamp in milliseconds. A schedule runs at
+    // most once a minute.
+    pub fn is_due(&self, now: u64) -> bool {
+        let ran_this_minute = self
+            .last_run
+            .is_some_and(|last| last / 60_000 == now / 60_000);
+        if self.paused || ran_this_minute {
+            return false;
+        }
+        let Some(time) = Local.timestamp_millis_opt(now as i64).single() else {
+            return false;
+        };
+        Cron::parse(&self.cron).is_ok_and(|cron| cron.matches(&time))
+    }
+}
+
+// Runs the schedule's command, adding its output, or its error output if there's none, to the
+// command item's stack
+async fn run(app: &tauri::AppHandle, state: &SharedState, schedule: &Schedule) {
+    let command = state.with_lock(|state| {
+        let item = state.view.items.get(&schedule.source_id)?;
+        let content = state.store.get_content(&item.hash)?;
+        Some((String::from_utf8_lossy(&content).to_string(), item.stack_id))
+    });
+    let Some((command, stack_id)) = command else {
+        tracing::warn!(name = "schedule", id = %schedule.id, "command item not found");
+        return;
+    };
+
+    let (cooked_command, content_type) = process_command(command.trim());
+    let output = match shell::command(&cooked_command).output().await {
+        Ok(output) => output,
+        Err(err) => {
+            tracing::warn!(name = "schedule", id = %schedule.id, ?err, "run");
+            return;
+        }
+    };
+    tracing::info!(name = "schedule", id = %schedule.id, status = ?output.status, "run");
+    let (content, content_type) = if !output.stdout.is_empty() {
+        (output.stdout, content_type)
+    } else if !output.stderr.is_empty() {
+        (output.stderr, None)
+    } else {
+        return;
+    };
+
+    state.with_lock(|state| {
+        let stack_id = stack_id.unwrap_or_else(|| state.get_curr_stack());
+        let packet = state.store.add(&content, MimeType::TextPlain, stack_id);
+        state.merge(&packet);
+        if let (Some(content_type), Some(hash)) = (content_type, packet.hash) {
+            let packet = state.store.update_content_type(hash, content_type);
+            state.merge(&packet);
+        }
+    });
+    events::emit(app, "refresh-items", true);
+}
+
+pub fn spawn(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            // wake at the start of each minute
+            let now = crate::privacy::now();
+            tokio::time::sleep(Duration::from_millis(60_000 - now % 60_000)).await;
+
+            let now = crate::privacy::now();
+            let due: Vec<Schedule> = state.with_lock(|state| {
+                let mut due = Vec::new();
+                for mut schedule in state.store.schedules() {
+                    if schedule.is_due(now) {
+                        schedule.last_run = Some(now);
+                        state.store.schedule_set(&schedule);
+                        due.push(schedule);
+                    }
+                }
+                due
+            });
+            for schedule in due {
+                let app = app.clone();
+                let state = state.clone();
+                tauri::async_runtime::spawn(async move {
+                    run(&app, &state, &schedule).await;
+                });
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cron() {
+        let at = |y, m, d, h, min| chrono::Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap();
+        // Monday 2023-10-02
+        let monday_nine = at(2023, 10, 2, 9, 0);
+
+        assert!(Cron::parse("* * * * *").unwrap().matches(&monday_nine));
+        let every_15 = Cron::parse("*/15 * * * *").unwrap();
+        assert!(every_15.matches(&at(2023, 10, 2, 9, 45)));
+        assert!(!every_15.matches(&at(2023, 10, 2, 9, 50)));
+
+        let weekdays = Cron::parse("0 9 * * 1-5").unwrap();
+        assert!(weekdays.matches(&monday_nine));
+        assert!(!weekdays.matches(&at(2023, 10, 1, 9, 0)));
+        assert!(!weekdays.matches(&at(2023, 10, 2, 10, 0)));
+
+        let sundays = Cron::parse("0 9 * * 7").unwrap();
+        assert!(sundays.matches(&at(2023, 10, 1, 9, 0)));
+
+        // either the day of month or the day of week
+        let first_or_monday = Cron::parse("0 9 1 * 1").unwrap();
+        assert!(first_or_monday.matches(&at(2023, 10, 1, 9, 0)));
+        assert!(first_or_monday.matches(&monday_nine));
+        assert!(!first_or_monday.matches(&at(2023, 10, 3, 9, 0)));
+
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_is_due() {
+        let mut schedule = Schedule {
+            id: scru128::new(),
+            source_id: scru128::new(),
+            cron: "* * * * *".to_string(),
+            paused: false,
+            last_run: None,
+        };
+        let now = 1_696_237_230_000;
+        assert!(schedule.is_due(now));
+        schedule.last_run = Some(now - 10_000);
+        assert!(!schedule.is_due(now));
+        schedule.last_run = Some(now - 60_000);
+        assert!(schedule.is_due(now));
+        schedule.paused = true;
+        assert!(!schedule.is_due(now));
+    }
+}
@@ -0,0 +1,52 @@
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// Normalizes text for matching. Both modes compose to NFC and case fold, so ß matches SS. Unless
+// strict, diacritics are also dropped, so café matches cafe and the Turkish İ and ı match i.
+pub fn fold(input: &str, strict: bool) -> String {
+    let folded: String = if strict {
+        input.nfc().flat_map(fold_case).collect()
+    } else {
+        input
+            .nfd()
+            .filter(|c| !is_combining_mark(*c))
+            .flat_map(fold_case)
+            .map(|c| if c == 'ı' { 'i' } else { c })
+            .collect()
+    };
+    // lowercasing can produce combining marks, e.g. İ -> i̇
+    folded.nfc().collect()
+}
+
+fn fold_case(c: char) -> Vec<char> {
+    match c {
+        'ß' | 'ẞ' => vec!['s', 's'],
+        // final sigma folds to sigma
+        'ς' => vec!['σ'],
+        c => c.to_lowercase().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(haystack: &str, needle: &str, strict: bool) -> bool {
+        fold(haystack, strict).contains(&fold(needle, strict))
+    }
+
+    #[test]
+    fn test_fold() {
+        assert!(matches("Café au lait", "cafe", false));
+        assert!(matches("cafe", "CAFÉ", false));
+        assert!(!matches("cafe", "café", true));
+        // decomposed and precomposed forms match in both modes
+        assert!(matches("cafe\u{301}", "caf\u{e9}", true));
+
+        assert!(matches("Straße", "STRASSE", true));
+        assert!(matches("İstanbul", "istanbul", false));
+        assert!(matches("ıstanbul", "ISTANBUL", false));
+        assert!(!matches("ıstanbul", "istanbul", true));
+        assert!(matches("ΟΔΥΣΣΕΥΣ", "οδυσσευς", true));
+    }
+}
@@ -0,0 +1,334 @@
+// Search by meaning, rather than by keyword. Text items are embedded in the background, in
+// batches, by an OpenAI compatible embeddings API: OpenAI's, or a local server such as Ollama's,
+// set with embeddings_url. Embeddings are stored by content hash, beside the content's metadata,
+// so content held by several items is embedded once. A query is embedded the same way, and
+// items are ranked by the cosine similarity of their content to it.
+//
+// This is off unless semantic_search is set, as it sends the content of text items to the API.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use ssri::Integrity;
+
+use crate::state::{SharedState, State};
+use crate::store::{MimeType, Settings};
+
+pub const DEFAULT_URL: &str = "https://api.openai.com/v1/embeddings";
+pub const DEFAULT_MODEL: &str = "text-embedding-3-small";
+
+// items embedded per request
+const BATCH: usize = 32;
+// longer content is cut, to stay within the model's input limit
+const MAX_CHARS: usize = 8_000;
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+const ERROR_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub url: String,
+    pub model: String,
+    pub token: Option<String>,
+}
+
+impl Config {
+    pub fn from_settings(settings: &Settings) -> Option<Config> {
+        if !settings.semantic_search.unwrap_or(false) {
+            return None;
+        }
+        Some(Config {
+            url: settings
+                .embeddings_url
+                .clone()
+                .unwrap_or(DEFAULT_URL.to_string()),
+            model: settings
+                .embeddings_model
+                .clone()
+                .unwrap_or(DEFAULT_MODEL.to_string()),
+            token: Some(settings.openai_access_token.clone()).filter(|token| !token.is_empty()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<Embedding>,
+}
+
+#[derive(Deserialize)]
+struct Embedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+pub async fn embed(
+    client: &reqwest::Client,
+    config: &Config,
+    input: &[String],
+) -> Result<Vec<Vec<f32>>, String> {
+    let mut req = client.post(&config.url).json(&EmbeddingsRequest {
+        model: &config.model,
+        input,
+    });
+    if let Some(token) = &config.token {
+        req = req.bearer_auth(token);
+    }
+    let res = req.send().await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!(
+            "{}: {}",
+            res.status(),
+            res.text().await.unwrap_or_default()
+        ));
+    }
+    let mut res: EmbeddingsResponse = res.json().await.map_err(|e| e.to_string())?;
+    if res.data.len() != input.len() {
+        return Err(format!(
+            "expected {} embeddings, got {}",
+            input.len(),
+            res.data.len()
+        ));
+    }
+    res.data.sort_by_key(|embedding| embedding.index);
+    Ok(res.data.into_iter().map(|e| e.embedding).collect())
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+// The k candidates most similar to the query, most similar first
+pub fn nearest<'a>(
+    query: &[f32],
+    candidates: impl Iterator<Item = (Scru128Id, &'a [f32])>,
+    k: usize,
+) -> Vec<(Scru128Id, f32)> {
+    let mut scored: Vec<(Scru128Id, f32)> = candidates
+        .map(|(id, embedding)| (id, cosine(query, embedding)))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(k);
+    scored
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Match {
+    pub id: Scru128Id,
+    pub score: f32,
+    pub terse: String,
+}
+
+pub async fn search(state: &SharedState, query: &str, k: usize) -> Result<Vec<Match>, String> {
+    let config = state
+        .with_lock(|state| state.store.settings_get())
+        .and_then(|settings| Config::from_settings(&settings))
+        .ok_or("semantic search is off")?;
+    let client = reqwest::Client::new();
+    let query = embed(&client, &config, &[query.to_string()])
+        .await?
+        .pop()
+        .ok_or("no embedding for the query")?;
+
+    // the embeddings are read, and compared, without holding the lock
+    let (embeddings, candidates) = state.with_read(|state| {
+        let candidates: Vec<(Scru128Id, Integrity)> = state
+            .view
+            .items
+            .values()
+            .filter(|item| !item.is_stack)
+            .map(|item| (item.id, item.hash.clone()))
+            .collect();
+        (state.store.embeddings(), candidates)
+    });
+    let candidates: Vec<(Scru128Id, Vec<f32>)> = candidates
+        .into_iter()
+        .filter_map(|(id, hash)| Some((id, embeddings.get(&hash)?)))
+        .collect();
+    let found = nearest(
+        &query,
+        candidates.iter().map(|(id, e)| (*id, e.as_slice())),
+        k,
+    );
+
+    state.with_read(|state| {
+        let matches = found
+            .into_iter()
+            .map(|(id, score)| Match {
+                id,
+                score,
+                terse: state
+                    .view
+                    .items
+                    .get(&id)
+                    .and_then(|item| state.store.get_content_meta(&item.hash))
+                    .map(|meta| meta.terse)
+                    .unwrap_or_default(),
+            })
+            .collect();
+        Ok(matches)
+    })
+}
+
+// Text held by items, which hasn't been embedded yet, up to limit. Content held by an item which
+// is sensitive, or due to expire, e.g. one copied in privacy mode, isn't sent to the API.
+fn pending(state: &State, limit: usize) -> Vec<(Integrity, String)> {
+    let private: HashSet<&Integrity> = state
+        .view
+        .items
+        .values()
+        .filter(|item| state.store.is_sensitive(&item.id) || state.store.has_expiry(&item.id))
+        .map(|item| &item.hash)
+        .collect();
+    let mut seen = HashSet::new();
+    state
+        .view
+        .items
+        .values()
+        .filter(|item| !item.is_stack && !item.ephemeral)
+        .filter(|item| !private.contains(&item.hash))
+        .filter(|item| seen.insert(item.hash.clone()))
+        .filter(|item| !state.store.has_embedding(&item.hash))
+        .filter(|item| {
+            state
+                .store
+                .get_content_meta(&item.hash)
+                .is_some_and(|meta| meta.mime_type == MimeType::TextPlain)
+        })
+        .filter_map(|item| {
+            let content = state.store.get_content(&item.hash)?;
+            let text: String = String::from_utf8_lossy(&content)
+                .chars()
+                .take(MAX_CHARS)
+                .collect();
+            Some((item.hash.clone(), text)).filter(|(_, text)| !text.trim().is_empty())
+        })
+        .take(limit)
+        .collect()
+}
+
+pub fn spawn(state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        let client = reqwest::Client::new();
+        loop {
+            let config = state
+                .with_lock(|state| state.store.settings_get())
+                .and_then(|settings| Config::from_settings(&settings));
+            let Some(config) = config else {
+                tokio::time::sleep(IDLE_INTERVAL).await;
+                continue;
+            };
+            state.with_lock(|state| state.store.embeddings_model_set(&config.model));
+
+            let batch = state.with_read(|state| pending(state, BATCH));
+            if batch.is_empty() {
+                tokio::time::sleep(IDLE_INTERVAL).await;
+                continue;
+            }
+            let input: Vec<String> = batch.iter().map(|(_, text)| text.clone()).collect();
+            match embed(&client, &config, &input).await {
+                Ok(embeddings) => state.with_lock(|state| {
+                    for ((hash, _), embedding) in batch.iter().zip(embeddings) {
+                        state.store.embedding_set(hash, &embedding);
+                    }
+                }),
+                Err(e) => {
+                    tracing::warn!(name = "semantic", %e, "failed to embed items");
+                    tokio::time::sleep(ERROR_INTERVAL).await;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest() {
+        let retry = scru128::new();
+        let lunch = scru128::new();
+        let empty = scru128::new();
+        let candidates = vec![
+            (lunch, vec![0.0, 1.0, 0.1]),
+            (retry, vec![0.9, 0.1, 0.0]),
+            (empty, vec![0.0, 0.0, 0.0]),
+        ];
+        let query = [1.0, 0.0, 0.0];
+        let found = nearest(
+            &query,
+            candidates.iter().map(|(id, e)| (*id, e.as_slice())),
+            2,
+        );
+        assert_eq!(
+            found.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![retry, lunch]
+        );
+        assert!((cosine(&query, &query) - 1.0).abs() < 1e-6);
+        assert_eq!(cosine(&query, &[1.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn test_pending() {
+        use crate::store::StackLockStatus;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(dir.path().to_str().unwrap(), sender);
+        let stack = state.store.add_stack(b"Stack", StackLockStatus::Unlocked);
+        state.merge(&stack);
+        let mut add = |content: &[u8]| {
+            let packet = state.store.add(content, MimeType::TextPlain, stack.id);
+            state.merge(&packet);
+            packet
+        };
+        let plain = add(b"retry with backoff");
+        let sensitive = add(b"hunter2");
+        let expiring = add(b"123456");
+        state.store.sensitive_set(sensitive.id, true);
+        state.store.expiry_set(expiring.id, 1_000);
+
+        let hashes: Vec<_> = pending(&state, BATCH)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        assert_eq!(hashes, vec![plain.hash.unwrap()]);
+    }
+
+    #[test]
+    fn test_config() {
+        let mut settings = Settings::default();
+        assert_eq!(Config::from_settings(&settings), None);
+        settings.semantic_search = Some(true);
+        settings.openai_access_token = "sk-test".to_string();
+        assert_eq!(
+            Config::from_settings(&settings),
+            Some(Config {
+                url: DEFAULT_URL.to_string(),
+                model: DEFAULT_MODEL.to_string(),
+                token: Some("sk-test".to_string()),
+            })
+        );
+        settings.embeddings_url = Some("http://localhost:11434/v1/embeddings".to_string());
+        settings.openai_access_token = String::new();
+        assert_eq!(Config::from_settings(&settings).unwrap().token, None);
+    }
+}
@@ -0,0 +1,159 @@
+use scru128::Scru128Id;
+use serde::Serialize;
+
+use tauri::GlobalShortcutManager;
+use tauri::Manager;
+
+use crate::commands;
+use crate::events;
+use crate::spotlight;
+use crate::state::{SharedState, State};
+use crate::usage::Access;
+
+// While sequential paste is armed, this shortcut pastes the next item in the stack
+pub const SHORTCUT: &str = "Command+Shift+V";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequentialPaste {
+    pub stack_id: Scru128Id,
+    pub items: Vec<Scru128Id>,
+    pub position: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub stack_id: Scru128Id,
+    pub position: usize,
+    pub total: usize,
+    pub next: Option<Scru128Id>,
+}
+
+impl SequentialPaste {
+    // Manually ordered stacks are pasted top to bottom; otherwise items are pasted oldest first,
+    // the order in which they were most likely copied
+    pub fn new(state: &State, stack_id: &Scru128Id) -> Option<Self> {
+        let stack = state.view.items.get(stack_id)?;
+        let mut items = state.view.children(stack);
+        if !stack.ordered {
+            items.reverse();
+        }
+        if items.is_empty() {
+            return None;
+        }
+        Some(Self {
+            stack_id: *stack_id,
+            items,
+            position: 0,
+        })
+    }
+
+    pub fn next(&mut self) -> Option<Scru128Id> {
+        let id = self.items.get(self.position).copied()?;
+        self.position += 1;
+        Some(id)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.items.len()
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            stack_id: self.stack_id,
+            position: self.position,
+            total: self.items.len(),
+            next: self.items.get(self.position).copied(),
+        }
+    }
+}
+
+#[tracing::instrument(skip_all)]
+fn paste_next(app: &tauri::AppHandle, state: &SharedState) {
+    let (pasted, progress) = state.with_lock(|state| {
+        let id = match state.sequential_paste.as_mut().and_then(|s| s.next()) {
+            Some(id) => id,
+            None => return (false, None),
+        };
+        state.skip_change_num = commands::copy_to_clipboard(state, &id);
+        state.record_usage(id, Access::Paste);
+        let progress = state.sequential_paste.as_ref().map(|s| s.progress());
+        if state.sequential_paste.as_ref().is_some_and(|s| s.is_done()) {
+            state.sequential_paste = None;
+        }
+        (true, progress)
+    });
+
+    if pasted {
+        spotlight::send_paste_keystroke();
+    }
+
+    match progress {
+        Some(progress) if progress.next.is_some() => {
+            events::emit(app, "sequential-paste", Some(progress));
+        }
+        _ => {
+            // the last item has been pasted: the shortcut can't be unregistered from within its
+            // own handler, so hand that off
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let _ = app.global_shortcut_manager().unregister(SHORTCUT);
+            });
+            events::emit(&app, "sequential-paste", None::<Progress>);
+        }
+    }
+}
+
+pub fn arm(app: &tauri::AppHandle, state: &mut State, stack_id: &Scru128Id) -> Option<Progress> {
+    let sequential = SequentialPaste::new(state, stack_id)?;
+    let progress = sequential.progress();
+    let already_armed = state.sequential_paste.is_some();
+    state.sequential_paste = Some(sequential);
+
+    if !already_armed {
+        let handle = app.clone();
+        let res = app.global_shortcut_manager().register(SHORTCUT, move || {
+            let state = handle.state::<SharedState>();
+            paste_next(&handle, &state);
+        });
+        if let Err(e) = res {
+            tracing::warn!(name = "sequential::arm", ?e, "failed to register shortcut");
+            state.sequential_paste = None;
+            return None;
+        }
+    }
+
+    events::emit(app, "sequential-paste", Some(progress.clone()));
+    Some(progress)
+}
+
+pub fn disarm(app: &tauri::AppHandle, state: &mut State) {
+    if state.sequential_paste.take().is_some() {
+        let _ = app.global_shortcut_manager().unregister(SHORTCUT);
+        events::emit(app, "sequential-paste", None::<Progress>);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_paste() {
+        let ids: Vec<Scru128Id> = (0..3).map(|_| scru128::new()).collect();
+        let mut sequential = SequentialPaste {
+            stack_id: scru128::new(),
+            items: ids.clone(),
+            position: 0,
+        };
+
+        assert_eq!(sequential.progress().next, Some(ids[0]));
+        assert_eq!(sequential.next(), Some(ids[0]));
+        assert_eq!(sequential.next(), Some(ids[1]));
+        assert!(!sequential.is_done());
+        assert_eq!(sequential.progress().position, 2);
+        assert_eq!(sequential.next(), Some(ids[2]));
+        assert!(sequential.is_done());
+        assert_eq!(sequential.progress().next, None);
+        assert_eq!(sequential.next(), None);
+    }
+}
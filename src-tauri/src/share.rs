@@ -0,0 +1,116 @@
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+// A read-only access token for a single stack, checked by the HTTP server
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ShareToken {
+    pub token: String,
+    pub stack_id: Scru128Id,
+    pub created_at: u64,
+    // unix timestamp, in milliseconds. None never expires
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthError {
+    Unknown,
+    Expired,
+}
+
+impl ShareToken {
+    pub fn new(stack_id: Scru128Id, now: u64, expires_in_secs: Option<u64>) -> Self {
+        Self {
+            token: generate_token(),
+            stack_id,
+            created_at: now,
+            expires_at: expires_in_secs.map(|secs| now + secs * 1000),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 24];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .expect("failed to read /dev/urandom");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The token local clients, such as stacks-cli, use to read the whole store. It's kept in the
+// store's directory, readable only by the user, and created the first time it's needed.
+pub const API_TOKEN_FILE: &str = "api-token";
+
+pub fn api_token(dir: &Path) -> std::io::Result<String> {
+    let path = dir.join(API_TOKEN_FILE);
+    if let Ok(token) = std::fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let token = generate_token();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+// Returns the stack the token grants access to
+pub fn authorize(tokens: &[ShareToken], token: &str, now: u64) -> Result<Scru128Id, AuthError> {
+    let share = tokens
+        .iter()
+        .find(|share| share.token == token)
+        .ok_or(AuthError::Unknown)?;
+    if share.is_expired(now) {
+        return Err(AuthError::Expired);
+    }
+    Ok(share.stack_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_authorize() {
+        let stack_id = scru128::new();
+        let forever = ShareToken::new(stack_id, 1_000, None);
+        let brief = ShareToken::new(stack_id, 1_000, Some(60));
+        assert_ne!(forever.token, brief.token);
+        assert_eq!(forever.token.len(), 48);
+
+        let tokens = vec![forever.clone(), brief.clone()];
+        assert_eq!(authorize(&tokens, &forever.token, 1_000_000), Ok(stack_id));
+        assert_eq!(authorize(&tokens, &brief.token, 60_999), Ok(stack_id));
+        assert_eq!(
+            authorize(&tokens, &brief.token, 61_000),
+            Err(AuthError::Expired)
+        );
+        assert_eq!(authorize(&tokens, "nope", 1_000), Err(AuthError::Unknown));
+    }
+
+    #[test]
+    fn test_api_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = api_token(dir.path()).unwrap();
+        assert_eq!(token.len(), 48);
+        assert_eq!(api_token(dir.path()).unwrap(), token);
+        let mode = std::fs::metadata(dir.path().join(API_TOKEN_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
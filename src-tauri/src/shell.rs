@@ -0,0 +1,29 @@
+// Commands are run with the user's shell, after sourcing its rc file, so they see the same aliases
+// and PATH as in the terminal.
+
+use std::process::Stdio;
+
+pub fn command(command: &str) -> tokio::process::Command {
+    let home_dir = dirs::home_dir().expect("Could not fetch home directory");
+    let shell = match std::env::var("SHELL") {
+        Ok(val) => val,
+        Err(_) => String::from("/bin/sh"), // default to sh if no SHELL variable is set
+    };
+
+    let rc_file = match shell.as_str() {
+        "/bin/bash" => ".bashrc",
+        "/bin/zsh" => ".zshrc",
+        _ => "", // if the shell is neither bash nor zsh, don't source an rc file
+    };
+
+    let rc_path = home_dir.join(rc_file);
+    let rc_command = format!("source {}\n{}", rc_path.to_str().unwrap_or(""), command);
+
+    let mut cmd = tokio::process::Command::new(shell);
+    cmd.arg("-c")
+        .arg(rc_command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    cmd
+}
@@ -0,0 +1,126 @@
+// Shutting down cleanly, when the app exits, or is sent SIGTERM or SIGINT. Work which mustn't be cut
+// off, streams from commands and clips being captured, holds a guard; on shutdown it's signalled to
+// wrap up, e.g. streams keep what they've read so far, and is waited for, up to TIMEOUT. Then the
+// capture sidecar is stopped, the store's pending writes are flushed to disk, and the store's
+// lockfile is released: see instance.
+
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tauri::Manager;
+use tokio::sync::watch;
+
+use crate::clipboard;
+use crate::instance;
+use crate::state::SharedState;
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref SIGNAL: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+    // the number of guards held, and a condvar to wait for it to reach 0
+    static ref ACTIVE: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+}
+
+// Held by work which should finish before the app exits
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let (active, done) = &*ACTIVE;
+        *active.lock().unwrap() -= 1;
+        done.notify_all();
+    }
+}
+
+pub fn guard() -> Guard {
+    *ACTIVE.0.lock().unwrap() += 1;
+    Guard(())
+}
+
+// Resolves once shutdown is requested
+pub async fn requested() {
+    let mut receiver = SIGNAL.1.clone();
+    while !*receiver.borrow_and_update() {
+        if receiver.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// Waits, up to the timeout, for the guards to be dropped. Returns whether they were.
+pub fn wait(timeout: Duration) -> bool {
+    let (active, done) = &*ACTIVE;
+    let (_, result) = done
+        .wait_timeout_while(active.lock().unwrap(), timeout, |active| *active > 0)
+        .unwrap();
+    !result.timed_out()
+}
+
+// Runs once: later calls return straight away
+pub fn run(state: &SharedState) {
+    if SIGNAL.0.send_replace(true) {
+        return;
+    }
+    tracing::info!(name = "shutdown", "shutting down");
+    if !wait(TIMEOUT) {
+        tracing::warn!(name = "shutdown", "gave up waiting for work in progress");
+    }
+    clipboard::stop();
+    state.with_lock(|state| {
+        state.store.flush();
+        instance::release(Path::new(&state.store.path));
+    });
+    tracing::info!(name = "shutdown", "store flushed");
+}
+
+// Shuts down, then exits the app. app.exit exits the process straight away, without the
+// RunEvent::Exit the shutdown otherwise runs on, so it's run first.
+pub fn exit(app: &tauri::AppHandle) {
+    run(&app.state::<SharedState>());
+    app.exit(0);
+}
+
+// Exits the app, after the shutdown, on SIGTERM or SIGINT
+pub fn spawn_signals(app: tauri::AppHandle) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tauri::async_runtime::spawn(async move {
+        let (Ok(mut term), Ok(mut int)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::interrupt()),
+        ) else {
+            tracing::warn!(name = "shutdown", "couldn't listen for signals");
+            return;
+        };
+        tokio::select! {
+            _ = term.recv() => {},
+            _ = int.recv() => {},
+        }
+        // the shutdown blocks while it waits for work in progress
+        if let Err(e) = tauri::async_runtime::spawn_blocking(move || exit(&app)).await {
+            tracing::warn!(name = "shutdown", %e, "couldn't shut down");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        assert!(wait(Duration::ZERO));
+
+        let guard = guard();
+        assert!(!wait(Duration::from_millis(10)));
+        let released = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+        assert!(wait(Duration::from_secs(5)));
+        released.join().unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use chrono::prelude::*;
+
+// Items with this content type are treated as snippet templates
+pub const CONTENT_TYPE: &str = "Snippet";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Cursor,
+    Date(String),
+    Clipboard,
+    Field(String),
+}
+
+// Splits a template into literal text and placeholders. Placeholders are wrapped in braces:
+// {cursor}, {clipboard}, {date} or {date:<strftime format>}, and anything else is a named field.
+// Use {{ and }} for literal braces.
+fn parse(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    // an unterminated placeholder is just text
+                    text.push('{');
+                    text.push_str(&name);
+                    continue;
+                }
+
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                let name = name.trim();
+                segments.push(match name {
+                    "cursor" => Segment::Cursor,
+                    "clipboard" => Segment::Clipboard,
+                    "date" => Segment::Date("%Y-%m-%d".to_string()),
+                    _ => match name.strip_prefix("date:") {
+                        Some(format) => Segment::Date(format.to_string()),
+                        None => Segment::Field(name.to_string()),
+                    },
+                });
+            }
+            _ => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
+// Returns the named fields in the template, in the order they first appear
+pub fn fields(template: &str) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for segment in parse(template) {
+        if let Segment::Field(name) = segment {
+            if !fields.contains(&name) {
+                fields.push(name);
+            }
+        }
+    }
+    fields
+}
+
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct Expanded {
+    pub text: String,
+    // char offset of the {cursor} placeholder, if present
+    pub cursor: Option<usize>,
+}
+
+pub fn expand(
+    template: &str,
+    fields: &HashMap<String, String>,
+    clipboard: Option<&str>,
+    now: DateTime<Local>,
+) -> Expanded {
+    let mut text = String::new();
+    let mut cursor = None;
+
+    for segment in parse(template) {
+        match segment {
+            Segment::Text(s) => text.push_str(&s),
+            Segment::Cursor => {
+                if cursor.is_none() {
+                    cursor = Some(text.chars().count());
+                }
+            }
+            Segment::Date(format) => {
+                // an invalid format string shouldn't take down the expansion
+                let mut formatted = String::new();
+                if write!(formatted, "{}", now.format(&format)).is_err() {
+                    formatted = format!("{{date:{}}}", format);
+                }
+                text.push_str(&formatted);
+            }
+            Segment::Clipboard => text.push_str(clipboard.unwrap_or("")),
+            Segment::Field(name) => {
+                text.push_str(fields.get(&name).map(|s| s.as_str()).unwrap_or(""))
+            }
+        }
+    }
+
+    Expanded { text, cursor }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2023, 10, 1, 9, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_fields() {
+        let template = "Hi {name}, re: {subject}. Thanks {name}! {cursor} {date}";
+        assert_eq!(fields(template), vec!["name", "subject"]);
+    }
+
+    #[test]
+    fn test_expand() {
+        let template = "Dear {name},\n{cursor}\n-- {date:%d/%m/%Y} {clipboard}";
+        let fields = HashMap::from([("name".to_string(), "Ada".to_string())]);
+        let expanded = expand(template, &fields, Some("pasted"), now());
+        assert_eq!(expanded.text, "Dear Ada,\n\n-- 01/10/2023 pasted");
+        assert_eq!(expanded.cursor, Some(10));
+    }
+
+    #[test]
+    fn test_expand_escapes() {
+        let expanded = expand("{{literal}} {date} {oops", &HashMap::new(), None, now());
+        assert_eq!(expanded.text, "{literal} 2023-10-01 {oops");
+        assert_eq!(expanded.cursor, None);
+    }
+}
@@ -0,0 +1,57 @@
+// Splits a text item into one item per segment, e.g. to paste a copied list one entry at a time
+// with sequential paste.
+
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "by", content = "pattern")]
+pub enum Delimiter {
+    Lines,
+    Commas,
+    Regex(String),
+}
+
+// Returns the segments, trimmed, with empty segments dropped
+pub fn split(text: &str, delimiter: &Delimiter) -> Result<Vec<String>, regex::Error> {
+    let segments: Vec<&str> = match delimiter {
+        Delimiter::Lines => text.lines().collect(),
+        Delimiter::Commas => text.split(',').collect(),
+        Delimiter::Regex(pattern) => Regex::new(pattern)?.split(text).collect(),
+    };
+    Ok(segments
+        .into_iter()
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("one\r\ntwo\n\n  three \n", &Delimiter::Lines).unwrap(),
+            vec!["one", "two", "three"]
+        );
+        assert_eq!(
+            split("a, b,,c", &Delimiter::Commas).unwrap(),
+            vec!["a", "b", "c"]
+        );
+        let by_semicolon_or_pipe = Delimiter::Regex(r"[;|]".to_string());
+        assert_eq!(
+            split("x;y|z", &by_semicolon_or_pipe).unwrap(),
+            vec!["x", "y", "z"]
+        );
+        assert!(split("x", &Delimiter::Regex("(".to_string())).is_err());
+
+        let delimiter: Delimiter =
+            serde_json::from_str(r#"{"by": "regex", "pattern": "\\s+"}"#).unwrap();
+        assert_eq!(delimiter, Delimiter::Regex(r"\s+".to_string()));
+        let delimiter: Delimiter = serde_json::from_str(r#"{"by": "lines"}"#).unwrap();
+        assert_eq!(delimiter, Delimiter::Lines);
+    }
+}
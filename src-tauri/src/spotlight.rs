@@ -2,10 +2,13 @@
 
 use std::sync::Mutex;
 
+#[cfg(target_os = "macos")]
 use cocoa::{
     appkit::{NSApplicationActivateIgnoringOtherApps, NSWindow, NSWindowCollectionBehavior},
-    base::id,
+    base::{id, nil},
+    foundation::NSString,
 };
+#[cfg(target_os = "macos")]
 use objc::{
     class, msg_send,
     runtime::{Class, Object},
@@ -20,6 +23,18 @@ pub struct Shortcut {
     pub command: bool,
 }
 
+// Control+Space
+impl Default for Shortcut {
+    fn default() -> Self {
+        Shortcut {
+            shift: false,
+            ctrl: true,
+            alt: false,
+            command: false,
+        }
+    }
+}
+
 impl Shortcut {
     // Method to generate a macOS-compatible shortcut string
     pub fn to_macos_shortcut(&self) -> String {
@@ -41,7 +56,7 @@ impl Shortcut {
     }
 }
 
-use tauri::{GlobalShortcutManager, Manager, Window, WindowEvent, Wry};
+use tauri::{AppHandle, GlobalShortcutManager, Manager, Window, WindowEvent, Wry};
 
 static SELF_KEY_PREFIX: &'static str = "self:";
 
@@ -49,26 +64,60 @@ static SELF_KEY_PREFIX: &'static str = "self:";
 pub enum Error {
     FailedToLockMutex,
     FailedToRegisterShortcut,
+    #[cfg(target_os = "macos")]
     FailedToGetNSWindow,
+    #[cfg(target_os = "macos")]
     FailedToGetNSWorkspaceClass,
     FailedToCheckWindowVisibility,
     FailedToHideWindow,
     FailedToShowWindow,
 }
 
-pub fn init(window: &Window<Wry>) -> Result<(), Error> {
+pub const WINDOW_LABEL: &str = "main";
+
+// The spotlight window, which is created the first time it's needed, rather than at startup, so
+// Stacks can run in the background without it: see agent
+pub fn window(app: &AppHandle<Wry>) -> Window<Wry> {
+    if let Some(window) = app.get_window(WINDOW_LABEL) {
+        return window;
+    }
+    let window = tauri::WindowBuilder::new(app, WINDOW_LABEL, tauri::WindowUrl::default())
+        .title("stacks")
+        .inner_size(1000.0, 600.0)
+        .fullscreen(false)
+        .always_on_top(true)
+        .visible(false)
+        .decorations(false)
+        .transparent(true)
+        .build()
+        .unwrap();
+    init(&window).unwrap();
+    window
+}
+
+fn init(window: &Window<Wry>) -> Result<(), Error> {
     handle_focus_state_change(&window);
-    set_spotlight_window_collection_behavior(&window)?;
-    set_window_level(&window, 7)?;
+    #[cfg(target_os = "macos")]
+    {
+        set_spotlight_window_collection_behavior(&window)?;
+        set_window_level(&window, 7)?;
+    }
     Ok(())
 }
 
-pub fn register_shortcut(window: &Window<Wry>, shortcut: &str) -> Result<(), Error> {
-    let window = window.to_owned();
-    let mut shortcut_manager = window.app_handle().global_shortcut_manager();
-    shortcut_manager.unregister_all().unwrap();
+pub fn register_shortcut(app: &AppHandle<Wry>, shortcut: &str) -> Result<(), Error> {
+    let app = app.to_owned();
+    let mut shortcut_manager = app.global_shortcut_manager();
+    // only unregister our previous shortcut: other global shortcuts may be registered
+    let mut registered = ACTIVATION_SHORTCUT
+        .lock()
+        .map_err(|_| Error::FailedToLockMutex)?;
+    if let Some(previous) = registered.take() {
+        let _ = shortcut_manager.unregister(&previous);
+    }
     shortcut_manager
         .register(shortcut, move || {
+            let window = window(&app);
             if window.is_visible().unwrap() {
                 hide(&window).unwrap();
             } else {
@@ -76,6 +125,7 @@ pub fn register_shortcut(window: &Window<Wry>, shortcut: &str) -> Result<(), Err
             }
         })
         .map_err(|_| Error::FailedToRegisterShortcut)?;
+    *registered = Some(shortcut.to_string());
     Ok(())
 }
 
@@ -116,6 +166,7 @@ use lazy_static::lazy_static;
 
 lazy_static! {
     static ref PREVIOUS_APP: Mutex<Option<String>> = Mutex::new(None);
+    static ref ACTIVATION_SHORTCUT: Mutex<Option<String>> = Mutex::new(None);
 }
 
 pub fn set_previous_app(value: Option<String>) {
@@ -128,6 +179,7 @@ pub fn get_previous_app() -> Result<Option<String>, Error> {
     Ok((*previous_app).clone())
 }
 
+#[cfg(target_os = "macos")]
 #[macro_export]
 macro_rules! nsstring_to_string {
     ($ns_string:expr) => {{
@@ -146,6 +198,7 @@ macro_rules! nsstring_to_string {
     }};
 }
 
+#[cfg(target_os = "macos")]
 fn active_another_app(bundle_url: &str) -> Result<(), Error> {
     let workspace = unsafe {
         if let Some(workspace_class) = Class::get("NSWorkspace") {
@@ -184,6 +237,12 @@ fn active_another_app(bundle_url: &str) -> Result<(), Error> {
     Ok(())
 }
 
+// Elsewhere there's no other app to hand focus back to: the window manager picks what's focused
+#[cfg(not(target_os = "macos"))]
+fn active_another_app(_bundle_url: &str) -> Result<(), Error> {
+    Ok(())
+}
+
 fn handle_focus_state_change(window: &Window<Wry>) {
     let w = window.to_owned();
     window.on_window_event(move |event| {
@@ -194,6 +253,7 @@ fn handle_focus_state_change(window: &Window<Wry>) {
 }
 
 /// Set the behaviors that makes the window appear on all workspaces
+#[cfg(target_os = "macos")]
 fn set_spotlight_window_collection_behavior(window: &Window<Wry>) -> Result<(), Error> {
     let handle: id = window.ns_window().map_err(|_| Error::FailedToGetNSWindow)? as _;
     unsafe {
@@ -207,12 +267,101 @@ fn set_spotlight_window_collection_behavior(window: &Window<Wry>) -> Result<(),
     Ok(())
 }
 
+#[cfg(target_os = "macos")]
 fn set_window_level(window: &Window<Wry>, level: i32) -> Result<(), Error> {
     let handle: id = window.ns_window().map_err(|_| Error::FailedToGetNSWindow)? as _;
     unsafe { handle.setLevel_((level).into()) };
     Ok(())
 }
 
+#[cfg(target_os = "macos")]
+pub fn bundle_id_for_path(path: &str) -> Option<String> {
+    unsafe {
+        let path = NSString::alloc(nil).init_str(path);
+        let bundle: id = msg_send![class!(NSBundle), bundleWithPath: path];
+        let () = msg_send![path, release];
+        if bundle.is_null() {
+            return None;
+        }
+        let bundle_id: id = msg_send![bundle, bundleIdentifier];
+        if bundle_id.is_null() {
+            return None;
+        }
+        nsstring_to_string!(bundle_id)
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn bundle_id_for_path(_path: &str) -> Option<String> {
+    None
+}
+
+// The bundle id of the app that was active before Stacks was shown: this is where a copied item
+// is going to be pasted.
+pub fn get_previous_app_bundle_id() -> Option<String> {
+    get_previous_app()
+        .ok()
+        .flatten()
+        .filter(|path| !path.starts_with(SELF_KEY_PREFIX))
+        .and_then(|path| bundle_id_for_path(&path))
+}
+
+#[cfg(target_os = "macos")]
+type CGEventRef = *mut std::ffi::c_void;
+
+#[cfg(target_os = "macos")]
+const KCG_HID_EVENT_TAP: u32 = 0;
+#[cfg(target_os = "macos")]
+const KCG_EVENT_FLAG_MASK_COMMAND: u64 = 0x00100000;
+#[cfg(target_os = "macos")]
+const KVK_ANSI_V: u16 = 0x09;
+
+#[cfg(target_os = "macos")]
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXIsProcessTrusted() -> bool;
+    fn CGEventCreateKeyboardEvent(
+        source: *const std::ffi::c_void,
+        keycode: u16,
+        keydown: bool,
+    ) -> CGEventRef;
+    fn CGEventSetFlags(event: CGEventRef, flags: u64);
+    fn CGEventPost(tap: u32, event: CGEventRef);
+    fn CFRelease(cf: *const std::ffi::c_void);
+}
+
+// Synthesizing key events requires the user to grant Stacks accessibility access
+#[cfg(target_os = "macos")]
+pub fn is_accessibility_trusted() -> bool {
+    unsafe { AXIsProcessTrusted() }
+}
+
+// Key events are only synthesized on macOS
+#[cfg(not(target_os = "macos"))]
+pub fn is_accessibility_trusted() -> bool {
+    false
+}
+
+// Synthesizes Cmd+V to the frontmost app
+#[cfg(target_os = "macos")]
+pub fn send_paste_keystroke() {
+    unsafe {
+        for keydown in [true, false] {
+            let event = CGEventCreateKeyboardEvent(std::ptr::null(), KVK_ANSI_V, keydown);
+            if event.is_null() {
+                return;
+            }
+            CGEventSetFlags(event, KCG_EVENT_FLAG_MASK_COMMAND);
+            CGEventPost(KCG_HID_EVENT_TAP, event);
+            CFRelease(event);
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn send_paste_keystroke() {}
+
+#[cfg(target_os = "macos")]
 pub fn get_frontmost_app_path() -> Option<String> {
     let shared_workspace: id = unsafe { msg_send![class!(NSWorkspace), sharedWorkspace] };
     let frontmost_app: id = unsafe { msg_send![shared_workspace, frontmostApplication] };
@@ -220,3 +369,8 @@ pub fn get_frontmost_app_path() -> Option<String> {
     let path: id = unsafe { msg_send![bundle_url, path] };
     unsafe { nsstring_to_string!(path) }
 }
+
+#[cfg(not(target_os = "macos"))]
+pub fn get_frontmost_app_path() -> Option<String> {
+    None
+}
@@ -0,0 +1,182 @@
+// Per-stack settings: a stack can be the target new clips are captured to, can have its old items
+// deleted after a while, and can transform text clips as they're captured into it. A stack's kind
+// says what its items are for, e.g. a Prompts stack holds prompt templates: see prompts.
+
+use std::time::Duration;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+use crate::events;
+use crate::paste;
+use crate::paste::Transform;
+use crate::state::{SharedState, State};
+use crate::store::MimeType;
+use crate::view::View;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StackKind {
+    #[default]
+    Items,
+    Prompts,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StackSettings {
+    // new clips are added to this stack, rather than the most recently used one. Only one stack
+    // is the capture target at a time.
+    #[serde(default)]
+    pub capture_target: bool,
+    // items untouched for longer than this are deleted
+    pub retention_days: Option<u64>,
+    // only this many of the most recently touched items are kept
+    pub max_items: Option<usize>,
+    // applied to text clips as they're captured into the stack
+    pub transform: Option<Transform>,
+    #[serde(default)]
+    pub kind: StackKind,
+}
+
+impl StackSettings {
+    pub fn has_retention(&self) -> bool {
+        self.retention_days.is_some() || self.max_items.is_some()
+    }
+}
+
+// Applies the stack's capture transform, if it has one, to a text clip
+pub fn transform_incoming(
+    settings: &StackSettings,
+    content: Vec<u8>,
+    mime_type: &MimeType,
+) -> Vec<u8> {
+    let Some(transform) = settings.transform.as_ref() else {
+        return content;
+    };
+    match paste::transform_content(transform, &content, mime_type) {
+        Some(transformed) if !transformed.is_empty() => transformed,
+        _ => content,
+    }
+}
+
+// Returns the ids of the stack's items which its retention policy says should be deleted
+pub fn expired(
+    view: &View,
+    stack_id: &Scru128Id,
+    settings: &StackSettings,
+    now: u64,
+) -> Vec<Scru128Id> {
+    let Some(stack) = view.items.get(stack_id) else {
+        return Vec::new();
+    };
+    let mut items: Vec<_> = view
+        .children(stack)
+        .into_iter()
+        .filter_map(|id| view.items.get(&id))
+        .filter(|item| !item.is_stack && !item.ephemeral && item.stack_id == Some(*stack_id))
+        .collect();
+    items.sort_by(|a, b| b.last_touched.cmp(&a.last_touched));
+
+    items
+        .iter()
+        .enumerate()
+        .filter(|(i, item)| {
+            let too_many = settings.max_items.is_some_and(|max| *i >= max);
+            let too_old = settings.retention_days.is_some_and(|days| {
+                now.saturating_sub(item.last_touched.timestamp()) > days * 86_400_000
+            });
+            too_many || too_old
+        })
+        .map(|(_, item)| item.id)
+        .collect()
+}
+
+// Deletes the items past their stack's retention, returning how many were deleted. Locked stacks
+// are left alone.
+pub fn enforce_retention(state: &mut State, now: u64) -> usize {
+    let mut deleted = 0;
+    for (stack_id, settings) in state.store.stack_settings_all() {
+        let unlocked = state
+            .view
+            .items
+            .get(&stack_id)
+            .is_some_and(|stack| !stack.locked);
+        if !settings.has_retention() || !unlocked {
+            continue;
+        }
+        for id in expired(&state.view, &stack_id, &settings, now) {
+            let packet = state.store.delete(id);
+            state.merge(&packet);
+            deleted += 1;
+        }
+    }
+    if deleted > 0 {
+        tracing::info!(name = "stack_settings", deleted, "retention");
+    }
+    deleted
+}
+
+pub fn spawn(app: tauri::AppHandle, state: SharedState) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let now = crate::privacy::now();
+            let deleted = state.with_lock(|state| enforce_retention(state, now));
+            if deleted > 0 {
+                events::emit(&app, "refresh-items", true);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_incoming() {
+        let settings = StackSettings {
+            transform: Some(Transform::Trim),
+            ..Default::default()
+        };
+        let text = transform_incoming(&settings, b"  hi \n".to_vec(), &MimeType::TextPlain);
+        assert_eq!(text, b"hi");
+        let image = transform_incoming(&settings, b"  hi \n".to_vec(), &MimeType::ImagePng);
+        assert_eq!(image, b"  hi \n");
+        // clips which would end up empty are kept as they are
+        let blank = transform_incoming(&settings, b"  ".to_vec(), &MimeType::TextPlain);
+        assert_eq!(blank, b"  ");
+    }
+
+    #[test]
+    fn test_expired() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = crate::store::Store::new(dir.path().to_str().unwrap());
+        let stack = store.add_stack(b"Stack 1", crate::store::StackLockStatus::Unlocked);
+        let first = store.add(b"1", MimeType::TextPlain, stack.id);
+        let second = store.add(b"2", MimeType::TextPlain, stack.id);
+        let third = store.add(b"3", MimeType::TextPlain, stack.id);
+        let mut view = View::new();
+        store.scan().for_each(|p| view.merge(&p));
+
+        let now = third.id.timestamp();
+        let keep_two = StackSettings {
+            max_items: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(expired(&view, &stack.id, &keep_two, now), vec![first.id]);
+
+        let a_day = StackSettings {
+            retention_days: Some(1),
+            ..Default::default()
+        };
+        assert!(expired(&view, &stack.id, &a_day, now).is_empty());
+        let later = now + 86_400_001 + third.id.timestamp() - second.id.timestamp();
+        assert_eq!(
+            expired(&view, &stack.id, &a_day, later),
+            vec![second.id, first.id]
+        );
+    }
+}
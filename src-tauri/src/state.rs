@@ -1,15 +1,23 @@
-use std::sync::mpsc::Sender;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use chrono::prelude::*;
 use scru128::Scru128Id;
 
-use tracing_mutex_span::TracingMutexSpan;
+use crate::disk::DiskStatus;
+use crate::privacy;
+use crate::privacy::Privacy;
+use crate::publish::ViewSender;
+use crate::rules;
+use crate::sequential::SequentialPaste;
+use crate::usage;
+use crate::usage::Access;
 
 pub use crate::store::{Packet, StackLockStatus, Store};
 pub use crate::ui::UI;
 pub use crate::view::View;
 
+pub const DEFAULT_DEDUPE_WINDOW_SECS: u64 = 3600;
+
 pub struct State {
     pub view: View,
     pub store: Store,
@@ -19,25 +27,70 @@ pub struct State {
     // about the item in the store. To avoid the clipboard poller from duplicating this
     // information, we use skip_change_num to ignore the change id associated with the item.
     pub skip_change_num: Option<i64>,
-    pub packet_sender: Sender<View>,
+    // the last clipboard change handled, so a change reported again isn't handled twice
+    pub last_change_num: Option<i64>,
+    pub sequential_paste: Option<SequentialPaste>,
+    // None until free disk space has been checked
+    pub disk_status: Option<DiskStatus>,
+    // toggled from the menubar: while paused, clipboard changes aren't captured
+    pub capture_paused: bool,
+    // set while the capture backend isn't running, e.g. the sidecar crashed: see clipboard::Health
+    pub capture_down: bool,
+    // a timed privacy mode: see privacy.rs
+    pub privacy: Option<Privacy>,
+    // the capture rules, compiled, once they're needed: see rules::cached
+    pub rules: Option<Vec<rules::Compiled>>,
+    pub packet_sender: ViewSender,
 }
 
 impl State {
-    pub fn new(db_path: &str, packet_sender: Sender<View>) -> Self {
-        let store = Store::new(db_path);
+    pub fn new(db_path: &str, packet_sender: ViewSender) -> Self {
+        Self::open(db_path, packet_sender).unwrap()
+    }
+
+    // Fails if the store can't be opened, e.g. it's from a newer build: see Store::open
+    pub fn open(db_path: &str, packet_sender: ViewSender) -> Result<Self, String> {
+        let store = Store::open(db_path)?;
         let mut view = View::new();
         store.scan().for_each(|p| view.merge(&p));
 
         let ui = UI::new(&view);
-        let state = Self {
+        let mut state = Self {
             view,
             store,
             ui,
             skip_change_num: None,
+            last_change_num: None,
+            sequential_paste: None,
+            disk_status: None,
+            capture_paused: false,
+            capture_down: false,
+            privacy: None,
+            rules: None,
             packet_sender,
         };
-        let _ = state.packet_sender.send(state.view.clone());
-        state
+        state.refresh_rank();
+        state.publish();
+        Ok(state)
+    }
+
+    // Scores items for the ranking setting, as of now: see usage
+    pub fn refresh_rank(&mut self) {
+        let ranking = self
+            .store
+            .settings_get()
+            .and_then(|settings| settings.ranking)
+            .unwrap_or_default();
+        self.ui.rank = usage::scores(ranking, self.store.usage_all(), privacy::now());
+        self.ui.refresh_view(&self.view);
+    }
+
+    pub fn record_usage(&mut self, id: Scru128Id, access: Access) {
+        if !self.view.items.contains_key(&id) {
+            return;
+        }
+        self.store.usage_record(id, access, privacy::now());
+        self.refresh_rank();
     }
 
     pub fn nav_set_filter(&mut self, filter: &str, content_type: &str) {
@@ -51,11 +104,23 @@ impl State {
     }
 
     pub fn get_curr_stack(&mut self) -> Scru128Id {
+        // a stack set as the capture target takes precedence, while it can be added to
+        if let Some(target) = self.store.capture_target() {
+            let usable = self
+                .view
+                .items
+                .get(&target)
+                .is_some_and(|item| item.is_stack && !item.locked && !item.archived);
+            if usable {
+                return target;
+            }
+        }
+
         let curr_stack = self
             .view
             .root()
             .iter()
-            .find(|&&item| !item.locked)
+            .find(|&&item| !item.locked && !item.archived)
             .map(|&item| item.id);
 
         if let Some(id) = curr_stack {
@@ -82,14 +147,117 @@ impl State {
         packet.id
     }
 
+    // If the content was already captured within the dedupe window, touch the existing item rather
+    // than adding a duplicate
+    pub fn touch_recent_duplicate(&mut self, hash: &ssri::Integrity) -> Option<Packet> {
+        let window_secs = self
+            .store
+            .settings_get()
+            .and_then(|settings| settings.dedupe_window_secs)
+            .unwrap_or(DEFAULT_DEDUPE_WINDOW_SECS);
+        let item = self.view.find_by_hash(hash)?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        if now.saturating_sub(item.last_touched.timestamp()) >= window_secs * 1000 {
+            return None;
+        }
+
+        let packet = self.store.update_touch(item.id);
+        self.merge(&packet);
+        Some(packet)
+    }
+
+    // Replays the packet log, for when packets have been removed rather than merged
+    pub fn rebuild_view(&mut self) {
+        let mut view = View::new();
+        self.store.scan().for_each(|p| view.merge(&p));
+        self.set_view(view);
+    }
+
+    // Swaps in a view built elsewhere, e.g. off the lock, keeping the UI's state
+    pub fn set_view(&mut self, view: View) {
+        let focus = view.get_best_focus(&self.ui.focused);
+        self.view = view;
+        self.ui.refresh_view(&self.view);
+        self.ui.select(focus);
+        self.publish();
+    }
+
+    // Items in a read-only stack, or nested anywhere within one, can't be deleted or edited
+    pub fn is_read_only(&self, id: &Scru128Id) -> bool {
+        let mut next = self.view.items.get(id);
+        while let Some(item) = next {
+            if item.read_only {
+                return true;
+            }
+            next = item.stack_id.and_then(|id| self.view.items.get(&id));
+        }
+        false
+    }
+
     pub fn merge(&mut self, packet: &Packet) {
         self.view.merge(packet);
         self.ui.refresh_view(&self.view);
-        let _ = self.packet_sender.send(self.view.clone());
+        self.publish();
+    }
+
+    // Merges an Add packet, returning the id of the item it ended up in: its own, or, if the stack
+    // already held the content, the item it touched. Side data, e.g. a note, is keyed on this id.
+    pub fn merge_add(&mut self, packet: &Packet) -> Scru128Id {
+        self.merge(packet);
+        if self.view.items.contains_key(&packet.id) {
+            return packet.id;
+        }
+        packet
+            .stack_id
+            .zip(packet.hash.as_ref())
+            .and_then(|(stack_id, hash)| self.view.find_in_stack(&stack_id, hash))
+            .map(|item| item.id)
+            .unwrap_or(packet.id)
+    }
+
+    fn publish(&self) {
+        self.packet_sender.send_modify(|(seq, view)| {
+            *seq += 1;
+            *view = self.view.clone();
+        });
     }
 }
 
-pub type SharedState = Arc<TracingMutexSpan<State>>;
+// The state, shared between commands, the HTTP server and background tasks. Reads, e.g. GETs,
+// previews and listing items, take the lock together, so they don't wait on each other; writes,
+// e.g. merging a packet, take it alone. A lock which was held by a thread which panicked is still
+// taken: the state is left as the panicking thread left it.
+pub struct StateLock {
+    name: &'static str,
+    lock: RwLock<State>,
+}
+
+impl StateLock {
+    pub fn new(name: &'static str, state: State) -> Self {
+        Self {
+            name,
+            lock: RwLock::new(state),
+        }
+    }
+
+    pub fn with_lock<R>(&self, f: impl FnOnce(&mut State) -> R) -> R {
+        let _span = tracing::trace_span!("with_lock", name = self.name).entered();
+        let mut state = self.lock.write().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+
+    pub fn with_read<R>(&self, f: impl FnOnce(&State) -> R) -> R {
+        let _span = tracing::trace_span!("with_read", name = self.name).entered();
+        let state = self.lock.read().unwrap_or_else(|e| e.into_inner());
+        f(&state)
+    }
+}
+
+pub type SharedState = Arc<StateLock>;
 
 #[cfg(test)]
 mod tests {
@@ -98,9 +266,85 @@ mod tests {
     fn test_state_get_curr_stack() {
         let dir = tempfile::tempdir().unwrap();
         let path = dir.path().to_str().unwrap();
-        let (sender, _receiver) = std::sync::mpsc::channel();
+        let (sender, _receiver) = crate::publish::channel();
         let mut state = State::new(path, sender);
         let _ = state.get_curr_stack();
         let _ = state.get_curr_stack();
     }
+
+    #[test]
+    fn test_with_read_concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let state: SharedState = Arc::new(StateLock::new("test", State::new(path, sender)));
+
+        // both readers hold the lock at once to pass the barrier: one at a time, they'd hang
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let state = state.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || state.with_read(|_| barrier.wait()))
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    // Readers keep going while items are added, and every item added is seen
+    #[test]
+    fn test_stress_reads_with_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let state: SharedState = Arc::new(StateLock::new("test", State::new(path, sender)));
+        const WRITES: usize = 200;
+
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut reads = 0;
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                        state.with_read(|state| {
+                            let _ = state.view.items.len();
+                        });
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        for n in 0..WRITES {
+            state.with_lock(|state| {
+                let stack_id = state.get_curr_stack();
+                let content = format!("item {}", n);
+                let packet = state.store.add(
+                    content.as_bytes(),
+                    crate::store::MimeType::TextPlain,
+                    stack_id,
+                );
+                state.merge(&packet);
+            });
+        }
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let reads: usize = readers.into_iter().map(|r| r.join().unwrap()).sum();
+        // readers weren't starved by the writes
+        assert!(reads > 0);
+        let items = state.with_read(|state| {
+            state
+                .view
+                .items
+                .values()
+                .filter(|item| !item.is_stack)
+                .count()
+        });
+        assert_eq!(items, WRITES);
+    }
 }
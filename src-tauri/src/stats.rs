@@ -0,0 +1,164 @@
+// Where the store's disk space goes. Content is stored once per hash, however many items hold it,
+// so the stats count the blobs stored, and what storing each item separately would have cost.
+// Blob sizes are kept in their content meta, and the content store is only checked for which blobs
+// it still has, outside the state lock, so it's safe to run while the app is in use.
+
+use std::collections::{BTreeMap, HashMap};
+
+use scru128::Scru128Id;
+use serde::Serialize;
+use ssri::Integrity;
+
+use crate::state::SharedState;
+use crate::store::ContentMeta;
+
+const LARGEST_ITEMS: usize = 10;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LargeItem {
+    pub id: Scru128Id,
+    pub terse: String,
+    pub mime_type: String,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct StoreStats {
+    pub items: usize,
+    pub unique_blobs: usize,
+    // the size of every blob in the store, including ones no item holds anymore
+    pub bytes: u64,
+    pub bytes_by_mime_type: BTreeMap<String, u64>,
+    pub largest: Vec<LargeItem>,
+    // bytes saved by items sharing content, rather than each storing their own copy
+    pub dedupe_savings: u64,
+    // blobs no item holds, which store_gc would remove
+    pub orphaned_blobs: usize,
+}
+
+// Summarizes the items, as (id, hash) pairs, and the blobs in the store with their sizes
+pub fn summarize(
+    items: &[(Scru128Id, Integrity)],
+    blobs: &HashMap<Integrity, (ContentMeta, u64)>,
+) -> StoreStats {
+    let mut stats = StoreStats {
+        items: items.len(),
+        unique_blobs: blobs.len(),
+        ..Default::default()
+    };
+    for (meta, bytes) in blobs.values() {
+        stats.bytes += bytes;
+        *stats
+            .bytes_by_mime_type
+            .entry(meta.mime_type.as_str().to_string())
+            .or_default() += bytes;
+    }
+
+    let mut holders: HashMap<&Integrity, usize> = HashMap::new();
+    for (_, hash) in items {
+        *holders.entry(hash).or_default() += 1;
+    }
+    for (hash, count) in &holders {
+        if let Some((_, bytes)) = blobs.get(*hash) {
+            stats.dedupe_savings += bytes * (*count as u64 - 1);
+        }
+    }
+    stats.orphaned_blobs = blobs
+        .keys()
+        .filter(|hash| !holders.contains_key(hash))
+        .count();
+
+    let mut largest: Vec<LargeItem> = items
+        .iter()
+        .filter_map(|(id, hash)| {
+            let (meta, bytes) = blobs.get(hash)?;
+            Some(LargeItem {
+                id: *id,
+                terse: meta.terse.clone(),
+                mime_type: meta.mime_type.as_str().to_string(),
+                bytes: *bytes,
+            })
+        })
+        .collect();
+    largest.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    largest.truncate(LARGEST_ITEMS);
+    stats.largest = largest;
+    stats
+}
+
+pub async fn compute(state: &SharedState) -> StoreStats {
+    let (cache_path, items, metas) = state.with_lock(|state| {
+        let items: Vec<_> = state
+            .view
+            .items
+            .values()
+            .filter(|item| !item.is_stack)
+            .map(|item| (item.id, item.hash.clone()))
+            .collect();
+        (
+            state.store.cache_path.clone(),
+            items,
+            state.store.scan_content_meta(),
+        )
+    });
+
+    let mut blobs = HashMap::new();
+    for (hash, meta) in metas {
+        // content which is missing from the content store takes no space
+        if !cacache::exists(&cache_path, &hash).await {
+            continue;
+        }
+        let bytes = meta.stats.bytes;
+        blobs.insert(hash, (meta, bytes));
+    }
+    summarize(&items, &blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MimeType;
+
+    #[test]
+    fn test_summarize() {
+        let blob = |content: &str, mime_type: MimeType, bytes: u64| {
+            let hash = Integrity::from(content);
+            let meta = ContentMeta {
+                hash: hash.clone(),
+                mime_type,
+                content_type: "Text".to_string(),
+                terse: content.to_string(),
+                tiktokens: 0,
+                stats: Default::default(),
+                truncated_from: None,
+            };
+            (hash, (meta, bytes))
+        };
+        let blobs: HashMap<_, _> = [
+            blob("hello", MimeType::TextPlain, 5),
+            blob("screenshot", MimeType::ImagePng, 1000),
+            blob("undone", MimeType::TextPlain, 6),
+        ]
+        .into_iter()
+        .collect();
+        let hello = Integrity::from("hello");
+        let screenshot = Integrity::from("screenshot");
+        let items = vec![
+            (scru128::new(), hello.clone()),
+            (scru128::new(), hello),
+            (scru128::new(), screenshot.clone()),
+            (scru128::new(), screenshot),
+        ];
+
+        let stats = summarize(&items, &blobs);
+        assert_eq!(stats.items, 4);
+        assert_eq!(stats.unique_blobs, 3);
+        assert_eq!(stats.bytes, 1011);
+        assert_eq!(stats.bytes_by_mime_type["text/plain"], 11);
+        assert_eq!(stats.bytes_by_mime_type["image/png"], 1000);
+        assert_eq!(stats.dedupe_savings, 1005);
+        assert_eq!(stats.orphaned_blobs, 1);
+        assert_eq!(stats.largest[0].bytes, 1000);
+        assert_eq!(stats.largest.len(), 4);
+    }
+}
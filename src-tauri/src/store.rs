@@ -1,10 +1,38 @@
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use scru128::Scru128Id;
 use serde::{Deserialize, Serialize};
 use ssri::Integrity;
 
+use crate::address;
+use crate::calendar;
+use crate::capture::Origin;
+use crate::clipboard::{CaptureBackend, Oversized};
+use crate::color;
+use crate::contact;
+use crate::contact::Contact;
+use crate::durability;
+use crate::durability::Durability;
+use crate::file_ref;
+use crate::hotkeys::Hotkey;
+use crate::ingest::ShellRun;
+use crate::links::LinkStatus;
+use crate::migrate;
+use crate::packet_store;
+use crate::packet_store::{Backend, PacketStore};
+use crate::paste;
+use crate::phone;
+use crate::schedule::Schedule;
+use crate::search;
+use crate::share::ShareToken;
 use crate::spotlight;
+use crate::stack_settings::StackSettings;
+use crate::theme::Theme;
+use crate::upload;
+use crate::usage::{Access, Ranking, Usage};
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub enum MimeType {
@@ -12,6 +40,61 @@ pub enum MimeType {
     TextPlain,
     #[serde(rename = "image/png")]
     ImagePng,
+    #[serde(rename = "image/jpeg")]
+    ImageJpeg,
+    #[serde(rename = "image/tiff")]
+    ImageTiff,
+    #[serde(rename = "image/gif")]
+    ImageGif,
+    #[serde(rename = "image/webp")]
+    ImageWebp,
+    // a reference to a file on disk: see file_ref
+    #[serde(rename = "application/x-file-ref")]
+    FileRef,
+}
+
+// The image formats captured, with their pasteboard types, in order of preference
+pub const IMAGE_TYPES: [(&str, MimeType); 5] = [
+    ("public.png", MimeType::ImagePng),
+    ("public.jpeg", MimeType::ImageJpeg),
+    ("public.tiff", MimeType::ImageTiff),
+    ("com.compuserve.gif", MimeType::ImageGif),
+    ("org.webmproject.webp", MimeType::ImageWebp),
+];
+
+impl MimeType {
+    pub fn is_image(&self) -> bool {
+        Self::from_image_mime(self.as_str()).is_some()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MimeType::TextPlain => "text/plain",
+            MimeType::ImagePng => "image/png",
+            MimeType::ImageJpeg => "image/jpeg",
+            MimeType::ImageTiff => "image/tiff",
+            MimeType::ImageGif => "image/gif",
+            MimeType::ImageWebp => "image/webp",
+            MimeType::FileRef => "application/x-file-ref",
+        }
+    }
+
+    pub fn pasteboard_type(&self) -> &'static str {
+        IMAGE_TYPES
+            .iter()
+            .find(|(_, mime_type)| mime_type == self)
+            .map(|(pasteboard_type, _)| *pasteboard_type)
+            .unwrap_or("public.utf8-plain-text")
+    }
+
+    // The image format for a mime type, as reported by infer
+    pub fn from_image_mime(mime: &str) -> Option<MimeType> {
+        IMAGE_TYPES
+            .iter()
+            .map(|(_, mime_type)| mime_type)
+            .find(|mime_type| mime_type.as_str() == mime)
+            .cloned()
+    }
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -21,6 +104,88 @@ pub struct ContentMeta {
     pub content_type: String,
     pub terse: String,
     pub tiktokens: usize,
+    // content meta in backups from before stats were added has none
+    #[serde(default)]
+    pub stats: ContentStats,
+    // the clip's size, in bytes, before it was truncated to max_item_bytes, if it was
+    #[serde(default)]
+    pub truncated_from: Option<u64>,
+}
+
+// Content meta from before truncated_from was added
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ContentMetaV2 {
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    pub terse: String,
+    pub tiktokens: usize,
+    pub stats: ContentStats,
+}
+
+// Content meta from before stats were added: see migrate
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ContentMetaV1 {
+    pub hash: Integrity,
+    pub mime_type: MimeType,
+    pub content_type: String,
+    pub terse: String,
+    pub tiktokens: usize,
+}
+
+pub fn deserialize_content_meta(value: &[u8]) -> Option<ContentMeta> {
+    bincode::deserialize::<ContentMeta>(value)
+        .or_else(|_| {
+            bincode::deserialize::<ContentMetaV2>(value).map(|v2| ContentMeta {
+                hash: v2.hash,
+                mime_type: v2.mime_type,
+                content_type: v2.content_type,
+                terse: v2.terse,
+                tiktokens: v2.tiktokens,
+                stats: v2.stats,
+                truncated_from: None,
+            })
+        })
+        .or_else(|_| {
+            bincode::deserialize::<ContentMetaV1>(value).map(|v1| ContentMeta {
+                hash: v1.hash,
+                mime_type: v1.mime_type,
+                content_type: v1.content_type,
+                terse: v1.terse,
+                tiktokens: v1.tiktokens,
+                stats: ContentStats::default(),
+                truncated_from: None,
+            })
+        })
+        .ok()
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ContentStats {
+    pub words: usize,
+    pub chars: usize,
+    pub lines: usize,
+    pub bytes: u64,
+}
+
+// Counts the content's words, characters and lines, if it's text, and its size. This is done once,
+// as content is written, and kept in its meta. Tokens are counted separately, off the write path,
+// as they're slow to count: see content_bus.
+pub fn analyze(content: &[u8], mime_type: &MimeType) -> ContentStats {
+    let bytes = content.len() as u64;
+    if *mime_type != MimeType::TextPlain {
+        return ContentStats {
+            bytes,
+            ..Default::default()
+        };
+    }
+    let text = String::from_utf8_lossy(content);
+    ContentStats {
+        words: text.split_whitespace().count(),
+        chars: text.chars().count(),
+        lines: text.lines().count(),
+        bytes,
+    }
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -41,6 +206,8 @@ impl InProgressStream {
             content_type: content_type.clone(),
             terse: "".to_string(),
             tiktokens: 0,
+            stats: ContentStats::default(),
+            truncated_from: None,
         };
 
         InProgressStream {
@@ -62,6 +229,34 @@ impl InProgressStream {
         }
     }
 
+    // Continues streaming into an existing item: what's streamed is appended to the item's content,
+    // which is stored as a new version of the item when the stream ends
+    pub fn append_to(source_id: Scru128Id, content_meta: ContentMeta, content: Vec<u8>) -> Self {
+        InProgressStream {
+            content_meta,
+            content,
+            packet: Packet {
+                id: scru128::new(),
+                packet_type: PacketType::Update,
+                source_id: Some(source_id),
+                // the item keeps its current content until the stream ends
+                hash: None,
+                stack_id: None,
+                ephemeral: true,
+                content_type: None,
+                movement: None,
+                lock_status: None,
+                sort_order: None,
+                cross_stream: false,
+            },
+        }
+    }
+
+    // The item being streamed into
+    pub fn item_id(&self) -> Scru128Id {
+        self.packet.source_id.unwrap_or(self.packet.id)
+    }
+
     pub fn append(&mut self, content: &[u8]) {
         // Append additional content
         self.content.extend_from_slice(content);
@@ -69,20 +264,34 @@ impl InProgressStream {
         // Update hash
         self.content_meta.hash = ssri::Integrity::from(&self.content);
 
-        let text_content = String::from_utf8_lossy(&self.content).into_owned();
-
         // Update terse
-        self.content_meta.terse = if text_content.len() > 100 {
-            text_content.chars().take(100).collect()
-        } else {
-            text_content
-        };
+        self.content_meta.terse = terse(
+            &String::from_utf8_lossy(&self.content),
+            DEFAULT_TERSE_LENGTH,
+        );
+        // the rest of the stats are counted once the stream ends
+        self.content_meta.stats.bytes = self.content.len() as u64;
 
         self.packet.hash = Some(self.content_meta.hash.clone());
     }
 
+    // Adds the chunk's tokens to the stream's count, rather than counting all its content again for
+    // each chunk. A token split across chunks is counted twice: the content bus counts the content
+    // once the stream ends.
+    pub fn count_tiktokens(&mut self, chunk: &[u8], tokenizer: Tokenizer) {
+        self.content_meta.tiktokens += count_tiktokens(&String::from_utf8_lossy(chunk), tokenizer);
+    }
+
     pub fn end_stream(&mut self, store: &mut Store) -> Packet {
-        let hash = store.cas_write(
+        let hash = cacache::write_hash_sync(&store.cache_path, &self.content).unwrap();
+        self.end_stream_written(store, hash)
+    }
+
+    // Ends the stream, once its content has been written to the content store, e.g. by write_blob
+    pub fn end_stream_written(&mut self, store: &mut Store, hash: Integrity) -> Packet {
+        self.content_meta.stats = analyze(&self.content, &self.content_meta.mime_type);
+        let hash = store.cas_record(
+            hash,
             &self.content,
             self.content_meta.mime_type.clone(),
             self.content_meta.content_type.clone(),
@@ -99,6 +308,17 @@ pub enum PacketType {
     Update,
     Fork,
     Delete,
+    // moves the stack source_id under the parent stack stack_id, or to the root if stack_id is
+    // None
+    Nest,
+    Archive,
+    Restore,
+    // bumps source_id to the top of its stack, e.g. when its content is copied again
+    Touch,
+    // adds the item source_id to the stack stack_id, in addition to the stacks it's already in
+    Link,
+    // removes the item source_id from the stack stack_id it was linked to
+    Unlink,
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -123,6 +343,8 @@ pub enum Movement {
 pub enum StackLockStatus {
     Unlocked,
     Locked,
+    // locked, and its items can't be deleted or edited until it's unlocked
+    ReadOnly,
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
@@ -147,7 +369,7 @@ pub struct Packet {
     pub cross_stream: bool,
 }
 
-fn deserialize_packet(value: &[u8]) -> Option<Packet> {
+pub fn deserialize_packet(value: &[u8]) -> Option<Packet> {
     bincode::deserialize::<Packet>(&value)
         .or_else(|_| {
             bincode::deserialize::<PacketV3>(&value).map(|v3_packet| Packet {
@@ -167,46 +389,151 @@ fn deserialize_packet(value: &[u8]) -> Option<Packet> {
         .ok()
 }
 
+// Content is indexed by its hash, and items' origins by the item's id
 pub struct Index {
     content_field: tantivy::schema::Field,
     hash_field: tantivy::schema::Field,
+    origin_field: tantivy::schema::Field,
+    id_field: tantivy::schema::Field,
     writer: tantivy::IndexWriter,
     reader: tantivy::IndexReader,
+    // the index couldn't be opened, and was recreated empty: it's filled again by Store::reindex
+    recreated: bool,
 }
 
 impl Index {
     fn new(path: std::path::PathBuf) -> Index {
         let mut schema_builder = tantivy::schema::Schema::builder();
         let content_field = schema_builder.add_text_field("content", tantivy::schema::TEXT);
-        let hash_field = schema_builder.add_bytes_field("hash", tantivy::schema::STORED);
+        // indexed, as well as stored, so content's document can be deleted along with it
+        let hash_field = schema_builder.add_bytes_field(
+            "hash",
+            tantivy::schema::BytesOptions::default()
+                .set_stored()
+                .set_indexed(),
+        );
+        let origin_field = schema_builder.add_text_field("origin", tantivy::schema::TEXT);
+        let id_field = schema_builder.add_bytes_field(
+            "id",
+            tantivy::schema::BytesOptions::default()
+                .set_stored()
+                .set_indexed(),
+        );
         let schema = schema_builder.build();
 
         std::fs::create_dir_all(&path).unwrap();
         let dir = tantivy::directory::MmapDirectory::open(&path).unwrap();
-        let index = tantivy::Index::open_or_create(dir, schema).unwrap();
+        // an index which can't be opened, e.g. one from before its schema changed, or a corrupted
+        // one, is replaced
+        let mut recreated = false;
+        let index = tantivy::Index::open_or_create(dir, schema.clone()).unwrap_or_else(|e| {
+            tracing::warn!(name = "Index::new", %e, "recreating the index");
+            recreated = true;
+            std::fs::remove_dir_all(&path).unwrap();
+            std::fs::create_dir_all(&path).unwrap();
+            tantivy::Index::create_in_dir(&path, schema).unwrap()
+        });
         let writer = index.writer_with_num_threads(1, 3_000_000).unwrap();
         let reader = index.reader().unwrap();
 
         Index {
             content_field,
             hash_field,
+            origin_field,
+            id_field,
             writer,
             reader,
+            recreated,
         }
     }
 
-    #[tracing::instrument(skip_all)]
-    fn write(&mut self, hash: &ssri::Integrity, content: &[u8]) {
+    fn hash_term(&self, hash: &ssri::Integrity) -> tantivy::schema::Term {
+        let bytes = bincode::serialize(&hash).unwrap();
+        tantivy::schema::Term::from_field_bytes(self.hash_field, &bytes)
+    }
+
+    // Adds, or replaces, the content's document. It's searchable once committed.
+    fn add(&mut self, hash: &ssri::Integrity, content: &[u8]) {
         let content = String::from_utf8_lossy(content);
         let mut doc = tantivy::Document::new();
         doc.add_text(self.content_field, &content);
         let bytes = bincode::serialize(&hash).unwrap();
         doc.add_bytes(self.hash_field, bytes);
+        self.writer.delete_term(self.hash_term(hash));
         self.writer.add_document(doc).unwrap();
+    }
+
+    fn delete(&mut self, hash: &ssri::Integrity) {
+        self.writer.delete_term(self.hash_term(hash));
+    }
+
+    fn id_term(&self, id: &Scru128Id) -> tantivy::schema::Term {
+        tantivy::schema::Term::from_field_bytes(self.id_field, &id.to_bytes())
+    }
+
+    // Adds, or replaces, the item's origin. It's folded as search::fold does, and matched by word.
+    fn add_origin(&mut self, id: &Scru128Id, origin: &Origin) {
+        let text = format!(
+            "{} {}",
+            origin.url,
+            origin.title.as_deref().unwrap_or_default()
+        );
+        let mut doc = tantivy::Document::new();
+        doc.add_text(self.origin_field, search::fold(&text, false));
+        doc.add_bytes(self.id_field, id.to_bytes().to_vec());
+        self.writer.delete_term(self.id_term(id));
+        self.writer.add_document(doc).unwrap();
+    }
+
+    fn delete_origin(&mut self, id: &Scru128Id) {
+        self.writer.delete_term(self.id_term(id));
+    }
+
+    // The ids of the items with an origin which has words starting with each of the filter's
+    fn query_origins(&self, filter: &str) -> Vec<Scru128Id> {
+        use tantivy::query::{BooleanQuery, FuzzyTermQuery, Occur, Query};
+
+        let words: Vec<(Occur, Box<dyn Query>)> = search::fold(filter, false)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let term = tantivy::schema::Term::from_field_text(self.origin_field, word);
+                let query: Box<dyn Query> = Box::new(FuzzyTermQuery::new_prefix(term, 0, true));
+                (Occur::Must, query)
+            })
+            .collect();
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        let searcher = self.reader.searcher();
+        let top_docs = searcher
+            .search(
+                &BooleanQuery::new(words),
+                &tantivy::collector::TopDocs::with_limit(10_000),
+            )
+            .unwrap_or_default();
+        top_docs
+            .into_iter()
+            .filter_map(|(_, doc_address)| {
+                let doc = searcher.doc(doc_address).ok()?;
+                let bytes = doc.get_first(self.id_field)?.as_bytes()?;
+                Some(Scru128Id::from_bytes(bytes.try_into().ok()?))
+            })
+            .collect()
+    }
+
+    fn commit(&mut self) {
         self.writer.commit().unwrap();
         self.reader.reload().unwrap();
     }
 
+    #[tracing::instrument(skip_all)]
+    fn write(&mut self, hash: &ssri::Integrity, content: &[u8]) {
+        self.add(hash, content);
+        self.commit();
+    }
+
     #[cfg(test)]
     pub fn query(&self, query: &str) -> HashSet<ssri::Integrity> {
         let term = tantivy::schema::Term::from_field_text(self.content_field, query);
@@ -235,6 +562,94 @@ pub struct Settings {
     pub openai_selected_model: String,
     pub cross_stream_access_token: Option<String>,
     pub activation_shortcut: Option<spotlight::Shortcut>,
+    pub paste_rules: Option<Vec<paste::PasteRule>>,
+    pub stack_transforms: Option<Vec<paste::StackTransform>>,
+    // Linux only: also capture the primary selection, i.e. text as soon as it's selected
+    pub capture_primary_selection: Option<bool>,
+    // copying content which was already captured within this many seconds bumps the existing
+    // item, rather than adding a new one. 0 disables this.
+    pub dedupe_window_secs: Option<u64>,
+    // runs the HTTP server, for stacks-cli and other integrations. Off by default
+    pub http_enabled: Option<bool>,
+    // where the HTTP server listens. Defaults to 127.0.0.1:9146. Only loopback addresses are used
+    pub http_bind_address: Option<String>,
+    pub http_port: Option<u16>,
+    // also listen on a Unix socket, socket/stacks.sock in the store's directory, which only the
+    // user can connect to. With http_tcp set to false, the server only listens on the socket.
+    pub http_unix_socket: Option<bool>,
+    pub http_tcp: Option<bool>,
+    // below this many free bytes, large clips aren't captured
+    pub low_disk_threshold_bytes: Option<u64>,
+    // match filters exactly, other than case, rather than ignoring diacritics
+    pub strict_search: Option<bool>,
+    // characters of an item's text shown in lists. Defaults to DEFAULT_TERSE_LENGTH
+    pub terse_length: Option<usize>,
+    // text previews are cut at whichever of these is reached first
+    pub preview_max_lines: Option<usize>,
+    pub preview_max_bytes: Option<usize>,
+    // macOS only: unlocking a read-only stack asks for Touch ID, or the user's password
+    pub unlock_requires_auth: Option<bool>,
+    // past this many packets, the log is compacted. Defaults to compact::DEFAULT_THRESHOLD_PACKETS
+    pub compact_threshold_packets: Option<usize>,
+    // where snapshots of the store are written, how often, and how many are kept: see backup.
+    // Scheduled snapshots are off unless an interval is set.
+    pub backup_dir: Option<String>,
+    pub backup_interval_hours: Option<u64>,
+    pub backup_keep: Option<usize>,
+    // embed text items, to search them by meaning: see semantic. Embeddings are fetched from an
+    // OpenAI compatible API, OpenAI's by default, using openai_access_token.
+    pub semantic_search: Option<bool>,
+    pub embeddings_url: Option<String>,
+    pub embeddings_model: Option<String>,
+    // the chat completions API actions are run with: see actions. The model defaults to
+    // openai_selected_model.
+    pub llm_url: Option<String>,
+    pub llm_model: Option<String>,
+    // the encoding tiktokens are counted with. Defaults to the one openai_selected_model uses.
+    pub tokenizer: Option<Tokenizer>,
+    // the theme previews are rendered with, by name, rather than the app's light or dark mode,
+    // and custom themes to choose from: see theme
+    pub preview_theme: Option<String>,
+    pub preview_themes: Option<Vec<Theme>>,
+    // the service Link items are shortened with, which is sent the URL as its url query parameter,
+    // and responds with the short URL as plain text, e.g. https://is.gd/create.php?format=simple
+    pub url_shortener: Option<String>,
+    // the plugins the user has allowed to fetch URLs with stacks.fetch, by name: see plugins
+    pub plugins_fetch_allowed: Option<Vec<String>>,
+    // desktop notifications, by category: see notifications. Commands only notify once they've run
+    // for notify_command_after_secs, 10 by default.
+    pub notify_rules: Option<bool>,
+    pub notify_commands: Option<bool>,
+    pub notify_command_after_secs: Option<u64>,
+    pub notify_sync: Option<bool>,
+    pub notify_capture: Option<bool>,
+    // how items are ordered within stacks, and search results: by when they were last touched, or
+    // by frecency. See usage.
+    pub ranking: Option<Ranking>,
+    // global hotkeys, other than activation_shortcut: see hotkeys
+    pub hotkeys: Option<Vec<Hotkey>>,
+    // macOS only: start Stacks when the user logs in, and start it without the spotlight window,
+    // which is created once it's asked for: see agent
+    pub launch_at_login: Option<bool>,
+    pub background_agent: Option<bool>,
+    // how soon writes are synced to disk: see durability. Defaults to leaving it to the OS.
+    pub durability: Option<Durability>,
+    // macOS only: watch the clipboard with the x-macos-pasteboard sidecar, the default, or by
+    // polling the pasteboard in process. Capture falls back to polling if the sidecar won't start.
+    pub capture_backend: Option<CaptureBackend>,
+    // clipboard changes within this many milliseconds of each other are collapsed into the last.
+    // Defaults to clipboard::DEFAULT_DEBOUNCE_MS; 0 handles every change.
+    pub capture_debounce_ms: Option<u64>,
+    // clips larger than this are skipped, or, for text, truncated with oversized_items set to
+    // truncate. Defaults to clipboard::DEFAULT_MAX_ITEM_BYTES; 0 is no limit.
+    pub max_item_bytes: Option<u64>,
+    pub oversized_items: Option<Oversized>,
+    // clips marked transient, e.g. one-time codes, are purged this many seconds after they're
+    // captured. Defaults to clipboard::DEFAULT_TRANSIENT_TTL_SECS.
+    pub transient_ttl_secs: Option<u64>,
+    // once a sensitive item's been copied, or pasted, the clipboard is cleared after this many
+    // seconds, restoring the clip from before it. Off by default.
+    pub clear_sensitive_after_secs: Option<u64>,
 }
 
 impl Default for Settings {
@@ -244,36 +659,308 @@ impl Default for Settings {
             openai_selected_model: String::new(),
             cross_stream_access_token: None,
             activation_shortcut: None,
+            paste_rules: None,
+            stack_transforms: None,
+            capture_primary_selection: None,
+            dedupe_window_secs: None,
+            http_enabled: None,
+            http_bind_address: None,
+            http_port: None,
+            http_unix_socket: None,
+            http_tcp: None,
+            low_disk_threshold_bytes: None,
+            strict_search: None,
+            terse_length: None,
+            preview_max_lines: None,
+            preview_max_bytes: None,
+            unlock_requires_auth: None,
+            compact_threshold_packets: None,
+            backup_dir: None,
+            backup_interval_hours: None,
+            backup_keep: None,
+            semantic_search: None,
+            embeddings_url: None,
+            embeddings_model: None,
+            llm_url: None,
+            llm_model: None,
+            tokenizer: None,
+            preview_theme: None,
+            preview_themes: None,
+            url_shortener: None,
+            plugins_fetch_allowed: None,
+            notify_rules: None,
+            notify_commands: None,
+            notify_command_after_secs: None,
+            notify_sync: None,
+            notify_capture: None,
+            ranking: None,
+            hotkeys: None,
+            launch_at_login: None,
+            background_agent: None,
+            durability: None,
+            capture_backend: None,
+            capture_debounce_ms: None,
+            max_item_bytes: None,
+            oversized_items: None,
+            transient_ttl_secs: None,
+            clear_sensitive_after_secs: None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct GcStats {
+    pub removed: usize,
+    pub bytes: u64,
+}
+
+// A version of an item's content: the content it was added with, or an edit
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ItemVersion {
+    // the id of the packet which set this content
+    pub id: Scru128Id,
+    pub hash: Integrity,
+    // the content this version replaced
+    pub previous: Option<Integrity>,
+    pub terse: String,
+}
+
+// A thumbnail of an item's content, generated on first use: see Store::thumbnail
+pub struct Thumbnail {
+    cache_path: String,
+    hash: Integrity,
+    mime_type: MimeType,
+    width: u32,
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Thumbnail {
+    // Returns the path to the thumbnail, generating it if it isn't already cached. Images which
+    // are already narrow enough are only transcoded.
+    pub fn generate(self) -> Option<PathBuf> {
+        if self.path.exists() {
+            return Some(self.path);
         }
+
+        let content = cacache::read_hash_sync(&self.cache_path, &self.hash).ok()?;
+        let content = if self.mime_type == MimeType::FileRef {
+            let file_ref = file_ref::parse(&content)?;
+            // check the file's signature first, to avoid reading large files which aren't images
+            let is_image = infer::get_from_path(&file_ref.path)
+                .ok()
+                .flatten()
+                .is_some_and(|kind| MimeType::from_image_mime(kind.mime_type()).is_some());
+            if !is_image {
+                return None;
+            }
+            std::fs::read(&file_ref.path).ok()?
+        } else {
+            content
+        };
+        let image = image::load_from_memory(&content).ok()?;
+        let image = if image.width() > self.width {
+            image.thumbnail(self.width, u32::MAX)
+        } else {
+            image
+        };
+        std::fs::create_dir_all(&self.dir).ok()?;
+        // written to the side and renamed, so a partially written thumbnail is never served
+        let tmp = self.path.with_extension("tmp");
+        image.save_with_format(&tmp, image::ImageFormat::Png).ok()?;
+        std::fs::rename(&tmp, &self.path).ok()?;
+        tracing::info!(name = "Thumbnail::generate", width = self.width, path = ?self.path, "generated");
+        Some(self.path)
     }
 }
 
 pub struct Store {
-    packets: sled::Tree,
+    // the sled database the trees below are in, to flush them: see shutdown
+    db: sled::Db,
+    // the durability setting, whether there are writes it hasn't synced yet, which durability::spawn
+    // checks without the state lock, and the content written since the last sync
+    durability: Durability,
+    unsynced: Arc<AtomicBool>,
+    unsynced_blobs: Vec<Integrity>,
+    packets: Box<dyn PacketStore>,
+    // bumped each time a packet is inserted or removed, so work done off the lock can tell the
+    // packets changed meanwhile: see compact::run
+    generation: u64,
     content_meta: sled::Tree,
     content_meta_cache: HashMap<ssri::Integrity, ContentMeta>,
+    link_status: sled::Tree,
+    link_status_cache: HashMap<ssri::Integrity, LinkStatus>,
+    contacts: sled::Tree,
+    contacts_cache: HashMap<ssri::Integrity, Contact>,
+    // item id -> where the item was captured from, e.g. a URL or file path
+    sources: sled::Tree,
+    // item id -> unix timestamp, in milliseconds, when the item is purged
+    expiries: sled::Tree,
+    // item id -> nothing, for items which are sensitive, e.g. passwords
+    sensitive: sled::Tree,
+    // item id -> a note the user attached to the item
+    notes: sled::Tree,
+    notes_cache: HashMap<Scru128Id, String>,
+    // stack id -> the stack's settings, as JSON
+    stack_settings: sled::Tree,
+    // read each time a clip is captured, so kept parsed
+    stack_settings_cache: HashMap<Scru128Id, StackSettings>,
+    // item id -> the page a selection pushed from the browser extension came from
+    origins: sled::Tree,
+    // item id -> how a command ingested from the shell ran
+    shell_runs: sled::Tree,
+    shell_runs_cache: HashMap<Scru128Id, ShellRun>,
+    // schedule id -> a schedule for running a command item, as JSON
+    schedules: sled::Tree,
+    // packet id -> a packet taken out of the packet store by a repair, as JSON: see verify
+    quarantine: sled::Tree,
+    // item id -> how often, and when, the item was used, as JSON: see usage
+    usage: sled::Tree,
+    usage_cache: HashMap<Scru128Id, Usage>,
+    // content hash -> the content's embedding, from the model in meta's embeddings_model: see
+    // semantic
+    embeddings: sled::Tree,
     syntaxes: HashSet<String>,
     pub content_bus_tx: tokio::sync::broadcast::Sender<ContentMeta>,
     pub meta: sled::Tree,
+    pub path: String,
     pub cache_path: String,
+    // downscaled previews of images, as PNGs named <content hash>-<width>.png
+    thumbnails_path: PathBuf,
+    // rendered previews of text content, as HTML named <content hash>-<key>.html: see
+    // ui::preview_key
+    previews_path: PathBuf,
     pub index: Index,
 }
 
+fn schema_version(meta: &sled::Tree) -> u32 {
+    let res = meta.get("schema_version").unwrap();
+    res.and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or(0)
+}
+
 impl Store {
     pub fn new(path: &str) -> Store {
+        Store::open(path).unwrap()
+    }
+
+    // Opens the store, migrating it if it's older than this build. A store from a newer build
+    // isn't opened, as it may be encoded in ways this one can't read.
+    pub fn open(path: &str) -> Result<Store, String> {
+        let backend = Backend::from_env();
+        tracing::info!(name = "Store::new", ?backend, "packet store");
         let path = std::path::Path::new(path);
         let db = sled::open(path.join("sled")).unwrap();
-        let packets = db.open_tree("packets").unwrap();
-        let content_meta = db.open_tree("content_meta").unwrap();
         let meta = db.open_tree("meta").unwrap();
+        migrate::check(schema_version(&meta))?;
+        // if the SQLite backend can't be opened, the store carries on with sled's log, rather than
+        // opening empty
+        let packets = packet_store::open(path, &db, backend).unwrap_or_else(|e| {
+            tracing::error!(name = "Store::new", %e, "couldn't open the packet store: using sled");
+            packet_store::open(path, &db, Backend::Sled).unwrap()
+        });
+        let content_meta = db.open_tree("content_meta").unwrap();
+        let link_status = db.open_tree("link_status").unwrap();
+        let contacts = db.open_tree("contacts").unwrap();
+        let sources = db.open_tree("sources").unwrap();
+        let expiries = db.open_tree("expiries").unwrap();
+        let sensitive = db.open_tree("sensitive").unwrap();
+        let notes = db.open_tree("notes").unwrap();
+        let stack_settings = db.open_tree("stack_settings").unwrap();
+        let schedules = db.open_tree("schedules").unwrap();
+        let shell_runs = db.open_tree("shell_runs").unwrap();
+        let origins = db.open_tree("origins").unwrap();
+        let quarantine = db.open_tree("quarantine").unwrap();
+        let embeddings = db.open_tree("embeddings").unwrap();
+        let usage = db.open_tree("usage").unwrap();
         let cache_path = path.join("cas").into_os_string().into_string().unwrap();
 
         let (content_bus_tx, _rx) = tokio::sync::broadcast::channel(20);
 
+        let link_status_cache = link_status
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let hash = bincode::deserialize::<ssri::Integrity>(&key).ok()?;
+                let status = bincode::deserialize::<LinkStatus>(&value).ok()?;
+                Some((hash, status))
+            })
+            .collect();
+
+        let contacts_cache = contacts
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let hash = bincode::deserialize::<ssri::Integrity>(&key).ok()?;
+                let contact = bincode::deserialize::<Contact>(&value).ok()?;
+                Some((hash, contact))
+            })
+            .collect();
+
+        let stack_settings_cache = stack_settings
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let id = Scru128Id::from_bytes(key.as_ref().try_into().ok()?);
+                Some((id, serde_json::from_slice(&value).ok()?))
+            })
+            .collect();
+
+        let notes_cache = notes
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let id = Scru128Id::from_bytes(key.as_ref().try_into().ok()?);
+                Some((id, String::from_utf8_lossy(&value).to_string()))
+            })
+            .collect();
+
+        let shell_runs_cache = shell_runs
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let id = Scru128Id::from_bytes(key.as_ref().try_into().ok()?);
+                Some((id, serde_json::from_slice(&value).ok()?))
+            })
+            .collect();
+
+        let usage_cache = usage
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let id = Scru128Id::from_bytes(key.as_ref().try_into().ok()?);
+                Some((id, serde_json::from_slice(&value).ok()?))
+            })
+            .collect();
+
         let mut store = Store {
+            db,
+            durability: Durability::default(),
+            unsynced: Arc::new(AtomicBool::new(false)),
+            unsynced_blobs: Vec::new(),
             packets,
+            generation: 0,
             content_meta,
             content_meta_cache: HashMap::new(),
+            link_status,
+            link_status_cache,
+            contacts,
+            contacts_cache,
+            sources,
+            expiries,
+            sensitive,
+            notes,
+            notes_cache,
+            stack_settings,
+            stack_settings_cache,
+            origins,
+            shell_runs,
+            shell_runs_cache,
+            schedules,
+            quarantine,
+            usage,
+            usage_cache,
+            embeddings,
             // TODO: oh my
             syntaxes: syntect::parsing::SyntaxSet::load_defaults_nonewlines()
                 .syntaxes()
@@ -284,30 +971,45 @@ impl Store {
 
             content_bus_tx,
             meta,
+            path: path.to_string_lossy().to_string(),
             cache_path,
+            thumbnails_path: path.join("thumbnails"),
+            previews_path: path.join("previews"),
             index: Index::new(path.join("index")),
         };
         store.content_meta_cache = store.scan_content_meta();
-        store
+        store.durability = store
+            .settings_get()
+            .and_then(|settings| settings.durability)
+            .unwrap_or_default();
+        // a step which fails is retried on the next start
+        match migrate::run(&mut store) {
+            Ok(ran) if !ran.is_empty() => tracing::info!(name = "migrate", ?ran),
+            Ok(_) => {}
+            Err(e) => tracing::error!(name = "migrate", %e, "store not migrated"),
+        }
+        // unless a migration has already filled it again
+        if store.index.recreated {
+            store.reindex();
+        }
+        Ok(store)
     }
 
     pub fn query(&self, filter: &str, content_type: &str) -> HashSet<ssri::Integrity> {
-        let filter = filter.to_lowercase();
-        let content_type = content_type.to_lowercase();
+        let strict = self
+            .settings_get()
+            .and_then(|settings| settings.strict_search)
+            .unwrap_or(false);
+        let filter = search::fold(filter, strict);
 
         self.content_meta_cache
             .iter()
             .filter_map(|(hash, meta)| {
-                let terse = meta.terse.to_lowercase();
-                let content_type_meta = meta.content_type.to_lowercase();
+                let terse = search::fold(&meta.terse, strict);
 
                 // TODO: oh my
                 if (filter.is_empty() || terse.contains(&filter))
-                    && (content_type.is_empty()
-                        || content_type == "all"
-                        || content_type_meta == content_type
-                        || (content_type == "source code"
-                            && self.syntaxes.contains(&content_type_meta)))
+                    && self.is_content_type(meta, content_type)
                 {
                     Some(hash.clone())
                 } else {
@@ -317,11 +1019,21 @@ impl Store {
             .collect()
     }
 
+    // Whether the content matches a content type filter: empty and "All" match everything
+    pub fn is_content_type(&self, meta: &ContentMeta, content_type: &str) -> bool {
+        let content_type = content_type.to_lowercase();
+        let content_type_meta = meta.content_type.to_lowercase();
+        content_type.is_empty()
+            || content_type == "all"
+            || content_type_meta == content_type
+            || (content_type == "source code" && self.syntaxes.contains(&content_type_meta))
+    }
+
     pub fn scan_content_meta(&self) -> HashMap<ssri::Integrity, ContentMeta> {
         let mut content_meta_cache = HashMap::new();
         for (key, value) in self.content_meta.iter().flatten() {
             if let Ok(hash) = bincode::deserialize::<ssri::Integrity>(&key) {
-                if let Ok(meta) = bincode::deserialize::<ContentMeta>(&value) {
+                if let Some(meta) = deserialize_content_meta(&value) {
                     if meta.mime_type == MimeType::TextPlain
                         && meta.tiktokens == 0
                         && meta.terse.len() > 0
@@ -365,6 +1077,21 @@ impl Store {
         content_type: String,
     ) -> Integrity {
         let hash = cacache::write_hash_sync(&self.cache_path, content).unwrap();
+        self.cas_record(hash, content, mime_type, content_type)
+    }
+
+    // Records the metadata of content already written to the content store, e.g. by write_blob
+    #[tracing::instrument(skip_all)]
+    pub fn cas_record(
+        &mut self,
+        hash: Integrity,
+        content: &[u8],
+        mime_type: MimeType,
+        content_type: String,
+    ) -> Integrity {
+        if content_type == contact::CONTENT_TYPE {
+            self.contact_index(&hash, content);
+        }
         if let Some(meta) = self.content_meta_cache.get_mut(&hash) {
             meta.content_type = content_type;
             return hash;
@@ -372,14 +1099,16 @@ impl Store {
 
         let terse = match mime_type {
             MimeType::TextPlain => {
-                let text_content = String::from_utf8_lossy(content).into_owned();
-                if text_content.len() > 100 {
-                    text_content.chars().take(100).collect()
-                } else {
-                    text_content
-                }
+                let length = self
+                    .settings_get()
+                    .and_then(|settings| settings.terse_length)
+                    .unwrap_or(DEFAULT_TERSE_LENGTH);
+                terse(&String::from_utf8_lossy(content), length)
             }
-            MimeType::ImagePng => "Image".to_string(),
+            MimeType::FileRef => file_ref::parse(content)
+                .map(|file_ref| file_ref.name)
+                .unwrap_or_default(),
+            _ => "Image".to_string(),
         };
 
         let meta = ContentMeta {
@@ -388,16 +1117,13 @@ impl Store {
             content_type,
             terse,
             tiktokens: 0,
+            stats: analyze(content, &mime_type),
+            truncated_from: None,
         };
-        let encoded: Vec<u8> = bincode::serialize(&meta).unwrap();
-        let bytes = bincode::serialize(&hash).unwrap();
-        self.content_meta.insert(bytes, encoded).unwrap();
-
-        self.content_meta_cache.insert(hash.clone(), meta.clone());
+        self.content_meta_save(&meta);
 
-        match mime_type {
-            MimeType::TextPlain => self.index.write(&hash, content),
-            MimeType::ImagePng => (),
+        if self.index_add(&hash, content, &mime_type) {
+            self.index.commit();
         }
 
         let _ = self.content_bus_tx.send(meta);
@@ -405,40 +1131,591 @@ impl Store {
         hash
     }
 
+    // Text is indexed by its content, and file references by their path. Returns whether the
+    // content was indexed.
+    fn index_add(&mut self, hash: &Integrity, content: &[u8], mime_type: &MimeType) -> bool {
+        let indexed = match mime_type {
+            MimeType::TextPlain => content.to_vec(),
+            MimeType::FileRef => match file_ref::parse(content) {
+                Some(file_ref) => file_ref.path.into_bytes(),
+                None => return false,
+            },
+            _ => return false,
+        };
+        self.index.add(hash, &indexed);
+        true
+    }
+
+    // Indexes all of the store's content again, e.g. once the index has been recreated
+    pub fn reindex(&mut self) {
+        let metas: Vec<_> = self.content_meta_cache.values().cloned().collect();
+        for meta in metas {
+            if let Some(content) = self.cas_read(&meta.hash) {
+                self.index_add(&meta.hash, &content, &meta.mime_type);
+            }
+        }
+        for (key, value) in self.origins.iter().flatten() {
+            let Ok(id) = key.as_ref().try_into().map(Scru128Id::from_bytes) else {
+                continue;
+            };
+            if let Ok(origin) = serde_json::from_slice(&value) {
+                self.index.add_origin(&id, &origin);
+            }
+        }
+        self.index.commit();
+        self.index.recreated = false;
+    }
+
     pub fn cas_read(&self, hash: &Integrity) -> Option<Vec<u8>> {
         cacache::read_hash_sync(&self.cache_path, hash).ok()
     }
 
+    // Removes content which no packet refers to, e.g. after adding it was undone
+    pub fn gc(&mut self) -> GcStats {
+        let referenced: HashSet<Integrity> = self.scan().filter_map(|p| p.hash).collect();
+        let orphans: Vec<Integrity> = self
+            .content_meta_cache
+            .keys()
+            .filter(|hash| !referenced.contains(hash))
+            .cloned()
+            .collect();
+
+        let mut stats = GcStats::default();
+        for hash in orphans {
+            if let Some(content) = self.cas_read(&hash) {
+                stats.bytes += content.len() as u64;
+            }
+            self.upload_remove(&hash);
+            let _ = cacache::remove_hash_sync(&self.cache_path, &hash);
+            self.derived_remove(&hash);
+            self.embedding_remove(&hash);
+            self.index.delete(&hash);
+            let hash_bytes = bincode::serialize(&hash).unwrap();
+            self.content_meta.remove(hash_bytes).unwrap();
+            self.content_meta_cache.remove(&hash);
+            stats.removed += 1;
+        }
+        if stats.removed > 0 {
+            self.index.commit();
+        }
+        if let Err(e) = self.content_meta.flush() {
+            tracing::error!(name = "Store::gc", %e, "couldn't flush content meta");
+        }
+        stats
+    }
+
+    fn thumbnail_path(&self, hash: &Integrity, width: u32) -> PathBuf {
+        self.thumbnails_path
+            .join(format!("{}-{}.png", hash.to_hex().1, width))
+    }
+
+    // What's needed to get a thumbnail, no wider than width, of image content, or of the referenced
+    // file if it's an image: see Thumbnail::generate, which needs no lock on the store
+    pub fn thumbnail(&self, hash: &Integrity, width: u32) -> Option<Thumbnail> {
+        let meta = self.get_content_meta(hash)?;
+        if !meta.mime_type.is_image() && meta.mime_type != MimeType::FileRef {
+            return None;
+        }
+        let width = width.clamp(1, MAX_THUMBNAIL_WIDTH);
+        Some(Thumbnail {
+            cache_path: self.cache_path.clone(),
+            hash: hash.clone(),
+            mime_type: meta.mime_type,
+            width,
+            dir: self.thumbnails_path.clone(),
+            path: self.thumbnail_path(hash, width),
+        })
+    }
+
+    fn preview_path(&self, hash: &Integrity, key: &str) -> PathBuf {
+        self.previews_path
+            .join(format!("{}-{}.html", hash.to_hex().1, key))
+    }
+
+    pub fn preview_get(&self, hash: &Integrity, key: &str) -> Option<String> {
+        std::fs::read_to_string(self.preview_path(hash, key)).ok()
+    }
+
+    pub fn preview_set(&self, hash: &Integrity, key: &str, preview: &str) {
+        let path = self.preview_path(hash, key);
+        if std::fs::create_dir_all(&self.previews_path).is_err() {
+            return;
+        }
+        // written to the side and renamed, so a partially written preview is never served
+        let tmp = path.with_extension("tmp");
+        if std::fs::write(&tmp, preview).is_ok() {
+            let _ = std::fs::rename(&tmp, &path);
+        }
+    }
+
+    // Removes the file uploaded to the HTTP server which the content refers to, if it does: each
+    // upload has its own directory in the uploads directory
+    fn upload_remove(&self, hash: &Integrity) {
+        let Some(meta) = self.get_content_meta(hash) else {
+            return;
+        };
+        if meta.mime_type != MimeType::FileRef {
+            return;
+        }
+        let Some(file_ref) = self
+            .cas_read(hash)
+            .and_then(|content| file_ref::parse(&content))
+        else {
+            return;
+        };
+        let Ok(uploads) = std::fs::canonicalize(Path::new(&self.path).join(upload::UPLOADS_DIR))
+        else {
+            return;
+        };
+        let Some(dir) = Path::new(&file_ref.path).parent() else {
+            return;
+        };
+        if dir.parent() == Some(uploads.as_path()) {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                tracing::warn!(name = "Store::upload_remove", ?dir, %e, "couldn't remove");
+            }
+        }
+    }
+
+    // Removes the thumbnails and previews made from the content
+    fn derived_remove(&self, hash: &Integrity) {
+        let prefix = format!("{}-", hash.to_hex().1);
+        for dir in [&self.thumbnails_path, &self.previews_path] {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+
+    fn content_meta_save(&mut self, meta: &ContentMeta) {
+        let encoded: Vec<u8> = bincode::serialize(meta).unwrap();
+        let hash_bytes = bincode::serialize(&meta.hash).unwrap();
+        self.content_meta.insert(hash_bytes, encoded).unwrap();
+        self.content_meta_cache
+            .insert(meta.hash.clone(), meta.clone());
+    }
+
     pub fn update_tiktokens(&mut self, hash: ssri::Integrity, tiktokens: usize) {
         if let Some(meta) = self.content_meta_cache.get(&hash) {
             let mut meta = meta.clone();
             meta.tiktokens = tiktokens;
+            self.content_meta_save(&meta);
+        }
+    }
 
-            let encoded: Vec<u8> = bincode::serialize(&meta).unwrap();
-            let hash_bytes = bincode::serialize(&hash).unwrap();
-            self.content_meta
-                .insert(hash_bytes, encoded.clone())
-                .unwrap();
-            self.content_meta_cache.insert(hash, meta.clone());
+    // Records that the content is a clip truncated to max_item_bytes, from its original size
+    pub fn update_truncated_from(&mut self, hash: ssri::Integrity, size: u64) {
+        if let Some(meta) = self.content_meta_cache.get(&hash) {
+            let mut meta = meta.clone();
+            meta.truncated_from = Some(size);
+            self.content_meta_save(&meta);
         }
     }
 
-    pub fn insert_packet(&mut self, packet: &Packet) {
-        let encoded: Vec<u8> = bincode::serialize(&packet).unwrap();
-        self.packets.insert(packet.id.to_bytes(), encoded).unwrap();
+    pub fn update_content_stats(&mut self, hash: ssri::Integrity, stats: ContentStats) {
+        if let Some(meta) = self.content_meta_cache.get(&hash) {
+            let mut meta = meta.clone();
+            meta.stats = stats;
+            self.content_meta_save(&meta);
+        }
+    }
+
+    pub fn link_status_get(&self, hash: &ssri::Integrity) -> Option<LinkStatus> {
+        self.link_status_cache.get(hash).cloned()
+    }
+
+    pub fn link_status_set(&mut self, hash: ssri::Integrity, status: LinkStatus) {
+        let encoded: Vec<u8> = bincode::serialize(&status).unwrap();
+        let hash_bytes = bincode::serialize(&hash).unwrap();
+        self.link_status.insert(hash_bytes, encoded).unwrap();
+        self.link_status_cache.insert(hash, status);
     }
 
-    pub fn scan(&self) -> impl Iterator<Item = Packet> {
-        self.packets
+    pub fn contact_get(&self, hash: &ssri::Integrity) -> Option<Contact> {
+        self.contacts_cache.get(hash).cloned()
+    }
+
+    // Parses the content's contact fields and stores them alongside the content's meta
+    fn contact_index(&mut self, hash: &ssri::Integrity, content: &[u8]) {
+        if self.contacts_cache.contains_key(hash) {
+            return;
+        }
+        if let Some(contact) = contact::parse(&String::from_utf8_lossy(content)) {
+            let encoded: Vec<u8> = bincode::serialize(&contact).unwrap();
+            let hash_bytes = bincode::serialize(hash).unwrap();
+            self.contacts.insert(hash_bytes, encoded).unwrap();
+            self.contacts_cache.insert(hash.clone(), contact);
+        }
+    }
+
+    // Attaches a note to an item; an empty note removes it
+    pub fn note_set(&mut self, id: Scru128Id, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            self.notes.remove(id.to_bytes()).unwrap();
+            self.notes_cache.remove(&id);
+        } else {
+            self.notes.insert(id.to_bytes(), text.as_bytes()).unwrap();
+            self.notes_cache.insert(id, text.to_string());
+        }
+    }
+
+    pub fn note_get(&self, id: &Scru128Id) -> Option<String> {
+        self.notes_cache.get(id).cloned()
+    }
+
+    // Returns the ids of the items whose note matches the filter, normalized as for query
+    pub fn notes_query(&self, filter: &str) -> Vec<Scru128Id> {
+        let strict = self
+            .settings_get()
+            .and_then(|settings| settings.strict_search)
+            .unwrap_or(false);
+        let filter = search::fold(filter, strict);
+        self.notes_cache
             .iter()
-            .filter_map(|item| item.ok().and_then(|(_, value)| deserialize_packet(&value)))
+            .filter(|(_, note)| search::fold(note, strict).contains(&filter))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    pub fn stack_settings_get(&self, id: &Scru128Id) -> StackSettings {
+        self.stack_settings_cache
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn stack_settings_all(&self) -> Vec<(Scru128Id, StackSettings)> {
+        self.stack_settings_cache
+            .iter()
+            .map(|(id, settings)| (*id, settings.clone()))
+            .collect()
+    }
+
+    // Setting a stack as the capture target unsets whichever stack was the target before
+    pub fn stack_settings_set(&mut self, id: Scru128Id, settings: &StackSettings) {
+        if settings.capture_target {
+            for (other, mut other_settings) in self.stack_settings_all() {
+                if other != id && other_settings.capture_target {
+                    other_settings.capture_target = false;
+                    self.stack_settings_put(other, &other_settings);
+                }
+            }
+        }
+        self.stack_settings_put(id, settings);
+    }
+
+    fn stack_settings_put(&mut self, id: Scru128Id, settings: &StackSettings) {
+        if *settings == StackSettings::default() {
+            self.stack_settings.remove(id.to_bytes()).unwrap();
+            self.stack_settings_cache.remove(&id);
+        } else {
+            let value = serde_json::to_vec(settings).unwrap();
+            self.stack_settings.insert(id.to_bytes(), value).unwrap();
+            self.stack_settings_cache.insert(id, settings.clone());
+        }
+    }
+
+    // The stack new clips are captured to, if one is set
+    pub fn capture_target(&self) -> Option<Scru128Id> {
+        self.stack_settings_cache
+            .iter()
+            .find(|(_, settings)| settings.capture_target)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn origin_set(&mut self, id: Scru128Id, origin: &Origin) {
+        let value = serde_json::to_vec(origin).unwrap();
+        self.origins.insert(id.to_bytes(), value).unwrap();
+        self.index.add_origin(&id, origin);
+        self.index.commit();
+    }
+
+    pub fn origin_get(&self, id: &Scru128Id) -> Option<Origin> {
+        let res = self.origins.get(id.to_bytes()).unwrap()?;
+        serde_json::from_slice(&res).ok()
+    }
+
+    // Returns the ids of the items with an origin whose URL or title has words starting with each
+    // of the filter's
+    pub fn origins_query(&self, filter: &str) -> Vec<Scru128Id> {
+        self.index.query_origins(filter)
+    }
+
+    pub fn shell_run_set(&mut self, id: Scru128Id, run: &ShellRun) {
+        let value = serde_json::to_vec(run).unwrap();
+        self.shell_runs.insert(id.to_bytes(), value).unwrap();
+        self.shell_runs_cache.insert(id, run.clone());
+    }
+
+    pub fn shell_run_get(&self, id: &Scru128Id) -> Option<ShellRun> {
+        self.shell_runs_cache.get(id).cloned()
+    }
+
+    pub fn schedules(&self) -> Vec<Schedule> {
+        self.schedules
+            .iter()
+            .flatten()
+            .filter_map(|(_, value)| serde_json::from_slice(&value).ok())
+            .collect()
+    }
+
+    pub fn schedule_set(&mut self, schedule: &Schedule) {
+        let value = serde_json::to_vec(schedule).unwrap();
+        self.schedules
+            .insert(schedule.id.to_bytes(), value)
+            .unwrap();
+    }
+
+    // Returns whether there was a schedule to delete
+    pub fn schedule_delete(&mut self, id: &Scru128Id) -> bool {
+        self.schedules.remove(id.to_bytes()).unwrap().is_some()
+    }
+
+    // Writes anything pending to disk
+    pub fn flush(&mut self) {
+        if let Err(e) = self.packets.flush() {
+            tracing::error!(name = "Store::flush", %e, "couldn't flush packets");
+        }
+        if let Err(e) = self.db.flush() {
+            tracing::error!(name = "Store::flush", %e, "couldn't flush sled");
+        }
+        for hash in self.unsynced_blobs.drain(..) {
+            if let Err(e) = durability::sync_blob(&self.cache_path, &hash) {
+                tracing::error!(name = "Store::flush", %hash, %e, "couldn't sync content");
+            }
+        }
+        self.unsynced.store(false, Ordering::Relaxed);
+    }
+
+    // Set while there are writes the durability setting has held back
+    pub fn unsynced(&self) -> Arc<AtomicBool> {
+        self.unsynced.clone()
+    }
+
+    // Syncs writes the durability setting has held back, returning whether there were any
+    pub fn sync_pending(&mut self) -> bool {
+        if !self.unsynced.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.flush();
+        true
+    }
+
+    pub fn usage_record(&mut self, id: Scru128Id, access: Access, now: u64) {
+        let usage = self.usage_cache.entry(id).or_default();
+        usage.record(access, now);
+        let value = serde_json::to_vec(usage).unwrap();
+        self.usage.insert(id.to_bytes(), value).unwrap();
+    }
+
+    pub fn usage_all(&self) -> &HashMap<Scru128Id, Usage> {
+        &self.usage_cache
+    }
+
+    pub fn source_set(&mut self, id: Scru128Id, source: &str) {
+        self.sources
+            .insert(id.to_bytes(), source.as_bytes())
+            .unwrap();
+    }
+
+    pub fn source_get(&self, id: &Scru128Id) -> Option<String> {
+        let res = self.sources.get(id.to_bytes()).unwrap();
+        res.map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    // Returns the ids of the items captured from source, oldest first
+    pub fn source_items(&self, source: &str) -> Vec<Scru128Id> {
+        self.sources
+            .iter()
+            .flatten()
+            .filter(|(_, value)| value.as_ref() == source.as_bytes())
+            .filter_map(|(key, _)| Some(Scru128Id::from_bytes(key.as_ref().try_into().ok()?)))
+            .collect()
+    }
+
+    pub fn sensitive_set(&mut self, id: Scru128Id, sensitive: bool) {
+        if sensitive {
+            self.sensitive.insert(id.to_bytes(), &[]).unwrap();
+        } else {
+            self.sensitive.remove(id.to_bytes()).unwrap();
+        }
+    }
+
+    pub fn is_sensitive(&self, id: &Scru128Id) -> bool {
+        self.sensitive.contains_key(id.to_bytes()).unwrap()
+    }
+
+    pub fn expiry_set(&mut self, id: Scru128Id, at: u64) {
+        self.expiries
+            .insert(id.to_bytes(), &at.to_be_bytes())
+            .unwrap();
+    }
+
+    pub fn has_expiry(&self, id: &Scru128Id) -> bool {
+        self.expiries.contains_key(id.to_bytes()).unwrap()
+    }
+
+    pub fn expiry_clear(&mut self, id: &Scru128Id) {
+        self.expiries.remove(id.to_bytes()).unwrap();
+    }
+
+    // Returns the ids of the items whose expiry is at, or before, now
+    pub fn expiries_due(&self, now: u64) -> Vec<Scru128Id> {
+        self.expiries
+            .iter()
+            .flatten()
+            .filter_map(|(key, value)| {
+                let at = u64::from_be_bytes(value.as_ref().try_into().ok()?);
+                let id = Scru128Id::from_bytes(key.as_ref().try_into().ok()?);
+                (at <= now).then_some(id)
+            })
+            .collect()
+    }
+
+    // Removes every packet which refers to the item, as if it had never been captured. Its
+    // content is left for gc to reclaim, but it's taken out of the search index, unless another
+    // item holds it. Returns the number of packets removed.
+    pub fn purge(&mut self, source_id: &Scru128Id) -> usize {
+        let packets: Vec<Packet> = self
+            .scan()
+            .filter(|p| p.id == *source_id || p.source_id == Some(*source_id))
+            .collect();
+        for packet in &packets {
+            self.remove_packet(&packet.id);
+            if let Some(hash) = &packet.hash {
+                self.embedding_remove(hash);
+            }
+        }
+        let referenced: HashSet<Integrity> = self.scan().filter_map(|p| p.hash).collect();
+        for hash in packets.iter().filter_map(|p| p.hash.as_ref()) {
+            if !referenced.contains(hash) {
+                self.index.delete(hash);
+                self.upload_remove(hash);
+            }
+        }
+        self.index.commit();
+        self.sources.remove(source_id.to_bytes()).unwrap();
+        self.sensitive.remove(source_id.to_bytes()).unwrap();
+        self.note_set(*source_id, "");
+        self.shell_runs.remove(source_id.to_bytes()).unwrap();
+        self.shell_runs_cache.remove(source_id);
+        self.origins.remove(source_id.to_bytes()).unwrap();
+        self.index.delete_origin(source_id);
+        self.usage.remove(source_id.to_bytes()).unwrap();
+        self.usage_cache.remove(source_id);
+        packets.len()
+    }
+
+    pub fn insert_packet(&mut self, packet: &Packet) {
+        self.generation += 1;
+        if let Err(e) = self.packets.insert(packet) {
+            tracing::error!(name = "Store::insert_packet", id = %packet.id, %e, "couldn't write");
+            return;
+        }
+        if self.durability == Durability::Os {
+            return;
+        }
+        // cacache doesn't sync the content it writes, so it's synced with the packet
+        if let Some(hash) = &packet.hash {
+            self.unsynced_blobs.push(hash.clone());
+        }
+        match self.durability {
+            Durability::Periodic => self.unsynced.store(true, Ordering::Relaxed),
+            _ => self.flush(),
+        }
+    }
+
+    pub fn scan(&self) -> impl Iterator<Item = Packet> + '_ {
+        self.packets.scan()
+    }
+
+    pub fn packet_count(&self) -> usize {
+        self.packets.count()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Returns the most recent packet that placed content on the clipboard at, or before, the
+    // given unix timestamp (in milliseconds). Packet ids are scru128, so the packets are already
+    // ordered by time and we only need to walk backwards from the timestamp.
+    pub fn clipboard_at(&self, timestamp: u64) -> Option<Packet> {
+        let timestamp = timestamp.min((1 << 48) - 1) as u128;
+        let upper = Scru128Id::from_u128((timestamp << 80) | ((1 << 80) - 1));
+        self.packets.scan_rev(&upper).find(|p| match p.packet_type {
+            PacketType::Add => p.stack_id.is_some() && p.hash.is_some() && !p.ephemeral,
+            PacketType::Update => p.source_id.is_some() && p.hash.is_some(),
+            _ => false,
+        })
+    }
+
+    // Returns the versions of an item's content, oldest first. Each edit is an Update packet with
+    // the new content's hash, so the chain of versions is read back from the packet log.
+    pub fn history(&self, source_id: &Scru128Id) -> Vec<ItemVersion> {
+        let mut versions: Vec<ItemVersion> = Vec::new();
+        for packet in self.scan() {
+            let is_version = match packet.packet_type {
+                PacketType::Add => packet.id == *source_id,
+                PacketType::Update => packet.source_id == Some(*source_id),
+                _ => false,
+            };
+            let Some(hash) = packet.hash.filter(|_| is_version) else {
+                continue;
+            };
+            let previous = versions.last().map(|version| version.hash.clone());
+            if previous.as_ref() == Some(&hash) {
+                continue;
+            }
+            let terse = self
+                .get_content_meta(&hash)
+                .map(|meta| meta.terse)
+                .unwrap_or_default();
+            versions.push(ItemVersion {
+                id: packet.id,
+                hash,
+                previous,
+                terse,
+            });
+        }
+        versions
     }
 
     pub fn add(&mut self, content: &[u8], mime_type: MimeType, stack_id: Scru128Id) -> Packet {
+        self.add_with_id(scru128::new(), content, mime_type, stack_id)
+    }
+
+    // Adds content under an id made for it, e.g. to keep when an imported item was copied
+    pub fn add_with_id(
+        &mut self,
+        id: Scru128Id,
+        content: &[u8],
+        mime_type: MimeType,
+        stack_id: Scru128Id,
+    ) -> Packet {
+        let hash = cacache::write_hash_sync(&self.cache_path, content).unwrap();
+        self.add_written(id, hash, content, mime_type, stack_id)
+    }
+
+    // Adds content already written to the content store, e.g. by write_blob
+    pub fn add_written(
+        &mut self,
+        id: Scru128Id,
+        hash: Integrity,
+        content: &[u8],
+        mime_type: MimeType,
+        stack_id: Scru128Id,
+    ) -> Packet {
         let (mime_type, content_type) = infer_mime_type(content, mime_type);
-        let hash = self.cas_write(content, mime_type, content_type.clone());
+        let hash = self.cas_record(hash, content, mime_type, content_type);
         let packet = Packet {
-            id: scru128::new(),
+            id,
             packet_type: PacketType::Add,
             source_id: None,
             hash: Some(hash),
@@ -455,9 +1732,18 @@ impl Store {
     }
 
     pub fn add_stack(&mut self, name: &[u8], lock_status: StackLockStatus) -> Packet {
+        self.add_stack_with_id(scru128::new(), name, lock_status)
+    }
+
+    pub fn add_stack_with_id(
+        &mut self,
+        id: Scru128Id,
+        name: &[u8],
+        lock_status: StackLockStatus,
+    ) -> Packet {
         let hash = self.cas_write(name, MimeType::TextPlain, "Text".to_string());
         let packet = Packet {
-            id: scru128::new(),
+            id,
             packet_type: PacketType::Add,
             source_id: None,
             hash: Some(hash),
@@ -504,7 +1790,7 @@ impl Store {
     pub fn update_touch(&mut self, source_id: Scru128Id) -> Packet {
         let packet = Packet {
             id: scru128::new(),
-            packet_type: PacketType::Update,
+            packet_type: PacketType::Touch,
             source_id: Some(source_id),
             hash: None,
             stack_id: None,
@@ -535,6 +1821,11 @@ impl Store {
             cross_stream: false,
         };
         self.insert_packet(&packet);
+        if content_type == contact::CONTENT_TYPE {
+            if let Some(content) = self.cas_read(&hash) {
+                self.contact_index(&hash, &content);
+            }
+        }
         meta.content_type = content_type;
         self.content_meta_cache.insert(hash, meta);
         packet
@@ -620,6 +1911,68 @@ impl Store {
         packet
     }
 
+    pub fn nest_stack(&mut self, source_id: Scru128Id, parent_id: Option<Scru128Id>) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: PacketType::Nest,
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: parent_id,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn link(&mut self, source_id: Scru128Id, stack_id: Scru128Id, linked: bool) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: if linked {
+                PacketType::Link
+            } else {
+                PacketType::Unlink
+            },
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: Some(stack_id),
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
+    pub fn update_stack_archived(&mut self, source_id: Scru128Id, archived: bool) -> Packet {
+        let packet = Packet {
+            id: scru128::new(),
+            packet_type: if archived {
+                PacketType::Archive
+            } else {
+                PacketType::Restore
+            },
+            source_id: Some(source_id),
+            hash: None,
+            stack_id: None,
+            ephemeral: false,
+            content_type: None,
+            movement: None,
+            lock_status: None,
+            sort_order: None,
+            cross_stream: false,
+        };
+        self.insert_packet(&packet);
+        packet
+    }
+
     pub fn fork(
         &mut self,
         source_id: Scru128Id,
@@ -667,11 +2020,85 @@ impl Store {
     }
 
     pub fn remove_packet(&mut self, id: &Scru128Id) -> Option<Packet> {
-        let removed = self.packets.remove(id.to_bytes()).unwrap();
-        removed.and_then(|value| deserialize_packet(&value))
+        self.generation += 1;
+        self.packets.remove(id).unwrap_or_else(|e| {
+            tracing::error!(name = "Store::remove_packet", %id, %e, "couldn't remove");
+            None
+        })
+    }
+
+    pub fn remove_packets(&mut self, ids: &HashSet<Scru128Id>) -> Result<(), String> {
+        self.generation += 1;
+        self.packets.remove_all(ids)?;
+        self.flush();
+        Ok(())
+    }
+
+    // Moves the packet out of the packet store, keeping it aside so it isn't lost
+    pub fn quarantine(&mut self, id: &Scru128Id) -> Option<Packet> {
+        let packet = self.remove_packet(id)?;
+        let value = serde_json::to_vec(&packet).unwrap();
+        self.quarantine.insert(id.to_bytes(), value).unwrap();
+        Some(packet)
+    }
+
+    pub fn quarantined(&self) -> Vec<Packet> {
+        self.quarantine
+            .iter()
+            .flatten()
+            .filter_map(|(_, value)| serde_json::from_slice(&value).ok())
+            .collect()
+    }
+
+    pub fn has_embedding(&self, hash: &ssri::Integrity) -> bool {
+        let key = bincode::serialize(hash).unwrap();
+        self.embeddings.contains_key(key).unwrap()
+    }
+
+    pub fn embedding_set(&mut self, hash: &ssri::Integrity, embedding: &[f32]) {
+        let key = bincode::serialize(hash).unwrap();
+        let encoded: Vec<u8> = bincode::serialize(embedding).unwrap();
+        self.embeddings.insert(key, encoded).unwrap();
+    }
+
+    pub fn embedding_remove(&mut self, hash: &ssri::Integrity) {
+        let key = bincode::serialize(hash).unwrap();
+        self.embeddings.remove(key).unwrap();
+    }
+
+    // A handle on the stored embeddings, which can be read without holding the state lock
+    pub fn embeddings(&self) -> Embeddings {
+        Embeddings(self.embeddings.clone())
+    }
+
+    // The stack shell commands are added to: see ingest
+    pub fn shell_stack_get(&self) -> Option<Scru128Id> {
+        let res = self.meta.get("shell_stack").unwrap()?;
+        Some(Scru128Id::from_bytes(res.as_ref().try_into().ok()?))
+    }
+
+    pub fn shell_stack_set(&mut self, id: Scru128Id) {
+        self.meta.insert("shell_stack", &id.to_bytes()).unwrap();
+    }
+
+    // The model the stored embeddings are from. Embeddings from different models can't be
+    // compared, so they're cleared when the model changes.
+    pub fn embeddings_model_get(&self) -> Option<String> {
+        let res = self.meta.get("embeddings_model").unwrap();
+        res.map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    pub fn embeddings_model_set(&mut self, model: &str) {
+        if self.embeddings_model_get().as_deref() != Some(model) {
+            self.embeddings.clear().unwrap();
+            self.meta
+                .insert("embeddings_model", model.as_bytes())
+                .unwrap();
+        }
     }
 
     pub fn settings_save(&mut self, settings: Settings) {
+        self.durability = settings.durability.unwrap_or_default();
         let settings_str = serde_json::to_string(&settings).unwrap();
         self.meta
             .insert("settings", settings_str.as_bytes())
@@ -685,6 +2112,60 @@ impl Store {
             serde_json::from_str(str).unwrap()
         })
     }
+
+    pub fn tokenizer_get(&self) -> Tokenizer {
+        Tokenizer::from_settings(&self.settings_get().unwrap_or_default())
+    }
+
+    // 0 for stores from before the schema was versioned: see migrate
+    pub fn schema_version_get(&self) -> u32 {
+        schema_version(&self.meta)
+    }
+
+    pub fn schema_version_save(&mut self, version: u32) {
+        let version_str = serde_json::to_string(&version).unwrap();
+        self.meta
+            .insert("schema_version", version_str.as_bytes())
+            .unwrap();
+    }
+
+    pub fn share_tokens_save(&mut self, tokens: Vec<ShareToken>) {
+        let tokens_str = serde_json::to_string(&tokens).unwrap();
+        self.meta
+            .insert("share_tokens", tokens_str.as_bytes())
+            .unwrap();
+    }
+
+    pub fn share_tokens_get(&self) -> Vec<ShareToken> {
+        let res = self.meta.get("share_tokens").unwrap();
+        res.map(|bytes| {
+            let str = std::str::from_utf8(bytes.as_ref()).unwrap();
+            serde_json::from_str(str).unwrap()
+        })
+        .unwrap_or_default()
+    }
+}
+
+// content hash -> embedding: see Store::embeddings
+#[derive(Clone)]
+pub struct Embeddings(sled::Tree);
+
+impl Embeddings {
+    pub fn get(&self, hash: &ssri::Integrity) -> Option<Vec<f32>> {
+        let key = bincode::serialize(hash).unwrap();
+        let value = self.0.get(key).ok()??;
+        bincode::deserialize(&value).ok()
+    }
+}
+
+pub const DEFAULT_TERSE_LENGTH: usize = 100;
+
+pub const DEFAULT_THUMBNAIL_WIDTH: u32 = 320;
+pub const MAX_THUMBNAIL_WIDTH: u32 = 2048;
+
+// The leading characters of text, used to list and filter items
+pub fn terse(text: &str, length: usize) -> String {
+    text.chars().take(length).collect()
 }
 
 pub fn is_valid_https_url(url: &[u8]) -> bool {
@@ -692,24 +2173,111 @@ pub fn is_valid_https_url(url: &[u8]) -> bool {
     re.is_match(url)
 }
 
+// The BPE encodings tokens can be counted with, named for the model families which use them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tokenizer {
+    // GPT-4o, and the o series
+    O200k,
+    // GPT-4 and GPT-3.5
+    #[default]
+    Cl100k,
+    // Codex, text-davinci-002 and -003
+    P50k,
+    // GPT-3, e.g. davinci
+    R50k,
+}
+
+impl Tokenizer {
+    pub fn for_model(model: &str) -> Tokenizer {
+        let model = model.to_lowercase();
+        let is = |prefixes: &[&str]| prefixes.iter().any(|prefix| model.starts_with(prefix));
+        if is(&["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"]) {
+            Tokenizer::O200k
+        } else if is(&["code-", "text-davinci-002", "text-davinci-003"]) {
+            Tokenizer::P50k
+        } else if is(&[
+            "davinci",
+            "curie",
+            "babbage",
+            "ada",
+            "text-davinci",
+            "text-curie",
+        ]) {
+            Tokenizer::R50k
+        } else {
+            Tokenizer::Cl100k
+        }
+    }
+
+    // The tokenizer setting, else the one for openai_selected_model
+    pub fn from_settings(settings: &Settings) -> Tokenizer {
+        settings
+            .tokenizer
+            .unwrap_or_else(|| Tokenizer::for_model(&settings.openai_selected_model))
+    }
+}
+
 #[tracing::instrument(skip_all)]
-pub fn count_tiktokens(content: &str) -> usize {
-    let bpe = tiktoken_rs::cl100k_base().unwrap();
+pub fn count_tiktokens(content: &str, tokenizer: Tokenizer) -> usize {
+    // the encodings are slow to load, so they're loaded once, and shared
+    let bpe = match tokenizer {
+        Tokenizer::O200k => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100k => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50k => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::R50k => tiktoken_rs::r50k_base_singleton(),
+    };
+    let bpe = bpe.lock();
     let tokens = bpe.encode_with_special_tokens(content);
     tokens.len()
 }
 
+// Writes content to the content store on the blocking pool, so a large item isn't written while
+// the state lock is held: the metadata is then recorded under the lock, with cas_record,
+// add_written or end_stream_written. The content is handed back, with its hash.
+pub async fn write_blob(
+    cache_path: String,
+    content: Vec<u8>,
+) -> Result<(Integrity, Vec<u8>), String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let hash = cacache::write_hash_sync(&cache_path, &content).map_err(|e| e.to_string())?;
+        Ok((hash, content))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 #[tracing::instrument(skip_all)]
 pub fn infer_mime_type(content: &[u8], mime_type: MimeType) -> (MimeType, String) {
+    // trust the content's signature over the pasteboard type it was offered as
+    let mime_type = if mime_type.is_image() {
+        infer::get(content)
+            .and_then(|kind| MimeType::from_image_mime(kind.mime_type()))
+            .unwrap_or(mime_type)
+    } else {
+        mime_type
+    };
+
     let content_type = match mime_type {
         MimeType::TextPlain => {
             if is_valid_https_url(content) {
                 "Link".to_string()
+            } else if color::parse(&String::from_utf8_lossy(content)).is_some() {
+                "Color".to_string()
+            } else if contact::parse(&String::from_utf8_lossy(content)).is_some() {
+                contact::CONTENT_TYPE.to_string()
+            } else if calendar::is_event(&String::from_utf8_lossy(content)) {
+                calendar::CONTENT_TYPE.to_string()
+            } else if address::is_address(&String::from_utf8_lossy(content)) {
+                address::CONTENT_TYPE.to_string()
+            } else if phone::is_phone(&String::from_utf8_lossy(content)) {
+                phone::CONTENT_TYPE.to_string()
             } else {
                 "Text".to_string()
             }
         }
-        MimeType::ImagePng => "Image".to_string(),
+        MimeType::FileRef => file_ref::CONTENT_TYPE.to_string(),
+        _ => "Image".to_string(),
     };
 
     (mime_type, content_type)
@@ -1,4 +1,12 @@
-use crate::store::{is_valid_https_url, MimeType, Packet, PacketType, StackLockStatus, Store};
+use std::collections::HashSet;
+
+use crate::schedule::Schedule;
+use crate::stack_settings::StackSettings;
+use crate::store::{
+    analyze, count_tiktokens, deserialize_content_meta, infer_mime_type, is_valid_https_url,
+    ContentMetaV1, ContentMetaV2, ContentStats, MimeType, Packet, PacketType, Settings,
+    StackLockStatus, Store, Tokenizer,
+};
 
 use tempfile::tempdir;
 
@@ -24,6 +32,23 @@ fn test_add() {
     }
 }
 
+#[test]
+fn test_generation() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item = store.add(b"Item 1", MimeType::TextPlain, stack.id);
+    let (count, generation) = (store.packet_count(), store.generation());
+
+    // an undo and an add leave as many packets, but they've changed
+    store.remove_packet(&item.id);
+    store.add(b"Item 2", MimeType::TextPlain, stack.id);
+    assert_eq!(store.packet_count(), count);
+    assert_ne!(store.generation(), generation);
+}
+
 #[test]
 fn test_update() {
     let dir = tempdir().unwrap();
@@ -127,8 +152,434 @@ fn test_query() {
     assert_eq!(results, vec![b"Hello, fuzzy world!".to_vec()]);
 }
 
+#[test]
+fn test_recreated_index() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item = store.add(b"Hello, fuzzy world!", MimeType::TextPlain, stack.id);
+    drop(store);
+
+    // an index which can't be opened is recreated, and filled again
+    std::fs::write(dir.path().join("index").join("meta.json"), b"corrupt").unwrap();
+    let store = Store::new(path);
+    assert_eq!(
+        store.index.query("fuzzy"),
+        HashSet::from([item.hash.unwrap()])
+    );
+}
+
+#[test]
+fn test_query_normalized() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let cafe = store.add("Café Straße".as_bytes(), MimeType::TextPlain, stack.id);
+
+    let found = store.query("cafe strasse", "");
+    assert!(found.contains(&cafe.hash.clone().unwrap()));
+
+    store.settings_save(Settings {
+        strict_search: Some(true),
+        ..Default::default()
+    });
+    assert!(store.query("cafe", "").is_empty());
+    assert!(store.query("CAFÉ", "").contains(&cafe.hash.unwrap()));
+}
+
 #[test]
 fn test_is_valid_https_url() {
     assert!(is_valid_https_url(b"https://www.example.com"));
     assert!(!is_valid_https_url(b"Good afternoon"));
 }
+
+#[test]
+fn test_infer_mime_type_image() {
+    let jpeg = [
+        0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00,
+    ];
+    assert_eq!(
+        infer_mime_type(&jpeg, MimeType::ImagePng),
+        (MimeType::ImageJpeg, "Image".to_string())
+    );
+    // unrecognized data keeps the type it was captured as
+    assert_eq!(
+        infer_mime_type(b"not an image", MimeType::ImageTiff),
+        (MimeType::ImageTiff, "Image".to_string())
+    );
+    assert_eq!(
+        MimeType::from_image_mime("image/webp"),
+        Some(MimeType::ImageWebp)
+    );
+    assert_eq!(MimeType::ImageGif.pasteboard_type(), "com.compuserve.gif");
+    assert_eq!(
+        MimeType::TextPlain.pasteboard_type(),
+        "public.utf8-plain-text"
+    );
+}
+
+#[test]
+fn test_clipboard_at() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let first = store.add(b"one", MimeType::TextPlain, stack.id);
+    let second = store.add(b"two", MimeType::TextPlain, stack.id);
+
+    // stacks aren't clipboard content
+    assert_eq!(store.clipboard_at(stack.id.timestamp() - 1), None);
+    assert_eq!(store.clipboard_at(first.id.timestamp() - 1), None);
+
+    let found = store.clipboard_at(second.id.timestamp()).unwrap();
+    assert_eq!(found, second);
+
+    let edit = store.update(first.id, Some(b"one, edited"), MimeType::TextPlain, None);
+    let found = store.clipboard_at(edit.id.timestamp()).unwrap();
+    assert_eq!(found, edit);
+}
+
+#[test]
+fn test_history() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let item = store.add(b"draft", MimeType::TextPlain, stack.id);
+    let other = store.add(b"other", MimeType::TextPlain, stack.id);
+    let edit = store.update(item.id, Some(b"final"), MimeType::TextPlain, None);
+    store.update(other.id, Some(b"other, edited"), MimeType::TextPlain, None);
+    // moves aren't versions
+    store.update(item.id, None, MimeType::TextPlain, Some(stack.id));
+
+    let history = store.history(&item.id);
+    let terse: Vec<_> = history.iter().map(|v| v.terse.as_str()).collect();
+    assert_eq!(terse, vec!["draft", "final"]);
+    assert_eq!(history[0].id, item.id);
+    assert_eq!(history[0].previous, None);
+    assert_eq!(history[1].id, edit.id);
+    assert_eq!(history[1].previous, item.hash);
+}
+
+#[test]
+fn test_notes() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let item = store.add(b"SELECT * FROM users", MimeType::TextPlain, stack.id);
+    store.note_set(item.id, "  Query for the Café report ");
+    assert_eq!(
+        store.note_get(&item.id),
+        Some("Query for the Café report".to_string())
+    );
+    assert_eq!(store.notes_query("cafe"), vec![item.id]);
+    assert!(store.notes_query("invoices").is_empty());
+
+    // notes persist
+    drop(store);
+    let mut store = Store::new(path);
+    assert_eq!(store.notes_query("report"), vec![item.id]);
+
+    store.note_set(item.id, "");
+    assert_eq!(store.note_get(&item.id), None);
+}
+
+#[test]
+fn test_stack_settings() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let work = store.add_stack(b"Work", StackLockStatus::Unlocked);
+    let home = store.add_stack(b"Home", StackLockStatus::Unlocked);
+    assert_eq!(store.stack_settings_get(&work.id), StackSettings::default());
+    assert_eq!(store.capture_target(), None);
+
+    let target = StackSettings {
+        capture_target: true,
+        max_items: Some(10),
+        ..Default::default()
+    };
+    store.stack_settings_set(work.id, &target);
+    assert_eq!(store.capture_target(), Some(work.id));
+
+    // only one stack is the capture target; the previous target keeps its other settings
+    store.stack_settings_set(home.id, &target);
+    assert_eq!(store.capture_target(), Some(home.id));
+    assert_eq!(
+        store.stack_settings_get(&work.id),
+        StackSettings {
+            max_items: Some(10),
+            ..Default::default()
+        }
+    );
+
+    // settings persist
+    drop(store);
+    let mut store = Store::new(path);
+    assert_eq!(store.capture_target(), Some(home.id));
+
+    store.stack_settings_set(home.id, &StackSettings::default());
+    assert_eq!(store.capture_target(), None);
+    assert_eq!(store.stack_settings_all().len(), 1);
+}
+
+#[test]
+fn test_schedules() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let item = store.add(b"df -h", MimeType::TextPlain, stack.id);
+    let mut schedule = Schedule {
+        id: scru128::new(),
+        source_id: item.id,
+        cron: "0 * * * *".to_string(),
+        paused: false,
+        last_run: None,
+    };
+    store.schedule_set(&schedule);
+    schedule.paused = true;
+    store.schedule_set(&schedule);
+    assert_eq!(store.schedules(), vec![schedule.clone()]);
+
+    // schedules persist
+    drop(store);
+    let mut store = Store::new(path);
+    assert_eq!(store.schedules(), vec![schedule.clone()]);
+    assert!(store.schedule_delete(&schedule.id));
+    assert!(!store.schedule_delete(&schedule.id));
+    assert!(store.schedules().is_empty());
+}
+
+#[test]
+fn test_gc() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let kept = store.add(b"kept", MimeType::TextPlain, stack.id);
+    let undone = store.add(b"undone", MimeType::TextPlain, stack.id);
+    store.embedding_set(undone.hash.as_ref().unwrap(), &[1.0, 0.0]);
+    store.remove_packet(&undone.id);
+
+    let stats = store.gc();
+    assert_eq!(stats.removed, 1);
+    assert_eq!(stats.bytes, 6);
+    assert!(!store.has_embedding(undone.hash.as_ref().unwrap()));
+    assert_eq!(store.cas_read(&undone.hash.unwrap()), None);
+    assert!(store.cas_read(&kept.hash.unwrap()).is_some());
+
+    assert_eq!(store.gc().removed, 0);
+}
+
+#[test]
+fn test_thumbnail() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let mut png = Vec::new();
+    image::DynamicImage::new_rgb8(640, 320)
+        .write_to(
+            &mut std::io::Cursor::new(&mut png),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let text = store.add(b"text", MimeType::TextPlain, stack.id);
+    let screenshot = store.add(&png, MimeType::ImagePng, stack.id);
+    let hash = screenshot.hash.unwrap();
+
+    assert!(store.thumbnail(&text.hash.unwrap(), 100).is_none());
+
+    let thumbnail = store.thumbnail(&hash, 100).unwrap().generate().unwrap();
+    let dimensions = image::image_dimensions(&thumbnail).unwrap();
+    assert_eq!(dimensions, (100, 50));
+    // cached
+    assert_eq!(
+        store.thumbnail(&hash, 100).unwrap().generate(),
+        Some(thumbnail.clone())
+    );
+
+    // smaller images aren't scaled up
+    let full = store.thumbnail(&hash, 1000).unwrap().generate().unwrap();
+    assert_eq!(image::image_dimensions(&full).unwrap(), (640, 320));
+
+    store.remove_packet(&screenshot.id);
+    store.gc();
+    assert!(!thumbnail.exists());
+    assert!(!full.exists());
+}
+
+#[test]
+fn test_preview_cache() {
+    let dir = tempdir().unwrap();
+    let mut store = Store::new(dir.path().to_str().unwrap());
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let text = store.add(b"fn main() {}", MimeType::TextPlain, stack.id);
+    let hash = text.hash.unwrap();
+
+    assert_eq!(store.preview_get(&hash, "key"), None);
+    store.preview_set(&hash, "key", "<pre>fn main() {}</pre>");
+    assert_eq!(
+        store.preview_get(&hash, "key").as_deref(),
+        Some("<pre>fn main() {}</pre>")
+    );
+    assert_eq!(store.preview_get(&hash, "other"), None);
+
+    store.remove_packet(&text.id);
+    store.gc();
+    assert_eq!(store.preview_get(&hash, "key"), None);
+}
+
+#[test]
+fn test_expiry_purge() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let kept = store.add(b"kept", MimeType::TextPlain, stack.id);
+    let secret = store.add(b"secret", MimeType::TextPlain, stack.id);
+    store.update_touch(secret.id);
+    store.expiry_set(secret.id, 1_000);
+
+    assert_eq!(store.expiries_due(999), vec![]);
+    assert_eq!(store.expiries_due(1_000), vec![secret.id]);
+
+    store.embedding_set(secret.hash.as_ref().unwrap(), &[1.0, 0.0]);
+    assert_eq!(
+        store.index.query("secret"),
+        HashSet::from([secret.hash.clone().unwrap()])
+    );
+
+    // the add and the touch
+    assert_eq!(store.purge(&secret.id), 2);
+    assert!(!store.has_embedding(secret.hash.as_ref().unwrap()));
+    assert!(store.index.query("secret").is_empty());
+    store.expiry_clear(&secret.id);
+    assert_eq!(store.expiries_due(1_000), vec![]);
+
+    assert_eq!(store.gc().removed, 1);
+    assert_eq!(store.cas_read(&secret.hash.unwrap()), None);
+    let ids: Vec<_> = store.scan().map(|p| p.id).collect();
+    assert_eq!(ids, vec![stack.id, kept.id]);
+}
+
+#[test]
+fn test_sensitive() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+    let mut store = Store::new(path);
+
+    let stack = store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let secret = store.add(b"secret", MimeType::TextPlain, stack.id);
+    assert!(!store.is_sensitive(&secret.id));
+
+    store.sensitive_set(secret.id, true);
+    assert!(store.is_sensitive(&secret.id));
+    store.sensitive_set(secret.id, false);
+    assert!(!store.is_sensitive(&secret.id));
+
+    // purging the item forgets it was sensitive
+    store.sensitive_set(secret.id, true);
+    store.purge(&secret.id);
+    assert!(!store.is_sensitive(&secret.id));
+}
+
+#[test]
+fn test_tokenizer() {
+    assert_eq!(Tokenizer::for_model("gpt-4o-mini"), Tokenizer::O200k);
+    assert_eq!(Tokenizer::for_model("o3-mini"), Tokenizer::O200k);
+    assert_eq!(Tokenizer::for_model("gpt-4-turbo"), Tokenizer::Cl100k);
+    assert_eq!(Tokenizer::for_model("text-davinci-003"), Tokenizer::P50k);
+    assert_eq!(Tokenizer::for_model("davinci"), Tokenizer::R50k);
+    assert_eq!(Tokenizer::for_model(""), Tokenizer::Cl100k);
+
+    let mut settings = Settings {
+        openai_selected_model: "gpt-4o".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(Tokenizer::from_settings(&settings), Tokenizer::O200k);
+    settings.tokenizer = Some(Tokenizer::R50k);
+    assert_eq!(Tokenizer::from_settings(&settings), Tokenizer::R50k);
+
+    assert_eq!(count_tiktokens("hello world", Tokenizer::Cl100k), 2);
+    assert_eq!(count_tiktokens("", Tokenizer::O200k), 0);
+    // encodings split whitespace differently
+    let code = "def f():\n        return 1";
+    assert_ne!(
+        count_tiktokens(code, Tokenizer::Cl100k),
+        count_tiktokens(code, Tokenizer::R50k)
+    );
+}
+
+#[test]
+fn test_content_stats() {
+    let stats = analyze(
+        "héllo wörld\nsecond line\n".as_bytes(),
+        &MimeType::TextPlain,
+    );
+    assert_eq!(
+        stats,
+        ContentStats {
+            words: 4,
+            chars: 24,
+            lines: 2,
+            bytes: 26,
+        }
+    );
+    let image = analyze(b"\x89PNG", &MimeType::ImagePng);
+    assert_eq!((image.words, image.bytes), (0, 4));
+
+    let dir = tempdir().unwrap();
+    let mut store = Store::new(dir.path().to_str().unwrap());
+    let stack = store.add_stack(b"Stack", StackLockStatus::Unlocked);
+    let item = store.add(b"one two", MimeType::TextPlain, stack.id);
+    let meta = store.get_content_meta(&item.hash.unwrap()).unwrap();
+    assert_eq!((meta.stats.words, meta.stats.bytes), (2, 7));
+
+    // content meta from before stats were added still decodes
+    let v1 = ContentMetaV1 {
+        hash: meta.hash.clone(),
+        mime_type: meta.mime_type.clone(),
+        content_type: meta.content_type.clone(),
+        terse: meta.terse.clone(),
+        tiktokens: 2,
+    };
+    let decoded = deserialize_content_meta(&bincode::serialize(&v1).unwrap()).unwrap();
+    assert_eq!(decoded.tiktokens, 2);
+    assert_eq!(decoded.stats, ContentStats::default());
+    // and from before truncated_from was
+    let v2 = ContentMetaV2 {
+        hash: meta.hash.clone(),
+        mime_type: meta.mime_type.clone(),
+        content_type: meta.content_type.clone(),
+        terse: meta.terse.clone(),
+        tiktokens: meta.tiktokens,
+        stats: meta.stats.clone(),
+    };
+    let decoded = deserialize_content_meta(&bincode::serialize(&v2).unwrap()).unwrap();
+    assert_eq!(decoded, meta);
+    let current = bincode::serialize(&meta).unwrap();
+    assert_eq!(deserialize_content_meta(&current), Some(meta.clone()));
+
+    store.update_truncated_from(meta.hash.clone(), 100);
+    let truncated = store.get_content_meta(&meta.hash).unwrap();
+    assert_eq!(truncated.truncated_from, Some(100));
+    let current = bincode::serialize(&truncated).unwrap();
+    assert_eq!(deserialize_content_meta(&current), Some(truncated));
+}
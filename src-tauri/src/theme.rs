@@ -0,0 +1,129 @@
+// Themes previews are rendered with. A theme names the syntect theme source code, and code blocks
+// in Markdown, are highlighted with, and can add CSS, which the webview applies to previews. Besides
+// the built-in themes, custom ones can be added with the preview_themes setting. Unless a theme is
+// chosen, previews follow the app's light or dark mode.
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::Settings;
+
+// the themes syntect ships with, which are the ones a theme can highlight with
+pub const SYNTAX_THEMES: [&str; 7] = [
+    "base16-ocean.dark",
+    "base16-eighties.dark",
+    "base16-mocha.dark",
+    "base16-ocean.light",
+    "InspiredGitHub",
+    "Solarized (dark)",
+    "Solarized (light)",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    pub dark: bool,
+    // one of SYNTAX_THEMES
+    pub syntax: String,
+    #[serde(default)]
+    pub css: Option<String>,
+}
+
+impl Theme {
+    fn builtin(name: &str, dark: bool, syntax: &str) -> Theme {
+        Theme {
+            name: name.to_string(),
+            dark,
+            syntax: syntax.to_string(),
+            css: None,
+        }
+    }
+
+    pub fn for_mode(mode: &str) -> Theme {
+        if mode == "dark" {
+            Theme::builtin("dark", true, "base16-ocean.dark")
+        } else {
+            Theme::builtin("light", false, "base16-ocean.light")
+        }
+    }
+}
+
+pub fn builtin() -> Vec<Theme> {
+    vec![
+        Theme::for_mode("light"),
+        Theme::for_mode("dark"),
+        Theme::builtin("solarized-light", false, "Solarized (light)"),
+        Theme::builtin("solarized-dark", true, "Solarized (dark)"),
+    ]
+}
+
+// The built-in themes, then the custom ones. A custom theme can't replace a built-in one.
+pub fn all(settings: &Settings) -> Vec<Theme> {
+    let mut themes = builtin();
+    for theme in settings.preview_themes.clone().unwrap_or_default() {
+        if !themes.iter().any(|t| t.name == theme.name) {
+            themes.push(theme);
+        }
+    }
+    themes
+}
+
+// The chosen theme, or the one for the app's mode
+pub fn active(settings: &Settings, mode: &str) -> Theme {
+    settings
+        .preview_theme
+        .as_ref()
+        .and_then(|name| all(settings).into_iter().find(|t| &t.name == name))
+        .unwrap_or_else(|| Theme::for_mode(mode))
+}
+
+// The syntect theme to highlight with, for a theme's syntax, or for light or dark, which previews
+// were rendered with before there were themes. Unknown themes fall back to dark.
+pub fn syntax_theme(syntax: &str) -> &str {
+    match syntax {
+        "light" => "base16-ocean.light",
+        "dark" => "base16-ocean.dark",
+        syntax if SYNTAX_THEMES.contains(&syntax) => syntax,
+        _ => "base16-ocean.dark",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active() {
+        let mut settings = Settings::default();
+        assert_eq!(active(&settings, "dark").syntax, "base16-ocean.dark");
+        assert_eq!(active(&settings, "light").syntax, "base16-ocean.light");
+
+        settings.preview_theme = Some("solarized-dark".to_string());
+        assert_eq!(active(&settings, "light").syntax, "Solarized (dark)");
+
+        let custom = Theme {
+            name: "paper".to_string(),
+            dark: false,
+            syntax: "InspiredGitHub".to_string(),
+            css: Some(".preview { font-family: serif }".to_string()),
+        };
+        let shadowing = Theme {
+            name: "dark".to_string(),
+            ..custom.clone()
+        };
+        settings.preview_themes = Some(vec![custom.clone(), shadowing]);
+        settings.preview_theme = Some("paper".to_string());
+        assert_eq!(active(&settings, "dark"), custom);
+        assert_eq!(all(&settings).len(), builtin().len() + 1);
+
+        // a theme which is gone falls back to the mode's
+        settings.preview_theme = Some("gone".to_string());
+        assert_eq!(active(&settings, "dark").name, "dark");
+    }
+
+    #[test]
+    fn test_syntax_theme() {
+        assert_eq!(syntax_theme("light"), "base16-ocean.light");
+        assert_eq!(syntax_theme("Solarized (light)"), "Solarized (light)");
+        assert_eq!(syntax_theme("not a theme"), "base16-ocean.dark");
+    }
+}
@@ -0,0 +1,81 @@
+use scru128::Scru128Id;
+use serde::Serialize;
+use similar::TextDiff;
+
+// A capture of a source document, and how it changed since the previous capture
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Version {
+    pub id: Scru128Id,
+    pub hash: ssri::Integrity,
+    // unified diff against the previous version; None for the first
+    pub diff: Option<String>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+// Orders the captures by when they were taken and diffs each against the one before it
+pub fn build(mut captures: Vec<(Scru128Id, ssri::Integrity, String)>) -> Vec<Version> {
+    captures.sort_by_key(|(id, _, _)| *id);
+    let mut versions: Vec<Version> = Vec::new();
+    let mut previous: Option<String> = None;
+
+    for (id, hash, content) in captures {
+        let version = match &previous {
+            None => Version {
+                id,
+                hash,
+                diff: None,
+                insertions: 0,
+                deletions: 0,
+            },
+            Some(previous) => {
+                let diff = TextDiff::from_lines(previous.as_str(), content.as_str());
+                let (mut insertions, mut deletions) = (0, 0);
+                for change in diff.iter_all_changes() {
+                    match change.tag() {
+                        similar::ChangeTag::Insert => insertions += 1,
+                        similar::ChangeTag::Delete => deletions += 1,
+                        similar::ChangeTag::Equal => {}
+                    }
+                }
+                Version {
+                    id,
+                    hash,
+                    diff: Some(diff.unified_diff().context_radius(3).to_string()),
+                    insertions,
+                    deletions,
+                }
+            }
+        };
+        versions.push(version);
+        previous = Some(content);
+    }
+    versions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let first = scru128::new();
+        let second = scru128::new();
+        let capture = |id, content: &str| (id, ssri::Integrity::from(content), content.to_string());
+
+        // out of order, to check they're sorted
+        let versions = build(vec![
+            capture(second, "# Title\nupdated body\nfooter\n"),
+            capture(first, "# Title\nbody\nfooter\n"),
+        ]);
+
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].id, first);
+        assert_eq!(versions[0].diff, None);
+        assert_eq!(versions[1].id, second);
+        assert_eq!((versions[1].insertions, versions[1].deletions), (1, 1));
+        let diff = versions[1].diff.as_ref().unwrap();
+        assert!(diff.contains("-body\n"));
+        assert!(diff.contains("+updated body\n"));
+    }
+}
@@ -0,0 +1,47 @@
+// Asks the user to authenticate, with Touch ID or their password, using LocalAuthentication's
+// LAContext.evaluatePolicy(.deviceOwnerAuthentication, ...). The system prompt shows the reason
+// given, and this blocks until the user has answered it.
+
+#[cfg(target_os = "macos")]
+use cocoa::base::{id, nil};
+#[cfg(target_os = "macos")]
+use cocoa::foundation::NSString;
+#[cfg(target_os = "macos")]
+use objc::{class, msg_send, sel, sel_impl};
+
+#[cfg(target_os = "macos")]
+#[link(name = "LocalAuthentication", kind = "framework")]
+extern "C" {}
+
+// LAPolicyDeviceOwnerAuthentication: Touch ID, or the user's password
+#[cfg(target_os = "macos")]
+const DEVICE_OWNER_AUTHENTICATION: i64 = 2;
+
+#[cfg(target_os = "macos")]
+pub fn authenticate(reason: &str) -> bool {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let reply = block::ConcreteBlock::new(move |success: objc::runtime::BOOL, _error: id| {
+        let _ = tx.send(success != objc::runtime::NO);
+    });
+    let reply = reply.copy();
+    unsafe {
+        let context: id = msg_send![class!(LAContext), alloc];
+        let context: id = msg_send![context, init];
+        let reason = NSString::alloc(nil).init_str(reason);
+        let () = msg_send![context, evaluatePolicy: DEVICE_OWNER_AUTHENTICATION
+                                    localizedReason: reason
+                                    reply: &*reply];
+        let authenticated = rx.recv().unwrap_or(false);
+        let () = msg_send![reason, release];
+        let () = msg_send![context, release];
+        if !authenticated {
+            tracing::warn!(name = "touch_id", "not authenticated");
+        }
+        authenticated
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn authenticate(_reason: &str) -> bool {
+    false
+}
@@ -0,0 +1,213 @@
+// The menubar icon's menu: the most recent clips, a toggle to pause capture, and a way back to the
+// main window. The menu is rebuilt from the view whenever it changes.
+
+use scru128::Scru128Id;
+use tauri::{CustomMenuItem, Manager, SystemTrayMenu, SystemTrayMenuItem};
+
+use crate::commands;
+use crate::events;
+use crate::shutdown;
+use crate::spotlight;
+use crate::state::{SharedState, State};
+use crate::usage::Access;
+use crate::view::Item;
+
+const RECENT_COUNT: usize = 5;
+const LABEL_MAX_CHARS: usize = 40;
+const RECENT_PREFIX: &str = "recent:";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Recent {
+    id: Scru128Id,
+    label: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Capture {
+    paused: bool,
+    // the capture backend isn't running, and is being restarted
+    down: bool,
+}
+
+fn capture(state: &State) -> Capture {
+    Capture {
+        paused: state.capture_paused,
+        down: state.capture_down,
+    }
+}
+
+// The most recently touched clips, newest first
+pub fn recent_items(state: &State) -> Vec<&Item> {
+    let mut items: Vec<_> = state
+        .view
+        .items
+        .values()
+        .filter(|item| !item.is_stack && !item.ephemeral)
+        .filter(|item| {
+            item.stack_id
+                .is_some_and(|stack_id| state.view.items.contains_key(&stack_id))
+        })
+        .collect();
+    items.sort_by(|a, b| b.last_touched.cmp(&a.last_touched));
+    items
+}
+
+fn recent(state: &State) -> Vec<Recent> {
+    recent_items(state)
+        .into_iter()
+        .take(RECENT_COUNT)
+        .filter_map(|item| {
+            let meta = state.store.get_content_meta(&item.hash)?;
+            Some(Recent {
+                id: item.id,
+                label: label(&meta.terse),
+            })
+        })
+        .collect()
+}
+
+// A single line, truncated to fit the menu
+fn label(terse: &str) -> String {
+    let line = terse.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() > LABEL_MAX_CHARS {
+        let truncated: String = line.chars().take(LABEL_MAX_CHARS - 1).collect();
+        format!("{}…", truncated)
+    } else {
+        line
+    }
+}
+
+fn menu(version: &str, recent: &[Recent], capture: Capture) -> SystemTrayMenu {
+    let mut menu = SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("".to_string(), "Stacks").disabled())
+        .add_item(CustomMenuItem::new("".to_string(), format!("Version {}", version)).disabled())
+        .add_native_item(SystemTrayMenuItem::Separator);
+
+    if recent.is_empty() {
+        menu = menu.add_item(CustomMenuItem::new("".to_string(), "No Recent Clips").disabled());
+    }
+    for item in recent {
+        menu = menu.add_item(CustomMenuItem::new(
+            format!("{}{}", RECENT_PREFIX, item.id),
+            item.label.clone(),
+        ));
+    }
+
+    let pause = if capture.paused {
+        "Resume Capture"
+    } else {
+        "Pause Capture"
+    };
+    menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+    if capture.down {
+        menu = menu.add_item(
+            CustomMenuItem::new("".to_string(), "Capture Stopped: Restarting...").disabled(),
+        );
+    }
+    menu.add_item(CustomMenuItem::new("toggle-capture".to_string(), pause))
+        .add_item(CustomMenuItem::new("open".to_string(), "Open Stacks"))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new(
+            "check-updates".to_string(),
+            "Check for Updates...",
+        ))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit".to_string(), "Quit"))
+}
+
+pub fn initial_menu(version: &str) -> SystemTrayMenu {
+    menu(version, &[], Capture::default())
+}
+
+fn version(app: &tauri::AppHandle) -> String {
+    app.package_info().version.to_string()
+}
+
+fn set_menu(app: &tauri::AppHandle, recent: &[Recent], capture: Capture) {
+    let menu = menu(&version(app), recent, capture);
+    if let Err(e) = app.tray_handle().set_menu(menu) {
+        tracing::error!(name = "tray", ?e, "failed to set menu");
+    }
+}
+
+// Pauses capture, or resumes it
+pub fn toggle_capture(app: &tauri::AppHandle) {
+    let state = app.state::<SharedState>();
+    let (recent, capture) = state.with_lock(|state| {
+        state.capture_paused = !state.capture_paused;
+        (recent(state), capture(state))
+    });
+    tracing::info!(
+        name = "tray",
+        capture_paused = capture.paused,
+        "toggled capture"
+    );
+    set_menu(app, &recent, capture);
+    events::emit(app, "capture-paused", capture.paused);
+}
+
+// Rebuilds the menu, e.g. when capture stops or starts again
+pub fn refresh(app: &tauri::AppHandle) {
+    let state = app.state::<SharedState>();
+    let (recent, capture) = state.with_read(|state| (recent(state), capture(state)));
+    set_menu(app, &recent, capture);
+}
+
+pub fn handle_click(app: &tauri::AppHandle, id: &str) {
+    let state = app.state::<SharedState>();
+
+    if let Some(source_id) = id.strip_prefix(RECENT_PREFIX) {
+        if let Ok(source_id) = source_id.parse::<Scru128Id>() {
+            state.with_lock(|state| {
+                if commands::copy_to_clipboard(state, &source_id).is_some() {
+                    state.record_usage(source_id, Access::Copy);
+                }
+            });
+        }
+        return;
+    }
+
+    match id {
+        "toggle-capture" => toggle_capture(app),
+        "open" => {
+            spotlight::show(&spotlight::window(app)).unwrap();
+        }
+        "check-updates" => {
+            app.trigger_global("tauri://update", None);
+        }
+        "quit" => shutdown::exit(app),
+        _ => {}
+    }
+}
+
+// Rebuilds the menu when the recent clips change
+pub fn spawn(app: tauri::AppHandle, state: SharedState) {
+    let mut receiver = state.with_lock(|state| state.packet_sender.subscribe());
+    std::thread::spawn(move || {
+        let mut previous: Option<(Vec<Recent>, Capture)> = None;
+        loop {
+            let current = state.with_lock(|state| (recent(state), capture(state)));
+            if previous.as_ref() != Some(&current) {
+                set_menu(&app, &current.0, current.1);
+                previous = Some(current);
+            }
+            if tauri::async_runtime::block_on(receiver.changed()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label("  hello\n  world "), "hello world");
+        let long = "x".repeat(100);
+        let truncated = label(&long);
+        assert_eq!(truncated.chars().count(), LABEL_MAX_CHARS);
+        assert!(truncated.ends_with('…'));
+    }
+}
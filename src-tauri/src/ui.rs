@@ -7,6 +7,17 @@ use tracing::info;
 
 pub use crate::store::{MimeType, Store};
 
+use crate::calc;
+use crate::capture::Origin;
+use crate::color;
+use crate::contact;
+use crate::contact::Contact;
+use crate::file_ref;
+use crate::ingest::ShellRun;
+use crate::links::LinkStatus;
+use crate::store::{ContentStats, Settings};
+use crate::theme;
+use crate::theme::Theme;
 use crate::util;
 use crate::view;
 
@@ -14,6 +25,8 @@ use crate::view;
 pub struct Item {
     pub id: Scru128Id,
     pub stack_id: Option<Scru128Id>,
+    // stacks can be nested, so a stack_id doesn't mean the item isn't a stack
+    pub is_stack: bool,
     pub name: String,
     pub last_touched: Scru128Id,
     pub touched: Vec<Scru128Id>,
@@ -21,7 +34,17 @@ pub struct Item {
     pub ephemeral: bool,
     pub ordered: bool,
     pub locked: bool,
+    pub read_only: bool,
     pub cross_stream: bool,
+    pub link_status: Option<LinkStatus>,
+    pub contact: Option<Contact>,
+    pub note: Option<String>,
+    // for commands ingested from the shell: their exit code and working directory
+    pub shell_run: Option<ShellRun>,
+    // for selections pushed from the browser extension: the page's URL and title
+    pub origin: Option<Origin>,
+    // the other stacks the item is linked into
+    pub linked: Vec<Scru128Id>,
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -36,6 +59,9 @@ pub struct Nav {
     pub root: Option<Layer>,
     pub sub: Option<Layer>,
     pub undo: Option<Item>,
+    pub calc: Option<String>,
+    // the stacks leading to the focused item, starting from the root
+    pub breadcrumbs: Vec<Item>,
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -45,7 +71,15 @@ pub struct UI {
     pub matches: Option<HashSet<ssri::Integrity>>,
     pub view: view::View,
     pub theme_mode: String,
+    // the theme previews are rendered with: see theme::active
+    pub theme: Theme,
     pub is_visible: bool,
+    // the result of evaluating the current filter as a calculation, if it looks like one
+    pub calc: Option<String>,
+    pub show_archived: bool,
+    // applied to the view: see usage
+    #[serde(skip)]
+    pub rank: Option<HashMap<Scru128Id, f64>>,
 }
 
 impl UI {
@@ -54,9 +88,23 @@ impl UI {
             focused: None,
             last_selected: HashMap::new(),
             matches: None,
-            view: v.clone(),
+            view: v.without_archived(),
             theme_mode: "light".to_string(),
+            theme: Theme::for_mode("light"),
             is_visible: false,
+            calc: None,
+            show_archived: false,
+            rank: None,
+        }
+    }
+
+    // A fresh UI for the view, e.g. after the log was rewritten, which keeps the theme
+    pub fn renew(&self, v: &view::View) -> Self {
+        Self {
+            theme_mode: self.theme_mode.clone(),
+            theme: self.theme.clone(),
+            rank: self.rank.clone(),
+            ..Self::new(v)
         }
     }
 
@@ -64,28 +112,55 @@ impl UI {
         self.focused = None;
         self.last_selected = HashMap::new();
         self.matches = None;
-        self.view = v;
+        self.calc = None;
+        self.show_archived = false;
+        self.refresh_view(&v);
     }
 
     pub fn set_filter(&mut self, store: &Store, v: &view::View, filter: &str, content_type: &str) {
         self.matches = if !filter.is_empty() || (content_type != "All" && !content_type.is_empty())
         {
-            let matches = store.query(filter, content_type);
+            let mut matches = store.query(filter, content_type);
+            // items whose note, or origin, matches the filter
+            if !filter.is_empty() {
+                let mut ids = store.notes_query(filter);
+                ids.extend(store.origins_query(filter));
+                matches.extend(
+                    ids.iter()
+                        .filter_map(|id| v.items.get(id))
+                        .filter_map(|item| store.get_content_meta(&item.hash))
+                        .filter(|meta| store.is_content_type(meta, content_type))
+                        .map(|meta| meta.hash),
+                );
+            }
             Some(matches)
         } else {
             None
         };
+        self.calc = calc::evaluate(filter);
         self.refresh_view(v);
     }
 
     pub fn refresh_view(&mut self, v: &view::View) {
+        // archived stacks are hidden from navigation, and search, unless explicitly requested
+        let mut v = if self.show_archived {
+            v.clone()
+        } else {
+            v.without_archived()
+        };
+        v.rank = self.rank.clone();
         self.view = if let Some(matches) = &self.matches {
             v.filter(matches)
         } else {
-            v.clone()
+            v
         }
     }
 
+    pub fn set_show_archived(&mut self, v: &view::View, show_archived: bool) {
+        self.show_archived = show_archived;
+        self.refresh_view(v);
+    }
+
     pub fn select(&mut self, focus: Option<view::Focus>) {
         if let Some(focus) = focus.as_ref() {
             if let Some(stack_id) = focus.item.stack_id {
@@ -107,7 +182,7 @@ impl UI {
     pub fn select_down_stack(&mut self) {
         let focused = self.focused.clone().or(self.view.first());
         if let Some(focused) = focused {
-            if focused.item.stack_id.is_none() {
+            if focused.item.is_stack {
                 self.select(self.view.get_best_focus_next(&Some(focused)));
                 return;
             }
@@ -122,7 +197,7 @@ impl UI {
     pub fn select_up_stack(&mut self) {
         let focused = self.focused.clone().or(self.view.first());
         if let Some(focused) = focused {
-            if focused.item.stack_id.is_none() {
+            if focused.item.is_stack {
                 self.select(self.view.get_best_focus_prev(&Some(focused)));
                 return;
             }
@@ -173,10 +248,19 @@ impl UI {
                 root: None,
                 sub: None,
                 undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
+                calc: self.calc.clone(),
+                breadcrumbs: Vec::new(),
             };
         }
         let focused = focused.unwrap();
 
+        let breadcrumbs: Vec<_> = self
+            .view
+            .breadcrumbs(&focused.item)
+            .iter()
+            .map(|item| with_meta(store, item))
+            .collect();
+
         // the sub layer is focused
         if let Some(stack_id) = focused.item.stack_id {
             let items: Vec<_> = self
@@ -188,15 +272,18 @@ impl UI {
                 .collect();
             let selected = with_meta(store, &focused.item);
 
+            let stack = self.view.items.get(&stack_id).unwrap();
+
             Nav {
                 root: Some(Layer {
+                    // for nested stacks, this is the parent stack's layer
                     items: self
                         .view
-                        .root()
+                        .get_peers(stack)
                         .iter()
                         .map(|item| with_meta(store, item))
                         .collect(),
-                    selected: with_meta(store, self.view.items.get(&stack_id).unwrap()),
+                    selected: with_meta(store, stack),
                     is_focus: false,
                 }),
                 sub: Some(Layer {
@@ -205,6 +292,8 @@ impl UI {
                     is_focus: true,
                 }),
                 undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
+                calc: self.calc.clone(),
+                breadcrumbs,
             }
         } else {
             // the root layer is focused
@@ -246,16 +335,43 @@ impl UI {
                 }),
                 sub,
                 undo: self.view.undo.as_ref().map(|item| with_meta(store, item)),
+                calc: self.calc.clone(),
+                breadcrumbs,
             }
         }
     }
 }
 
+// A stack, with its meta, and the stacks nested within it
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct StackTree {
+    pub item: Item,
+    pub children: Vec<StackTree>,
+}
+
+pub fn stack_tree(store: &Store, v: &view::View) -> Vec<StackTree> {
+    fn with_children(store: &Store, v: &view::View, node: &view::StackNode) -> Option<StackTree> {
+        Some(StackTree {
+            item: with_meta(store, v.items.get(&node.id)?),
+            children: node
+                .children
+                .iter()
+                .filter_map(|child| with_children(store, v, child))
+                .collect(),
+        })
+    }
+    v.stack_tree()
+        .iter()
+        .filter_map(|node| with_children(store, v, node))
+        .collect()
+}
+
 pub fn with_meta(store: &Store, item: &view::Item) -> Item {
     let content_meta = store.get_content_meta(&item.hash).unwrap();
     Item {
         id: item.id,
         stack_id: item.stack_id,
+        is_stack: item.is_stack,
         name: content_meta.terse.clone(),
         last_touched: item.last_touched,
         touched: item.touched.clone(),
@@ -263,7 +379,14 @@ pub fn with_meta(store: &Store, item: &view::Item) -> Item {
         ephemeral: item.ephemeral,
         ordered: item.ordered,
         locked: item.locked,
+        read_only: item.read_only,
         cross_stream: item.cross_stream,
+        link_status: store.link_status_get(&item.hash),
+        contact: store.contact_get(&item.hash),
+        note: store.note_get(&item.id),
+        shell_run: store.shell_run_get(&item.id),
+        origin: store.origin_get(&item.id),
+        linked: item.linked.clone(),
     }
 }
 
@@ -271,7 +394,7 @@ use comrak::plugins::syntect::SyntectAdapter;
 use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
 
 pub fn markdown_to_html(theme_mode: &str, input: &Vec<u8>) -> String {
-    let adapter = SyntectAdapter::new(&format!("base16-ocean.{}", theme_mode));
+    let adapter = SyntectAdapter::new(theme::syntax_theme(theme_mode));
 
     let mut options = ComrakOptions::default();
     options.extension.tasklist = true;
@@ -306,7 +429,7 @@ pub fn code_to_html(theme_mode: &str, input: &Vec<u8>, ext: &str) -> String {
     let ts = ThemeSet::load_defaults();
     let syntax = ps.find_syntax_by_extension(ext).unwrap();
     info!("Theme mode: {}", theme_mode);
-    let theme = &ts.themes[&format!("base16-ocean.{}", theme_mode)];
+    let theme = &ts.themes[theme::syntax_theme(theme_mode)];
     let input_str = String::from_utf8(input.clone()).unwrap();
     let highlighted_html = highlighted_html_for_string(&input_str, &ps, syntax, theme);
     highlighted_html.unwrap()
@@ -314,6 +437,295 @@ pub fn code_to_html(theme_mode: &str, input: &Vec<u8>, ext: &str) -> String {
 
 use maud::html;
 
+pub const DEFAULT_PREVIEW_MAX_LINES: usize = 2000;
+pub const DEFAULT_PREVIEW_MAX_BYTES: usize = 256 * 1024;
+
+// Bounds how much of a text item is rendered, so huge items don't bog down the webview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviewLimits {
+    pub max_lines: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for PreviewLimits {
+    fn default() -> Self {
+        Self {
+            max_lines: DEFAULT_PREVIEW_MAX_LINES,
+            max_bytes: DEFAULT_PREVIEW_MAX_BYTES,
+        }
+    }
+}
+
+impl PreviewLimits {
+    pub fn from_settings(settings: Option<Settings>) -> Self {
+        let settings = settings.unwrap_or_default();
+        Self {
+            max_lines: settings
+                .preview_max_lines
+                .unwrap_or(DEFAULT_PREVIEW_MAX_LINES)
+                .max(1),
+            max_bytes: settings
+                .preview_max_bytes
+                .unwrap_or(DEFAULT_PREVIEW_MAX_BYTES)
+                .max(1),
+        }
+    }
+
+    // Returns the leading part of the text which fits, and whether anything was cut
+    pub fn truncate<'a>(&self, data: &'a [u8]) -> (&'a [u8], bool) {
+        let mut end = data.len().min(self.max_bytes);
+        // don't split a utf-8 sequence
+        while end < data.len() && end > 0 && data[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        if let Some((pos, _)) = data[..end]
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'\n')
+            .nth(self.max_lines - 1)
+        {
+            end = pos;
+        }
+        (&data[..end], end < data.len())
+    }
+}
+
+// Truncates text content to the limits, before it's passed to generate_preview
+pub fn truncate_content(
+    content: Option<Vec<u8>>,
+    mime_type: &MimeType,
+    limits: &PreviewLimits,
+) -> (Option<Vec<u8>>, bool) {
+    match content {
+        Some(data) if *mime_type == MimeType::TextPlain => {
+            let (head, truncated) = limits.truncate(&data);
+            if truncated {
+                (Some(head.to_vec()), true)
+            } else {
+                (Some(data), false)
+            }
+        }
+        content => (content, false),
+    }
+}
+
+// File extensions for the content types which are highlighted as source code
+const CODE_EXTENSIONS: [(&str, &str); 30] = [
+    ("C++", "cpp"),
+    ("C", "c"),
+    ("CSS", "css"),
+    ("Diff", "diff"),
+    ("Erlang", "erl"),
+    ("Go", "go"),
+    ("Graphviz", "dot"),
+    ("HTML", "html"),
+    ("Haskell", "hs"),
+    ("Java", "java"),
+    ("JSON", "json"),
+    ("JavaScript", "js"),
+    ("Lisp", "lisp"),
+    ("Lua", "lua"),
+    ("Makefile", "make"),
+    ("MATLAB", "matlab"),
+    ("OCaml", "ml"),
+    ("Objective-C", "m"),
+    ("PHP", "php"),
+    ("Perl", "pl"),
+    ("Python", "py"),
+    ("R", "r"),
+    ("Regular Expression", "re"),
+    ("reStructuredText", "rst"),
+    ("Ruby", "rb"),
+    ("Rust", "rs"),
+    ("Shell", "sh"),
+    ("SQL", "sql"),
+    ("XML", "xml"),
+    ("YAML", "yaml"),
+];
+
+pub fn file_extension(content_type: &str) -> Option<&'static str> {
+    CODE_EXTENSIONS
+        .iter()
+        .find(|(name, _)| *name == content_type)
+        .map(|(_, ext)| *ext)
+}
+
+// plain text previews show at most this many characters
+const TEXT_PREVIEW_MAX_CHARS: usize = 2048;
+
+// bumped when previews are rendered differently, so previews cached on disk aren't reused
+const PREVIEW_VERSION: u32 = 2;
+
+// the rest of a large item is rendered in chunks of about this size: see render_chunk
+pub const FULL_PREVIEW_CHUNK_BYTES: usize = 256 * 1024;
+
+fn is_plain_text(mime_type: &MimeType, content_type: &str) -> bool {
+    *mime_type == MimeType::TextPlain
+        && content_type != "Markdown"
+        && file_extension(content_type).is_none()
+}
+
+// How many bytes of the content its preview shows: text is cut to the limits, and plain text
+// previews stop after TEXT_PREVIEW_MAX_CHARS
+pub fn preview_shown(
+    content: &[u8],
+    mime_type: &MimeType,
+    content_type: &str,
+    limits: &PreviewLimits,
+) -> usize {
+    if *mime_type != MimeType::TextPlain {
+        return content.len();
+    }
+    let (head, _) = limits.truncate(content);
+    if !is_plain_text(mime_type, content_type) {
+        return head.len();
+    }
+    let Ok(text) = std::str::from_utf8(head) else {
+        return head.len();
+    };
+    text.char_indices()
+        .nth(TEXT_PREVIEW_MAX_CHARS)
+        .map(|(i, _)| i)
+        .unwrap_or(head.len())
+}
+
+// Says how much of a large item its preview shows
+pub fn truncation_note(shown: &[u8], stats: &ContentStats) -> String {
+    let lines = shown.iter().filter(|b| **b == b'\n').count() + 1;
+    let note = html! {
+        p.truncated {
+            "Showing " (file_ref::format_size(shown.len() as u64))
+            " of " (file_ref::format_size(stats.bytes))
+            ", " (lines.min(stats.lines)) " of " (stats.lines) " lines"
+        }
+    };
+    note.into_string()
+}
+
+// Splits the content into chunks of about size bytes, at line ends where there are any, so each
+// chunk can be rendered on its own
+pub fn chunks(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(size.max(1));
+        if end < rest.len() {
+            if let Some(newline) = rest[..end].iter().rposition(|b| *b == b'\n') {
+                end = newline + 1;
+            } else {
+                // don't split a utf-8 sequence
+                while end > 1 && rest[end] & 0xC0 == 0x80 {
+                    end -= 1;
+                }
+            }
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+// Renders a chunk of a large item: highlighted if it's source code, otherwise as plain text, as
+// Markdown split into chunks may not render as it would whole
+pub fn render_chunk(theme_mode: &str, data: &[u8], content_type: &str) -> String {
+    match file_extension(content_type) {
+        Some(ext) => code_to_html(theme_mode, &data.to_vec(), ext),
+        None => {
+            let text = html! { (String::from_utf8_lossy(data)) };
+            text_preview(&text.into_string(), false)
+        }
+    }
+}
+
+fn text_preview(escaped: &str, ephemeral: bool) -> String {
+    let pre = html! {
+        pre.("scroll-me")[ephemeral] style="margin: 0; white-space: pre-wrap; overflow-x: hidden" {
+            (maud::PreEscaped(escaped))
+        }
+    };
+    pre.into_string()
+}
+
+// Names what a cached preview depends on besides its content: the theme, the content type, and
+// the limits it was cut to, or None if it wasn't
+pub fn preview_key(theme_mode: &str, content_type: &str, limits: Option<&PreviewLimits>) -> String {
+    let limits = limits
+        .map(|limits| format!("{}x{}", limits.max_lines, limits.max_bytes))
+        .unwrap_or("full".to_string());
+    let key = format!(
+        "{}:{}:{}:{}",
+        PREVIEW_VERSION, theme_mode, content_type, limits
+    );
+    Integrity::from(key).to_hex().1[..16].to_string()
+}
+
+// Previews content as it's streamed in, without rendering all of it for each chunk. Only the head
+// of the content, up to the limits, is previewed, so once the limits are reached the preview stops
+// changing, and isn't rendered again. Until then, plain text only has its new tail escaped.
+pub struct StreamPreview {
+    limits: PreviewLimits,
+    // the syntax of the theme it's rendered with
+    theme_mode: String,
+    // the bytes of the content the preview was rendered from
+    rendered: usize,
+    // plain text escaped so far, and how many characters it holds
+    escaped: String,
+    chars: usize,
+    preview: String,
+}
+
+impl StreamPreview {
+    pub fn new(limits: PreviewLimits, theme_mode: &str) -> Self {
+        Self {
+            limits,
+            theme_mode: theme_mode.to_string(),
+            rendered: 0,
+            escaped: String::new(),
+            chars: 0,
+            preview: String::new(),
+        }
+    }
+
+    // Returns the preview of the content streamed so far, and whether it was cut to the limits
+    pub fn update(
+        &mut self,
+        content: &[u8],
+        mime_type: &MimeType,
+        content_type: &String,
+    ) -> (&str, bool) {
+        let (head, truncated) = self.limits.truncate(content);
+        if head.len() == self.rendered && !self.preview.is_empty() {
+            return (&self.preview, truncated);
+        }
+        if *mime_type == MimeType::TextPlain && content_type == "Text" {
+            // a chunk can end mid character, which is left for the next one
+            let tail = &head[self.rendered..];
+            let valid = match std::str::from_utf8(tail) {
+                Ok(tail) => tail,
+                Err(e) => std::str::from_utf8(&tail[..e.valid_up_to()]).unwrap(),
+            };
+            let remaining = TEXT_PREVIEW_MAX_CHARS - self.chars;
+            let text: String = valid.chars().take(remaining).collect();
+            self.chars += text.chars().count();
+            self.escaped.push_str(&html! { (text) }.into_string());
+            self.rendered += valid.len();
+            self.preview = text_preview(&self.escaped, true);
+        } else {
+            self.preview = generate_preview(
+                &self.theme_mode,
+                &Some(head.to_vec()),
+                mime_type,
+                content_type,
+                true,
+            );
+            self.rendered = head.len();
+        }
+        (&self.preview, truncated)
+    }
+}
+
+// theme_mode is the syntax of the theme previews are rendered with: see theme::syntax_theme
 #[tracing::instrument(
     skip(content)
     fields(
@@ -330,51 +742,84 @@ pub fn generate_preview(
     content_type: &String,
     ephemeral: bool,
 ) -> String {
-    let file_extensions: HashMap<&str, &str> = [
-        ("C++", "cpp"),
-        ("C", "c"),
-        ("CSS", "css"),
-        ("Diff", "diff"),
-        ("Erlang", "erl"),
-        ("Go", "go"),
-        ("Graphviz", "dot"),
-        ("HTML", "html"),
-        ("Haskell", "hs"),
-        ("Java", "java"),
-        ("JSON", "json"),
-        ("JavaScript", "js"),
-        ("Lisp", "lisp"),
-        ("Lua", "lua"),
-        ("Makefile", "make"),
-        ("MATLAB", "matlab"),
-        ("OCaml", "ml"),
-        ("Objective-C", "m"),
-        ("PHP", "php"),
-        ("Perl", "pl"),
-        ("Python", "py"),
-        ("R", "r"),
-        ("Regular Expression", "re"),
-        ("reStructuredText", "rst"),
-        ("Ruby", "rb"),
-        ("Rust", "rs"),
-        ("Shell", "sh"),
-        ("SQL", "sql"),
-        ("XML", "xml"),
-        ("YAML", "yaml"),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
     match content {
         None => "loading...".to_string(),
         Some(data) => {
-            if *mime_type == MimeType::ImagePng {
-                let img_data = format!("data:image/png;base64,{}", util::b64encode(data));
+            let color = if content_type == "Color" {
+                color::parse(&String::from_utf8_lossy(data))
+            } else {
+                None
+            };
+            let contact = if content_type == contact::CONTENT_TYPE {
+                contact::parse(&String::from_utf8_lossy(data))
+            } else {
+                None
+            };
+            let file_ref = if *mime_type == MimeType::FileRef {
+                file_ref::parse(data)
+            } else {
+                None
+            };
+
+            if mime_type.is_image() {
+                let img_data = format!(
+                    "data:{};base64,{}",
+                    mime_type.as_str(),
+                    util::b64encode(data)
+                );
                 let img = html! {
                     img src=(img_data) style="opacity: 0.95; border-radius: 0.5rem; max-height: 100%; height: auto; width: auto; object-fit: contain";
                 };
                 img.into_string()
+            } else if let Some(file_ref) = file_ref {
+                let exists = std::path::Path::new(&file_ref.path).exists();
+                let div = html! {
+                    div.preview.file {
+                        table {
+                            tr { td { "Name" } td { code { (file_ref.name) } } }
+                            tr { td { "Path" } td { code { (file_ref.path) } } }
+                            tr { td { "Size" } td { code { (file_ref::format_size(file_ref.size)) } } }
+                        }
+                        @if !exists {
+                            p { "The file has been moved or deleted." }
+                        }
+                    }
+                };
+                div.into_string()
+            } else if let Some(color) = color {
+                let swatch = format!(
+                    "background-color: {}; height: 8rem; border-radius: 0.5rem; margin-bottom: 1rem",
+                    color.to_hex()
+                );
+                let div = html! {
+                    div.preview.color {
+                        div style=(swatch) {}
+                        table {
+                            tr { td { "HEX" } td { code { (color.to_hex()) } } }
+                            tr { td { "RGB" } td { code { (color.to_rgb()) } } }
+                            tr { td { "HSL" } td { code { (color.to_hsl()) } } }
+                        }
+                    }
+                };
+                div.into_string()
+            } else if let Some(contact) = contact {
+                let fields = [
+                    ("Name", contact.name),
+                    ("Email", contact.email),
+                    ("Phone", contact.phone),
+                ];
+                let div = html! {
+                    div.preview.contact {
+                        table {
+                            @for (label, value) in fields.iter() {
+                                @if let Some(value) = value {
+                                    tr { td { (label) } td { code { (value) } } }
+                                }
+                            }
+                        }
+                    }
+                };
+                div.into_string()
             } else if content_type == "Markdown" {
                 let md_html = markdown_to_html(theme_mode, data);
                 let md_html = maud::PreEscaped(md_html);
@@ -384,7 +829,7 @@ pub fn generate_preview(
                     }
                 };
                 div.into_string()
-            } else if let Some(ext) = file_extensions.get(content_type.as_str()) {
+            } else if let Some(ext) = file_extension(content_type) {
                 let html = code_to_html(theme_mode, data, ext);
                 let html = maud::PreEscaped(html);
                 let div = html! {
@@ -395,12 +840,8 @@ pub fn generate_preview(
                 div.into_string()
             } else {
                 let data = String::from_utf8(data.clone()).unwrap();
-                let pre = html! {
-                    pre.("scroll-me")[ephemeral] style="margin: 0; white-space: pre-wrap; overflow-x: hidden" {
-                        (data.chars().take(2048).collect::<String>())
-                    }
-                };
-                pre.into_string()
+                let text: String = data.chars().take(TEXT_PREVIEW_MAX_CHARS).collect();
+                text_preview(&html! { (text) }.into_string(), ephemeral)
             }
         }
     }
@@ -1,7 +1,11 @@
+use crate::publish;
 use crate::state::State;
 use crate::store::{MimeType, StackLockStatus};
 
-use crate::ui::Nav;
+use crate::ui::{
+    chunks, generate_preview, preview_key, preview_shown, truncate_content, truncation_note, Nav,
+    PreviewLimits, StreamPreview,
+};
 
 type NavExpected<'a> = (
     Option<(&'a str, Vec<&'a str>, bool)>, // root
@@ -74,7 +78,7 @@ fn test_ui_render() {
     let dir = tempfile::tempdir().unwrap();
     let path = dir.path().to_str().unwrap();
 
-    let (sender, _receiver) = std::sync::mpsc::channel();
+    let (sender, _receiver) = publish::channel();
     let mut state = State::new(path, sender);
 
     let stack_ids: Vec<_> = (1..=3)
@@ -105,7 +109,8 @@ fn test_ui_render() {
     assert_nav_as_expected!(&state.ui.render(&state.store), (None, None));
 
     // post initial merge state
-    state.store.scan().for_each(|p| state.merge(&p));
+    let packets: Vec<_> = state.store.scan().collect();
+    packets.iter().for_each(|p| state.merge(p));
     assert_nav_as_expected!(
         &state.ui.render(&state.store),
         (
@@ -356,3 +361,137 @@ fn test_ui_render() {
     state.nav_set_filter("FOOBAR", "");
     assert_nav_as_expected!(&state.ui.render(&state.store), (None, None));
 }
+
+#[test]
+fn test_ui_nested_stacks() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = publish::channel();
+    let mut state = State::new(path, sender);
+
+    let _stack_3 = state.store.add_stack(b"Stack 3", StackLockStatus::Unlocked);
+    let stack_1 = state.store.add_stack(b"Stack 1", StackLockStatus::Unlocked);
+    let _ = state.store.add(b"Item 1", MimeType::TextPlain, stack_1.id);
+    let stack_2 = state.store.add_stack(b"Stack 2", StackLockStatus::Unlocked);
+    let _ = state.store.nest_stack(stack_2.id, Some(stack_1.id));
+    let packets: Vec<_> = state.store.scan().collect();
+    packets.iter().for_each(|p| state.merge(p));
+
+    // the nested stack is a stack, though it's in one
+    state.nav_select(&stack_2.id);
+    let nav = state.ui.render(&state.store);
+    assert!(nav.sub.as_ref().unwrap().selected.is_stack);
+
+    // user press: opt + down # moves on within the parent stack, not past it
+    state.ui.select_down_stack();
+    assert_nav_as_expected!(
+        &state.ui.render(&state.store),
+        (
+            Some(("Stack 1", vec!["Stack 1", "Stack 3"], false)),
+            Some(("Item 1", vec!["Stack 2", "Item 1"], true)),
+        ),
+    );
+}
+
+#[test]
+fn test_preview_limits() {
+    let limits = PreviewLimits {
+        max_lines: 2,
+        max_bytes: 10,
+    };
+    assert_eq!(
+        limits.truncate(b"one\ntwo\nthree"),
+        (&b"one\ntwo"[..], true)
+    );
+    assert_eq!(limits.truncate(b"short"), (&b"short"[..], false));
+    // cut before the multi-byte é, rather than through it
+    assert_eq!(
+        limits.truncate("aaaaaaaaaé".as_bytes()),
+        (&b"aaaaaaaaa"[..], true)
+    );
+
+    let image = Some(vec![b'\n'; 100]);
+    assert_eq!(
+        truncate_content(image.clone(), &MimeType::ImagePng, &limits),
+        (image, false)
+    );
+}
+
+#[test]
+fn test_stream_preview() {
+    let limits = PreviewLimits {
+        max_lines: 3,
+        max_bytes: 1024,
+    };
+    let mut previewer = StreamPreview::new(limits, "dark");
+    let text = "<b>café</b>\nline two\nline three\nline four".as_bytes();
+    let mut streamed = Vec::new();
+    // the first chunk ends in the middle of the é
+    for chunk in [&text[..7], &text[7..20], &text[20..]] {
+        streamed.extend_from_slice(chunk);
+        previewer.update(&streamed, &MimeType::TextPlain, &"Text".to_string());
+    }
+    let (preview, truncated) =
+        previewer.update(&streamed, &MimeType::TextPlain, &"Text".to_string());
+    let (head, _) = limits.truncate(text);
+    let expected = generate_preview(
+        "dark",
+        &Some(head.to_vec()),
+        &MimeType::TextPlain,
+        &"Text".to_string(),
+        true,
+    );
+    assert_eq!(preview, expected);
+    assert!(preview.contains("&lt;b&gt;café"));
+    assert!(truncated);
+
+    let key = preview_key("dark", "Rust", Some(&limits));
+    assert_eq!(key, preview_key("dark", "Rust", Some(&limits)));
+    assert_ne!(key, preview_key("light", "Rust", Some(&limits)));
+    assert_ne!(key, preview_key("dark", "Rust", None));
+}
+
+#[test]
+fn test_preview_shown() {
+    let limits = PreviewLimits {
+        max_lines: 2000,
+        max_bytes: 10_000,
+    };
+    // plain text previews stop after 2048 characters
+    let text = "é".repeat(3000);
+    let shown = preview_shown(text.as_bytes(), &MimeType::TextPlain, "Text", &limits);
+    assert_eq!(shown, 2048 * 2);
+    // source code is cut to the limits
+    let code = "x".repeat(12_000);
+    assert_eq!(
+        preview_shown(code.as_bytes(), &MimeType::TextPlain, "Rust", &limits),
+        10_000
+    );
+    assert_eq!(
+        preview_shown(b"short", &MimeType::TextPlain, "Text", &limits),
+        5
+    );
+    assert_eq!(
+        preview_shown(&[0; 12_000], &MimeType::ImagePng, "Image", &limits),
+        12_000
+    );
+
+    let stats = crate::store::analyze(b"one\ntwo\nthree\nfour", &MimeType::TextPlain);
+    assert_eq!(
+        truncation_note(b"one\ntwo", &stats),
+        "<p class=\"truncated\">Showing 7 bytes of 18 bytes, 2 of 4 lines</p>"
+    );
+}
+
+#[test]
+fn test_chunks() {
+    assert_eq!(
+        chunks(b"one\ntwo\nthree\n", 9),
+        vec![&b"one\ntwo\n"[..], &b"three\n"[..]]
+    );
+    // without line ends, chunks are cut between characters
+    let text = "ééé".as_bytes();
+    assert_eq!(chunks(text, 3), vec![&text[..2], &text[2..4], &text[4..]]);
+    assert!(chunks(b"", 3).is_empty());
+}
@@ -0,0 +1,205 @@
+// Content posted to the HTTP server which isn't text: images, and other files, sent as the body
+// with their Content-Type, or as multipart form uploads. Images are stored as images. Other binary
+// content can't be stored as an item, so it's written to the store's uploads directory, and added
+// as a reference to the file.
+
+use std::path::{Path, PathBuf};
+
+use scru128::Scru128Id;
+
+use crate::clipboard;
+use crate::file_ref::FileRef;
+use crate::state::SharedState;
+use crate::store;
+use crate::store::MimeType;
+
+pub const UPLOADS_DIR: &str = "uploads";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stored {
+    Content(MimeType),
+    File,
+}
+
+// Uploads are limited to max_item_bytes, like clips: None is no limit
+pub fn max_bytes(store: &store::Store) -> Option<usize> {
+    let max = store
+        .settings_get()
+        .and_then(|settings| settings.max_item_bytes)
+        .unwrap_or(clipboard::DEFAULT_MAX_ITEM_BYTES);
+    (max > 0).then_some(max as usize)
+}
+
+// Whether a request with the Content-Type is streamed in as text. curl sends bodies as form
+// encoded unless told otherwise.
+pub fn is_text(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    mime.starts_with("text/")
+        || mime == "application/x-www-form-urlencoded"
+        || mime == "application/json"
+}
+
+// The content's signature is trusted over the type it was declared as
+pub fn classify(content: &[u8], declared: Option<&str>) -> Stored {
+    if let Some(image) =
+        infer::get(content).and_then(|kind| MimeType::from_image_mime(kind.mime_type()))
+    {
+        return Stored::Content(image);
+    }
+    let declared_text = declared.is_some_and(is_text);
+    if declared_text || (std::str::from_utf8(content).is_ok() && !content.contains(&0)) {
+        return Stored::Content(MimeType::TextPlain);
+    }
+    Stored::File
+}
+
+// The content to store for the upload, writing it to the uploads directory if it's a file. Empty
+// uploads are ignored. This doesn't need the state, so large uploads are written without its lock.
+pub fn prepare(
+    store_path: &Path,
+    content: &[u8],
+    declared: Option<&str>,
+    file_name: Option<&str>,
+) -> Result<Option<(Vec<u8>, MimeType)>, String> {
+    if content.is_empty() {
+        return Ok(None);
+    }
+    let prepared = match classify(content, declared) {
+        Stored::Content(mime_type) => (content.to_vec(), mime_type),
+        Stored::File => {
+            // only the name is kept from the client's file name, never its directories
+            let name = file_name
+                .and_then(|name| Path::new(name).file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or("upload".to_string());
+            let dir = store_path.join(UPLOADS_DIR).join(scru128::new_string());
+            std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            let path = dir.join(name);
+            std::fs::write(&path, content).map_err(|e| e.to_string())?;
+            let file_ref = FileRef::resolve(&path.to_string_lossy())
+                .ok_or(format!("couldn't resolve {}", path.display()))?;
+            (file_ref.to_bytes(), MimeType::FileRef)
+        }
+    };
+    Ok(Some(prepared))
+}
+
+// Adds the upload to the current stack, returning the new item's id. The upload, and its content,
+// are written on the blocking pool: the state is only locked to add the item.
+pub async fn add(
+    state: &SharedState,
+    content: Vec<u8>,
+    declared: Option<String>,
+    file_name: Option<String>,
+) -> Result<Option<Scru128Id>, String> {
+    let (store_path, cache_path) = state.with_read(|state| {
+        (
+            PathBuf::from(&state.store.path),
+            state.store.cache_path.clone(),
+        )
+    });
+    let prepared = tauri::async_runtime::spawn_blocking(move || {
+        prepare(
+            &store_path,
+            &content,
+            declared.as_deref(),
+            file_name.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    let Some((content, mime_type)) = prepared else {
+        return Ok(None);
+    };
+    let (hash, content) = store::write_blob(cache_path, content).await?;
+    let id = state.with_lock(|state| {
+        let stack_id = state.get_curr_stack();
+        let packet = state
+            .store
+            .add_written(scru128::new(), hash, &content, mime_type, stack_id);
+        state.merge(&packet);
+        packet.id
+    });
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_classify() {
+        assert!(is_text("text/plain; charset=utf-8"));
+        assert!(is_text("application/x-www-form-urlencoded"));
+        assert!(!is_text("application/octet-stream"));
+
+        assert_eq!(
+            classify(PNG, Some("application/octet-stream")),
+            Stored::Content(MimeType::ImagePng)
+        );
+        assert_eq!(classify(PNG, None), Stored::Content(MimeType::ImagePng));
+        assert_eq!(
+            classify(b"hello", None),
+            Stored::Content(MimeType::TextPlain)
+        );
+        assert_eq!(classify(b"\xff\xfe\0\x01", None), Stored::File);
+        assert_eq!(
+            classify(b"%PDF-1.7\n\0", Some("application/pdf")),
+            Stored::File
+        );
+    }
+
+    #[test]
+    fn test_add() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let state: SharedState = std::sync::Arc::new(crate::state::StateLock::new(
+            "test",
+            crate::state::State::new(path, sender),
+        ));
+        let upload = |content: &[u8], declared: Option<&str>, file_name: Option<&str>| {
+            tauri::async_runtime::block_on(add(
+                &state,
+                content.to_vec(),
+                declared.map(str::to_string),
+                file_name.map(str::to_string),
+            ))
+        };
+
+        let id = upload(PNG, Some("image/png"), Some("shot.png"))
+            .unwrap()
+            .unwrap();
+        let (hash, meta) = state.with_read(|state| {
+            let hash = state.view.items[&id].hash.clone();
+            (hash.clone(), state.store.get_content_meta(&hash).unwrap())
+        });
+        assert_eq!(meta.mime_type, MimeType::ImagePng);
+        assert_eq!(
+            state.with_read(|state| state.store.get_content(&hash)),
+            Some(PNG.to_vec())
+        );
+
+        let id = upload(b"%PDF-1.7\n\0", None, Some("../../report.pdf"))
+            .unwrap()
+            .unwrap();
+        let content = state.with_read(|state| {
+            let hash = &state.view.items[&id].hash;
+            state.store.get_content(hash).unwrap()
+        });
+        let file_ref = crate::file_ref::parse(&content).unwrap();
+        assert_eq!(file_ref.name, "report.pdf");
+        assert!(Path::new(&file_ref.path).starts_with(dir.path().join(UPLOADS_DIR)));
+        assert_eq!(std::fs::read(&file_ref.path).unwrap(), b"%PDF-1.7\n\0");
+
+        assert_eq!(upload(b"", None, None), Ok(None));
+
+        // the file's removed along with the last item referring to it
+        let upload_dir = Path::new(&file_ref.path).parent().unwrap().to_path_buf();
+        state.with_lock(|state| state.store.purge(&id));
+        assert!(!upload_dir.exists());
+        assert!(dir.path().join(UPLOADS_DIR).exists());
+    }
+}
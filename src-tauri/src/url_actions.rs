@@ -0,0 +1,264 @@
+// Actions for Link items: open the URL in the default browser, archive the page, as its readable
+// part, extracted the way browsers' reader modes do, or shorten the URL with the service set in
+// url_shortener. Archives and short URLs are added next to the Link.
+
+use scru128::Scru128Id;
+use tauri::Manager;
+
+use crate::capture::Origin;
+use crate::error::StacksError;
+use crate::events;
+use crate::state::{SharedState, State};
+use crate::store::MimeType;
+
+// pages, and shortener responses, larger than this aren't read
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Open,
+    Archive,
+    Shorten,
+}
+
+impl Action {
+    pub const ALL: [Action; 3] = [Action::Open, Action::Archive, Action::Shorten];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Open => "open",
+            Action::Archive => "archive",
+            Action::Shorten => "shorten",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+// The Link item's URL, and its stack
+pub fn link(state: &State, id: &Scru128Id) -> Result<(String, Scru128Id), StacksError> {
+    let item = state
+        .view
+        .items
+        .get(id)
+        .filter(|item| !item.is_stack)
+        .ok_or(StacksError::not_found(id))?;
+    let stack_id = item.stack_id.ok_or(StacksError::not_found(id))?;
+    let meta = state.store.get_content_meta(&item.hash);
+    if !meta.is_some_and(|meta| meta.content_type == "Link") {
+        return Err(StacksError::invalid_input(format!("not a link: {}", id)));
+    }
+    let content = state
+        .store
+        .get_content(&item.hash)
+        .ok_or(StacksError::content_not_found(id))?;
+    Ok((
+        String::from_utf8_lossy(&content).trim().to_string(),
+        stack_id,
+    ))
+}
+
+// The page's title, and its readable part, as HTML
+pub fn extract(html: &str, url: &str) -> Result<(String, String), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let product =
+        readability::extractor::extract(&mut html.as_bytes(), &url).map_err(|e| e.to_string())?;
+    Ok((product.title, product.content))
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .unwrap()
+}
+
+// Reads the response's body, up to MAX_BODY_BYTES
+async fn get(req: reqwest::RequestBuilder) -> Result<String, String> {
+    let mut res = req.send().await.map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(res.status().to_string());
+    }
+    let too_large = || format!("the response is larger than {} bytes", MAX_BODY_BYTES);
+    if res
+        .content_length()
+        .is_some_and(|len| len > MAX_BODY_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+// Adds what the action made next to the Link, returning its item: if the stack already held it,
+// e.g. the page was archived before, the existing item is moved to the top. An archive's title is
+// kept as its origin.
+fn add(
+    state: &mut State,
+    stack_id: Scru128Id,
+    url: &str,
+    content: &str,
+    title: Option<String>,
+) -> Scru128Id {
+    let packet = state
+        .store
+        .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+    let id = state.merge_add(&packet);
+    state.store.source_set(id, url);
+    if let (Some(title), Some(hash)) = (title, packet.hash) {
+        state.store.origin_set(
+            id,
+            &Origin {
+                url: url.to_string(),
+                title: Some(title).filter(|title| !title.is_empty()),
+            },
+        );
+        let packet = state.store.update_content_type(hash, "HTML".to_string());
+        state.merge(&packet);
+    }
+    id
+}
+
+// Runs the action on the Link item. Returns the id of the item it added, if any.
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    id: Scru128Id,
+    action: Action,
+) -> Result<Option<Scru128Id>, StacksError> {
+    let (url, stack_id, shortener) = state.with_lock(|state| {
+        let (url, stack_id) = link(state, &id)?;
+        if action != Action::Open && state.is_read_only(&stack_id) {
+            return Err(StacksError::read_only(&stack_id));
+        }
+        let settings = state.store.settings_get().unwrap_or_default();
+        Ok((url, stack_id, settings.url_shortener))
+    })?;
+
+    let (content, title) = match action {
+        Action::Open => {
+            tauri::api::shell::open(&app.shell_scope(), &url, None).map_err(|e| e.to_string())?;
+            return Ok(None);
+        }
+        Action::Archive => {
+            let html = get(client().get(&url)).await?;
+            let (title, content) = {
+                let url = url.clone();
+                tauri::async_runtime::spawn_blocking(move || extract(&html, &url))
+                    .await
+                    .map_err(|e| e.to_string())??
+            };
+            (content, Some(title))
+        }
+        Action::Shorten => {
+            let shortener = shortener.ok_or("url_shortener isn't set")?;
+            let short = get(client().get(&shortener).query(&[("url", &url)])).await?;
+            let short = short.trim().to_string();
+            if reqwest::Url::parse(&short).is_err() {
+                return Err(format!("the shortener responded with: {}", short).into());
+            }
+            (short, None)
+        }
+    };
+
+    let new_id = state.with_lock(|state| add(state, stack_id, &url, &content, title));
+    events::emit(app, "refresh-items", true);
+    Ok(Some(new_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let html = r#"<html>
+            <head><title>Ownership - The Book</title></head>
+            <body>
+                <nav><a href="/">Home</a> <a href="/about">About</a></nav>
+                <article>
+                    <h1>Ownership</h1>
+                    <p>Ownership is a set of rules that govern how a Rust program manages memory.
+                    All programs have to manage the way they use a computer's memory while
+                    running, and some languages have garbage collection that regularly looks
+                    for no-longer-used memory as the program runs.</p>
+                    <p>In other languages, the programmer must explicitly allocate and free the
+                    memory. Rust uses a third approach: memory is managed through a system of
+                    ownership with a set of rules that the compiler checks.</p>
+                </article>
+                <footer>Copyright, and other things nobody reads.</footer>
+            </body>
+        </html>"#;
+        let (title, content) =
+            extract(html, "https://doc.rust-lang.org/book/ownership.html").unwrap();
+        assert_eq!(title, "Ownership - The Book");
+        assert!(content.contains("a set of rules that govern how a Rust program manages memory"));
+
+        assert!(extract(html, "not a url").is_err());
+    }
+
+    #[test]
+    fn test_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack_id = state.get_curr_stack();
+        let packet = state
+            .store
+            .add(b"https://example.com/page", MimeType::TextPlain, stack_id);
+        state.merge(&packet);
+        assert_eq!(
+            link(&state, &packet.id),
+            Ok(("https://example.com/page".to_string(), stack_id))
+        );
+
+        let packet = state
+            .store
+            .add(b"not a link", MimeType::TextPlain, stack_id);
+        state.merge(&packet);
+        assert!(link(&state, &packet.id).is_err());
+    }
+
+    #[test]
+    fn test_add() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+        let stack_id = state.get_curr_stack();
+
+        let url = "https://example.com/page";
+        let archived = add(
+            &mut state,
+            stack_id,
+            url,
+            "<p>Page</p>",
+            Some("Page".into()),
+        );
+        assert!(state.view.items.contains_key(&archived));
+        assert_eq!(state.store.source_get(&archived), Some(url.to_string()));
+
+        // archiving the page again, from another link to it, is the same item
+        let other = "https://example.com/page?ref=feed";
+        let again = add(
+            &mut state,
+            stack_id,
+            other,
+            "<p>Page</p>",
+            Some("Page".into()),
+        );
+        assert_eq!(again, archived);
+        assert_eq!(state.store.source_get(&archived), Some(other.to_string()));
+        assert_eq!(state.store.origin_get(&archived).unwrap().url, other);
+    }
+}
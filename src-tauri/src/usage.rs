@@ -0,0 +1,107 @@
+// How often, and how recently, items are used: copied or pasted, not merely selected. With the
+// frecency ranking, items used often and lately float to the top of their stacks, and of search results,
+// rather than stacks only being ordered by when items were last touched.
+
+use std::collections::HashMap;
+
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Ranking {
+    #[default]
+    Recent,
+    Frecency,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Copy,
+    Paste,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub copies: u64,
+    pub pastes: u64,
+    // unix timestamp, in milliseconds
+    pub last_access: u64,
+}
+
+impl Usage {
+    pub fn record(&mut self, access: Access, now: u64) {
+        match access {
+            Access::Copy => self.copies += 1,
+            Access::Paste => self.pastes += 1,
+        }
+        self.last_access = now;
+    }
+
+    // Uses, weighed by kind, then by how long ago the item was last used. A paste is the surest
+    // sign an item is wanted.
+    pub fn frecency(&self, now: u64) -> f64 {
+        let uses = self.copies as f64 + self.pastes as f64 * 2.0;
+        let days = now.saturating_sub(self.last_access) / (24 * 60 * 60 * 1000);
+        let recency = match days {
+            0..=3 => 1.0,
+            4..=14 => 0.7,
+            15..=31 => 0.5,
+            32..=90 => 0.3,
+            _ => 0.1,
+        };
+        uses * recency
+    }
+}
+
+// The items' scores, for the ranking, or None to order items by when they were last touched
+pub fn scores(
+    ranking: Ranking,
+    usage: &HashMap<Scru128Id, Usage>,
+    now: u64,
+) -> Option<HashMap<Scru128Id, f64>> {
+    match ranking {
+        Ranking::Recent => None,
+        Ranking::Frecency => Some(
+            usage
+                .iter()
+                .map(|(id, usage)| (*id, usage.frecency(now)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frecency() {
+        let day = 24 * 60 * 60 * 1000;
+        let now = 1_000 * day;
+
+        let mut pasted = Usage::default();
+        pasted.record(Access::Paste, now - day);
+        let mut copied = Usage::default();
+        copied.record(Access::Copy, now - day);
+        assert_eq!(pasted.pastes, 1);
+        assert!(pasted.frecency(now) > copied.frecency(now));
+
+        // the same use counts for less as it gets older
+        let mut stale = Usage::default();
+        for _ in 0..2 {
+            stale.record(Access::Copy, now - 100 * day);
+        }
+        assert!(stale.frecency(now) < copied.frecency(now));
+
+        let usage = HashMap::from([(scru128::new(), pasted)]);
+        assert_eq!(scores(Ranking::Recent, &usage, now), None);
+        assert_eq!(
+            scores(Ranking::Frecency, &usage, now)
+                .unwrap()
+                .values()
+                .next(),
+            Some(&2.0)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// Checks the store's packets against the content store: blobs a packet refers to can go missing,
+// or be corrupted, e.g. by a disk error or a partial restore from backup. Repairing re-derives
+// content metadata from blobs which are intact, and quarantines packets whose blobs are gone, so
+// the rest of the store loads cleanly. Quarantined packets are kept, rather than deleted.
+
+use std::collections::HashSet;
+
+use scru128::Scru128Id;
+use serde::Serialize;
+use ssri::Integrity;
+
+use crate::error::StacksError;
+use crate::events;
+use crate::state::{SharedState, State};
+use crate::store::{infer_mime_type, MimeType, Packet};
+use crate::view::View;
+
+// progress is emitted every this many blobs
+const PROGRESS_EVERY: usize = 100;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    pub packets: usize,
+    pub blobs: usize,
+    pub missing: Vec<Integrity>,
+    pub corrupt: Vec<Integrity>,
+    // blobs which are intact, but have no content metadata
+    pub missing_meta: Vec<Integrity>,
+    // filled in by a repair
+    pub rederived: usize,
+    pub quarantined: Vec<Scru128Id>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty() && self.missing_meta.is_empty()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub checked: usize,
+    pub total: usize,
+}
+
+// Reads every blob the packets refer to, which verifies its integrity. This can take a while for a
+// large store, so it's run without the state lock.
+pub fn check(
+    cache_path: &str,
+    packets: &[Packet],
+    metas: &HashSet<Integrity>,
+    mut progress: impl FnMut(Progress),
+) -> VerifyReport {
+    let hashes: HashSet<&Integrity> = packets.iter().filter_map(|p| p.hash.as_ref()).collect();
+    let mut report = VerifyReport {
+        packets: packets.len(),
+        blobs: hashes.len(),
+        ..Default::default()
+    };
+    let total = hashes.len();
+    for (i, hash) in hashes.into_iter().enumerate() {
+        if !cacache::exists_sync(cache_path, hash) {
+            report.missing.push(hash.clone());
+        } else if cacache::read_hash_sync(cache_path, hash).is_err() {
+            report.corrupt.push(hash.clone());
+        } else if !metas.contains(hash) {
+            report.missing_meta.push(hash.clone());
+        }
+        if (i + 1) % PROGRESS_EVERY == 0 || i + 1 == total {
+            progress(Progress {
+                checked: i + 1,
+                total,
+            });
+        }
+    }
+    report
+}
+
+pub fn repair(state: &mut State, report: &mut VerifyReport) {
+    for hash in &report.missing_meta {
+        let Some(content) = state.store.cas_read(hash) else {
+            continue;
+        };
+        // images are recognized by their signature: anything else is kept as text
+        let mime_type = infer::get(&content)
+            .and_then(|kind| MimeType::from_image_mime(kind.mime_type()))
+            .unwrap_or(MimeType::TextPlain);
+        let (mime_type, content_type) = infer_mime_type(&content, mime_type);
+        state.store.cas_write(&content, mime_type, content_type);
+        report.rederived += 1;
+    }
+
+    let broken: HashSet<&Integrity> = report.missing.iter().chain(&report.corrupt).collect();
+    let ids: Vec<Scru128Id> = state
+        .store
+        .scan()
+        .filter(|packet| {
+            packet
+                .hash
+                .as_ref()
+                .is_some_and(|hash| broken.contains(hash))
+        })
+        .map(|packet| packet.id)
+        .collect();
+    for id in ids {
+        if state.store.quarantine(&id).is_some() {
+            report.quarantined.push(id);
+        }
+    }
+
+    if !report.quarantined.is_empty() {
+        let mut view = View::new();
+        state.store.scan().for_each(|p| view.merge(&p));
+        state.ui = state.ui.renew(&view);
+        state.view = view;
+    }
+}
+
+// Verifies the store, emitting verify-progress as blobs are checked, and repairs it if asked to
+pub async fn run(
+    app: &tauri::AppHandle,
+    state: &SharedState,
+    fix: bool,
+) -> Result<VerifyReport, StacksError> {
+    let (cache_path, packets, metas) = state.with_lock(|state| {
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let metas: HashSet<Integrity> = state.store.scan_content_meta().into_keys().collect();
+        (state.store.cache_path.clone(), packets, metas)
+    });
+
+    let progress_app = app.clone();
+    let mut report = tauri::async_runtime::spawn_blocking(move || {
+        check(&cache_path, &packets, &metas, |progress| {
+            events::emit(&progress_app, "verify-progress", progress);
+        })
+    })
+    .await
+    // a check which didn't finish mustn't pass for a healthy store
+    .map_err(|e| e.to_string())?;
+    tracing::info!(
+        name = "verify",
+        missing = report.missing.len(),
+        corrupt = report.corrupt.len(),
+        missing_meta = report.missing_meta.len(),
+    );
+
+    if fix && !report.is_ok() {
+        state.with_lock(|state| repair(state, &mut report));
+        tracing::info!(
+            name = "verify",
+            rederived = report.rederived,
+            quarantined = report.quarantined.len(),
+            "repaired"
+        );
+        events::emit(app, "refresh-items", true);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_and_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let stack = state
+            .store
+            .add_stack(b"Stack", crate::store::StackLockStatus::Unlocked);
+        state.merge(&stack);
+        let kept = state.store.add(b"kept", MimeType::TextPlain, stack.id);
+        state.merge(&kept);
+        let lost = state.store.add(b"lost", MimeType::TextPlain, stack.id);
+        state.merge(&lost);
+
+        let lost_hash = lost.hash.clone().unwrap();
+        cacache::remove_hash_sync(&state.store.cache_path, &lost_hash).unwrap();
+
+        let packets: Vec<Packet> = state.store.scan().collect();
+        let kept_hash = kept.hash.clone().unwrap();
+        // the kept item's metadata is missing, but its blob is intact
+        let metas: HashSet<Integrity> = state
+            .store
+            .scan_content_meta()
+            .into_keys()
+            .filter(|hash| hash != &kept_hash)
+            .collect();
+        let mut checked = Vec::new();
+        let mut report = check(&state.store.cache_path, &packets, &metas, |progress| {
+            checked.push(progress)
+        });
+        assert_eq!(report.packets, 3);
+        assert_eq!(report.missing, vec![lost_hash]);
+        assert_eq!(report.missing_meta, vec![kept_hash]);
+        assert_eq!(checked.last().map(|p| p.checked), Some(3));
+        assert!(!report.is_ok());
+
+        repair(&mut state, &mut report);
+        assert_eq!(report.rederived, 1);
+        assert_eq!(report.quarantined, vec![lost.id]);
+        assert!(!state.view.items.contains_key(&lost.id));
+        assert!(state.view.items.contains_key(&kept.id));
+        assert_eq!(state.store.quarantined(), vec![lost]);
+    }
+}
@@ -13,10 +13,17 @@ pub struct Item {
     pub hash: Integrity,
     pub stack_id: Option<Scru128Id>,
     children: Vec<Scru128Id>,
+    pub is_stack: bool,
     pub ephemeral: bool,
     pub ordered: bool,
     pub locked: bool,
+    // a read-only stack is also locked
+    pub read_only: bool,
     pub cross_stream: bool,
+    pub archived: bool,
+    // stacks the item is linked into, besides stack_id. The item is shared, rather than copied,
+    // so edits show in every stack.
+    pub linked: Vec<Scru128Id>,
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -25,10 +32,21 @@ pub struct Focus {
     pub index: usize,
 }
 
+// A stack and the stacks nested within it
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct StackNode {
+    pub id: Scru128Id,
+    pub children: Vec<StackNode>,
+}
+
 #[derive(serde::Serialize, Debug, Clone)]
 pub struct View {
     pub items: HashMap<Scru128Id, Item>,
     pub undo: Option<Item>,
+    // items' scores, which unordered stacks are sorted by, before when items were last touched:
+    // see usage
+    #[serde(skip)]
+    pub rank: Option<HashMap<Scru128Id, f64>>,
 }
 
 impl Default for View {
@@ -42,6 +60,7 @@ impl View {
         View {
             items: HashMap::new(),
             undo: None,
+            rank: None,
         }
     }
 
@@ -60,7 +79,9 @@ impl View {
                         let children = stack.children.clone();
                         for child_id in children {
                             if let Some(child) = self.items.get_mut(&child_id) {
-                                if !child.ephemeral && &child.hash == packet.hash.as_ref().unwrap()
+                                if !child.ephemeral
+                                    && !child.is_stack
+                                    && &child.hash == packet.hash.as_ref().unwrap()
                                 {
                                     // If it exists, update it
                                     child.touched.push(packet.id);
@@ -85,13 +106,17 @@ impl View {
                     hash: packet.hash.clone().unwrap(),
                     stack_id: packet.stack_id,
                     children: Vec::new(),
+                    is_stack: packet.stack_id.is_none(),
                     ephemeral: packet.ephemeral,
                     ordered: false,
-                    locked: match packet.lock_status {
-                        Some(StackLockStatus::Locked) => true,
-                        _ => false,
-                    },
+                    locked: matches!(
+                        packet.lock_status,
+                        Some(StackLockStatus::Locked | StackLockStatus::ReadOnly)
+                    ),
+                    read_only: packet.lock_status == Some(StackLockStatus::ReadOnly),
                     cross_stream: false,
+                    archived: false,
+                    linked: Vec::new(),
                 };
 
                 if let Some(stack) = packet.stack_id.and_then(|id| self.items.get_mut(&id)) {
@@ -194,10 +219,8 @@ impl View {
 
                 if let Some(lock_status) = &packet.lock_status {
                     if let Some(item) = self.items.get_mut(&source_id) {
-                        match lock_status {
-                            StackLockStatus::Unlocked => item.locked = false,
-                            StackLockStatus::Locked => item.locked = true,
-                        }
+                        item.locked = *lock_status != StackLockStatus::Unlocked;
+                        item.read_only = *lock_status == StackLockStatus::ReadOnly;
                     }
                     return;
                 }
@@ -216,12 +239,20 @@ impl View {
                             old_stack.children.retain(|&id| id != source_id);
                         }
                         item.stack_id = Some(new_stack_id);
-                        if let Some(new_stack) = self.items.get_mut(&new_stack_id) {
+                        // moving an item into a stack it's linked to makes that its own stack
+                        if item.linked.contains(&new_stack_id) {
+                            item.linked.retain(|&id| id != new_stack_id);
+                        } else if let Some(new_stack) = self.items.get_mut(&new_stack_id) {
                             new_stack.children.push(source_id);
                         }
                     }
 
-                    item.touched.push(packet.id);
+                    // an update streamed into the item is merged as it starts, while ephemeral,
+                    // and again when it ends
+                    item.ephemeral = packet.ephemeral;
+                    if item.touched.last() != Some(&packet.id) {
+                        item.touched.push(packet.id);
+                    }
                     item.last_touched = packet.id;
                     if let Some(stack) = item.stack_id.and_then(|id| self.items.get_mut(&id)) {
                         stack.last_touched = packet.id;
@@ -235,15 +266,13 @@ impl View {
                 let source_id = packet.source_id.unwrap();
 
                 if let Some(item) = self.items.get(&source_id) {
-                    assert!(
-                        item.stack_id.is_some(),
-                        "Forking Stacks is not supported yet"
-                    );
+                    assert!(!item.is_stack, "Forking Stacks is not supported yet");
 
                     let mut new_item = item.clone();
                     new_item.id = packet.id;
 
                     new_item.children = Vec::new();
+                    new_item.linked = Vec::new();
 
                     if let Some(hash) = &packet.hash {
                         new_item.hash = hash.clone();
@@ -273,10 +302,201 @@ impl View {
                         stack.children.retain(|&id| id != source_id);
                         stack.last_touched = packet.id;
                     }
+                    for stack_id in &item.linked {
+                        if let Some(stack) = self.items.get_mut(stack_id) {
+                            stack.children.retain(|&id| id != source_id);
+                        }
+                    }
+                    // items linked into a deleted stack are no longer linked to it; undo replays
+                    // the links
+                    if item.is_stack {
+                        for child_id in &item.children {
+                            if let Some(child) = self.items.get_mut(child_id) {
+                                child.linked.retain(|&id| id != source_id);
+                            }
+                        }
+                    }
                     item.last_touched = packet.id;
                     self.undo = Some(item);
                 }
             }
+
+            PacketType::Nest => {
+                let source_id = packet.source_id.unwrap();
+
+                let old_parent_id = match self.items.get(&source_id) {
+                    Some(item) if item.is_stack => item.stack_id,
+                    _ => return,
+                };
+
+                if let Some(parent_id) = packet.stack_id {
+                    let is_stack = self
+                        .items
+                        .get(&parent_id)
+                        .map(|parent| parent.is_stack)
+                        .unwrap_or(false);
+                    // a stack can't be nested under itself, or one of its own descendants
+                    if !is_stack
+                        || parent_id == source_id
+                        || self.ancestors(&parent_id).contains(&source_id)
+                    {
+                        return;
+                    }
+                }
+
+                if let Some(old_parent) = old_parent_id.and_then(|id| self.items.get_mut(&id)) {
+                    old_parent.children.retain(|&id| id != source_id);
+                }
+
+                if let Some(parent) = packet.stack_id.and_then(|id| self.items.get_mut(&id)) {
+                    parent.children.push(source_id);
+                    parent.last_touched = packet.id;
+                }
+
+                if let Some(item) = self.items.get_mut(&source_id) {
+                    item.stack_id = packet.stack_id;
+                    item.touched.push(packet.id);
+                    item.last_touched = packet.id;
+                }
+            }
+
+            PacketType::Archive | PacketType::Restore => {
+                let source_id = packet.source_id.unwrap();
+                if let Some(item) = self.items.get_mut(&source_id) {
+                    if item.is_stack {
+                        item.archived = packet.packet_type == PacketType::Archive;
+                    }
+                }
+            }
+
+            PacketType::Link => {
+                let source_id = packet.source_id.unwrap();
+                let stack_id = packet.stack_id.unwrap();
+                let is_stack = self
+                    .items
+                    .get(&stack_id)
+                    .is_some_and(|stack| stack.is_stack);
+                match self.items.get_mut(&source_id) {
+                    Some(item)
+                        if is_stack
+                            && !item.is_stack
+                            && item.stack_id != Some(stack_id)
+                            && !item.linked.contains(&stack_id) =>
+                    {
+                        item.linked.push(stack_id);
+                        item.touched.push(packet.id);
+                        item.last_touched = packet.id;
+                    }
+                    _ => return,
+                }
+                if let Some(stack) = self.items.get_mut(&stack_id) {
+                    stack.children.push(source_id);
+                    stack.last_touched = packet.id;
+                }
+            }
+
+            PacketType::Unlink => {
+                let source_id = packet.source_id.unwrap();
+                let stack_id = packet.stack_id.unwrap();
+                match self.items.get_mut(&source_id) {
+                    Some(item) if item.linked.contains(&stack_id) => {
+                        item.linked.retain(|&id| id != stack_id);
+                    }
+                    _ => return,
+                }
+                if let Some(stack) = self.items.get_mut(&stack_id) {
+                    stack.children.retain(|&id| id != source_id);
+                }
+            }
+
+            PacketType::Touch => {
+                let source_id = packet.source_id.unwrap();
+                let stack_id = match self.items.get_mut(&source_id) {
+                    Some(item) => {
+                        item.touched.push(packet.id);
+                        item.last_touched = packet.id;
+                        item.stack_id
+                    }
+                    None => return,
+                };
+                if let Some(stack) = stack_id.and_then(|id| self.items.get_mut(&id)) {
+                    stack.last_touched = packet.id;
+                }
+            }
+        }
+    }
+
+    // Returns the most recently touched item with the given content, ignoring stacks, ephemeral
+    // items and anything archived
+    pub fn find_by_hash(&self, hash: &Integrity) -> Option<&Item> {
+        self.items
+            .values()
+            .filter(|item| &item.hash == hash && !item.is_stack && !item.ephemeral)
+            .filter(|item| {
+                !self
+                    .ancestors(&item.id)
+                    .iter()
+                    .any(|id| self.items.get(id).is_some_and(|stack| stack.archived))
+            })
+            .max_by_key(|item| item.last_touched)
+    }
+
+    // Returns the stack's item holding the content, e.g. the one an Add packet for content the
+    // stack already held was merged into
+    pub fn find_in_stack(&self, stack_id: &Scru128Id, hash: &Integrity) -> Option<&Item> {
+        self.items
+            .get(stack_id)?
+            .children
+            .iter()
+            .filter_map(|id| self.items.get(id))
+            .find(|item| &item.hash == hash && !item.is_stack && !item.ephemeral)
+    }
+
+    // Returns the ids of the stacks containing the item, nearest first
+    pub fn ancestors(&self, id: &Scru128Id) -> Vec<Scru128Id> {
+        let mut ancestors = Vec::new();
+        let mut curr = self.items.get(id).and_then(|item| item.stack_id);
+        while let Some(id) = curr {
+            // guard against cycles, which the merge logic should never allow
+            if ancestors.contains(&id) || ancestors.len() > self.items.len() {
+                break;
+            }
+            ancestors.push(id);
+            curr = self.items.get(&id).and_then(|item| item.stack_id);
+        }
+        ancestors
+    }
+
+    // Returns the stacks leading to the item, starting from the root
+    pub fn breadcrumbs(&self, item: &Item) -> Vec<&Item> {
+        let mut crumbs: Vec<&Item> = self
+            .ancestors(&item.id)
+            .iter()
+            .filter_map(|id| self.items.get(id))
+            .collect();
+        crumbs.reverse();
+        crumbs
+    }
+
+    // Returns the stacks as a tree, in the same order they're listed in
+    pub fn stack_tree(&self) -> Vec<StackNode> {
+        self.root()
+            .iter()
+            .filter(|item| item.is_stack)
+            .map(|stack| self.stack_node(stack))
+            .collect()
+    }
+
+    fn stack_node(&self, stack: &Item) -> StackNode {
+        StackNode {
+            id: stack.id,
+            children: self
+                .children(stack)
+                .iter()
+                .filter_map(|id| self.items.get(id))
+                .filter(|item| item.is_stack && item.stack_id == Some(stack.id))
+                .map(|child| self.stack_node(child))
+                .collect(),
         }
     }
 
@@ -298,12 +518,23 @@ impl View {
         if item.ordered {
             return children;
         }
-        children.sort_by_key(|child| {
+        let last_touched = |id: &Scru128Id| {
             self.items
-                .get(child)
+                .get(id)
                 .map(|item| item.last_touched)
                 .unwrap_or_default()
-        });
+        };
+        match &self.rank {
+            Some(rank) => {
+                let score = |id: &Scru128Id| rank.get(id).copied().unwrap_or_default();
+                children.sort_by(|a, b| {
+                    score(a)
+                        .total_cmp(&score(b))
+                        .then_with(|| last_touched(a).cmp(&last_touched(b)))
+                });
+            }
+            None => children.sort_by_key(last_touched),
+        }
         children.reverse();
         children
     }
@@ -407,6 +638,51 @@ impl View {
         self.get_best_focus_with_offset(focus, -1)
     }
 
+    // Returns a copy of the view with archived stacks, and everything within them, removed
+    #[tracing::instrument(skip_all)]
+    pub fn without_archived(&self) -> Self {
+        let is_archived = |id: &Scru128Id| self.items.get(id).is_some_and(|i| i.archived);
+        let hidden: HashSet<Scru128Id> = self
+            .items
+            .values()
+            .filter(|item| item.archived || self.ancestors(&item.id).iter().any(is_archived))
+            .map(|item| item.id)
+            .collect();
+
+        if hidden.is_empty() {
+            return self.clone();
+        }
+
+        let items = self
+            .items
+            .values()
+            .filter(|item| !hidden.contains(&item.id))
+            .map(|item| {
+                let mut item = item.clone();
+                item.children.retain(|id| !hidden.contains(id));
+                (item.id, item)
+            })
+            .collect();
+
+        View {
+            items,
+            undo: self.undo.clone(),
+            rank: self.rank.clone(),
+        }
+    }
+
+    // an item matches if its content matches, a stack if any of its descendants do
+    fn matches_any(&self, item: &Item, matches: &HashSet<ssri::Integrity>) -> bool {
+        if item.is_stack {
+            item.children
+                .iter()
+                .filter_map(|id| self.items.get(id))
+                .any(|child| self.matches_any(child, matches))
+        } else {
+            matches.contains(&item.hash)
+        }
+    }
+
     #[tracing::instrument(skip_all)]
     pub fn filter(&self, matches: &HashSet<ssri::Integrity>) -> Self {
         let items: HashMap<Scru128Id, Item> = self
@@ -414,13 +690,13 @@ impl View {
             .values()
             .filter_map(|item| {
                 let mut item = item.clone();
-                if item.stack_id.is_none() {
+                if item.is_stack {
                     item.children = self
                         .children(&item)
                         .into_iter()
                         .filter(|child_id| {
                             if let Some(child) = self.items.get(child_id) {
-                                return matches.contains(&child.hash);
+                                return self.matches_any(child, matches);
                             }
                             false
                         })
@@ -438,6 +714,7 @@ impl View {
         View {
             items,
             undo: self.undo.clone(),
+            rank: self.rank.clone(),
         }
     }
 }
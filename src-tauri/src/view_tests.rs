@@ -1,6 +1,7 @@
+use crate::publish;
 pub use crate::state::State;
 pub use crate::store::{MimeType, StackLockStatus, Store};
-pub use crate::view::View;
+pub use crate::view::{StackNode, View};
 
 macro_rules! assert_view_as_expected {
     ($store:expr, $view:expr, $expected:expr $(,)?) => {
@@ -151,7 +152,7 @@ fn test_no_duplicate_entry_on_same_hash() {
     let dir = tempfile::tempdir().unwrap();
     let path = dir.path().to_str().unwrap();
 
-    let (sender, _receiver) = std::sync::mpsc::channel();
+    let (sender, _receiver) = publish::channel();
     let mut state = State::new(path, sender);
 
     let stack_id = state
@@ -163,7 +164,8 @@ fn test_no_duplicate_entry_on_same_hash() {
     // Add second item with same hash
     let id2 = state.store.add(b"Item 1", MimeType::TextPlain, stack_id).id;
 
-    state.store.scan().for_each(|p| state.merge(&p));
+    let packets: Vec<_> = state.store.scan().collect();
+    packets.iter().for_each(|p| state.merge(p));
 
     // Check that the stack item only has one child and that the item has been updated correctly
     assert_view_as_expected!(&state.store, &state.view, vec![("Stack 1", vec!["Item 1"])]);
@@ -173,3 +175,330 @@ fn test_no_duplicate_entry_on_same_hash() {
     assert_eq!(item.touched, vec![id1, id2]);
     assert_eq!(item.last_touched, id2);
 }
+
+#[test]
+fn test_read_only_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = publish::channel();
+    let mut state = State::new(path, sender);
+
+    let stack = state
+        .store
+        .add_stack(b"Snippets", StackLockStatus::Unlocked);
+    state.merge(&stack);
+    let nested = state.store.add_stack(b"SQL", StackLockStatus::Unlocked);
+    state.merge(&nested);
+    let packet = state.store.nest_stack(nested.id, Some(stack.id));
+    state.merge(&packet);
+    let item = state.store.add(b"SELECT 1", MimeType::TextPlain, nested.id);
+    state.merge(&item);
+    assert!(!state.is_read_only(&item.id));
+
+    let packet = state
+        .store
+        .update_stack_lock_status(stack.id, StackLockStatus::ReadOnly);
+    state.merge(&packet);
+    let view_stack = state.view.items.get(&stack.id).unwrap();
+    assert!(view_stack.locked && view_stack.read_only);
+    assert!(state.is_read_only(&stack.id));
+    assert!(state.is_read_only(&nested.id));
+    assert!(state.is_read_only(&item.id));
+
+    let packet = state
+        .store
+        .update_stack_lock_status(stack.id, StackLockStatus::Unlocked);
+    state.merge(&packet);
+    let view_stack = state.view.items.get(&stack.id).unwrap();
+    assert!(!view_stack.locked && !view_stack.read_only);
+    assert!(!state.is_read_only(&item.id));
+}
+
+#[test]
+fn test_nest_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+
+    let stack_id_1 = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let _item_id_1 = store.add(b"Item 1", MimeType::TextPlain, stack_id_1).id;
+    let stack_id_2 = store.add_stack(b"Stack 2", StackLockStatus::Unlocked).id;
+    let _item_id_2 = store.add(b"Item 2", MimeType::TextPlain, stack_id_2).id;
+
+    // User nests "Stack 2" under "Stack 1"
+    store.nest_stack(stack_id_2, Some(stack_id_1));
+    // Nesting "Stack 1" under its own child is ignored
+    store.nest_stack(stack_id_1, Some(stack_id_2));
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(&store, &view, vec![("Stack 1", vec!["Stack 2", "Item 1"])]);
+
+    let item_2 = view
+        .children(view.items.get(&stack_id_2).unwrap())
+        .first()
+        .and_then(|id| view.items.get(id))
+        .unwrap()
+        .clone();
+    let crumbs: Vec<_> = view.breadcrumbs(&item_2).iter().map(|i| i.id).collect();
+    assert_eq!(crumbs, vec![stack_id_1, stack_id_2]);
+
+    assert_eq!(
+        view.stack_tree(),
+        vec![StackNode {
+            id: stack_id_1,
+            children: vec![StackNode {
+                id: stack_id_2,
+                children: vec![],
+            }],
+        }]
+    );
+
+    // User moves "Stack 2" back to the root
+    store.nest_stack(stack_id_2, None);
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 2", vec!["Item 2"]), ("Stack 1", vec!["Item 1"])],
+    );
+}
+
+#[test]
+fn test_archive_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+
+    let stack_id_1 = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let _item_id_1 = store.add(b"Item 1", MimeType::TextPlain, stack_id_1).id;
+    let stack_id_2 = store.add_stack(b"Stack 2", StackLockStatus::Unlocked).id;
+    let _item_id_2 = store.add(b"Item 2", MimeType::TextPlain, stack_id_2).id;
+
+    // User archives "Stack 2"
+    store.update_stack_archived(stack_id_2, true);
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view.without_archived(),
+        vec![("Stack 1", vec!["Item 1"])],
+    );
+
+    // User restores "Stack 2"
+    store.update_stack_archived(stack_id_2, false);
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view.without_archived(),
+        vec![("Stack 2", vec!["Item 2"]), ("Stack 1", vec!["Item 1"])],
+    );
+}
+
+#[test]
+fn test_touch_item() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+
+    let stack_id_1 = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let item_id_1 = store.add(b"Item 1", MimeType::TextPlain, stack_id_1).id;
+    let _item_id_2 = store.add(b"Item 2", MimeType::TextPlain, stack_id_1).id;
+    let stack_id_2 = store.add_stack(b"Stack 2", StackLockStatus::Unlocked).id;
+    let _item_id_3 = store.add(b"Item 3", MimeType::TextPlain, stack_id_2).id;
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    let hash = ssri::Integrity::from(b"Item 1");
+    assert_eq!(
+        view.find_by_hash(&hash).map(|item| item.id),
+        Some(item_id_1)
+    );
+
+    // "Item 1" is copied again
+    store.update_touch(item_id_1);
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![
+            ("Stack 1", vec!["Item 1", "Item 2"]),
+            ("Stack 2", vec!["Item 3"]),
+        ],
+    );
+}
+
+#[test]
+fn test_link_item() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let view = |store: &Store| {
+        let mut view = View::new();
+        store.scan().for_each(|p| view.merge(&p));
+        view
+    };
+
+    let stack_id = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let item_id = store.add(b"Item 1", MimeType::TextPlain, stack_id).id;
+    let stack_id_2 = store.add_stack(b"Stack 2", StackLockStatus::Unlocked).id;
+
+    // User links the item into "Stack 2"
+    store.link(item_id, stack_id_2, true);
+    // linking again, or into the item's own stack, is a no-op
+    store.link(item_id, stack_id_2, true);
+    store.link(item_id, stack_id, true);
+    assert_view_as_expected!(
+        &store,
+        &view(&store),
+        vec![("Stack 2", vec!["Item 1"]), ("Stack 1", vec!["Item 1"])],
+    );
+
+    // edits show in both stacks
+    store.update(
+        item_id,
+        Some(b"Item 1 - updated"),
+        MimeType::TextPlain,
+        None,
+    );
+    assert_view_as_expected!(
+        &store,
+        &view(&store),
+        vec![
+            ("Stack 1", vec!["Item 1 - updated"]),
+            ("Stack 2", vec!["Item 1 - updated"]),
+        ],
+    );
+
+    store.link(item_id, stack_id_2, false);
+    assert_view_as_expected!(
+        &store,
+        &view(&store),
+        vec![("Stack 1", vec!["Item 1 - updated"]), ("Stack 2", vec![])],
+    );
+
+    // deleting the item removes it from every stack
+    store.link(item_id, stack_id_2, true);
+    store.delete(item_id);
+    assert_view_as_expected!(
+        &store,
+        &view(&store),
+        vec![("Stack 1", vec![]), ("Stack 2", vec![])],
+    );
+}
+
+#[test]
+fn test_link_delete_stack() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+    let view = |store: &Store| {
+        let mut view = View::new();
+        store.scan().for_each(|p| view.merge(&p));
+        view
+    };
+
+    let stack_id = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let item_id = store.add(b"Item 1", MimeType::TextPlain, stack_id).id;
+    let stack_id_2 = store.add_stack(b"Stack 2", StackLockStatus::Unlocked).id;
+    store.link(item_id, stack_id_2, true);
+
+    // deleting the linked stack leaves the item in its own stack, unlinked
+    let delete = store.delete(stack_id_2);
+    let v = view(&store);
+    assert!(v.items[&item_id].linked.is_empty());
+    assert_view_as_expected!(&store, &v, vec![("Stack 1", vec!["Item 1"])]);
+
+    // undoing the delete restores the link
+    assert_eq!(
+        v.undo.as_ref().map(|item| item.last_touched),
+        Some(delete.id)
+    );
+    store.remove_packet(&delete.id);
+    let v = view(&store);
+    assert_eq!(v.items[&item_id].linked, vec![stack_id_2]);
+    assert_view_as_expected!(
+        &store,
+        &v,
+        vec![("Stack 2", vec!["Item 1"]), ("Stack 1", vec!["Item 1"])],
+    );
+}
+
+#[test]
+fn test_append_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let (sender, _receiver) = publish::channel();
+    let mut state = State::new(path, sender);
+
+    let stack = state.store.add_stack(b"Logs", StackLockStatus::Unlocked);
+    state.merge(&stack);
+    let item = state.store.add(b"line 1\n", MimeType::TextPlain, stack.id);
+    state.merge(&item);
+    let hash = state.view.items[&item.id].hash.clone();
+
+    // while streaming, the item is ephemeral and keeps its content
+    let meta = state.store.get_content_meta(&hash).unwrap();
+    let mut streamer =
+        crate::store::InProgressStream::append_to(item.id, meta, b"line 1\n".to_vec());
+    state.merge(&streamer.packet);
+    assert!(state.view.items[&item.id].ephemeral);
+    assert_eq!(state.view.items[&item.id].hash, hash);
+
+    streamer.append(b"line 2\n");
+    assert_eq!(streamer.item_id(), item.id);
+    let packet = streamer.end_stream(&mut state.store);
+    state.merge(&packet);
+    state.store.insert_packet(&packet);
+
+    let view_item = &state.view.items[&item.id];
+    assert!(!view_item.ephemeral);
+    assert_eq!(view_item.touched, vec![item.id, packet.id]);
+    assert_eq!(
+        state.store.get_content(&view_item.hash),
+        Some(b"line 1\nline 2\n".to_vec())
+    );
+}
+
+#[test]
+fn test_rank_items() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let mut store = Store::new(path);
+
+    let stack_id = store.add_stack(b"Stack 1", StackLockStatus::Unlocked).id;
+    let item_id_1 = store.add(b"Item 1", MimeType::TextPlain, stack_id).id;
+    let _item_id_2 = store.add(b"Item 2", MimeType::TextPlain, stack_id).id;
+    let _item_id_3 = store.add(b"Item 3", MimeType::TextPlain, stack_id).id;
+
+    let mut view = View::new();
+    store.scan().for_each(|p| view.merge(&p));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 1", vec!["Item 3", "Item 2", "Item 1"])],
+    );
+
+    // "Item 1" is used the most, and the rest, which haven't been used, keep their order
+    view.rank = Some(std::collections::HashMap::from([(item_id_1, 2.0)]));
+    assert_view_as_expected!(
+        &store,
+        &view,
+        vec![("Stack 1", vec!["Item 1", "Item 3", "Item 2"])],
+    );
+}
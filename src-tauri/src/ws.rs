@@ -0,0 +1,285 @@
+// GET /ws upgrades to a WebSocket, for integrations which want more than one-shot HTTP calls, e.g.
+// an editor plugin. Clients are sent the events the webviews are, and can send commands: see
+// docs/ingest.md for the messages.
+
+use futures::{SinkExt, StreamExt};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use scru128::Scru128Id;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::commands;
+use crate::events;
+use crate::state::{SharedState, State};
+use crate::store::MimeType;
+use crate::usage::Access;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    // only send the events matching the filter
+    Subscribe {
+        filter: events::Filter,
+    },
+    // adds text to the stack, or the current stack
+    Add {
+        content: String,
+        #[serde(default)]
+        stack_id: Option<Scru128Id>,
+    },
+    Delete {
+        id: Scru128Id,
+    },
+    Touch {
+        id: Scru128Id,
+    },
+    Copy {
+        id: Scru128Id,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Incoming {
+    // echoed in the reply, so clients can match replies to their requests
+    #[serde(default)]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub seq: Option<u64>,
+    // the item the command added, or acted on
+    pub id: Option<Scru128Id>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Outgoing {
+    Event(events::Event),
+    Reply(Reply),
+}
+
+// Applies a command, other than subscribe, to the store
+pub fn apply(state: &mut State, command: &Command) -> Result<Option<Scru128Id>, String> {
+    let found = |state: &State, id: &Scru128Id| match state.view.items.contains_key(id) {
+        true => Ok(()),
+        false => Err(format!("item not found: {}", id)),
+    };
+    match command {
+        Command::Subscribe { .. } => Ok(None),
+        Command::Add { content, stack_id } => {
+            let stack_id = match stack_id {
+                Some(stack_id) => {
+                    if !state.view.items.get(stack_id).is_some_and(|s| s.is_stack) {
+                        return Err(format!("stack not found: {}", stack_id));
+                    }
+                    *stack_id
+                }
+                None => state.get_curr_stack(),
+            };
+            if state.is_read_only(&stack_id) {
+                return Err("the stack is read-only".to_string());
+            }
+            let packet = state
+                .store
+                .add(content.as_bytes(), MimeType::TextPlain, stack_id);
+            state.merge(&packet);
+            Ok(Some(packet.id))
+        }
+        Command::Delete { id } => {
+            found(state, id)?;
+            if state.is_read_only(id) {
+                return Err("the item is read-only".to_string());
+            }
+            let packet = state.store.delete(*id);
+            state.merge(&packet);
+            Ok(Some(*id))
+        }
+        Command::Touch { id } => {
+            found(state, id)?;
+            let packet = state.store.update_touch(*id);
+            state.merge(&packet);
+            Ok(Some(*id))
+        }
+        Command::Copy { id } => {
+            found(state, id)?;
+            commands::copy_to_clipboard(state, id).ok_or("failed to copy")?;
+            state.record_usage(*id, Access::Copy);
+            Ok(Some(*id))
+        }
+    }
+}
+
+fn handle(
+    text: &str,
+    state: &SharedState,
+    app_handle: &tauri::AppHandle,
+    filter: &mut events::Filter,
+) -> Reply {
+    let request = match serde_json::from_str::<Incoming>(text) {
+        Ok(request) => request,
+        Err(e) => {
+            return Reply {
+                seq: None,
+                id: None,
+                error: Some(e.to_string()),
+            }
+        }
+    };
+    let res = match &request.command {
+        Command::Subscribe { filter: subscribe } => {
+            *filter = subscribe.clone();
+            Ok(None)
+        }
+        command => {
+            let res = state.with_lock(|state| apply(state, command));
+            if res.is_ok() && !matches!(command, Command::Copy { .. }) {
+                events::emit(app_handle, "refresh-items", true);
+            }
+            res
+        }
+    };
+    let (id, error) = match res {
+        Ok(id) => (id, None),
+        Err(e) => (None, Some(e)),
+    };
+    Reply {
+        seq: request.seq,
+        id,
+        error,
+    }
+}
+
+async fn session(
+    mut ws: WebSocketStream<Upgraded>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) {
+    let mut events = events::listen();
+    let mut filter = events::Filter::default();
+    loop {
+        let outgoing = tokio::select! {
+            message = ws.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    Outgoing::Reply(handle(&text, &state, &app_handle, &mut filter))
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // pings are answered by the next send
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    tracing::warn!(name = "ws", %e, "receive");
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) if filter.matches(&event.topic, &event.scope()) => Outgoing::Event(event),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(name = "ws", skipped, "events lagged");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let text = serde_json::to_string(&outgoing).unwrap();
+        if ws.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+// Completes the WebSocket handshake, handing the connection to a session once hyper has switched
+// protocols
+pub fn upgrade(
+    mut req: Request<Body>,
+    state: SharedState,
+    app_handle: tauri::AppHandle,
+) -> Response<Body> {
+    let is_websocket = req
+        .headers()
+        .get("Upgrade")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = req.headers().get("Sec-WebSocket-Key");
+    let (true, Some(key)) = (is_websocket, key) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Expected a WebSocket upgrade"))
+            .unwrap();
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    let upgrade = hyper::upgrade::on(&mut req);
+    tauri::async_runtime::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                session(ws, state, app_handle).await;
+            }
+            Err(e) => tracing::error!(name = "ws", %e, "upgrade"),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let (sender, _receiver) = crate::publish::channel();
+        let mut state = State::new(path, sender);
+
+        let request: Incoming =
+            serde_json::from_str(r#"{"seq": 1, "command": "add", "content": "hello"}"#).unwrap();
+        assert_eq!(request.seq, Some(1));
+        let id = apply(&mut state, &request.command).unwrap().unwrap();
+        let stack_id = state.view.items[&id].stack_id.unwrap();
+
+        let touch = Command::Touch { id };
+        assert_eq!(apply(&mut state, &touch), Ok(Some(id)));
+
+        // commands on read-only stacks are refused
+        let packet = state
+            .store
+            .update_stack_lock_status(stack_id, crate::store::StackLockStatus::ReadOnly);
+        state.merge(&packet);
+        let add = Command::Add {
+            content: "world".to_string(),
+            stack_id: Some(stack_id),
+        };
+        assert!(apply(&mut state, &add).is_err());
+        assert!(apply(&mut state, &Command::Delete { id }).is_err());
+
+        let packet = state
+            .store
+            .update_stack_lock_status(stack_id, crate::store::StackLockStatus::Unlocked);
+        state.merge(&packet);
+        assert_eq!(apply(&mut state, &Command::Delete { id }), Ok(Some(id)));
+        assert!(apply(&mut state, &Command::Delete { id }).is_err());
+
+        let not_a_stack = Command::Add {
+            content: "world".to_string(),
+            stack_id: Some(scru128::new()),
+        };
+        assert!(apply(&mut state, &not_a_stack).is_err());
+    }
+}
@@ -18,7 +18,7 @@ import { Action, getContent, Stack } from "./types";
 export const actions: Action[] = [
   {
     name: "Set content type",
-    canApply: (stack: Stack) => stack.selected()?.stack_id != null,
+    canApply: (stack: Stack) => stack.selected()?.is_stack === false,
     keys: [<Icon name="IconCommandKey" />, <Icon name="IconShiftKey" />, "U"],
     matchKeyEvent: (event: KeyboardEvent) =>
       matchKeyEvent(event, { meta: true, shift: true, key: "u" }),
@@ -31,7 +31,7 @@ export const actions: Action[] = [
     name: "Copy clip to stack",
     keys: ["TAB"],
     matchKeyEvent: (event: KeyboardEvent) => event.key === "Tab",
-    canApply: (stack: Stack) => stack.selected()?.stack_id != null,
+    canApply: (stack: Stack) => stack.selected()?.is_stack === false,
     trigger: (stack: Stack) => {
       modes.activate(stack, addToStackMode);
     },
@@ -99,7 +99,7 @@ export const actions: Action[] = [
     canApply: (stack: Stack) => {
       const item = stack.selected();
       if (item) {
-        return !!item.stack_id;
+        return !item.is_stack;
       }
       return false;
     },
@@ -118,7 +118,7 @@ export const actions: Action[] = [
     canApply: (stack: Stack) => {
       const item = stack.selected();
       if (item) {
-        return !item.stack_id;
+        return item.is_stack;
       }
       return false;
     },
@@ -21,7 +21,7 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
   };
 
   let meta: MetaValue[] = [
-    { name: item.stack_id ? content.content_type : "Stack", value: item.id },
+    { name: item.is_stack ? "Stack" : content.content_type, value: item.id },
   ];
 
   /*
@@ -41,10 +41,11 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
   }
   */
 
-  if (item.stack_id && content.mime_type == "text/plain") {
+  if (!item.is_stack && content.mime_type == "text/plain") {
     const info = [
       { s: "word", n: content.words },
       { s: "char", n: content.chars },
+      { s: "line", n: content.lines },
       { s: "token", n: content.tiktokens },
     ]
       .filter((item) => item.n)
@@ -60,6 +61,13 @@ function getMeta(stack: Stack, item: Item, content: Content): MetaValue[] {
     });
   }
 
+  if (content.truncated_from) {
+    meta.push({
+      name: "Truncated",
+      value: `${content.bytes} of ${content.truncated_from} bytes`,
+    });
+  }
+
   if (content.content_type == "Link") {
     const url = content.terse;
     meta.push({
@@ -1,4 +1,7 @@
-import { useEffect, useRef } from "preact/hooks";
+import { useEffect, useRef, useState } from "preact/hooks";
+
+import { invoke } from "@tauri-apps/api/tauri";
+import { listen } from "@tauri-apps/api/event";
 
 import { Icon } from "../ui/icons";
 import { borderRight, previewItem } from "../ui/app.css";
@@ -110,12 +113,21 @@ const renderItems = (
   );
 };
 
+interface PreviewChunk {
+  id: string;
+  seq: number;
+  html: string;
+  done: boolean;
+}
+
 export function Preview(
-  { content, active, ...rest }:
-    & { content: string; active: boolean }
+  { content, active, id, truncated, ...rest }:
+    & { content: string; active: boolean; id: string; truncated: boolean }
     & JSX.HTMLAttributes,
 ) {
   const anchorRef = useRef<HTMLDivElement>(null);
+  // the rest of a large item, rendered on demand, in chunks
+  const [more, setMore] = useState<string[]>([]);
 
   useEffect(() => {
     if (active && anchorRef.current) {
@@ -128,15 +140,42 @@ export function Preview(
     }
   }, [active, anchorRef]);
 
+  useEffect(() => setMore([]), [id, content]);
+
+  async function showAll() {
+    const chunks: string[] = [];
+    const unlisten = await listen<PreviewChunk>("preview-chunk", (event) => {
+      if (event.payload.id != id) return;
+      chunks[event.payload.seq] = event.payload.html;
+      setMore([...chunks]);
+      if (event.payload.done) unlisten();
+    });
+    const count = await invoke<number>("store_render_full_preview", { id });
+    if (count == 0) unlisten();
+  }
+
   return (
     <div
       className={`${previewItem} ${active ? "active" : "not-active"}`}
       ref={anchorRef as any}
-      dangerouslySetInnerHTML={{
-        __html: content || "<i>loading</i>",
-      }}
       {...rest}
     >
+      <div
+        dangerouslySetInnerHTML={{
+          __html: content || "<i>loading</i>",
+        }}
+      />
+      {more.map((html) => <div dangerouslySetInnerHTML={{ __html: html }} />)}
+      {truncated && more.length == 0 && (
+        <a
+          onMouseDown={(e) => {
+            e.stopPropagation();
+            showAll();
+          }}
+        >
+          Show all
+        </a>
+      )}
     </div>
   );
 }
@@ -186,6 +225,8 @@ export function Nav({ stack }: { stack: Stack }) {
                           }}
                           content={getContent(item).value?.preview || ""}
                           active={item?.id == nav.sub?.selected.id}
+                          id={item.id}
+                          truncated={getContent(item).value?.truncated || false}
                         />
                       );
                     })}
@@ -203,7 +244,7 @@ export function Nav({ stack }: { stack: Stack }) {
 const RowIcon = (
   { item, content }: { item: Item; content: Content | null },
 ) => {
-  if (!item.stack_id) return <Icon name="IconStack" />;
+  if (item.is_stack) return <Icon name="IconStack" />;
 
   if (!content) return <Icon name="IconClipboard" />;
 
@@ -1,6 +1,7 @@
 import { signal, effect } from "@preact/signals";
 
 import { invoke } from "@tauri-apps/api/tauri";
+import { listen } from "@tauri-apps/api/event";
 
 let matchMedia = window.matchMedia("(prefers-color-scheme: dark)");
 
@@ -10,11 +11,32 @@ function preferredMode(mql: MediaQueryList): string {
 
 const themeMode = signal(preferredMode(matchMedia));
 
+// the theme previews are rendered with
+export interface Theme {
+  name: string;
+  dark: boolean;
+  syntax: string;
+  css: string | null;
+}
+
+// a custom theme's CSS is applied to the whole webview, so it can style previews
+function applyTheme(theme: Theme) {
+  let style = document.getElementById("preview-theme");
+  if (!style) {
+    style = document.createElement("style");
+    style.id = "preview-theme";
+    document.head.appendChild(style);
+  }
+  style.textContent = theme.css || "";
+}
+
 effect(() => {
     console.log("theme", themeMode.value);
-    invoke("store_set_theme_mode", {mode: themeMode.value});
+    invoke<Theme>("store_set_theme_mode", {mode: themeMode.value}).then(applyTheme);
 });
 
+listen<Theme>("theme", (event) => applyTheme(event.payload));
+
 matchMedia.addEventListener("change", (e: MediaQueryListEvent) => {
   themeMode.value = preferredMode(e.target as MediaQueryList);
   console.log("SYSTEM PREFERRED COLOR SCHEME CHANGED:", themeMode.value);
@@ -17,7 +17,13 @@ export interface Content {
   tiktokens: number;
   words: number;
   chars: number;
+  lines: number;
+  bytes: number;
   preview: string;
+  // the preview only shows the start of the content: see store_render_full_preview
+  truncated: boolean;
+  // the clip's size, in bytes, before it was truncated to max_item_bytes
+  truncated_from: number | null;
 }
 
 export interface Cacheable {
@@ -109,6 +115,7 @@ export const ContentCache = (() => {
 export interface Item {
   id: Scru128Id;
   stack_id?: Scru128Id;
+  is_stack: boolean;
   name: string;
   last_touched: Scru128Id;
   touched: Scru128Id[];